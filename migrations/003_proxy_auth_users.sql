@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS proxy_auth_users (
+    id TEXT PRIMARY KEY,
+    app_id TEXT NOT NULL REFERENCES apps(id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    UNIQUE(app_id, username)
+);
@@ -0,0 +1 @@
+ALTER TABLE deployments ADD COLUMN finished_at TEXT;
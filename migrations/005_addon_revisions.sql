@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS addon_revisions (
+    id TEXT PRIMARY KEY,
+    addon_id TEXT NOT NULL REFERENCES addons(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL,
+    image TEXT NOT NULL,
+    config_json TEXT NOT NULL,
+    status TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS addon_revisions_addon_id ON addon_revisions(addon_id, created_at);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS release_pins (
+    release_id TEXT PRIMARY KEY REFERENCES releases(id) ON DELETE CASCADE,
+    pinned_at TEXT NOT NULL
+);
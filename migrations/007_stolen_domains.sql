@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS stolen_domains (
+    app_id TEXT NOT NULL REFERENCES apps(id) ON DELETE CASCADE,
+    host TEXT NOT NULL,
+    taken_by TEXT NOT NULL,
+    stolen_at TEXT NOT NULL,
+    PRIMARY KEY (app_id, host)
+);
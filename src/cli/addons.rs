@@ -3,13 +3,22 @@ use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use super::deploy::{apply_addon_env, write_app_quadlet};
-use crate::cli::require_app;
+mod backup;
+mod bindings;
+mod inspect;
+mod sync;
+mod upgrade;
+
+use super::deploy::{
+    apply_addon_env, attach_app_network, attach_to_network, detach_from_network, write_app_quadlet,
+};
+use crate::cli::{confirm, print_table, require_app};
+use crate::config::{ResourcesConfig, load_host_config};
 use crate::db::{AddonRow, AppRow, Storage};
 use crate::runner;
-use crate::runtime::Runtime;
+use crate::runtime::{NETWORK_NAME, Runtime, app_network_name, network_lines};
 use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
 
 const DEFAULT_ADDON_DIR: &str = "/srv/deep/addons";
@@ -26,7 +35,7 @@ pub enum AddonsCommand {
     /// Create an addon (quadlet-backed)
     #[command(alias = "a")]
     Create {
-        #[arg(help = "Addon kind (e.g. postgres, redis)")]
+        #[arg(help = "Addon kind (built in: postgres, redis, mysql, minio)")]
         kind: String,
         #[arg(help = "Addon name")]
         name: String,
@@ -37,13 +46,40 @@ pub enum AddonsCommand {
         #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
         config_dir: PathBuf,
     },
-    /// Destroy an addon record
+    /// Destroy an addon: stop it and remove its quadlet, config file and record
     #[command(alias = "rm")]
     Destroy {
         #[arg(help = "Addon name")]
         name: String,
         #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
         config_dir: PathBuf,
+        #[arg(
+            short = 'P',
+            long,
+            help = "Also remove the addon's named volumes (deletes its data)"
+        )]
+        purge_volumes: bool,
+        #[arg(
+            short = 'y',
+            long,
+            requires = "purge_volumes",
+            help = "Skip the confirmation prompt"
+        )]
+        yes: bool,
+    },
+    /// Reconcile addon config files, database records and quadlets
+    #[command(alias = "sy")]
+    Sync {
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+        #[arg(short = 'D', long, help = "Only report what would change")]
+        dry_run: bool,
+        #[arg(
+            short = 'p',
+            long,
+            help = "Remove quadlets that have no config file and no record"
+        )]
+        prune: bool,
     },
     /// Start an addon service
     #[command(alias = "st")]
@@ -63,6 +99,45 @@ pub enum AddonsCommand {
         #[arg(help = "Addon name")]
         name: String,
     },
+    /// Show unit and container state, image, volumes and bound apps
+    #[command(alias = "s")]
+    Status {
+        #[arg(help = "Addon name (all addons when omitted)")]
+        name: Option<String>,
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+    },
+    /// Show an addon's container logs
+    #[command(alias = "l")]
+    Logs {
+        #[arg(help = "Addon name")]
+        name: String,
+        #[arg(short = 'f', long, help = "Follow log output")]
+        follow: bool,
+        #[arg(short = 'n', long, help = "Number of lines to show from the end")]
+        tail: Option<u32>,
+        #[arg(
+            short = 'S',
+            long,
+            help = "Only show entries newer than a duration (e.g. 30s, 15m, 2h, 1d) or a timestamp"
+        )]
+        since: Option<String>,
+        #[arg(short = 't', long, help = "Show timestamps")]
+        timestamps: bool,
+    },
+    /// Run a command in an addon container (e.g. `deep addons exec pg -- psql -U postgres`)
+    #[command(alias = "x")]
+    Exec {
+        #[arg(help = "Addon name")]
+        name: String,
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "Command and arguments"
+        )]
+        command: Vec<String>,
+    },
     /// Bind an addon to an app
     #[command(alias = "b")]
     Bind {
@@ -82,15 +157,123 @@ pub enum AddonsCommand {
         app: String,
         #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
         config_dir: PathBuf,
+        // No short flag: `-D` means --dry-run elsewhere.
+        #[arg(
+            long,
+            help = "Run the addon's deprovision commands (drops the app's data)"
+        )]
+        drop_data: bool,
+        #[arg(
+            short = 'y',
+            long,
+            requires = "drop_data",
+            help = "Skip the confirmation prompt"
+        )]
+        yes: bool,
+    },
+    /// List apps bound to an addon with their resources and env keys (values redacted)
+    #[command(alias = "bs")]
+    Bindings {
+        #[arg(help = "Addon name")]
+        addon: String,
+    },
+    /// Regenerate an app's addon credentials and restart only that app
+    #[command(alias = "ro")]
+    Rotate {
+        #[arg(help = "Addon name")]
+        addon: String,
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+    },
+    /// Back up an addon's data now, or schedule recurring backups
+    #[command(alias = "bk")]
+    Backup {
+        #[arg(help = "Addon name")]
+        name: String,
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+        #[arg(short = 'o', long, default_value = backup::DEFAULT_BACKUP_DIR, help = "Backup root directory")]
+        output_dir: PathBuf,
+        #[arg(
+            short = 'k',
+            long,
+            help = "Backups to keep (overrides [backup] keep; 0 keeps all)"
+        )]
+        keep: Option<usize>,
+        #[arg(
+            short = 's',
+            long,
+            help = "Install a systemd timer with this OnCalendar schedule (e.g. daily)"
+        )]
+        schedule: Option<String>,
+        #[arg(
+            short = 'u',
+            long,
+            conflicts_with = "schedule",
+            help = "Remove the backup timer"
+        )]
+        unschedule: bool,
+    },
+    /// Move an addon to a new image, rolling back if it turns unhealthy
+    #[command(alias = "up")]
+    Upgrade {
+        #[arg(help = "Addon name")]
+        name: String,
+        #[arg(short = 'i', long, help = "New image reference")]
+        image: String,
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+        #[arg(short = 'b', long, help = "Back up the addon's data first")]
+        backup: bool,
+        #[arg(short = 'o', long, default_value = backup::DEFAULT_BACKUP_DIR, help = "Backup root directory")]
+        output_dir: PathBuf,
+        #[arg(
+            short = 't',
+            long,
+            default_value_t = 120,
+            help = "Seconds to wait for health_cmd to pass"
+        )]
+        health_timeout: u64,
+    },
+    /// Restore an addon from a backup directory or timestamp
+    #[command(alias = "rst")]
+    Restore {
+        #[arg(help = "Addon name")]
+        name: String,
+        #[arg(help = "Backup directory, a file in it, or its timestamp")]
+        backup: String,
+        #[arg(short = 'C', long, default_value = DEFAULT_ADDON_DIR, help = "Addon config directory")]
+        config_dir: PathBuf,
+        #[arg(short = 'o', long, default_value = backup::DEFAULT_BACKUP_DIR, help = "Backup root directory")]
+        output_dir: PathBuf,
+        #[arg(
+            short = 'y',
+            long,
+            help = "Confirm overwriting the addon's current data"
+        )]
+        yes: bool,
     },
 }
 
 /// Handle addon subcommands.
-pub fn handle(storage: &mut Storage, command: AddonsCommand) -> Result<()> {
+pub fn handle(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    command: AddonsCommand,
+) -> Result<()> {
     match command {
         AddonsCommand::List { config_dir } => {
             let addons = list_addon_configs(&config_dir)?;
-            if addons.is_empty() {
+            let unsynced: Vec<String> = storage
+                .list_addons()?
+                .into_iter()
+                .filter(|row| !addons.iter().any(|addon| addon.name == row.name))
+                .map(|row| row.name)
+                .collect();
+            if addons.is_empty() && unsynced.is_empty() {
                 println!("no addons found");
                 return Ok(());
             }
@@ -102,6 +285,9 @@ pub fn handle(storage: &mut Storage, command: AddonsCommand) -> Result<()> {
                     addon.image
                 );
             }
+            for name in unsynced {
+                println!("{}  (no config file; run `deep addons sync`)", name);
+            }
             Ok(())
         }
         AddonsCommand::Create {
@@ -128,27 +314,141 @@ pub fn handle(storage: &mut Storage, command: AddonsCommand) -> Result<()> {
             }
             ensure_addon_dir(&config_dir)?;
             let config_path = addon_config_path(&config_dir, &name);
+            // Re-creating keeps the existing file (and its passwords) as the base.
+            let base = if config_path.exists() {
+                Some(load_addon_config_file(&config_path)?)
+            } else {
+                catalog_config(&kind, &name)?
+            };
+            if let Some(base) = base {
+                addon_config = merge_addon_config(base, addon_config);
+            }
             write_addon_config_file(&config_path, &addon_config)?;
             let config_json = addon_config_to_json(&addon_config)?;
             require_addon_image(&addon_config)?;
             let addon = storage.upsert_addon(&name, &kind, &config_json)?;
-            maybe_start_addon_quadlet(&name, &addon_config)?;
+            maybe_start_addon_quadlet(storage, runtime, host_config, &name, &addon_config)?;
             println!("created addon {} ({})", addon.name, addon.id);
             println!("addon config: {}", config_path.display());
             Ok(())
         }
-        AddonsCommand::Destroy { name, config_dir } => {
+        AddonsCommand::Destroy {
+            name,
+            config_dir,
+            purge_volumes,
+            yes,
+        } => {
             let config_path = addon_config_path(&config_dir, &name);
+            let row = storage.get_addon_by_name(&name)?;
+            let config = if config_path.exists() {
+                Some(load_addon_config_file(&config_path)?)
+            } else {
+                row.as_ref()
+                    .map(|row| addon_config_from_json(&row.config_json, &row.kind))
+                    .transpose()?
+            };
+            if let Some(row) = &row {
+                let apps: Vec<String> = storage
+                    .list_addon_bindings(&row.id)?
+                    .into_iter()
+                    .map(|binding| binding.app_name)
+                    .collect();
+                if !apps.is_empty() {
+                    bail!(
+                        "addon {} is bound to {}; run `deep addons unbind {} <app>` for each first",
+                        name,
+                        apps.join(", "),
+                        name
+                    );
+                }
+            }
+            let volumes: Vec<String> = config
+                .as_ref()
+                .map(|config| named_volumes(&config.volumes))
+                .unwrap_or_default();
+            if purge_volumes && !volumes.is_empty() {
+                let prompt = format!(
+                    "Remove volumes {} of addon {}? This deletes its data.",
+                    volumes.join(", "),
+                    name
+                );
+                if !confirm(&prompt, yes)? {
+                    bail!("aborted");
+                }
+            }
+            if sync::remove_addon_quadlet(runtime, &name)? {
+                println!("removed quadlet for {}", name);
+            }
+            if backup::has_schedule(&name) {
+                backup::remove_schedule(&name)?;
+                println!("removed backup timer for {}", name);
+            }
             if config_path.exists() {
                 std::fs::remove_file(&config_path)?;
             }
             storage.destroy_addon(&name)?;
+            if purge_volumes {
+                for volume in &volumes {
+                    if runtime.volume_exists(volume) {
+                        runtime.remove_volume(volume)?;
+                        println!("removed volume {}", volume);
+                    }
+                }
+            } else if !volumes.is_empty() {
+                println!(
+                    "kept volumes {} (use --purge-volumes to remove)",
+                    volumes.join(", ")
+                );
+            }
+            let payload = serde_json::json!({
+                "addon": name,
+                "purged_volumes": if purge_volumes { volumes } else { Vec::new() },
+            });
+            let _ = storage.insert_event("addon_destroy", &payload.to_string());
             println!("destroyed addon {}", name);
             Ok(())
         }
+        AddonsCommand::Sync {
+            config_dir,
+            dry_run,
+            prune,
+        } => {
+            let entries =
+                sync::sync_addons(storage, runtime, host_config, &config_dir, dry_run, prune)?;
+            if entries.is_empty() {
+                println!("no addons found");
+                return Ok(());
+            }
+            let rows: Vec<Vec<String>> = entries.iter().map(sync::SyncEntry::columns).collect();
+            let action = if dry_run { "PLANNED" } else { "ACTION" };
+            print_table(&["NAME", "FILE", "RECORD", "QUADLET", action], &rows);
+            Ok(())
+        }
         AddonsCommand::Start { name } => addon_action(&name, "start"),
         AddonsCommand::Stop { name } => addon_action(&name, "stop"),
         AddonsCommand::Restart { name } => addon_action(&name, "restart"),
+        AddonsCommand::Status { name, config_dir } => {
+            inspect::handle_status(storage, runtime, name, &config_dir)
+        }
+        AddonsCommand::Logs {
+            name,
+            follow,
+            tail,
+            since,
+            timestamps,
+        } => {
+            let options = inspect::log_options(follow, timestamps, since.as_deref(), tail);
+            inspect::handle_logs(
+                runtime,
+                &name,
+                &options,
+                &mut |line| println!("{}", line),
+                &mut |line| eprintln!("{}", line),
+            )
+        }
+        AddonsCommand::Exec { name, command } => {
+            inspect::handle_exec(storage, runtime, &name, &command)
+        }
         AddonsCommand::Bind {
             addon,
             app,
@@ -162,48 +462,195 @@ pub fn handle(storage: &mut Storage, command: AddonsCommand) -> Result<()> {
                 .unwrap_or_else(|| "generic".to_string());
             let config_json = addon_config_to_json(&addon_config)?;
             let addon_row = storage.upsert_addon(&addon, &kind, &config_json)?;
-            let binding_env = provision_addon_on_bind(&addon_row, &addon_config, &app_row)?;
-            let binding_json = serde_json::json!({ "env": binding_env }).to_string();
-            storage.bind_addon(&app_row.id, &addon_row.id, &binding_json)?;
-            restart_app_with_bindings(storage, &app_row)?;
+            let binding = provision_addon_on_bind(runtime, &addon_row, &addon_config, &app_row)?;
+            storage.bind_addon(&app_row.id, &addon_row.id, &binding.to_json()?)?;
+            restart_app_with_bindings(storage, runtime, host_config, &app_row)?;
+            sync_shared_network(storage, runtime, &addon)?;
             println!("bound addon {} to {}", addon, app);
             Ok(())
         }
         AddonsCommand::Unbind {
             addon,
             app,
-            config_dir: _,
+            config_dir,
+            drop_data,
+            yes,
         } => {
             let app_row = require_app(storage, &app)?;
             let addon_row = storage
                 .get_addon_by_name(&addon)?
                 .context("addon not found")?;
+            let addon_config = if addon_config_path(&config_dir, &addon).exists() {
+                load_addon_config_by_name(&config_dir, &addon)?
+            } else {
+                addon_config_from_json(&addon_row.config_json, &addon_row.kind)?
+            };
+            if drop_data {
+                if addon_config.deprovision.is_empty() {
+                    bail!("addon {} has no deprovision commands", addon);
+                }
+                let prompt = format!(
+                    "Drop the data of {} in addon {}? This cannot be undone.",
+                    app, addon
+                );
+                if !confirm(&prompt, yes)? {
+                    bail!("aborted");
+                }
+            }
+            let private = app_uses_private_network(storage, &app_row.id)?;
             storage.unbind_addon(&app_row.id, &addon_row.id)?;
-            restart_app_with_bindings(storage, &app_row)?;
+            restart_app_with_bindings(storage, runtime, host_config, &app_row)?;
+            sync_shared_network(storage, runtime, &addon)?;
+            if private {
+                // The binding was the only reason the addon sat on the app's network.
+                detach_from_network(
+                    runtime,
+                    &app_network_name(&app_row.name),
+                    &format!("deep-addon-{}", addon),
+                )?;
+            }
             println!("unbound addon {} from {}", addon, app);
+            if drop_data {
+                // The app no longer uses the addon, so its data can go.
+                deprovision_addon_on_unbind(storage, runtime, &addon_row, &addon_config, &app_row)
+                    .context("binding removed but deprovisioning failed; clean up manually")?;
+                println!("dropped data of {} in addon {}", app, addon);
+            }
+            Ok(())
+        }
+        AddonsCommand::Bindings { addon } => bindings::handle_bindings(storage, &addon),
+        AddonsCommand::Rotate {
+            addon,
+            app,
+            config_dir,
+        } => {
+            bindings::rotate_binding(storage, runtime, host_config, &addon, &app, &config_dir)?;
+            println!("rotated credentials of {} in addon {}", app, addon);
+            Ok(())
+        }
+        AddonsCommand::Backup {
+            name,
+            config_dir,
+            output_dir,
+            keep,
+            schedule,
+            unschedule,
+        } => {
+            if unschedule {
+                backup::remove_schedule(&name)?;
+                println!("removed backup timer for {}", name);
+                return Ok(());
+            }
+            let addon_config = load_addon_config_by_name(&config_dir, &name)?;
+            if let Some(schedule) = schedule {
+                let db_path = storage
+                    .path()
+                    .context("scheduled backups need a file-backed database")?
+                    .to_string();
+                let timer = backup::install_schedule(
+                    &name,
+                    &schedule,
+                    &db_path,
+                    host_config,
+                    &config_dir,
+                    &output_dir,
+                )?;
+                println!("scheduled backups of {} ({})", name, schedule);
+                println!("timer: {}", timer.display());
+                return Ok(());
+            }
+            let dir = backup::run_backup(runtime, &name, &addon_config, &output_dir, keep)?;
+            let payload = serde_json::json!({ "addon": name, "path": dir });
+            let _ = storage.insert_event("addon_backup", &payload.to_string());
+            println!("backed up addon {} to {}", name, dir.display());
+            Ok(())
+        }
+        AddonsCommand::Upgrade {
+            name,
+            image,
+            config_dir,
+            backup,
+            output_dir,
+            health_timeout,
+        } => {
+            let options = upgrade::UpgradeOptions {
+                image: &image,
+                host_config,
+                config_dir: &config_dir,
+                backup_dir: backup.then_some(output_dir.as_path()),
+                health_timeout: std::time::Duration::from_secs(health_timeout),
+            };
+            upgrade::upgrade_addon(storage, runtime, &name, &options)?;
+            println!("upgraded addon {} to {}", name, image);
+            Ok(())
+        }
+        AddonsCommand::Restore {
+            name,
+            backup,
+            config_dir,
+            output_dir,
+            yes,
+        } => {
+            if !yes {
+                bail!(
+                    "restoring replaces the current data of {}; pass --yes to confirm",
+                    name
+                );
+            }
+            let addon_config = load_addon_config_by_name(&config_dir, &name)?;
+            let dir = backup::run_restore(runtime, &name, &addon_config, &output_dir, &backup)?;
+            let payload = serde_json::json!({ "addon": name, "path": dir });
+            let _ = storage.insert_event("addon_restore", &payload.to_string());
+            println!("restored addon {} from {}", name, dir.display());
             Ok(())
         }
     }
 }
 
-fn maybe_start_addon_quadlet(name: &str, config: &AddonConfigFile) -> Result<()> {
-    let runtime = Runtime::detect()?;
+fn maybe_start_addon_quadlet(
+    storage: &Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    name: &str,
+    config: &AddonConfigFile,
+) -> Result<()> {
     runtime.ensure_deep_network()?;
-    let image = config.image.as_str();
-    let env = &config.env;
-    let volumes = &config.volumes;
-    let ports = &config.ports;
-    if !ports.is_empty() {
+    let private: Vec<String> = addon_networks(storage, name, config)?
+        .into_iter()
+        .filter(|network| crate::runtime::app_of_network(network).is_some())
+        .collect();
+    runtime.ensure_networks(&private)?;
+    if !config.ports.is_empty() {
         eprintln!(
             "warning: addon {} publishes ports to the host; omit ports to keep it internal",
             name
         );
     }
-    let network = config.network.as_deref().unwrap_or("deep-net");
     let unit_name = format!("deep-addon-{}", name);
     let quadlet_dir = default_quadlet_dir();
-    let quadlet_path = std::path::Path::new(&quadlet_dir).join(format!("{}.container", unit_name));
-    std::fs::create_dir_all(&quadlet_dir)?;
+    let contents = render_addon_quadlet(storage, host_config, runtime, name, config)?;
+    crate::units::write_unit(runtime, &quadlet_dir, &unit_name, &contents)?;
+    systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
+    systemctl_for_dir(
+        &quadlet_dir,
+        &["enable", "--now", &format!("{}.service", unit_name)],
+    )?;
+    Ok(())
+}
+
+/// Render an addon's quadlet from its config, bindings and the host logging settings.
+fn render_addon_quadlet(
+    storage: &Storage,
+    host_config: &Path,
+    runtime: &Runtime,
+    name: &str,
+    config: &AddonConfigFile,
+) -> Result<String> {
+    let networks = addon_networks(storage, name, config)?;
+    let image = config.image.as_str();
+    let env = &config.env;
+    let volumes = &config.volumes;
+    let ports = &config.ports;
     let mut env_lines = Vec::new();
     for (key, value) in env {
         env_lines.push(format!("Environment={}={}", key, value));
@@ -216,22 +663,302 @@ fn maybe_start_addon_quadlet(name: &str, config: &AddonConfigFile) -> Result<()>
     for port in ports.iter().cloned() {
         port_lines.push(format!("PublishPort={}", port));
     }
+    let logging = load_host_config(host_config)?.logging;
     let template = include_str!("../../templates/addon.container");
-    let contents = template
+    Ok(template
         .replace("{{name}}", name)
         .replace("{{image}}", image)
-        .replace("{{network}}", network)
+        .replace("{{networks}}", &network_lines(&networks))
         .replace("{{env}}", &env_lines.join("\n"))
         .replace("{{volumes}}", &volume_lines.join("\n"))
         .replace("{{ports}}", &port_lines.join("\n"))
-        .replace("{{health}}", &health_lines_for_addon(config));
-    std::fs::write(&quadlet_path, contents)?;
-    systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
-    systemctl_for_dir(
-        &quadlet_dir,
-        &["enable", "--now", &format!("{}.service", unit_name)],
-    )?;
-    Ok(())
+        .replace("{{health}}", &health_lines_for_addon(config))
+        .replace("{{resources}}", &config.resources.quadlet_lines())
+        .replace("{{logging}}", &logging.quadlet_lines())
+        .replace("{{auth}}", &crate::registry::auth_line(runtime.auth_file()))
+        .replace("{{exec}}", &exec_line_for_addon(config)))
+}
+
+/// The addon's own network followed by the private networks of the apps bound to it.
+///
+/// `deep-net` is left out when every bound app runs on its own network, so those
+/// apps' addons are not reachable from the shared network. An unbound addon keeps it.
+fn addon_networks(storage: &Storage, name: &str, config: &AddonConfigFile) -> Result<Vec<String>> {
+    let own = config
+        .network
+        .clone()
+        .unwrap_or_else(|| NETWORK_NAME.to_string());
+    let Some(addon) = storage.get_addon_by_name(name)? else {
+        return Ok(vec![own]);
+    };
+    let bindings = storage.list_addon_bindings(&addon.id)?;
+    let mut private = Vec::new();
+    for binding in &bindings {
+        if app_uses_private_network(storage, &binding.app_id)? {
+            private.push(app_network_name(&binding.app_name));
+        }
+    }
+    let shared_unused = !bindings.is_empty() && private.len() == bindings.len();
+    let mut networks = Vec::new();
+    if own != NETWORK_NAME || !shared_unused {
+        networks.push(own);
+    }
+    networks.extend(private);
+    Ok(networks)
+}
+
+/// Attach an addon to `deep-net` or detach it, following `addon_networks`.
+pub(crate) fn sync_shared_network(storage: &Storage, runtime: &Runtime, name: &str) -> Result<()> {
+    let Some(addon) = storage.get_addon_by_name(name)? else {
+        return Ok(());
+    };
+    let config = addon_config_from_json(&addon.config_json, &addon.kind)?;
+    let container = format!("deep-addon-{}", name);
+    if addon_networks(storage, name, &config)?
+        .iter()
+        .any(|network| network == NETWORK_NAME)
+    {
+        attach_to_network(runtime, NETWORK_NAME, &container)
+    } else {
+        detach_from_network(runtime, NETWORK_NAME, &container)
+    }
+}
+
+/// Whether the app's current release runs on a private network.
+fn app_uses_private_network(storage: &Storage, app_id: &str) -> Result<bool> {
+    let Some(release_id) = storage.current_release_id(app_id)? else {
+        return Ok(false);
+    };
+    let Some(release) = storage.get_release_by_id(&release_id)? else {
+        return Ok(false);
+    };
+    let snapshot: crate::config::ConfigSnapshot =
+        serde_json::from_str(&release.config_json).context("invalid release config")?;
+    Ok(snapshot.deploy.private_network)
+}
+
+/// Kinds with built-in defaults for `deep addons create`.
+const CATALOG_KINDS: [&str; 4] = ["postgres", "redis", "mysql", "minio"];
+
+/// Sanitized app name usable as a role, user and database name. The checksum of the
+/// raw name keeps `my-app` and `my_app` apart; the prefix is cut to fit MySQL's 32
+/// character user names.
+const PROVISION_IDENT: &str = r#"suffix="$(printf '%s' "$DEEP_APP" | cksum | cut -d ' ' -f 1)"
+ident="$(printf '%s' "$DEEP_APP" | tr -c 'A-Za-z0-9' '_' | cut -c 1-20)_$suffix""#;
+/// Fresh random password for the bound app.
+const PROVISION_PASSWORD: &str = r#"pass="$(head -c 24 /dev/urandom | od -An -tx1 | tr -d ' \n')""#;
+
+const POSTGRES_PROVISION: &str = r#"set -e
+q() { psql -v ON_ERROR_STOP=1 -U "$POSTGRES_USER" -d postgres -Atc "$1"; }
+if [ "$(q "SELECT 1 FROM pg_roles WHERE rolname = '$ident'")" = 1 ]; then
+  q "ALTER ROLE \"$ident\" LOGIN PASSWORD '$pass'" >/dev/null
+else
+  q "CREATE ROLE \"$ident\" LOGIN PASSWORD '$pass'" >/dev/null
+fi
+if [ "$(q "SELECT 1 FROM pg_database WHERE datname = '$ident'")" != 1 ]; then
+  q "CREATE DATABASE \"$ident\" OWNER \"$ident\"" >/dev/null
+fi
+echo "resource.database=$ident"
+echo "resource.role=$ident"
+echo "DATABASE_URL=postgres://$ident:$pass@$DEEP_ADDON:5432/$ident""#;
+
+const MYSQL_PROVISION: &str = r#"set -e
+MYSQL_PWD="$MYSQL_ROOT_PASSWORD" mysql -uroot -e "CREATE DATABASE IF NOT EXISTS \`$ident\`; CREATE USER IF NOT EXISTS '$ident'@'%' IDENTIFIED BY '$pass'; ALTER USER '$ident'@'%' IDENTIFIED BY '$pass'; GRANT ALL PRIVILEGES ON \`$ident\`.* TO '$ident'@'%';"
+echo "resource.database=$ident"
+echo "resource.user=$ident"
+echo "DATABASE_URL=mysql://$ident:$pass@$DEEP_ADDON:3306/$ident""#;
+
+const MINIO_PROVISION: &str = r#"set -e
+bucket="$(printf '%s' "$DEEP_APP" | tr 'A-Z_' 'a-z-' | cut -c 1-40)-$suffix"
+mc alias set deep http://127.0.0.1:9000 "$MINIO_ROOT_USER" "$MINIO_ROOT_PASSWORD" >/dev/null
+mc mb --ignore-existing "deep/$bucket" >/dev/null
+mc admin user add deep "$ident" "$pass" >/dev/null
+printf '{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Action":["s3:*"],"Resource":["arn:aws:s3:::%s","arn:aws:s3:::%s/*"]}]}' "$bucket" "$bucket" > /tmp/deep-policy.json
+mc admin policy create deep "$ident" /tmp/deep-policy.json >/dev/null
+mc admin policy attach deep "$ident" --user "$ident" >/dev/null 2>&1 || true
+rm -f /tmp/deep-policy.json
+echo "resource.bucket=$bucket"
+echo "resource.user=$ident"
+echo "S3_ENDPOINT=http://$DEEP_ADDON:9000"
+echo "S3_BUCKET=$bucket"
+echo "AWS_ACCESS_KEY_ID=$ident"
+echo "AWS_SECRET_ACCESS_KEY=$pass""#;
+
+const POSTGRES_DEPROVISION: &str = r#"set -e
+q() { psql -v ON_ERROR_STOP=1 -U "$POSTGRES_USER" -d postgres -Atc "$1"; }
+q "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = '$ident'" >/dev/null
+q "DROP DATABASE IF EXISTS \"$ident\"" >/dev/null
+q "DROP ROLE IF EXISTS \"$ident\"" >/dev/null
+echo "dropped database and role $ident""#;
+
+const MYSQL_DEPROVISION: &str = r#"set -e
+MYSQL_PWD="$MYSQL_ROOT_PASSWORD" mysql -uroot -e "DROP DATABASE IF EXISTS \`$ident\`; DROP USER IF EXISTS '$ident'@'%';"
+echo "dropped database and user $ident""#;
+
+const MINIO_DEPROVISION: &str = r#"set -e
+bucket="$(printf '%s' "$DEEP_APP" | tr 'A-Z_' 'a-z-' | cut -c 1-40)-$suffix"
+mc alias set deep http://127.0.0.1:9000 "$MINIO_ROOT_USER" "$MINIO_ROOT_PASSWORD" >/dev/null
+mc rb --force "deep/$bucket" >/dev/null 2>&1 || true
+mc admin user rm deep "$ident" >/dev/null 2>&1 || true
+mc admin policy rm deep "$ident" >/dev/null 2>&1 || true
+echo "removed bucket $bucket and user $ident""#;
+
+const REDIS_PROVISION: &str =
+    r#"echo "REDIS_URL=redis://default:$REDIS_PASSWORD@$DEEP_ADDON:6379/0""#;
+
+/// Built-in defaults for a catalog kind, with freshly generated passwords.
+fn catalog_config(kind: &str, name: &str) -> Result<Option<AddonConfigFile>> {
+    if !CATALOG_KINDS.contains(&kind) {
+        return Ok(None);
+    }
+    let password = generate_password()?;
+    let volume = |path: &str| format!("deep-addon-{}-data:{}", name, path);
+    let provision = |script: &str| {
+        vec![format!(
+            "{}\n{}\n{}",
+            PROVISION_IDENT, PROVISION_PASSWORD, script
+        )]
+    };
+    let deprovision = |script: &str| vec![format!("{}\n{}", PROVISION_IDENT, script)];
+    let mut config = AddonConfigFile {
+        kind: Some(kind.to_string()),
+        image: String::new(),
+        env: BTreeMap::new(),
+        volumes: Vec::new(),
+        ports: Vec::new(),
+        network: None,
+        command: None,
+        provision: Vec::new(),
+        deprovision: Vec::new(),
+        export_env: Vec::new(),
+        bind_env: BTreeMap::new(),
+        health_cmd: None,
+        health_interval_ms: Some(2000),
+        health_timeout_ms: Some(2000),
+        health_retries: Some(5),
+        resources: ResourcesConfig::default(),
+        backup: backup::BackupConfig::default(),
+    };
+    match kind {
+        "postgres" => {
+            config.image = "docker.io/library/postgres:16.4".to_string();
+            config
+                .env
+                .insert("POSTGRES_USER".to_string(), "postgres".to_string());
+            config.env.insert("POSTGRES_PASSWORD".to_string(), password);
+            config.volumes = vec![volume("/var/lib/postgresql/data")];
+            config.health_cmd = Some("pg_isready -U postgres".to_string());
+            config.provision = provision(POSTGRES_PROVISION);
+            config.deprovision = deprovision(POSTGRES_DEPROVISION);
+        }
+        "redis" => {
+            config.image = "docker.io/library/redis:7.4".to_string();
+            // The password stays in the environment: systemd would expand `$VAR` in the
+            // generated unit, so the shell reads it with printenv. Going through the image
+            // entrypoint keeps the switch to the redis user.
+            config.command = Some(
+                r#"sh -c 'exec docker-entrypoint.sh redis-server --requirepass "$(printenv REDIS_PASSWORD)" --save 60 1 --dir /data'"#
+                    .to_string(),
+            );
+            // redis-cli authenticates with REDISCLI_AUTH from the container environment.
+            config.health_cmd = Some("redis-cli ping".to_string());
+            config
+                .env
+                .insert("REDISCLI_AUTH".to_string(), password.clone());
+            config.env.insert("REDIS_PASSWORD".to_string(), password);
+            config.volumes = vec![volume("/data")];
+            config.provision = vec![REDIS_PROVISION.to_string()];
+        }
+        "mysql" => {
+            config.image = "docker.io/library/mysql:8.4".to_string();
+            config
+                .env
+                .insert("MYSQL_ROOT_PASSWORD".to_string(), password);
+            config.volumes = vec![volume("/var/lib/mysql")];
+            // mysqladmin ping succeeds without credentials once the server is up.
+            config.health_cmd = Some("mysqladmin ping -h 127.0.0.1 --silent".to_string());
+            config.provision = provision(MYSQL_PROVISION);
+            config.deprovision = deprovision(MYSQL_DEPROVISION);
+        }
+        "minio" => {
+            config.image = "quay.io/minio/minio:RELEASE.2024-10-13T13-34-11Z".to_string();
+            config.command = Some("server /data --console-address :9001".to_string());
+            config
+                .env
+                .insert("MINIO_ROOT_USER".to_string(), "deep-admin".to_string());
+            config
+                .env
+                .insert("MINIO_ROOT_PASSWORD".to_string(), password);
+            config.volumes = vec![volume("/data")];
+            config.health_cmd = Some("mc ready local".to_string());
+            config.provision = provision(MINIO_PROVISION);
+            config.deprovision = deprovision(MINIO_DEPROVISION);
+        }
+        _ => unreachable!("kind checked against CATALOG_KINDS"),
+    }
+    Ok(Some(config))
+}
+
+/// Overlay user-supplied settings on catalog (or previously written) defaults.
+fn merge_addon_config(base: AddonConfigFile, user: AddonConfigFile) -> AddonConfigFile {
+    let pick_list =
+        |base: Vec<String>, user: Vec<String>| if user.is_empty() { base } else { user };
+    let merge_map = |mut base: BTreeMap<String, String>, user: BTreeMap<String, String>| {
+        base.extend(user);
+        base
+    };
+    AddonConfigFile {
+        kind: user.kind.or(base.kind),
+        image: if user.image.trim().is_empty() {
+            base.image
+        } else {
+            user.image
+        },
+        env: merge_map(base.env, user.env),
+        volumes: pick_list(base.volumes, user.volumes),
+        ports: pick_list(base.ports, user.ports),
+        network: user.network.or(base.network),
+        command: user.command.or(base.command),
+        provision: pick_list(base.provision, user.provision),
+        deprovision: pick_list(base.deprovision, user.deprovision),
+        export_env: pick_list(base.export_env, user.export_env),
+        bind_env: merge_map(base.bind_env, user.bind_env),
+        health_cmd: user.health_cmd.or(base.health_cmd),
+        health_interval_ms: user.health_interval_ms.or(base.health_interval_ms),
+        health_timeout_ms: user.health_timeout_ms.or(base.health_timeout_ms),
+        health_retries: user.health_retries.or(base.health_retries),
+        resources: if user.resources.is_empty() {
+            base.resources
+        } else {
+            user.resources
+        },
+        backup: if user.backup.is_default() {
+            base.backup
+        } else {
+            user.backup
+        },
+    }
+}
+
+/// 32 random alphanumeric characters from /dev/urandom.
+fn generate_password() -> Result<String> {
+    use std::io::Read;
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .context("failed to read /dev/urandom")?;
+    Ok(bytes
+        .iter()
+        .map(|byte| ALPHABET[*byte as usize % ALPHABET.len()] as char)
+        .collect())
+}
+
+fn exec_line_for_addon(config: &AddonConfigFile) -> String {
+    match config.command.as_deref().map(str::trim) {
+        Some(command) if !command.is_empty() => format!("Exec={}", command),
+        _ => String::new(),
+    }
 }
 
 fn require_addon_image(config: &AddonConfigFile) -> Result<()> {
@@ -251,6 +978,7 @@ struct AddonListEntry {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct AddonConfigFile {
     kind: Option<String>,
+    #[serde(default)]
     image: String,
     #[serde(default)]
     env: BTreeMap<String, String>,
@@ -259,9 +987,13 @@ struct AddonConfigFile {
     #[serde(default)]
     ports: Vec<String>,
     network: Option<String>,
+    /// Container command (quadlet `Exec=`), e.g. server flags.
+    command: Option<String>,
     #[serde(default)]
     provision: Vec<String>,
     #[serde(default)]
+    deprovision: Vec<String>,
+    #[serde(default)]
     export_env: Vec<String>,
     #[serde(default)]
     bind_env: BTreeMap<String, String>,
@@ -269,6 +1001,10 @@ struct AddonConfigFile {
     health_interval_ms: Option<u64>,
     health_timeout_ms: Option<u64>,
     health_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "ResourcesConfig::is_empty")]
+    resources: ResourcesConfig,
+    #[serde(default, skip_serializing_if = "backup::BackupConfig::is_default")]
+    backup: backup::BackupConfig,
 }
 
 fn ensure_addon_dir(dir: &PathBuf) -> Result<()> {
@@ -341,13 +1077,17 @@ fn addon_config_to_json(cfg: &AddonConfigFile) -> Result<String> {
         "volumes": cfg.volumes,
         "ports": cfg.ports,
         "network": cfg.network,
+        "command": cfg.command,
         "provision": cfg.provision,
+        "deprovision": cfg.deprovision,
         "export_env": cfg.export_env,
         "bind_env": cfg.bind_env,
         "health_cmd": cfg.health_cmd,
         "health_interval_ms": cfg.health_interval_ms,
         "health_timeout_ms": cfg.health_timeout_ms,
         "health_retries": cfg.health_retries,
+        "resources": cfg.resources,
+        "backup": cfg.backup,
     });
     Ok(value.to_string())
 }
@@ -378,11 +1118,20 @@ fn addon_config_from_json(config_json: &str, kind: &str) -> Result<AddonConfigFi
         .get("network")
         .and_then(|v| v.as_str())
         .map(|s| s.to_string());
+    let command = value
+        .get("command")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
     let provision = value
         .get("provision")
         .and_then(|v| v.as_array())
         .map(json_array_to_vec)
         .unwrap_or_default();
+    let deprovision = value
+        .get("deprovision")
+        .and_then(|v| v.as_array())
+        .map(json_array_to_vec)
+        .unwrap_or_default();
     let export_env = value
         .get("export_env")
         .and_then(|v| v.as_array())
@@ -403,6 +1152,22 @@ fn addon_config_from_json(config_json: &str, kind: &str) -> Result<AddonConfigFi
         .get("health_retries")
         .and_then(|v| v.as_u64())
         .map(|v| v as u32);
+    let resources = value
+        .get("resources")
+        .filter(|v| !v.is_null())
+        .cloned()
+        .map(serde_json::from_value)
+        .transpose()
+        .with_context(|| "invalid addon resources")?
+        .unwrap_or_default();
+    let backup = value
+        .get("backup")
+        .filter(|v| !v.is_null())
+        .cloned()
+        .map(serde_json::from_value)
+        .transpose()
+        .with_context(|| "invalid addon backup settings")?
+        .unwrap_or_default();
     Ok(AddonConfigFile {
         kind: Some(kind.to_string()),
         image,
@@ -410,16 +1175,30 @@ fn addon_config_from_json(config_json: &str, kind: &str) -> Result<AddonConfigFi
         volumes,
         ports,
         network,
+        command,
         provision,
+        deprovision,
         export_env,
         bind_env,
         health_cmd,
         health_interval_ms,
         health_timeout_ms,
         health_retries,
+        resources,
+        backup,
     })
 }
 
+/// Sources of named volumes in quadlet `Volume=` specs (host paths are skipped).
+fn named_volumes(volumes: &[String]) -> Vec<String> {
+    volumes
+        .iter()
+        .filter_map(|spec| spec.split(':').next())
+        .filter(|source| !source.is_empty() && !source.starts_with('/') && !source.starts_with('.'))
+        .map(str::to_string)
+        .collect()
+}
+
 fn addon_action(name: &str, action: &str) -> Result<()> {
     let unit_name = format!("deep-addon-{}", name);
     let quadlet_dir = default_quadlet_dir();
@@ -434,7 +1213,12 @@ fn addon_action(name: &str, action: &str) -> Result<()> {
     Ok(())
 }
 
-fn restart_app_with_bindings(storage: &mut Storage, app_row: &AppRow) -> Result<()> {
+fn restart_app_with_bindings(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    app_row: &AppRow,
+) -> Result<()> {
     let release_id = storage
         .current_release_id(&app_row.id)?
         .context("no current release set")?;
@@ -449,11 +1233,15 @@ fn restart_app_with_bindings(storage: &mut Storage, app_row: &AppRow) -> Result<
     if snapshot.deploy.quadlet_dir.is_none() {
         snapshot.deploy.quadlet_dir = Some(default_quadlet_dir());
     }
+    if snapshot.deploy.private_network {
+        attach_app_network(runtime, &app_row.name, &snapshot)?;
+    }
     let quadlet_dir = snapshot.deploy.quadlet_dir.clone().unwrap_or_default();
     let unit_name = crate::runtime::app_container_name(&app_row.name, &release_id);
     write_app_quadlet(
+        runtime,
+        host_config,
         &quadlet_dir,
-        &unit_name,
         &release.image_ref,
         &snapshot,
         &app_row.name,
@@ -468,70 +1256,113 @@ fn restart_app_with_bindings(storage: &mut Storage, app_row: &AppRow) -> Result<
 }
 
 fn provision_addon_on_bind(
+    runtime: &Runtime,
     addon: &AddonRow,
     config: &AddonConfigFile,
     app: &AppRow,
-) -> Result<BTreeMap<String, String>> {
+) -> Result<bindings::BindingConfig> {
     let mut envs = config.bind_env.clone();
     let container = format!("deep-addon-{}", addon.name);
-    let command_envs = run_provision_commands(&container, app, &config.provision)?;
+    let (command_envs, resources) =
+        run_provision_commands(runtime, &container, app, &config.provision)?;
     for (key, value) in command_envs {
         envs.insert(key, value);
     }
-    let mut exported = read_container_env(&container)?;
+    let mut exported = read_container_env(runtime, &container)?;
     for key in &config.export_env {
         if let Some(value) = exported.remove(key) {
             envs.insert(key.clone(), value);
         }
     }
-    Ok(envs)
+    Ok(bindings::BindingConfig::provisioned(envs, resources))
 }
 
+/// Run provision commands; returns the env they print and the resources they report.
 fn run_provision_commands(
+    runtime: &Runtime,
     container: &str,
     app: &AppRow,
     commands: &[String],
-) -> Result<BTreeMap<String, String>> {
+) -> Result<(BTreeMap<String, String>, BTreeMap<String, String>)> {
     let mut envs = BTreeMap::new();
+    let mut resources = BTreeMap::new();
     for cmd in commands {
-        let output = runner::run_output(
-            "podman",
-            &[
-                "exec",
-                "-e",
-                &format!("DEEP_APP={}", app.name),
-                "-e",
-                &format!("DEEP_APP_ID={}", app.id),
-                "-e",
-                &format!("DEEP_ADDON={}", container),
-                container,
-                "sh",
-                "-lc",
-                cmd,
-            ],
-        )
-        .with_context(|| "failed to run addon provision command")?;
-        if !output.status.success() {
-            bail!(
-                "addon provision failed: {}",
-                String::from_utf8_lossy(&output.stderr).trim()
-            );
-        }
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            if let Some((key, value)) = line.split_once('=') {
-                if !key.trim().is_empty() {
-                    envs.insert(key.trim().to_string(), value.trim().to_string());
-                }
+        let stdout = exec_addon_command(runtime, container, app, cmd, "provision")?;
+        bindings::parse_provision_output(&stdout, &mut envs, &mut resources);
+    }
+    Ok((envs, resources))
+}
+
+/// Run `deprovision` commands after an unbind and record the outcome as an event.
+fn deprovision_addon_on_unbind(
+    storage: &Storage,
+    runtime: &Runtime,
+    addon: &AddonRow,
+    config: &AddonConfigFile,
+    app: &AppRow,
+) -> Result<()> {
+    let container = format!("deep-addon-{}", addon.name);
+    let mut output = Vec::new();
+    let mut result = Ok(());
+    for cmd in &config.deprovision {
+        match exec_addon_command(runtime, &container, app, cmd, "deprovision") {
+            Ok(stdout) => output.push(stdout.trim().to_string()),
+            Err(err) => {
+                result = Err(err);
+                break;
             }
         }
     }
-    Ok(envs)
+    let payload = serde_json::json!({
+        "addon": addon.name,
+        "app": app.name,
+        "app_id": app.id,
+        "commands": config.deprovision.len(),
+        "output": output,
+        "error": result.as_ref().err().map(|err| err.to_string()),
+    });
+    let _ = storage.insert_event("addon_deprovision", &payload.to_string());
+    result
+}
+
+/// Run one command in an addon container with the app's identity in its env.
+fn exec_addon_command(
+    runtime: &Runtime,
+    container: &str,
+    app: &AppRow,
+    cmd: &str,
+    stage: &str,
+) -> Result<String> {
+    let output = runner::run_output(
+        runtime.program(),
+        &[
+            "exec",
+            "-e",
+            &format!("DEEP_APP={}", app.name),
+            "-e",
+            &format!("DEEP_APP_ID={}", app.id),
+            "-e",
+            &format!("DEEP_ADDON={}", container),
+            container,
+            "sh",
+            "-lc",
+            cmd,
+        ],
+    )
+    .with_context(|| format!("failed to run addon {} command", stage))?;
+    if !output.status.success() {
+        bail!(
+            "addon {} failed: {}",
+            stage,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-fn read_container_env(container: &str) -> Result<BTreeMap<String, String>> {
+fn read_container_env(runtime: &Runtime, container: &str) -> Result<BTreeMap<String, String>> {
     let output = runner::run_output(
-        "podman",
+        runtime.program(),
         &["inspect", "--format", "{{json .Config.Env}}", container],
     )
     .with_context(|| "failed to read addon container env")?;
@@ -574,77 +1405,213 @@ fn format_duration_ms(ms: u64) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::{ReleaseRow, Storage};
-    use crate::runner::{Runner, set_runner_for_tests};
-    use std::process::{ExitStatus, Output};
+    use crate::db::Storage;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, release_row, test_podman,
+    };
     use std::sync::{Arc, Mutex, OnceLock};
 
-    #[derive(Default)]
-    struct TestRunner {
-        rules: Mutex<Vec<Rule>>,
-    }
+    #[test]
+    fn catalog_defaults_are_overridden_by_user_config() -> Result<()> {
+        let base = catalog_config("postgres", "pg-main")?.expect("postgres in catalog");
+        assert_eq!(base.image, "docker.io/library/postgres:16.4");
+        assert_eq!(
+            base.volumes,
+            ["deep-addon-pg-main-data:/var/lib/postgresql/data"]
+        );
+        assert_eq!(base.health_cmd.as_deref(), Some("pg_isready -U postgres"));
+        let password = base.env["POSTGRES_PASSWORD"].clone();
+        assert_eq!(password.len(), 32);
+        assert!(password.chars().all(|ch| ch.is_ascii_alphanumeric()));
+        assert!(base.provision[0].contains("CREATE DATABASE"));
+        assert!(base.provision[0].contains("echo \"DATABASE_URL=postgres://"));
+        assert!(catalog_config("custom", "x")?.is_none());
+        let other = catalog_config("postgres", "pg-other")?.expect("postgres in catalog");
+        assert_ne!(other.env["POSTGRES_PASSWORD"], password);
 
-    #[derive(Clone)]
-    struct Rule {
-        contains: Vec<String>,
-        status: i32,
-        stdout: String,
-        stderr: String,
-    }
+        let mut user = addon_config_from_json(
+            r#"{"image":"postgres:17","env":{"TZ":"UTC"},"health_retries":9}"#,
+            "postgres",
+        )?;
+        user.env
+            .insert("POSTGRES_USER".to_string(), "admin".to_string());
+        let merged = merge_addon_config(base, user);
+        assert_eq!(merged.image, "postgres:17");
+        assert_eq!(merged.env["POSTGRES_USER"], "admin");
+        assert_eq!(merged.env["POSTGRES_PASSWORD"], password);
+        assert_eq!(merged.env["TZ"], "UTC");
+        assert_eq!(merged.health_retries, Some(9));
+        assert_eq!(merged.health_interval_ms, Some(2000));
+        assert_eq!(merged.volumes.len(), 1);
+        assert_eq!(merged.provision.len(), 1);
 
-    impl Rule {
-        fn matches(&self, cmd: &str) -> bool {
-            self.contains.iter().all(|needle| cmd.contains(needle))
+        let redis = catalog_config("redis", "cache")?.expect("redis in catalog");
+        let redis_password = &redis.env["REDIS_PASSWORD"];
+        assert_eq!(&redis.env["REDISCLI_AUTH"], redis_password);
+        for command in [&redis.command, &redis.health_cmd] {
+            let command = command.as_deref().expect("redis command and health_cmd");
+            assert!(!command.contains(redis_password.as_str()));
         }
+        assert!(
+            redis
+                .command
+                .as_deref()
+                .is_some_and(|command| command.contains("printenv REDIS_PASSWORD"))
+        );
+        assert!(redis.provision[0].contains("REDIS_URL=redis://"));
+        Ok(())
     }
 
-    impl TestRunner {
-        fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
-            self.rules.lock().expect("rules lock").push(Rule {
-                contains: contains.iter().map(|s| s.to_string()).collect(),
-                status,
-                stdout: stdout.to_string(),
-                stderr: stderr.to_string(),
-            });
-        }
+    #[test]
+    fn provision_idents_do_not_collide() -> Result<()> {
+        let ident = |app: &str| -> Result<String> {
+            let output = std::process::Command::new("sh")
+                .args([
+                    "-c",
+                    &format!("{}\nprintf '%s' \"$ident\"", PROVISION_IDENT),
+                ])
+                .env("DEEP_APP", app)
+                .output()?;
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        };
+        let dashed = ident("my-app")?;
+        assert!(dashed.starts_with("my_app_"));
+        assert_ne!(dashed, ident("my_app")?);
+        assert!(ident(&"a".repeat(60))?.len() <= 32);
+        Ok(())
     }
 
-    impl Runner for TestRunner {
-        fn output(&self, program: &str, args: &[&str]) -> anyhow::Result<Output> {
-            let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-            let cmdline = format!("{} {}", program, args_joined);
-            if let Some(rule) = self
-                .rules
-                .lock()
-                .expect("rules lock")
-                .iter()
-                .find(|rule| rule.matches(&cmdline))
-                .cloned()
-            {
-                return Ok(Output {
-                    status: exit_status(rule.status),
-                    stdout: rule.stdout.into_bytes(),
-                    stderr: rule.stderr.into_bytes(),
-                });
-            }
-            Ok(Output {
-                status: exit_status(0),
-                stdout: Vec::new(),
-                stderr: Vec::new(),
-            })
-        }
-    }
+    #[test]
+    fn deprovision_runs_with_app_env_and_records_events() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(
+            &["podman exec", "DEEP_APP=web", "deep-addon-pg", "drop-db"],
+            0,
+            "dropped web\n",
+            "",
+        );
+        runner.add_rule(&["podman exec", "broken"], 1, "", "permission denied");
+        let _guard = set_runner_for_tests(runner);
+        let storage = Storage::open(&temp.path().join("deep.db"))?;
+        let app = storage.create_app("web", "/tmp")?;
+        let addon = storage.create_addon("pg", "postgres", "{}")?;
+        let mut config = addon_config_from_json(r#"{"deprovision":["drop-db"]}"#, "postgres")?;
+
+        deprovision_addon_on_unbind(&storage, &test_podman(), &addon, &config, &app)?;
+        config.deprovision.push("broken".to_string());
+        let err = deprovision_addon_on_unbind(&storage, &test_podman(), &addon, &config, &app)
+            .unwrap_err();
+        assert!(err.to_string().contains("permission denied"));
 
-    #[cfg(unix)]
-    fn exit_status(code: i32) -> ExitStatus {
-        use std::os::unix::process::ExitStatusExt;
-        ExitStatus::from_raw(code << 8)
+        let events = storage.list_events("addon_deprovision")?;
+        assert_eq!(events.len(), 2);
+        let first: Value = serde_json::from_str(&events[0].payload_json)?;
+        assert_eq!(first["app"], "web");
+        assert_eq!(first["output"][0], "dropped web");
+        assert!(first["error"].is_null());
+        let second: Value = serde_json::from_str(&events[1].payload_json)?;
+        assert!(
+            second["error"]
+                .as_str()
+                .is_some_and(|error| error.contains("permission denied"))
+        );
+
+        let catalog = catalog_config("postgres", "pg")?.expect("postgres in catalog");
+        assert!(catalog.deprovision[0].contains("DROP DATABASE IF EXISTS"));
+        Ok(())
     }
 
-    #[cfg(windows)]
-    fn exit_status(code: i32) -> ExitStatus {
-        use std::os::windows::process::ExitStatusExt;
-        ExitStatus::from_raw(code as u32)
+    #[test]
+    fn upgrade_rolls_back_when_health_fails() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let home = temp.path().join("home");
+        std::fs::create_dir_all(&home)?;
+        let _env_guard = set_home_for_test(&home)?;
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule_once(
+            &["podman exec deep-addon-pg", "pg_isready"],
+            1,
+            "",
+            "no response",
+        );
+        let _guard = set_runner_for_tests(runner.clone());
+
+        let storage = Storage::open(&temp.path().join("deep.db"))?;
+        let config_dir = temp.path().join("addons");
+        let mut config = addon_config_from_json(
+            r#"{"image":"postgres:16","health_cmd":"pg_isready","health_interval_ms":1}"#,
+            "postgres",
+        )?;
+        write_addon_config_file(&addon_config_path(&config_dir, "pg"), &config)?;
+        let addon = storage.create_addon("pg", "postgres", &addon_config_to_json(&config)?)?;
+        let host_config = temp.path().join("deep.toml");
+        let options = |image| upgrade::UpgradeOptions {
+            image,
+            host_config: &host_config,
+            config_dir: &config_dir,
+            backup_dir: None,
+            health_timeout: std::time::Duration::ZERO,
+        };
+
+        runner.add_rule_once(&["podman pull", "postgres:99"], 1, "", "manifest unknown");
+        let err = upgrade::upgrade_addon(&storage, &test_podman(), "pg", &options("postgres:99"))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("addon left unchanged"));
+        assert!(storage.list_addon_revisions(&addon.id)?.is_empty());
+
+        let err = upgrade::upgrade_addon(&storage, &test_podman(), "pg", &options("postgres:17"))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("no response"));
+        config = load_addon_config_by_name(&config_dir, "pg")?;
+        assert_eq!(config.image, "postgres:16");
+        let quadlet = std::fs::read_to_string(
+            std::path::Path::new(&default_quadlet_dir()).join("deep-addon-pg.container"),
+        )?;
+        assert!(quadlet.contains("Image=postgres:16"));
+
+        upgrade::upgrade_addon(&storage, &test_podman(), "pg", &options("postgres:17"))?;
+        config = load_addon_config_by_name(&config_dir, "pg")?;
+        assert_eq!(config.image, "postgres:17");
+        let row = storage.get_addon_by_name("pg")?.expect("addon row");
+        assert!(row.config_json.contains("postgres:17"));
+
+        let revisions = storage.list_addon_revisions(&addon.id)?;
+        let mut statuses: Vec<(&str, &str)> = revisions
+            .iter()
+            .map(|rev| (rev.image.as_str(), rev.status.as_str()))
+            .collect();
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            [("postgres:16", "restored"), ("postgres:16", "superseded")]
+        );
+        let events = storage.list_events("addon_upgrade")?;
+        let statuses: Vec<Value> = events
+            .iter()
+            .map(|event| {
+                serde_json::from_str::<Value>(&event.payload_json).map(|v| v["status"].clone())
+            })
+            .collect::<Result<_, _>>()?;
+        assert_eq!(statuses, ["rolled_back", "upgraded"]);
+
+        runner.add_rule(
+            &["podman exec deep-addon-pg", "pg_isready"],
+            1,
+            "",
+            "no response",
+        );
+        let err = upgrade::upgrade_addon(&storage, &test_podman(), "pg", &options("postgres:18"))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("restoring the previous revision failed"));
+        let revisions = storage.list_addon_revisions(&addon.id)?;
+        assert!(
+            revisions
+                .iter()
+                .any(|rev| rev.image == "postgres:17" && rev.status == "restore_failed")
+        );
+        Ok(())
     }
 
     #[test]
@@ -653,7 +1620,7 @@ mod tests {
         runner.add_rule(
             &["podman exec", "deep-addon-pg", "init-db"],
             0,
-            "DB=app\n",
+            "DB=app\nresource.database=app\n",
             "",
         );
         runner.add_rule(
@@ -687,16 +1654,23 @@ mod tests {
             volumes: Vec::new(),
             ports: Vec::new(),
             network: None,
+            command: None,
             provision: vec!["init-db".to_string()],
+            deprovision: Vec::new(),
             export_env: vec!["HOST".to_string()],
             bind_env,
             health_cmd: None,
             health_interval_ms: None,
             health_timeout_ms: None,
             health_retries: None,
+            resources: ResourcesConfig::default(),
+            backup: backup::BackupConfig::default(),
         };
 
-        let envs = provision_addon_on_bind(&addon, &cfg, &app)?;
+        let binding = provision_addon_on_bind(&test_podman(), &addon, &cfg, &app)?;
+        assert_eq!(binding.resources.get("database"), Some(&"app".to_string()));
+        assert_eq!(binding.exported_keys, ["DB", "HOST", "STATIC"]);
+        let envs = binding.env;
         assert_eq!(envs.get("STATIC"), Some(&"1".to_string()));
         assert_eq!(envs.get("DB"), Some(&"app".to_string()));
         assert_eq!(envs.get("HOST"), Some(&"127.0.0.1".to_string()));
@@ -704,24 +1678,6 @@ mod tests {
         Ok(())
     }
 
-    #[derive(Default)]
-    struct RecordingRunner {
-        commands: Mutex<Vec<String>>,
-    }
-
-    impl Runner for RecordingRunner {
-        fn output(&self, program: &str, args: &[&str]) -> anyhow::Result<Output> {
-            let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-            let cmdline = format!("{} {}", program, args_joined);
-            self.commands.lock().expect("commands lock").push(cmdline);
-            Ok(Output {
-                status: exit_status(0),
-                stdout: Vec::new(),
-                stderr: Vec::new(),
-            })
-        }
-    }
-
     #[test]
     fn addon_quadlet_renders_env_ports_volumes_and_health() -> Result<()> {
         let temp = tempfile::TempDir::new()?;
@@ -729,7 +1685,7 @@ mod tests {
         std::fs::create_dir_all(&home)?;
         let _env_guard = set_home_for_test(&home)?;
 
-        let runner = Arc::new(RecordingRunner::default());
+        let runner = Arc::new(TestRunner::default());
         let _guard = set_runner_for_tests(runner);
 
         let mut env = BTreeMap::new();
@@ -741,16 +1697,32 @@ mod tests {
             volumes: vec!["redis-data:/data".to_string()],
             ports: vec!["127.0.0.1:6379:6379".to_string()],
             network: Some("deep-net".to_string()),
+            command: Some("redis-server --save 60 1".to_string()),
             provision: Vec::new(),
+            deprovision: Vec::new(),
             export_env: Vec::new(),
             bind_env: BTreeMap::new(),
             health_cmd: Some("redis-cli ping".to_string()),
             health_interval_ms: Some(1200),
             health_timeout_ms: Some(800),
             health_retries: Some(4),
+            resources: ResourcesConfig {
+                memory: Some("256m".to_string()),
+                cpus: Some(0.5),
+                pids_limit: Some(128),
+                ..ResourcesConfig::default()
+            },
+            backup: backup::BackupConfig::default(),
         };
 
-        maybe_start_addon_quadlet("cache", &config)?;
+        let storage = Storage::open(&temp.path().join("deep.db"))?;
+        maybe_start_addon_quadlet(
+            &storage,
+            &test_podman(),
+            &temp.path().join("deep.toml"),
+            "cache",
+            &config,
+        )?;
 
         let quadlet_dir = default_quadlet_dir();
         let quadlet_path = std::path::Path::new(&quadlet_dir).join("deep-addon-cache.container");
@@ -765,6 +1737,10 @@ mod tests {
         assert!(contents.contains("HealthInterval=1200ms"));
         assert!(contents.contains("HealthTimeout=800ms"));
         assert!(contents.contains("HealthRetries=4"));
+        assert!(contents.contains("Memory=256m"));
+        assert!(contents.contains("PodmanArgs=--cpus=0.5"));
+        assert!(contents.contains("PidsLimit=128"));
+        assert!(contents.contains("Exec=redis-server --save 60 1"));
 
         Ok(())
     }
@@ -776,7 +1752,7 @@ mod tests {
         let quadlet_dir = temp.path().join("quadlets");
         std::fs::create_dir_all(&quadlet_dir)?;
 
-        let runner = Arc::new(RecordingRunner::default());
+        let runner = Arc::new(TestRunner::default());
         let _guard = set_runner_for_tests(runner);
 
         let mut storage = Storage::open(&db_path)?;
@@ -792,37 +1768,24 @@ mod tests {
         storage.bind_addon(&app.id, &addon.id, &binding_config.to_string())?;
 
         let snapshot = crate::config::ConfigSnapshot {
-            env: Default::default(),
             port: 15432,
             domains: vec!["app.example.com".to_string()],
-            addons: Vec::new(),
-            healthcheck: crate::config::HealthcheckConfig::default(),
             deploy: crate::config::DeployConfig {
                 image: Some("ghcr.io/me/app:latest".to_string()),
-                image_prefix: None,
-                tag_strategy: None,
-                git_ref: None,
                 quadlet_dir: Some(quadlet_dir.to_string_lossy().to_string()),
-                image_template: None,
                 retain: 5,
+                ..Default::default()
             },
+            ..base_snapshot()
         };
-        let release = ReleaseRow {
-            id: "r1".to_string(),
-            app_id: app.id.clone(),
-            created_at: "2024-01-01T00:00:00Z".to_string(),
-            git_sha: "deadbeef".to_string(),
-            image_ref: "ghcr.io/me/app:latest".to_string(),
-            image_digest: "ghcr.io/me/app@sha256:deadbeef".to_string(),
-            config_json: serde_json::to_string(&snapshot)?,
-            status: "active".to_string(),
-        };
-        let tx = storage.transaction()?;
-        Storage::insert_release(&tx, &release)?;
-        Storage::set_current_release(&tx, &app.id, &release.id)?;
-        tx.commit()?;
+        insert_current_release(&mut storage, &release_row(&app.id, "r1", &snapshot))?;
 
-        restart_app_with_bindings(&mut storage, &app)?;
+        restart_app_with_bindings(
+            &mut storage,
+            &test_podman(),
+            &temp.path().join("deep.toml"),
+            &app,
+        )?;
 
         let quadlet_path = quadlet_dir.join("deep-app-app-r1.container");
         let contents = std::fs::read_to_string(&quadlet_path)?;
@@ -831,7 +1794,95 @@ mod tests {
         Ok(())
     }
 
-    struct EnvGuard {
+    #[test]
+    fn private_app_networks_follow_bindings() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let home = temp.path().join("home");
+        std::fs::create_dir_all(&home)?;
+        let _env_guard = set_home_for_test(&home)?;
+        let runner = Arc::new(TestRunner::default());
+        let _guard = set_runner_for_tests(runner);
+
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        let shared = storage.create_app("shared", "/tmp")?;
+        let config = addon_config_from_json(r#"{"image":"redis:7"}"#, "redis")?;
+        let addon = storage.create_addon("cache", "redis", &addon_config_to_json(&config)?)?;
+        storage.bind_addon(&web.id, &addon.id, "{}")?;
+        storage.bind_addon(&shared.id, &addon.id, "{}")?;
+        let quadlet_dir = default_quadlet_dir();
+        for (app, private) in [(&web, true), (&shared, false)] {
+            let snapshot = crate::config::ConfigSnapshot {
+                deploy: crate::config::DeployConfig {
+                    quadlet_dir: Some(quadlet_dir.clone()),
+                    private_network: private,
+                    ..Default::default()
+                },
+                ..base_snapshot()
+            };
+            let release = release_row(&app.id, &format!("{}-r1", app.name), &snapshot);
+            insert_current_release(&mut storage, &release)?;
+        }
+
+        maybe_start_addon_quadlet(
+            &storage,
+            &test_podman(),
+            &temp.path().join("deep.toml"),
+            "cache",
+            &config,
+        )?;
+        let addon_quadlet = std::path::Path::new(&quadlet_dir).join("deep-addon-cache.container");
+        let contents = std::fs::read_to_string(&addon_quadlet)?;
+        assert!(contents.contains("Network=deep-net\nNetwork=deep-app-web-net\n"));
+        assert!(!contents.contains("deep-app-shared-net"));
+
+        // Only private apps left: the addon leaves deep-net.
+        storage.unbind_addon(&shared.id, &addon.id)?;
+        assert_eq!(
+            addon_networks(&storage, "cache", &config)?,
+            ["deep-app-web-net"]
+        );
+        sync_shared_network(&storage, &test_podman(), "cache")?;
+        let contents = std::fs::read_to_string(&addon_quadlet)?;
+        assert!(!contents.contains("Network=deep-net"));
+        storage.bind_addon(&shared.id, &addon.id, "{}")?;
+        sync_shared_network(&storage, &test_podman(), "cache")?;
+
+        restart_app_with_bindings(
+            &mut storage,
+            &test_podman(),
+            &temp.path().join("deep.toml"),
+            &web,
+        )?;
+        let app_quadlet = std::path::Path::new(&quadlet_dir).join("deep-app-web-web-r1.container");
+        let contents = std::fs::read_to_string(&app_quadlet)?;
+        assert!(contents.contains("Network=deep-app-web-net"));
+        assert!(!contents.contains("Network=deep-net"));
+
+        detach_from_network(&test_podman(), "deep-app-web-net", "deep-addon-cache")?;
+        let contents = std::fs::read_to_string(&addon_quadlet)?;
+        assert!(!contents.contains("deep-app-web-net"));
+        assert!(contents.contains("Network=deep-net"));
+
+        let err = handle(
+            &mut storage,
+            &test_podman(),
+            &temp.path().join("deep.toml"),
+            AddonsCommand::Destroy {
+                name: "cache".to_string(),
+                config_dir: temp.path().join("addons"),
+                purge_volumes: false,
+                yes: false,
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is bound to"));
+        assert!(addon_quadlet.exists());
+        assert!(storage.get_addon_by_name("cache")?.is_some());
+        Ok(())
+    }
+
+    pub(super) struct EnvGuard {
         previous: Option<String>,
         _lock: std::sync::MutexGuard<'static, ()>,
     }
@@ -849,7 +1900,7 @@ mod tests {
         }
     }
 
-    fn set_home_for_test(path: &std::path::Path) -> Result<EnvGuard> {
+    pub(super) fn set_home_for_test(path: &std::path::Path) -> Result<EnvGuard> {
         static ENV_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
         let lock = ENV_LOCK.get_or_init(|| Mutex::new(()));
         let guard = lock.lock().expect("env lock");
@@ -0,0 +1,764 @@
+//! Addon backups and restores with a strategy per addon kind.
+//!
+//! Each backup is a directory `<root>/<addon>/<timestamp>` holding the strategy's
+//! files plus a `backup.json` manifest used by restore and retention.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::AddonConfigFile;
+use crate::cli::volumes::backup_stamp;
+use crate::runner;
+use crate::runtime::Runtime;
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir, systemd_unit_dir};
+
+pub(super) const DEFAULT_BACKUP_DIR: &str = "/srv/deep/backups";
+const MANIFEST: &str = "backup.json";
+const DEFAULT_KEEP: usize = 7;
+const CONTAINER_TMP: &str = "/tmp/deep-backup";
+const REDIS_CLI: &str =
+    r#"[ -n "$REDIS_PASSWORD" ] && export REDISCLI_AUTH="$REDIS_PASSWORD"; redis-cli"#;
+const PG_USER: &str = r#""${POSTGRES_USER:-postgres}""#;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// `[backup]` section of an addon config file.
+pub(super) struct BackupConfig {
+    /// Strategy override: `postgres`, `redis` or `volumes` (default: by kind).
+    pub strategy: Option<String>,
+    /// Number of backups to keep (default 7; 0 keeps all).
+    pub keep: Option<usize>,
+}
+
+impl BackupConfig {
+    pub(super) fn is_default(&self) -> bool {
+        self == &BackupConfig::default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Manifest written next to the backup files.
+struct BackupManifest {
+    addon: String,
+    kind: String,
+    strategy: String,
+    image: String,
+    created_at: String,
+    files: Vec<String>,
+}
+
+/// The addon a strategy operates on.
+pub(super) struct BackupTarget<'a> {
+    pub runtime: &'a Runtime,
+    pub name: &'a str,
+    pub container: String,
+    pub config: &'a AddonConfigFile,
+}
+
+impl BackupTarget<'_> {
+    fn unit(&self) -> String {
+        format!("{}.service", self.container)
+    }
+
+    /// Run a shell script in the addon container and return its stdout.
+    fn exec(&self, script: &str) -> Result<String> {
+        let output = runner::run_output(
+            self.runtime.program(),
+            &["exec", &self.container, "sh", "-c", script],
+        )
+        .with_context(|| format!("failed to exec in {}", self.container))?;
+        if !output.status.success() {
+            bail!(
+                "command in {} failed: {}",
+                self.container,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn copy_from(&self, source: &str, dest: &Path) -> Result<()> {
+        let from = format!("{}:{}", self.container, source);
+        self.cp(&from, &dest.to_string_lossy())
+    }
+
+    fn copy_to(&self, source: &Path, dest: &str) -> Result<()> {
+        let to = format!("{}:{}", self.container, dest);
+        self.cp(&source.to_string_lossy(), &to)
+    }
+
+    fn cp(&self, from: &str, to: &str) -> Result<()> {
+        let program = self.runtime.program();
+        let output = runner::run_output(program, &["cp", from, to])?;
+        if !output.status.success() {
+            bail!(
+                "{} cp {} {} failed: {}",
+                program,
+                from,
+                to,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A way to dump an addon's data to files and load it back.
+trait BackupStrategy {
+    fn name(&self) -> &'static str;
+    /// Write backup files into `dir` and return their file names.
+    fn backup(&self, target: &BackupTarget, dir: &Path) -> Result<Vec<String>>;
+    /// Load the files listed in the manifest back into the addon.
+    fn restore(&self, target: &BackupTarget, dir: &Path, files: &[String]) -> Result<()>;
+}
+
+fn strategy_for(kind: &str, config: &BackupConfig) -> Result<Box<dyn BackupStrategy>> {
+    let name = config.strategy.as_deref().unwrap_or(match kind {
+        "postgres" | "postgresql" => "postgres",
+        "redis" => "redis",
+        _ => "volumes",
+    });
+    match name {
+        "postgres" => Ok(Box::new(PostgresStrategy)),
+        "redis" => Ok(Box::new(RedisStrategy)),
+        "volumes" => Ok(Box::new(VolumesStrategy)),
+        other => bail!(
+            "unknown backup strategy {}; use postgres, redis or volumes",
+            other
+        ),
+    }
+}
+
+/// Run a backup now and prune old ones; returns the new backup directory.
+pub(super) fn run_backup(
+    runtime: &Runtime,
+    name: &str,
+    config: &AddonConfigFile,
+    root: &Path,
+    keep: Option<usize>,
+) -> Result<PathBuf> {
+    let kind = config.kind.clone().unwrap_or_else(|| "generic".to_string());
+    let strategy = strategy_for(&kind, &config.backup)?;
+    let target = BackupTarget {
+        runtime,
+        name,
+        container: format!("deep-addon-{}", name),
+        config,
+    };
+    let addon_dir = root.join(name);
+    let dir = addon_dir.join(backup_stamp());
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let files = match strategy.backup(&target, &dir) {
+        Ok(files) => files,
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(err.context(format!("{} backup of {} failed", strategy.name(), name)));
+        }
+    };
+    let manifest = BackupManifest {
+        addon: name.to_string(),
+        kind,
+        strategy: strategy.name().to_string(),
+        image: config.image.clone(),
+        created_at: crate::cli::now_rfc3339(),
+        files,
+    };
+    std::fs::write(dir.join(MANIFEST), serde_json::to_string_pretty(&manifest)?)?;
+    let keep = keep.or(config.backup.keep).unwrap_or(DEFAULT_KEEP);
+    for removed in prune_backups(&addon_dir, keep)? {
+        println!("pruned backup {}", removed.display());
+    }
+    Ok(dir)
+}
+
+/// Restore a backup given as a directory, a file inside it, or a timestamp under `root`.
+pub(super) fn run_restore(
+    runtime: &Runtime,
+    name: &str,
+    config: &AddonConfigFile,
+    root: &Path,
+    backup: &str,
+) -> Result<PathBuf> {
+    let dir = resolve_backup_dir(root, name, backup)?;
+    let raw = std::fs::read_to_string(dir.join(MANIFEST))
+        .with_context(|| format!("{} is not a deep backup (no {})", dir.display(), MANIFEST))?;
+    let manifest: BackupManifest = serde_json::from_str(&raw).context("invalid backup manifest")?;
+    for file in &manifest.files {
+        backup_file(&dir, file).context("invalid backup manifest")?;
+    }
+    if manifest.addon != name {
+        eprintln!(
+            "warning: backup was taken from addon {}; restoring into {}",
+            manifest.addon, name
+        );
+    }
+    let strategy = strategy_for(
+        &manifest.kind,
+        &BackupConfig {
+            strategy: Some(manifest.strategy.clone()),
+            keep: None,
+        },
+    )?;
+    let target = BackupTarget {
+        runtime,
+        name,
+        container: format!("deep-addon-{}", name),
+        config,
+    };
+    strategy
+        .restore(&target, &dir, &manifest.files)
+        .with_context(|| format!("{} restore of {} failed", strategy.name(), name))?;
+    Ok(dir)
+}
+
+fn resolve_backup_dir(root: &Path, name: &str, backup: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(backup);
+    if path.is_dir() {
+        return Ok(path);
+    }
+    if path.is_file() {
+        return Ok(path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(".")));
+    }
+    let under_root = root.join(name).join(backup);
+    if under_root.is_dir() {
+        return Ok(under_root);
+    }
+    bail!(
+        "backup {} not found (looked in {})",
+        backup,
+        root.join(name).display()
+    )
+}
+
+/// Delete the oldest backups so that at most `keep` remain (0 keeps all).
+pub(super) fn prune_backups(addon_dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    if keep == 0 || !addon_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(addon_dir)
+        .with_context(|| format!("failed to read {}", addon_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(MANIFEST).is_file())
+        .collect();
+    // Timestamps sort lexically, oldest first.
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for path in &removed {
+        std::fs::remove_dir_all(path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(removed)
+}
+
+/// Install a systemd timer that runs `deep addons backup <name>` on a calendar.
+pub(super) fn install_schedule(
+    name: &str,
+    schedule: &str,
+    db_path: &str,
+    host_config: &Path,
+    config_dir: &Path,
+    root: &Path,
+) -> Result<PathBuf> {
+    validate_schedule(schedule)?;
+    let quadlet_dir = default_quadlet_dir();
+    let unit_dir = PathBuf::from(systemd_unit_dir(&quadlet_dir));
+    std::fs::create_dir_all(&unit_dir)
+        .with_context(|| format!("failed to create {}", unit_dir.display()))?;
+    let deep = std::env::current_exe().context("failed to locate the deep binary")?;
+    let command = [
+        deep.to_string_lossy().as_ref(),
+        "addons",
+        "--db",
+        db_path,
+        "--host-config",
+        host_config.to_string_lossy().as_ref(),
+        "backup",
+        name,
+        "--config-dir",
+        config_dir.to_string_lossy().as_ref(),
+        "--output-dir",
+        root.to_string_lossy().as_ref(),
+    ]
+    .map(systemd_quote)
+    .join(" ");
+    let unit = format!("deep-backup-{}", name);
+    let service = include_str!("../../../templates/addon-backup.service")
+        .replace("{{name}}", name)
+        .replace("{{command}}", &command);
+    let timer = include_str!("../../../templates/addon-backup.timer")
+        .replace("{{name}}", name)
+        .replace("{{schedule}}", schedule);
+    std::fs::write(unit_dir.join(format!("{}.service", unit)), service)?;
+    let timer_path = unit_dir.join(format!("{}.timer", unit));
+    std::fs::write(&timer_path, timer)?;
+    systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
+    systemctl_for_dir(
+        &quadlet_dir,
+        &["enable", "--now", &format!("{}.timer", unit)],
+    )?;
+    Ok(timer_path)
+}
+
+/// Check an `OnCalendar=` expression with `systemd-analyze calendar`.
+fn validate_schedule(schedule: &str) -> Result<()> {
+    if schedule.trim().is_empty() || schedule.chars().any(char::is_control) {
+        bail!("invalid backup schedule {:?}", schedule);
+    }
+    let output = runner::run_output("systemd-analyze", &["calendar", schedule])
+        .context("failed to run systemd-analyze calendar")?;
+    if !output.status.success() {
+        bail!(
+            "invalid backup schedule {:?}: {}",
+            schedule,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Whether a backup timer is installed for an addon.
+pub(super) fn has_schedule(name: &str) -> bool {
+    let unit_dir = PathBuf::from(systemd_unit_dir(&default_quadlet_dir()));
+    unit_dir
+        .join(format!("deep-backup-{}.timer", name))
+        .exists()
+}
+
+/// Disable and remove the backup timer for an addon.
+pub(super) fn remove_schedule(name: &str) -> Result<()> {
+    let quadlet_dir = default_quadlet_dir();
+    let unit_dir = PathBuf::from(systemd_unit_dir(&quadlet_dir));
+    let unit = format!("deep-backup-{}", name);
+    let _ = systemctl_for_dir(
+        &quadlet_dir,
+        &["disable", "--now", &format!("{}.timer", unit)],
+    );
+    for suffix in ["timer", "service"] {
+        let path = unit_dir.join(format!("{}.{}", unit, suffix));
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+    }
+    systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
+    Ok(())
+}
+
+/// `pg_dump -Fc` per database plus `pg_dumpall --globals-only` for roles.
+struct PostgresStrategy;
+
+impl BackupStrategy for PostgresStrategy {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn backup(&self, target: &BackupTarget, dir: &Path) -> Result<Vec<String>> {
+        let databases = target.exec(&format!(
+                "psql -U {} -d postgres -Atc \"SELECT datname FROM pg_database WHERE datallowconn AND NOT datistemplate ORDER BY datname\"",
+                PG_USER
+            ),
+        )?;
+        let mut files = Vec::new();
+        target.exec(&format!(
+            "pg_dumpall -U {} --globals-only -f {}",
+            PG_USER, CONTAINER_TMP
+        ))?;
+        target.copy_from(CONTAINER_TMP, &dir.join("globals.sql"))?;
+        files.push("globals.sql".to_string());
+        for database in databases.lines().map(str::trim).filter(|db| !db.is_empty()) {
+            let file = format!("{}.dump", database);
+            let path = backup_file(dir, &file)?;
+            target.exec(&format!(
+                "pg_dump -U {} -Fc -f {} {}",
+                PG_USER,
+                CONTAINER_TMP,
+                shell_quote(database)
+            ))?;
+            target.copy_from(CONTAINER_TMP, &path)?;
+            files.push(file);
+        }
+        target.exec(&format!("rm -f {}", CONTAINER_TMP))?;
+        Ok(files)
+    }
+
+    fn restore(&self, target: &BackupTarget, dir: &Path, files: &[String]) -> Result<()> {
+        if files.iter().any(|file| file == "globals.sql") {
+            target.copy_to(&dir.join("globals.sql"), CONTAINER_TMP)?;
+            // Existing roles make psql report errors; it carries on by default.
+            target.exec(&format!(
+                "psql -U {} -d postgres -q -f {} >/dev/null 2>&1 || true",
+                PG_USER, CONTAINER_TMP
+            ))?;
+        }
+        for file in files {
+            let Some(database) = file.strip_suffix(".dump") else {
+                continue;
+            };
+            target.copy_to(&dir.join(file), CONTAINER_TMP)?;
+            target.exec(&format!(
+                    "createdb -U {user} {db} 2>/dev/null; pg_restore -U {user} --clean --if-exists -d {db} {tmp}",
+                    user = PG_USER,
+                    db = shell_quote(database),
+                    tmp = CONTAINER_TMP
+                ),
+            )?;
+            println!("restored database {}", database);
+        }
+        target.exec(&format!("rm -f {}", CONTAINER_TMP))?;
+        Ok(())
+    }
+}
+
+/// `BGSAVE`, wait for it to finish, then copy the RDB file out.
+struct RedisStrategy;
+
+impl RedisStrategy {
+    fn config_value(target: &BackupTarget, key: &str) -> Result<String> {
+        let output = target.exec(&format!("{} CONFIG GET {}", REDIS_CLI, key))?;
+        output
+            .lines()
+            .nth(1)
+            .map(|value| value.trim().to_string())
+            .with_context(|| format!("redis did not report {}", key))
+    }
+
+    fn rdb_path(target: &BackupTarget) -> Result<String> {
+        let dir = Self::config_value(target, "dir")?;
+        let file = Self::config_value(target, "dbfilename")?;
+        Ok(format!("{}/{}", dir.trim_end_matches('/'), file))
+    }
+}
+
+impl BackupStrategy for RedisStrategy {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn backup(&self, target: &BackupTarget, dir: &Path) -> Result<Vec<String>> {
+        let lastsave = format!("{} LASTSAVE", REDIS_CLI);
+        let before = target.exec(&lastsave)?;
+        target.exec(&format!("{} BGSAVE", REDIS_CLI))?;
+        let mut finished = false;
+        for _ in 0..120 {
+            if target.exec(&lastsave)?.trim() != before.trim() {
+                finished = true;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        if !finished {
+            bail!("BGSAVE did not finish within 60s");
+        }
+        target.copy_from(&Self::rdb_path(target)?, &dir.join("dump.rdb"))?;
+        Ok(vec!["dump.rdb".to_string()])
+    }
+
+    fn restore(&self, target: &BackupTarget, dir: &Path, _files: &[String]) -> Result<()> {
+        if Self::config_value(target, "appendonly")? == "yes" {
+            bail!(
+                "appendonly is enabled; redis would ignore the RDB file, restore the AOF instead"
+            );
+        }
+        target.copy_to(&dir.join("dump.rdb"), &Self::rdb_path(target)?)?;
+        // Shut down without saving so the copied RDB is what redis loads on start.
+        let _ = target.exec(&format!("{} SHUTDOWN NOSAVE", REDIS_CLI));
+        systemctl_for_dir(&default_quadlet_dir(), &["restart", &target.unit()])?;
+        Ok(())
+    }
+}
+
+/// Stop the addon and archive each of its volumes (named volumes or host paths).
+struct VolumesStrategy;
+
+impl VolumesStrategy {
+    /// Volume sources with the archive name used for each.
+    fn sources(config: &AddonConfigFile) -> Vec<(String, String)> {
+        config
+            .volumes
+            .iter()
+            .filter_map(|spec| spec.split(':').next())
+            .filter(|source| !source.is_empty())
+            .map(|source| {
+                let file = format!("{}.tar", source.trim_start_matches('/').replace('/', "_"));
+                (source.to_string(), file)
+            })
+            .collect()
+    }
+
+    /// Run `action` with the addon stopped, starting it again afterwards.
+    fn with_addon_stopped<T>(
+        target: &BackupTarget,
+        action: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let quadlet_dir = default_quadlet_dir();
+        systemctl_for_dir(&quadlet_dir, &["stop", &target.unit()])?;
+        let result = action();
+        let started = systemctl_for_dir(&quadlet_dir, &["start", &target.unit()]);
+        let value = result?;
+        started?;
+        Ok(value)
+    }
+}
+
+impl BackupStrategy for VolumesStrategy {
+    fn name(&self) -> &'static str {
+        "volumes"
+    }
+
+    fn backup(&self, target: &BackupTarget, dir: &Path) -> Result<Vec<String>> {
+        let sources = Self::sources(target.config);
+        if sources.is_empty() {
+            bail!("addon {} has no volumes to back up", target.name);
+        }
+        let runtime = target.runtime;
+        Self::with_addon_stopped(target, || {
+            let mut files = Vec::new();
+            for (source, file) in &sources {
+                let path = dir.join(file);
+                if source.starts_with('/') {
+                    tar(&["-C", source, "-cf", &path.to_string_lossy(), "."])?;
+                } else {
+                    runtime.export_volume(source, &path)?;
+                }
+                files.push(file.clone());
+            }
+            Ok(files)
+        })
+    }
+
+    fn restore(&self, target: &BackupTarget, dir: &Path, files: &[String]) -> Result<()> {
+        let runtime = target.runtime;
+        let sources: Vec<(String, String)> = Self::sources(target.config)
+            .into_iter()
+            .filter(|(_, file)| files.contains(file))
+            .collect();
+        if sources.is_empty() {
+            bail!(
+                "none of the backup files match the volumes of addon {}",
+                target.name
+            );
+        }
+        Self::with_addon_stopped(target, || {
+            for (source, file) in &sources {
+                let path = dir.join(file);
+                if source.starts_with('/') {
+                    tar(&["-C", source, "-xf", &path.to_string_lossy()])?;
+                } else {
+                    runtime.import_volume(source, &path)?;
+                }
+                println!("restored volume {}", source);
+            }
+            Ok(())
+        })
+    }
+}
+
+fn tar(args: &[&str]) -> Result<()> {
+    let output = runner::run_output("tar", args)?;
+    if !output.status.success() {
+        bail!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Join a file name onto a backup directory, rejecting names that would leave it.
+fn backup_file(dir: &Path, file: &str) -> Result<PathBuf> {
+    if file.is_empty() || file == "." || file == ".." || file.contains(['/', '\\']) {
+        bail!(
+            "backup file name {:?} must not be empty or contain path separators",
+            file
+        );
+    }
+    Ok(dir.join(file))
+}
+
+/// Quote one `ExecStart=` argument the way systemd unquotes it.
+fn systemd_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ResourcesConfig;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{TestRunner, test_podman};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    /// Scripted dump tool answers, with `podman cp` creating its destination.
+    fn backup_runner() -> Arc<TestRunner> {
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(&["pg_database"], 0, "app\npostgres\n", "");
+        // The second LASTSAVE reports a newer save.
+        runner.add_rule_once(&["LASTSAVE"], 0, "1700000001\n", "");
+        runner.add_rule(&["LASTSAVE"], 0, "1700000002\n", "");
+        runner.add_rule(&["CONFIG GET dir"], 0, "dir\n/data\n", "");
+        runner.add_rule(&["CONFIG GET dbfilename"], 0, "dbfilename\ndump.rdb\n", "");
+        runner.add_rule(
+            &["systemd-analyze", "someday"],
+            1,
+            "",
+            "Failed to parse calendar specification 'someday'",
+        );
+        runner.on_command(|program, args| {
+            if program == "podman"
+                && args.first() == Some(&"cp")
+                && args[1].starts_with("deep-addon-")
+            {
+                std::fs::write(args[2], b"data")?;
+            }
+            Ok(())
+        });
+        runner
+    }
+
+    fn addon_config(kind: &str, volumes: Vec<String>) -> AddonConfigFile {
+        AddonConfigFile {
+            kind: Some(kind.to_string()),
+            image: format!("{}:latest", kind),
+            env: BTreeMap::new(),
+            volumes,
+            ports: Vec::new(),
+            network: None,
+            command: None,
+            provision: Vec::new(),
+            deprovision: Vec::new(),
+            export_env: Vec::new(),
+            bind_env: BTreeMap::new(),
+            health_cmd: None,
+            health_interval_ms: None,
+            health_timeout_ms: None,
+            health_retries: None,
+            resources: ResourcesConfig::default(),
+            backup: BackupConfig::default(),
+        }
+    }
+
+    #[test]
+    fn postgres_backup_dumps_each_database_and_restores_it() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = backup_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let config = addon_config("postgres", Vec::new());
+
+        let dir = run_backup(&test_podman(), "pg", &config, temp.path(), None)?;
+        assert!(dir.starts_with(temp.path().join("pg")));
+        let manifest: BackupManifest =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(MANIFEST))?)?;
+        assert_eq!(manifest.strategy, "postgres");
+        assert_eq!(manifest.files, ["globals.sql", "app.dump", "postgres.dump"]);
+        assert!(dir.join("app.dump").is_file());
+
+        let stamp = dir.file_name().unwrap().to_string_lossy().to_string();
+        run_restore(&test_podman(), "pg", &config, temp.path(), &stamp)?;
+        let calls = runner.calls();
+        assert!(calls.iter().any(|call| call.contains("pg_dump -U")
+            && call.contains("-Fc")
+            && call.contains("'app'")));
+        assert!(calls.iter().any(|call| call.contains("pg_restore")
+            && call.contains("--clean")
+            && call.contains("-d 'app'")));
+
+        let mut tampered = manifest.clone();
+        tampered.files.push("../escape.dump".to_string());
+        std::fs::write(dir.join(MANIFEST), serde_json::to_string(&tampered)?)?;
+        let err = run_restore(&test_podman(), "pg", &config, temp.path(), &stamp).unwrap_err();
+        assert!(format!("{:#}", err).contains("path separators"));
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_command_quotes_arguments_for_systemd() {
+        assert_eq!(systemd_quote("/srv/deep data"), r#""/srv/deep data""#);
+        assert_eq!(systemd_quote(r#"a"b\c%d$e"#), r#""a\"b\\c%%d$$e""#);
+    }
+
+    #[test]
+    fn schedule_is_checked_with_systemd_analyze() {
+        let runner = backup_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        validate_schedule("daily").expect("valid schedule");
+        let err = validate_schedule("someday").unwrap_err();
+        assert!(err.to_string().contains("Failed to parse"), "{err}");
+        assert!(validate_schedule("daily\nExecStart=/bin/sh").is_err());
+        let calls = runner.calls();
+        assert_eq!(
+            calls,
+            [
+                "systemd-analyze calendar daily",
+                "systemd-analyze calendar someday"
+            ]
+        );
+    }
+
+    #[test]
+    fn redis_backup_waits_for_bgsave_and_copies_rdb() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = backup_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let config = addon_config("redis", Vec::new());
+
+        let dir = run_backup(&test_podman(), "cache", &config, temp.path(), None)?;
+        assert!(dir.join("dump.rdb").is_file());
+        let calls = runner.calls();
+        let bgsave = calls.iter().position(|call| call.contains("BGSAVE"));
+        let copy = calls
+            .iter()
+            .position(|call| call.starts_with("podman cp deep-addon-cache:/data/dump.rdb"));
+        assert!(bgsave.is_some() && copy.is_some() && bgsave < copy);
+        Ok(())
+    }
+
+    #[test]
+    fn volumes_backup_stops_addon_and_retention_prunes_oldest() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = backup_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let config = addon_config("minio", vec!["minio-data:/data".to_string()]);
+        let addon_dir = temp.path().join("files");
+        for stamp in ["20240101T000000Z", "20240102T000000Z", "20240103T000000Z"] {
+            std::fs::create_dir_all(addon_dir.join(stamp))?;
+            std::fs::write(addon_dir.join(stamp).join(MANIFEST), "{}")?;
+        }
+
+        let dir = run_backup(&test_podman(), "files", &config, temp.path(), Some(2))?;
+        let calls = runner.calls();
+        let stop = calls
+            .iter()
+            .position(|call| call.contains("stop deep-addon-files.service"));
+        let export = calls.iter().position(|call| {
+            call.contains("volume export")
+                && call.contains("minio-data.tar")
+                && call.ends_with(" minio-data")
+        });
+        let start = calls
+            .iter()
+            .position(|call| call.contains("start deep-addon-files.service"));
+        assert!(stop < export && export < start);
+
+        let mut remaining: Vec<PathBuf> = std::fs::read_dir(&addon_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        remaining.sort();
+        assert_eq!(remaining, [addon_dir.join("20240103T000000Z"), dir]);
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+//! What each binding provisioned, and per-app credential rotation.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::{
+    addon_config_from_json, addon_config_path, load_addon_config_by_name, provision_addon_on_bind,
+    restart_app_with_bindings,
+};
+use crate::cli::{now_rfc3339, print_table, require_app};
+use crate::db::{BindingRow, Storage};
+use crate::runtime::Runtime;
+
+/// Prefix for provision output lines that describe a resource instead of an env var.
+const RESOURCE_PREFIX: &str = "resource.";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// Schema of `bindings.config_json`.
+pub(super) struct BindingConfig {
+    /// Env injected into the app (the only key older bindings have).
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Resources created for the app, e.g. `database`, `role`, `bucket`.
+    #[serde(default)]
+    pub resources: BTreeMap<String, String>,
+    /// Names of the env keys above, kept so reports never need the values.
+    #[serde(default)]
+    pub exported_keys: Vec<String>,
+    pub provisioned_at: Option<String>,
+    pub rotated_at: Option<String>,
+}
+
+impl BindingConfig {
+    /// Parse a stored binding; bindings without `exported_keys` export all of `env`.
+    pub(super) fn from_json(raw: &str) -> Result<Self> {
+        let mut config: Self = serde_json::from_str(raw).context("invalid binding config")?;
+        if config.exported_keys.is_empty() {
+            config.exported_keys = config.env.keys().cloned().collect();
+        }
+        Ok(config)
+    }
+
+    /// Record a fresh provisioning run.
+    pub(super) fn provisioned(
+        env: BTreeMap<String, String>,
+        resources: BTreeMap<String, String>,
+    ) -> Self {
+        Self {
+            exported_keys: env.keys().cloned().collect(),
+            env,
+            resources,
+            provisioned_at: Some(now_rfc3339()),
+            rotated_at: None,
+        }
+    }
+
+    pub(super) fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).context("failed to serialize binding config")
+    }
+}
+
+/// Split provision output into env vars and `resource.<name>=<value>` lines.
+pub(super) fn parse_provision_output(
+    stdout: &str,
+    env: &mut BTreeMap<String, String>,
+    resources: &mut BTreeMap<String, String>,
+) {
+    for line in stdout.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            continue;
+        }
+        let value = value.trim().to_string();
+        match key.strip_prefix(RESOURCE_PREFIX) {
+            Some(resource) => resources.insert(resource.to_string(), value),
+            None => env.insert(key.to_string(), value),
+        };
+    }
+}
+
+/// Table rows for `deep addons bindings`: app, resources, exported keys, dates.
+pub(super) fn binding_rows(bindings: &[BindingRow]) -> Result<Vec<Vec<String>>> {
+    bindings
+        .iter()
+        .map(|binding| {
+            let config = BindingConfig::from_json(&binding.config_json)
+                .with_context(|| format!("binding of {}", binding.app_name))?;
+            let resources: Vec<String> = config
+                .resources
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            let keys: Vec<String> = config
+                .exported_keys
+                .iter()
+                .map(|key| format!("{}=<redacted>", key))
+                .collect();
+            let dash = |value: String| {
+                if value.is_empty() {
+                    "-".to_string()
+                } else {
+                    value
+                }
+            };
+            Ok(vec![
+                binding.app_name.clone(),
+                dash(resources.join(" ")),
+                dash(keys.join(" ")),
+                config
+                    .provisioned_at
+                    .unwrap_or_else(|| binding.created_at.clone()),
+                config.rotated_at.unwrap_or_else(|| "-".to_string()),
+            ])
+        })
+        .collect()
+}
+
+/// Print the apps bound to an addon without secret values.
+pub(super) fn handle_bindings(storage: &Storage, addon: &str) -> Result<()> {
+    let addon_row = storage
+        .get_addon_by_name(addon)?
+        .with_context(|| format!("addon {} not found", addon))?;
+    let bindings = storage.list_addon_bindings(&addon_row.id)?;
+    if bindings.is_empty() {
+        println!("no apps bound to {}", addon);
+        return Ok(());
+    }
+    print_table(
+        &["APP", "RESOURCES", "ENV", "PROVISIONED", "ROTATED"],
+        &binding_rows(&bindings)?,
+    );
+    Ok(())
+}
+
+/// Re-run the addon's provision commands for one app and restart only that app.
+///
+/// Catalog provision scripts set a new password on an existing role, so the old
+/// credentials stop working as soon as this returns.
+pub(super) fn rotate_binding(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    addon: &str,
+    app: &str,
+    config_dir: &PathBuf,
+) -> Result<BindingConfig> {
+    let app_row = require_app(storage, app)?;
+    let addon_row = storage
+        .get_addon_by_name(addon)?
+        .context("addon not found")?;
+    let previous = storage
+        .list_addon_bindings(&addon_row.id)?
+        .into_iter()
+        .find(|binding| binding.app_id == app_row.id)
+        .with_context(|| format!("addon {} is not bound to {}", addon, app))?;
+    let addon_config = if addon_config_path(config_dir, addon).exists() {
+        load_addon_config_by_name(config_dir, addon)?
+    } else {
+        addon_config_from_json(&addon_row.config_json, &addon_row.kind)?
+    };
+    if addon_config.provision.is_empty() {
+        bail!("addon {} has no provision commands to rotate", addon);
+    }
+    let previous = BindingConfig::from_json(&previous.config_json)
+        .with_context(|| format!("binding of {} to {}", addon, app))?;
+    let mut binding = provision_addon_on_bind(runtime, &addon_row, &addon_config, &app_row)?;
+    if binding.env == previous.env {
+        eprintln!(
+            "warning: provisioning returned the same env; {} may share credentials across apps",
+            addon
+        );
+    }
+    binding.provisioned_at = previous.provisioned_at.or(binding.provisioned_at);
+    binding.rotated_at = Some(now_rfc3339());
+    storage.bind_addon(&app_row.id, &addon_row.id, &binding.to_json()?)?;
+    restart_app_with_bindings(storage, runtime, host_config, &app_row)?;
+    let payload = serde_json::json!({
+        "addon": addon,
+        "app": app,
+        "keys": binding.exported_keys,
+    });
+    let _ = storage.insert_event("addon_rotate", &payload.to_string());
+    Ok(binding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provision_output_and_binding_rows_redact_values() {
+        let mut env = BTreeMap::new();
+        let mut resources = BTreeMap::new();
+        parse_provision_output(
+            "resource.database=web\nresource.role=web\nDATABASE_URL=postgres://web:s3cret@pg/web\nnoise\n",
+            &mut env,
+            &mut resources,
+        );
+        assert_eq!(resources["database"], "web");
+        assert_eq!(env.len(), 1);
+        let current = BindingConfig::provisioned(env, resources);
+
+        let row = |app: &str, config_json: String| BindingRow {
+            app_id: format!("{}-id", app),
+            app_name: app.to_string(),
+            addon_id: "pg-id".to_string(),
+            config_json,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+        };
+        let rows = binding_rows(&[
+            row("web", current.to_json().expect("json")),
+            // Bindings made before the schema only carry `env`.
+            row("legacy", r#"{"env":{"REDIS_URL":"redis://x"}}"#.to_string()),
+        ])
+        .expect("rows");
+        assert_eq!(rows[0][1], "database=web role=web");
+        assert_eq!(rows[0][2], "DATABASE_URL=<redacted>");
+        assert!(rows.iter().flatten().all(|cell| !cell.contains("s3cret")));
+        assert_eq!(
+            rows[1],
+            [
+                "legacy",
+                "-",
+                "REDIS_URL=<redacted>",
+                "2024-01-01T00:00:00Z",
+                "-"
+            ]
+        );
+        let err = binding_rows(&[row("broken", "{not json".to_string())]).unwrap_err();
+        assert!(format!("{:#}", err).contains("binding of broken"));
+    }
+}
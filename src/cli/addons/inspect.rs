@@ -0,0 +1,230 @@
+//! Read-only views of running addons: status, logs and exec.
+
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+
+use super::{AddonConfigFile, addon_config_from_json, addon_config_path, load_addon_config_file};
+use crate::cli::available_runtime;
+use crate::cli::logs::parse_log_time;
+use crate::cli::ps::{ServiceStatus, unix_now};
+use crate::db::{AddonRow, Storage};
+use crate::runtime::{LogOptions, Runtime};
+use crate::systemd::{default_quadlet_dir, is_system_dir, journal_logs, unit_state_for_dir};
+
+/// Print unit, container, image, volumes and bound apps for one or all addons.
+pub(super) fn handle_status(
+    storage: &Storage,
+    runtime: &Runtime,
+    name: Option<String>,
+    config_dir: &PathBuf,
+) -> Result<()> {
+    let addons = match name {
+        Some(name) => vec![require_addon(storage, &name)?],
+        None => storage.list_addons()?,
+    };
+    if addons.is_empty() {
+        println!("no addons found");
+        return Ok(());
+    }
+    let runtime = available_runtime(runtime, "container state unavailable");
+    for (index, addon) in addons.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        for line in status_lines(storage, runtime, addon, config_dir)? {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn status_lines(
+    storage: &Storage,
+    runtime: Option<&Runtime>,
+    addon: &AddonRow,
+    config_dir: &PathBuf,
+) -> Result<Vec<String>> {
+    let config = addon_config(addon, config_dir)?;
+    let container = format!("deep-addon-{}", addon.name);
+    let unit = format!("{}.service", container);
+    let status = ServiceStatus::probe(
+        runtime,
+        &container,
+        unit_state_for_dir(&default_quadlet_dir(), &unit),
+    );
+    let [state, health, restarts, uptime] = status.columns(unix_now());
+    let apps: Vec<String> = storage
+        .list_addon_bindings(&addon.id)?
+        .into_iter()
+        .map(|binding| binding.app_name)
+        .collect();
+    let list = |items: &[String]| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    Ok(vec![
+        format!("addon: {} ({})", addon.name, addon.kind),
+        format!("image: {}", config.image),
+        format!("unit: {} {}", unit, status.unit_state),
+        format!(
+            "container: {} {}  health={}  restarts={}  uptime={}",
+            container, state, health, restarts, uptime
+        ),
+        format!("volumes: {}", list(&config.volumes)),
+        format!("apps: {}", list(&apps)),
+    ])
+}
+
+/// Stream addon logs, falling back to journald when the container is gone.
+pub(super) fn handle_logs(
+    runtime: &Runtime,
+    name: &str,
+    options: &LogOptions,
+    sink: &mut dyn FnMut(&str),
+    err_sink: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let container = format!("deep-addon-{}", name);
+    if runtime.container_exists(&container) {
+        return runtime.logs(&container, options, sink, err_sink);
+    }
+    let quadlet_dir = default_quadlet_dir();
+    let unit = format!("{}.service", container);
+    eprintln!(
+        "container {} not found; reading journald logs for {}",
+        container, unit
+    );
+    journal_logs(&unit, !is_system_dir(&quadlet_dir), options, sink, err_sink)
+}
+
+/// Build log options from the CLI flags.
+pub(super) fn log_options(
+    follow: bool,
+    timestamps: bool,
+    since: Option<&str>,
+    tail: Option<u32>,
+) -> LogOptions {
+    LogOptions {
+        follow,
+        timestamps,
+        since: since.map(parse_log_time),
+        until: None,
+        tail,
+    }
+}
+
+/// Run a command in the addon container attached to the terminal.
+pub(super) fn handle_exec(
+    storage: &Storage,
+    runtime: &Runtime,
+    name: &str,
+    command: &[String],
+) -> Result<()> {
+    require_addon(storage, name)?;
+    let container = format!("deep-addon-{}", name);
+    if !runtime.container_running(&container) {
+        bail!(
+            "addon container {} is not running; try `deep addons start {}`",
+            container,
+            name
+        );
+    }
+    let status = runtime.exec_interactive(&container, command)?;
+    if !status.success() {
+        bail!("{} exited with {}", command.join(" "), status);
+    }
+    Ok(())
+}
+
+fn require_addon(storage: &Storage, name: &str) -> Result<AddonRow> {
+    storage
+        .get_addon_by_name(name)?
+        .with_context(|| format!("addon {} not found", name))
+}
+
+/// The addon's config file when present, else the config stored with the addon row.
+fn addon_config(addon: &AddonRow, config_dir: &PathBuf) -> Result<AddonConfigFile> {
+    let path = addon_config_path(config_dir, &addon.name);
+    if path.exists() {
+        return load_addon_config_file(&path);
+    }
+    addon_config_from_json(&addon.config_json, &addon.kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{TestRunner, test_podman};
+    use std::sync::Arc;
+
+    #[test]
+    fn status_lists_state_volumes_and_bound_apps() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(&["is-active deep-addon-pg"], 0, "active", "");
+        runner.add_rule(
+            &["inspect --format {{.State.Running}} deep-addon-pg"],
+            0,
+            "true",
+            "",
+        );
+        runner.add_rule(
+            &["inspect deep-addon-pg"],
+            0,
+            r#"[{"State":{"Status":"running","Health":{"Status":"healthy"},"StartedAt":"2000-01-01T00:00:00Z"},"RestartCount":0}]"#,
+            "",
+        );
+        runner.add_rule(&["deep-addon-cache"], 1, "", "");
+        let _guard = set_runner_for_tests(runner.clone());
+        let storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        let worker = storage.create_app("worker", "/tmp")?;
+        let pg = storage.create_addon(
+            "pg",
+            "postgres",
+            r#"{"image":"postgres:16","volumes":["deep-addon-pg-data:/var/lib/postgresql/data"]}"#,
+        )?;
+        let cache = storage.create_addon("cache", "redis", r#"{"image":"redis:7"}"#)?;
+        storage.bind_addon(&worker.id, &pg.id, "{}")?;
+        storage.bind_addon(&web.id, &pg.id, "{}")?;
+        let config_dir = temp.path().join("addons");
+        let runtime = test_podman();
+
+        let lines = status_lines(&storage, Some(&runtime), &pg, &config_dir)?;
+        assert_eq!(lines[0], "addon: pg (postgres)");
+        assert_eq!(lines[1], "image: postgres:16");
+        assert_eq!(lines[2], "unit: deep-addon-pg.service active");
+        // Uptime depends on the current time.
+        assert!(
+            lines[3].starts_with("container: deep-addon-pg running  health=healthy  restarts=0")
+        );
+        assert_eq!(
+            lines[4],
+            "volumes: deep-addon-pg-data:/var/lib/postgresql/data"
+        );
+        assert_eq!(lines[5], "apps: web, worker");
+
+        let lines = status_lines(&storage, Some(&runtime), &cache, &config_dir)?;
+        assert_eq!(
+            lines[3],
+            "container: deep-addon-cache missing  health=-  restarts=-  uptime=-"
+        );
+        assert_eq!(lines[5], "apps: none");
+
+        handle_exec(
+            &storage,
+            &runtime,
+            "pg",
+            &["psql".to_string(), "-U".to_string(), "postgres".to_string()],
+        )?;
+        let err = handle_exec(&storage, &runtime, "cache", &["redis-cli".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("not running"));
+        let calls = runner.calls();
+        assert!(calls.iter().any(|call| call.starts_with("podman exec -i")
+            && call.ends_with("deep-addon-pg psql -U postgres")));
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+//! Reconcile addon config files, DB rows and container units.
+//!
+//! The TOML file is the source of truth when it exists; a DB row without a file
+//! writes the file back. Quadlets with neither are orphans.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::{
+    AddonConfigFile, addon_config_from_json, addon_config_path, addon_config_to_json,
+    load_addon_config_file, maybe_start_addon_quadlet, render_addon_quadlet,
+    write_addon_config_file,
+};
+use crate::db::Storage;
+use crate::runtime::Runtime;
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+use crate::units::{remove_unit, render_unit, unit_names, unit_path};
+
+const QUADLET_PREFIX: &str = "deep-addon-";
+
+/// Where one addon exists and what sync did about it.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SyncEntry {
+    pub name: String,
+    pub file: bool,
+    pub row: bool,
+    pub quadlet: bool,
+    pub action: String,
+}
+
+impl SyncEntry {
+    pub(super) fn columns(&self) -> Vec<String> {
+        let mark = |present: bool| if present { "yes" } else { "-" }.to_string();
+        vec![
+            self.name.clone(),
+            mark(self.file),
+            mark(self.row),
+            mark(self.quadlet),
+            self.action.clone(),
+        ]
+    }
+}
+
+/// Reconcile every addon known to any of the three sources.
+///
+/// With `dry_run` nothing changes and actions read as what would happen.
+/// With `prune` quadlets that have neither a config file nor a row are removed.
+pub(super) fn sync_addons(
+    storage: &Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    config_dir: &PathBuf,
+    dry_run: bool,
+    prune: bool,
+) -> Result<Vec<SyncEntry>> {
+    let quadlet_dir = default_quadlet_dir();
+    let files = config_file_names(config_dir)?;
+    let rows: BTreeSet<String> = storage
+        .list_addons()?
+        .into_iter()
+        .map(|addon| addon.name)
+        .collect();
+    let quadlets: BTreeSet<String> = unit_names(runtime, &quadlet_dir, QUADLET_PREFIX)?
+        .iter()
+        .filter_map(|unit| unit.strip_prefix(QUADLET_PREFIX))
+        .map(str::to_string)
+        .collect();
+    let names: BTreeSet<String> = files
+        .iter()
+        .chain(rows.iter())
+        .chain(quadlets.iter())
+        .cloned()
+        .collect();
+
+    let mut entries = Vec::new();
+    for name in names {
+        let mut entry = SyncEntry {
+            file: files.contains(&name),
+            row: rows.contains(&name),
+            quadlet: quadlets.contains(&name),
+            action: String::new(),
+            name,
+        };
+        let actions = match reconcile(
+            storage,
+            runtime,
+            host_config,
+            config_dir,
+            &entry,
+            dry_run,
+            prune,
+        ) {
+            Ok(actions) if actions.is_empty() => vec!["in sync".to_string()],
+            Ok(actions) => actions,
+            Err(err) => vec![format!("error: {:#}", err)],
+        };
+        entry.action = actions.join(", ");
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn reconcile(
+    storage: &Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    config_dir: &PathBuf,
+    entry: &SyncEntry,
+    dry_run: bool,
+    prune: bool,
+) -> Result<Vec<String>> {
+    let name = entry.name.as_str();
+    let path = addon_config_path(config_dir, name);
+    let mut actions = Vec::new();
+    let config: AddonConfigFile = match (entry.file, entry.row) {
+        (true, _) => {
+            let config = load_addon_config_file(&path)?;
+            let kind = config.kind.clone().unwrap_or_else(|| "generic".to_string());
+            let config_json = addon_config_to_json(&config)?;
+            let row = storage.get_addon_by_name(name)?;
+            let stale = row
+                .as_ref()
+                .is_some_and(|row| row.kind != kind || !same_json(&row.config_json, &config_json));
+            if row.is_none() || stale {
+                actions.push(if row.is_none() {
+                    "create row"
+                } else {
+                    "update row"
+                });
+                if !dry_run {
+                    storage.upsert_addon(name, &kind, &config_json)?;
+                }
+            }
+            config
+        }
+        (false, true) => {
+            let row = storage
+                .get_addon_by_name(name)?
+                .context("addon row disappeared")?;
+            let config = addon_config_from_json(&row.config_json, &row.kind)?;
+            actions.push("write config file");
+            if !dry_run {
+                write_addon_config_file(&path, &config)?;
+            }
+            config
+        }
+        (false, false) => {
+            if !prune {
+                return Ok(vec!["orphan quadlet (use --prune to remove)".to_string()]);
+            }
+            if !dry_run {
+                remove_addon_quadlet(runtime, name)?;
+            }
+            return Ok(vec!["remove quadlet".to_string()]);
+        }
+    };
+    if !entry.quadlet {
+        if config.image.trim().is_empty() {
+            actions.push("no quadlet (config has no image)");
+        } else {
+            actions.push("write quadlet");
+            if !dry_run {
+                maybe_start_addon_quadlet(storage, runtime, host_config, name, &config)?;
+            }
+        }
+    } else if !config.image.trim().is_empty()
+        && quadlet_outdated(storage, runtime, host_config, name, &config)?
+    {
+        actions.push("rewrite quadlet");
+        if !dry_run {
+            maybe_start_addon_quadlet(storage, runtime, host_config, name, &config)?;
+            // `enable --now` leaves a running unit alone; restart to pick up the change.
+            systemctl_for_dir(
+                &default_quadlet_dir(),
+                &["restart", &format!("{}{}.service", QUADLET_PREFIX, name)],
+            )?;
+        }
+    }
+    Ok(actions.into_iter().map(str::to_string).collect())
+}
+
+/// Whether the unit on disk differs from what the config renders today.
+fn quadlet_outdated(
+    storage: &Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    name: &str,
+    config: &AddonConfigFile,
+) -> Result<bool> {
+    let unit_name = format!("{}{}", QUADLET_PREFIX, name);
+    let quadlet = render_addon_quadlet(storage, host_config, runtime, name, config)?;
+    let rendered = render_unit(runtime, &unit_name, &quadlet)?;
+    let path = unit_path(runtime, &default_quadlet_dir(), &unit_name);
+    let current = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(current != rendered)
+}
+
+/// Stop the addon's unit and remove its quadlet file.
+pub(super) fn remove_addon_quadlet(runtime: &Runtime, name: &str) -> Result<bool> {
+    let quadlet_dir = default_quadlet_dir();
+    let unit_name = format!("{}{}", QUADLET_PREFIX, name);
+    let unit = format!("{}.service", unit_name);
+    let _ = systemctl_for_dir(&quadlet_dir, &["stop", &unit]);
+    let _ = systemctl_for_dir(&quadlet_dir, &["disable", &unit]);
+    if !remove_unit(runtime, &quadlet_dir, &unit_name)? {
+        return Ok(false);
+    }
+    systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
+    Ok(true)
+}
+
+fn same_json(left: &str, right: &str) -> bool {
+    match (
+        serde_json::from_str::<Value>(left),
+        serde_json::from_str::<Value>(right),
+    ) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+fn config_file_names(dir: &Path) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if !dir.exists() {
+        return Ok(names);
+    }
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+    {
+        let file = entry?.file_name();
+        if let Some(name) = file.to_str().and_then(|file| file.strip_suffix(".toml")) {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{TestRunner, test_podman};
+    use std::sync::Arc;
+
+    fn quadlet_path(dir: &Path, name: &str) -> PathBuf {
+        dir.join(format!("{}{}.container", QUADLET_PREFIX, name))
+    }
+
+    #[test]
+    fn sync_reconciles_files_rows_and_quadlets() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        let _guard = set_runner_for_tests(runner.clone());
+        let home = temp.path().join("home");
+        let _home = super::super::tests::set_home_for_test(&home)?;
+        let quadlet_dir = PathBuf::from(default_quadlet_dir());
+        std::fs::create_dir_all(&quadlet_dir)?;
+        let storage = Storage::open(&temp.path().join("deep.db"))?;
+        let config_dir = temp.path().join("addons");
+        let host_config = temp.path().join("deep.toml");
+
+        // File only, and a file whose row is stale; both lack quadlets.
+        let fresh = addon_config_from_json(r#"{"image":"redis:7"}"#, "redis")?;
+        write_addon_config_file(&addon_config_path(&config_dir, "cache"), &fresh)?;
+        let pg = addon_config_from_json(r#"{"image":"postgres:17"}"#, "postgres")?;
+        write_addon_config_file(&addon_config_path(&config_dir, "pg"), &pg)?;
+        storage.create_addon("pg", "postgres", r#"{"image":"postgres:16"}"#)?;
+        // Row only, with a quadlet.
+        storage.create_addon("mq", "generic", r#"{"image":"rabbitmq:3"}"#)?;
+        std::fs::write(quadlet_path(&quadlet_dir, "mq"), "")?;
+        // Quadlet only.
+        std::fs::write(quadlet_path(&quadlet_dir, "old"), "")?;
+
+        let planned = sync_addons(
+            &storage,
+            &test_podman(),
+            &host_config,
+            &config_dir,
+            true,
+            true,
+        )?;
+        let actions: Vec<(&str, &str)> = planned
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.action.as_str()))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("cache", "create row, write quadlet"),
+                ("mq", "write config file, rewrite quadlet"),
+                ("old", "remove quadlet"),
+                ("pg", "update row, write quadlet"),
+            ]
+        );
+        assert!(storage.get_addon_by_name("cache")?.is_none());
+        assert!(quadlet_path(&quadlet_dir, "old").exists());
+
+        let entries = sync_addons(
+            &storage,
+            &test_podman(),
+            &host_config,
+            &config_dir,
+            false,
+            false,
+        )?;
+        assert_eq!(entries[2].action, "orphan quadlet (use --prune to remove)");
+        assert!(quadlet_path(&quadlet_dir, "cache").exists());
+        let row = storage.get_addon_by_name("pg")?.expect("pg row");
+        assert!(row.config_json.contains("postgres:17"));
+        let restored = load_addon_config_file(&addon_config_path(&config_dir, "mq"))?;
+        assert_eq!(restored.image, "rabbitmq:3");
+
+        sync_addons(
+            &storage,
+            &test_podman(),
+            &host_config,
+            &config_dir,
+            false,
+            true,
+        )?;
+        assert!(!quadlet_path(&quadlet_dir, "old").exists());
+        let entries = sync_addons(
+            &storage,
+            &test_podman(),
+            &host_config,
+            &config_dir,
+            true,
+            false,
+        )?;
+        assert!(entries.iter().all(|entry| entry.action == "in sync"));
+        let calls = runner.calls();
+        assert!(
+            calls
+                .iter()
+                .any(|call| call == "systemctl --user stop deep-addon-old.service")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Addon image upgrades gated on the addon's `health_cmd`.
+//!
+//! The current config is recorded in `addon_revisions` before anything changes,
+//! so a failed upgrade can put the previous image back, along with the data from
+//! the pre-upgrade backup when one was taken.
+
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::{
+    AddonConfigFile, addon_config_path, addon_config_to_json, backup, load_addon_config_file,
+    maybe_start_addon_quadlet, write_addon_config_file,
+};
+use crate::db::Storage;
+use crate::runner;
+use crate::runtime::Runtime;
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+
+/// Options for one upgrade run.
+pub(super) struct UpgradeOptions<'a> {
+    pub image: &'a str,
+    /// Host config with the `[logging]` settings units are written with.
+    pub host_config: &'a Path,
+    pub config_dir: &'a Path,
+    /// Take a backup into this root directory first.
+    pub backup_dir: Option<&'a Path>,
+    pub health_timeout: Duration,
+}
+
+/// Move an addon to a new image, restoring the previous revision when it fails its health check.
+pub(super) fn upgrade_addon(
+    storage: &Storage,
+    runtime: &Runtime,
+    name: &str,
+    options: &UpgradeOptions,
+) -> Result<()> {
+    let addon = storage
+        .get_addon_by_name(name)?
+        .context("addon not found")?;
+    let config_path = addon_config_path(&options.config_dir.to_path_buf(), name);
+    let previous = load_addon_config_file(&config_path)
+        .with_context(|| format!("addon config not found: {}", config_path.display()))?;
+    if previous.image == options.image {
+        bail!("addon {} already runs {}", name, options.image);
+    }
+    let backup = match options.backup_dir {
+        Some(root) => Some(
+            backup::run_backup(runtime, name, &previous, root, None)
+                .context("pre-upgrade backup failed; addon left unchanged")?,
+        ),
+        None => None,
+    };
+    if let Some(dir) = &backup {
+        println!("backed up addon {} to {}", name, dir.display());
+    }
+
+    if lacks_health_cmd(&previous) {
+        eprintln!(
+            "warning: addon {} has no health_cmd; only checking that it runs",
+            name
+        );
+    }
+    runtime
+        .pull_image(options.image)
+        .with_context(|| format!("failed to pull {}; addon left unchanged", options.image))?;
+    // Recorded only once the upgrade really starts, so a failed backup or pull
+    // leaves no snapshot for `addons rollback` to pick up.
+    let revision = storage.insert_addon_revision(
+        &addon.id,
+        &previous.image,
+        &addon_config_to_json(&previous)?,
+        "snapshot",
+    )?;
+
+    let mut next = previous.clone();
+    next.image = options.image.to_string();
+    let result = apply_revision(
+        storage,
+        runtime,
+        options.host_config,
+        name,
+        &config_path,
+        &next,
+    )
+    .and_then(|()| wait_for_health(runtime, name, &next, options.health_timeout));
+    let mut restore_error = None;
+    let status = match &result {
+        Ok(()) => {
+            storage.upsert_addon(&addon.name, &addon.kind, &addon_config_to_json(&next)?)?;
+            storage.update_addon_revision_status(&revision.id, "superseded")?;
+            "upgraded"
+        }
+        Err(err) => {
+            eprintln!("upgrade of {} failed: {:#}", name, err);
+            eprintln!("restoring {}", previous.image);
+            let restored = apply_revision(
+                storage,
+                runtime,
+                options.host_config,
+                name,
+                &config_path,
+                &previous,
+            )
+            .and_then(|()| wait_for_health(runtime, name, &previous, options.health_timeout))
+            .and_then(|()| match (&backup, options.backup_dir) {
+                (Some(dir), Some(root)) => {
+                    eprintln!("restoring data from {}", dir.display());
+                    backup::run_restore(runtime, name, &previous, root, &dir.to_string_lossy())
+                        .map(|_| ())
+                }
+                _ => Ok(()),
+            });
+            if let Err(err) = restored {
+                restore_error = Some(err);
+                storage.update_addon_revision_status(&revision.id, "restore_failed")?;
+                "restore_failed"
+            } else {
+                storage.update_addon_revision_status(&revision.id, "restored")?;
+                "rolled_back"
+            }
+        }
+    };
+    let payload = serde_json::json!({
+        "addon": name,
+        "revision": revision.id,
+        "from": previous.image,
+        "to": options.image,
+        "backup": backup,
+        "status": status,
+        "error": result.as_ref().err().map(|err| format!("{:#}", err)),
+        "restore_error": restore_error.as_ref().map(|err| format!("{:#}", err)),
+    });
+    let _ = storage.insert_event("addon_upgrade", &payload.to_string());
+    if let Some(err) = restore_error {
+        return Err(err.context("restoring the previous revision failed; check the addon manually"));
+    }
+    result.with_context(|| format!("upgrade rolled back to {}", previous.image))
+}
+
+fn lacks_health_cmd(config: &AddonConfigFile) -> bool {
+    config
+        .health_cmd
+        .as_deref()
+        .is_none_or(|cmd| cmd.trim().is_empty())
+}
+
+/// Write the config and quadlet for a revision and restart the addon on it.
+fn apply_revision(
+    storage: &Storage,
+    runtime: &Runtime,
+    host_config: &Path,
+    name: &str,
+    config_path: &Path,
+    config: &AddonConfigFile,
+) -> Result<()> {
+    write_addon_config_file(&config_path.to_path_buf(), config)?;
+    maybe_start_addon_quadlet(storage, runtime, host_config, name, config)?;
+    // `enable --now` leaves a running unit alone; restart to pick up the new image.
+    systemctl_for_dir(
+        &default_quadlet_dir(),
+        &["restart", &format!("deep-addon-{}.service", name)],
+    )?;
+    Ok(())
+}
+
+/// Poll `health_cmd` inside the addon until it succeeds or the timeout passes.
+fn wait_for_health(
+    runtime: &Runtime,
+    name: &str,
+    config: &AddonConfigFile,
+    timeout: Duration,
+) -> Result<()> {
+    let container = format!("deep-addon-{}", name);
+    let interval = Duration::from_millis(config.health_interval_ms.unwrap_or(1000));
+    let health_cmd = config
+        .health_cmd
+        .as_deref()
+        .map(str::trim)
+        .filter(|cmd| !cmd.is_empty());
+    let deadline = Instant::now() + timeout;
+    loop {
+        let args: Vec<&str> = match health_cmd {
+            Some(cmd) => vec!["exec", &container, "sh", "-c", cmd],
+            None => vec!["inspect", "--format", "{{.State.Running}}", &container],
+        };
+        let last_error = match runner::run_output(runtime.program(), &args) {
+            Ok(output) if output.status.success() => {
+                if health_cmd.is_some() || String::from_utf8_lossy(&output.stdout).trim() == "true"
+                {
+                    return Ok(());
+                }
+                "container is not running".to_string()
+            }
+            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(err) => err.to_string(),
+        };
+        if Instant::now() >= deadline {
+            bail!(
+                "addon {} not healthy after {}s: {}",
+                name,
+                timeout.as_secs(),
+                last_error
+            );
+        }
+        std::thread::sleep(interval);
+    }
+}
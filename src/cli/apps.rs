@@ -1,11 +1,20 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::Subcommand;
 use std::path::PathBuf;
 
-use crate::cli::require_app;
-use crate::db::Storage;
-use crate::runtime::app_container_name;
-use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+use crate::cli::addons::sync_shared_network;
+use crate::cli::deploy::detach_from_network;
+use crate::cli::domains::{check_domain_conflicts, route_with_steal};
+use crate::cli::ps::{ServiceStatus, unix_now};
+use crate::cli::{
+    available_runtime, current_release_snapshot, read_password_from_stdin, read_stdin_line,
+    refresh_app_route, require_app,
+};
+use crate::config::{ResourcesConfig, validate_auth_username};
+use crate::db::{AppRow, Storage};
+use crate::proxy::{CaddyFile, RouteStatus};
+use crate::runtime::{ContainerStats, Runtime, app_container_name, app_network_name};
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir, unit_state_for_dir};
 
 #[derive(Subcommand, Debug)]
 /// App-related commands.
@@ -42,6 +51,14 @@ pub enum AppsCommand {
             help = "Dockerfile path"
         )]
         dockerfile: String,
+        #[arg(
+            short = 'H',
+            long = "domain",
+            help = "Domain to route to the app (repeatable)"
+        )]
+        domains: Vec<String>,
+        #[arg(short = 's', long, help = "Move domains already claimed by other apps")]
+        steal: bool,
         #[arg(short = 'D', long, help = "Print actions without executing")]
         dry_run: bool,
     },
@@ -51,6 +68,12 @@ pub enum AppsCommand {
         #[arg(help = "App name")]
         name: String,
     },
+    /// Show live status for one app or all apps
+    #[command(alias = "stat")]
+    Status {
+        #[arg(help = "App name (all apps when omitted)")]
+        name: Option<String>,
+    },
     /// Start the current release
     #[command(alias = "st")]
     Start {
@@ -69,10 +92,55 @@ pub enum AppsCommand {
         #[arg(help = "App name")]
         name: String,
     },
+    /// Manage basic auth users for an app route
+    #[command(alias = "au")]
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+/// Basic auth user commands.
+pub enum AuthCommand {
+    /// Add or update a basic auth user
+    #[command(alias = "a")]
+    Add {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(help = "Username")]
+        user: String,
+        // No argv form: a password there shows up in the process list and shell history.
+        #[arg(
+            short = 's',
+            long,
+            help = "Read the password from stdin without prompting"
+        )]
+        password_stdin: bool,
+    },
+    /// Remove a basic auth user
+    #[command(alias = "rm")]
+    Remove {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(help = "Username")]
+        user: String,
+    },
+    /// List basic auth users
+    #[command(alias = "ls")]
+    List {
+        #[arg(help = "App name")]
+        app: String,
+    },
 }
 
 /// Handle app subcommands.
-pub fn handle(storage: &mut Storage, command: AppsCommand) -> Result<()> {
+pub fn handle(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    command: AppsCommand,
+) -> Result<()> {
     match command {
         AppsCommand::List => {
             let apps = storage.list_apps()?;
@@ -92,11 +160,21 @@ pub fn handle(storage: &mut Storage, command: AppsCommand) -> Result<()> {
             git,
             image_template,
             dockerfile,
+            domains,
+            steal,
             dry_run,
         } => {
             let repo_path = repo_path.unwrap_or_else(|| format!("/srv/deep/repos/{}.git", name));
             let app_dir = config_dir.join(&name);
             let app_toml = app_dir.join("app.toml");
+            let domains = if !domains.is_empty() {
+                domains
+            } else if app_toml.exists() {
+                crate::config::load_app_config(&app_toml)?.app.domains
+            } else {
+                vec![format!("{}.example.com", name)]
+            };
+            let conflicts = check_domain_conflicts(storage, proxy, &name, &domains, steal)?;
             if dry_run {
                 print_add_plan(
                     &name,
@@ -106,19 +184,26 @@ pub fn handle(storage: &mut Storage, command: AppsCommand) -> Result<()> {
                     image_template.as_deref(),
                     &dockerfile,
                 );
+                for conflict in &conflicts {
+                    println!(
+                        "would move {} from {} (--steal)",
+                        conflict.owner_host, conflict.owner
+                    );
+                }
                 return Ok(());
             }
             let app = storage.create_app(&name, &repo_path)?;
             if !app_toml.exists() {
                 std::fs::create_dir_all(&app_dir)?;
-                std::fs::write(&app_toml, default_app_toml(&name))?;
+                std::fs::write(&app_toml, default_app_toml(&name, &domains))?;
             }
+            route_with_steal(storage, proxy, &name, None, &conflicts)?;
             if git {
                 let repo_path = crate::cli::git::init_repo_for_app(
                     storage,
+                    runtime,
                     &name,
-                    PathBuf::from("/srv/deep/repos"),
-                    Some(PathBuf::from(&repo_path)),
+                    PathBuf::from(&repo_path),
                     image_template,
                     &dockerfile,
                     "deep",
@@ -130,19 +215,244 @@ pub fn handle(storage: &mut Storage, command: AppsCommand) -> Result<()> {
             Ok(())
         }
         AppsCommand::Remove { name } => {
+            let app = require_app(storage, &name)?;
+            let addons: Vec<String> = storage
+                .addon_snapshots_for_app(&app.id)?
+                .into_iter()
+                .map(|addon| addon.name)
+                .collect();
             storage.remove_app(&name)?;
+            let network = app_network_name(&name);
+            if runtime.network_exists(&network) {
+                remove_app_network(storage, runtime, proxy, &network, &addons)?;
+            }
             println!("removed app {}", name);
             Ok(())
         }
+        AppsCommand::Status { name } => handle_status(storage, runtime, proxy, name),
         AppsCommand::Start { name } => app_action(storage, &name, "start"),
         AppsCommand::Stop { name } => app_action(storage, &name, "stop"),
         AppsCommand::Restart { name } => app_action(storage, &name, "restart"),
+        AppsCommand::Auth { command } => handle_auth(storage, proxy, command),
     }
 }
 
-fn default_app_toml(name: &str) -> String {
+/// Take Caddy and the app's addons off its private network, then delete the network.
+fn remove_app_network(
+    storage: &Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    network: &str,
+    addons: &[String],
+) -> Result<()> {
+    detach_from_network(runtime, network, proxy.container_name())?;
+    for addon in addons {
+        detach_from_network(runtime, network, &format!("deep-addon-{}", addon))?;
+        sync_shared_network(storage, runtime, addon)?;
+    }
+    match runtime.remove_network(network) {
+        Ok(()) => println!("removed network {}", network),
+        // The app's own containers may still be running; gc retries later.
+        Err(err) => eprintln!(
+            "warning: kept network {}: {:#}; `deep host gc` removes it once nothing uses it",
+            network, err
+        ),
+    }
+    Ok(())
+}
+
+fn handle_auth(storage: &mut Storage, proxy: &CaddyFile, command: AuthCommand) -> Result<()> {
+    match command {
+        AuthCommand::Add {
+            app,
+            user,
+            password_stdin,
+        } => {
+            let app_row = require_app(storage, &app)?;
+            validate_auth_username(&user)?;
+            let password = if password_stdin {
+                read_stdin_line()?
+            } else {
+                read_password_from_stdin()?
+            };
+            if password.is_empty() {
+                bail!("password must not be empty");
+            }
+            let hash = proxy.hash_password(&password)?;
+            storage.upsert_proxy_user(&app_row.id, &user, &hash)?;
+            refresh_app_route(storage, proxy, &app_row)?;
+            println!("added auth user {} for {}", user, app_row.name);
+            Ok(())
+        }
+        AuthCommand::Remove { app, user } => {
+            let app_row = require_app(storage, &app)?;
+            let users = storage.list_proxy_users(&app_row.id)?;
+            if !users.iter().any(|row| row.username == user) {
+                bail!("auth user {} not found for {}", user, app_row.name);
+            }
+            if users.len() == 1 && current_auth_enabled(storage, &app_row)? {
+                bail!(
+                    "cannot remove the last auth user while [proxy.auth] is enabled for {}",
+                    app_row.name
+                );
+            }
+            storage.remove_proxy_user(&app_row.id, &user)?;
+            refresh_app_route(storage, proxy, &app_row)?;
+            println!("removed auth user {} from {}", user, app_row.name);
+            Ok(())
+        }
+        AuthCommand::List { app } => {
+            let app_row = require_app(storage, &app)?;
+            let users = storage.list_proxy_users(&app_row.id)?;
+            if users.is_empty() {
+                println!("no auth users for {}", app_row.name);
+                return Ok(());
+            }
+            for user in users {
+                println!("{}  {}", user.username, user.created_at);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn current_auth_enabled(storage: &Storage, app_row: &crate::db::AppRow) -> Result<bool> {
+    let Some(release_id) = storage.current_release_id(&app_row.id)? else {
+        return Ok(false);
+    };
+    let Some(release) = storage.get_release_by_id(&release_id)? else {
+        return Ok(false);
+    };
+    let snapshot: crate::config::ConfigSnapshot =
+        serde_json::from_str(&release.config_json).context("invalid release config")?;
+    Ok(snapshot.proxy.auth.is_some())
+}
+
+fn default_app_toml(name: &str, domains: &[String]) -> String {
     let template = include_str!("../../templates/app.toml");
-    template.replace("{{app}}", name)
+    let domains: Vec<String> = domains
+        .iter()
+        .map(|domain| format!("\"{}\"", domain))
+        .collect();
+    template
+        .replace("{{domains}}", &domains.join(", "))
+        .replace("{{app}}", name)
+}
+
+fn handle_status(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    name: Option<String>,
+) -> Result<()> {
+    let apps = match name {
+        Some(name) => vec![require_app(storage, &name)?],
+        None => storage.list_apps()?,
+    };
+    if apps.is_empty() {
+        println!("no apps found");
+        return Ok(());
+    }
+    let runtime = available_runtime(runtime, "container state unavailable");
+    let routes = proxy.list_routes().unwrap_or_default();
+    for (index, app_row) in apps.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        print_app_status(storage, runtime, &routes, app_row)?;
+    }
+    Ok(())
+}
+
+fn print_app_status(
+    storage: &mut Storage,
+    runtime: Option<&Runtime>,
+    routes: &[RouteStatus],
+    app_row: &AppRow,
+) -> Result<()> {
+    println!("app: {}", app_row.name);
+    let Some((release, snapshot)) = current_release_snapshot(storage, app_row)? else {
+        println!("release: none");
+        return Ok(());
+    };
+    let container = app_container_name(&app_row.name, &release.id);
+    let unit = format!("{}.service", container);
+    let quadlet_dir = snapshot
+        .deploy
+        .quadlet_dir
+        .clone()
+        .unwrap_or_else(default_quadlet_dir);
+    let status = ServiceStatus::probe(runtime, &container, unit_state_for_dir(&quadlet_dir, &unit));
+    println!(
+        "release: {} ({}, created {})",
+        release.id, release.status, release.created_at
+    );
+    println!("unit: {} {}", unit, status.unit_state);
+    let [state, health, restarts, uptime] = status.columns(unix_now());
+    println!(
+        "container: {} {}  health={}  restarts={}  uptime={}",
+        container, state, health, restarts, uptime
+    );
+    match routes.iter().find(|route| route.app == app_row.name) {
+        Some(route) => println!("domains: {}", route.hosts.join(", ")),
+        None if snapshot.domains.is_empty() => println!("domains: none"),
+        None => println!(
+            "domains: none published (release expects {}; run `deep proxy check`)",
+            snapshot.domains.join(", ")
+        ),
+    }
+    let addons: Vec<String> = storage
+        .addon_snapshots_for_app(&app_row.id)?
+        .into_iter()
+        .map(|addon| format!("{} ({})", addon.name, addon.kind))
+        .collect();
+    if addons.is_empty() {
+        println!("addons: none");
+    } else {
+        println!("addons: {}", addons.join(", "));
+    }
+    let stats = runtime
+        .filter(|_| {
+            status
+                .container
+                .as_ref()
+                .is_some_and(|c| c.status == "running")
+        })
+        .and_then(|runtime| runtime.container_stats(&[&container]).ok())
+        .and_then(|stats| stats.into_iter().next());
+    for line in resource_lines(&snapshot.resources, stats.as_ref()) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Format resource usage next to the configured limits.
+fn resource_lines(resources: &ResourcesConfig, stats: Option<&ContainerStats>) -> Vec<String> {
+    let usage = |value: Option<String>| value.filter(|v| !v.is_empty()).unwrap_or("-".to_string());
+    let limit = |value: Option<String>| value.unwrap_or("none".to_string());
+    let mut memory_limit = limit(resources.memory.clone());
+    if let Some(swap) = &resources.memory_swap {
+        memory_limit.push_str(&format!(" (swap {})", swap));
+    }
+    vec![
+        format!(
+            "memory: {} ({})  limit={}",
+            usage(stats.map(|s| s.mem_usage.clone())),
+            usage(stats.map(|s| s.mem_percent.clone())),
+            memory_limit
+        ),
+        format!(
+            "cpu: {}  limit={}",
+            usage(stats.map(|s| s.cpu_percent.clone())),
+            limit(resources.cpus.map(|cpus| format!("{} cpus", cpus)))
+        ),
+        format!(
+            "pids: {}  limit={}",
+            usage(stats.map(|s| s.pids.clone())),
+            limit(resources.pids_limit.map(|pids| pids.to_string()))
+        ),
+        format!("shm_size: {}", limit(resources.shm_size.clone())),
+    ]
 }
 
 fn app_action(storage: &mut Storage, name: &str, action: &str) -> Result<()> {
@@ -199,3 +509,33 @@ fn print_add_plan(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_lines_show_usage_next_to_limits() {
+        let resources = ResourcesConfig {
+            memory: Some("512m".to_string()),
+            memory_swap: Some("1g".to_string()),
+            cpus: Some(1.5),
+            ..ResourcesConfig::default()
+        };
+        let stats = ContainerStats::parse_all(
+            r#"[{"name":"deep-app-app-r1","cpu_percent":"0.15%","mem_usage":"12.3MB / 536.9MB","mem_percent":"2.29%","pids":"5"}]"#,
+        );
+        let lines = resource_lines(&resources, stats.first());
+        assert_eq!(
+            lines,
+            vec![
+                "memory: 12.3MB / 536.9MB (2.29%)  limit=512m (swap 1g)",
+                "cpu: 0.15%  limit=1.5 cpus",
+                "pids: 5  limit=none",
+                "shm_size: none",
+            ]
+        );
+        let lines = resource_lines(&ResourcesConfig::default(), None);
+        assert_eq!(lines[0], "memory: - (-)  limit=none");
+    }
+}
@@ -1,16 +1,20 @@
 use anyhow::{Context, Result, bail};
 use clap::Args;
-use std::collections::HashSet;
+use std::path::Path;
 use ulid::Ulid;
 
+use crate::cli::domains::{DomainConflict, check_domain_conflicts, route_with_steal};
+use crate::cli::retention::enforce_retention;
 use crate::cli::{
     now_rfc3339, record_proxy_error, require_app, resolve_config_path, resolve_healthcheck,
+    route_extras_for_app,
 };
-use crate::config::load_app_config;
-use crate::db::{ReleaseRow, Storage};
-use crate::proxy::CaddyFile;
-use crate::runtime::{Runtime, app_container_name};
+use crate::config::{load_app_config, load_host_config};
+use crate::db::{AppRow, ReleaseRow, Storage};
+use crate::proxy::{CaddyFile, render_app_block};
+use crate::runtime::{NETWORK_NAME, Runtime, app_container_name, app_network_name, network_lines};
 use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+use crate::units::{find_unit, write_unit};
 
 #[derive(Clone, Args, Debug)]
 #[command(about = "Deploy a new release for an app")]
@@ -44,6 +48,8 @@ pub struct DeployArgs {
     pub record_only: bool,
     #[arg(short = 'D', long, help = "Print actions without executing")]
     pub dry_run: bool,
+    #[arg(short = 's', long, help = "Move domains already claimed by other apps")]
+    pub steal: bool,
 }
 
 #[derive(Args, Debug)]
@@ -56,10 +62,18 @@ pub struct RollbackArgs {
     pub release_id: String,
     #[arg(short = 'D', long, help = "Print actions without executing")]
     pub dry_run: bool,
+    #[arg(short = 's', long, help = "Move domains already claimed by other apps")]
+    pub steal: bool,
 }
 
 /// Deploy a new release for an app.
-pub fn handle_deploy(storage: &mut Storage, proxy: &CaddyFile, args: DeployArgs) -> Result<()> {
+pub fn handle_deploy(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    host_config: &Path,
+    args: DeployArgs,
+) -> Result<()> {
     let app = require_app(storage, &args.app)?;
     let config_path = resolve_config_path(&args.config, &app.repo_path, &app.name)?;
     let config = load_app_config(&config_path)?;
@@ -74,12 +88,12 @@ pub fn handle_deploy(storage: &mut Storage, proxy: &CaddyFile, args: DeployArgs)
     let git_sha_base = resolve_git_sha_base(snapshot.deploy.git_ref.clone(), &app.repo_path)?;
     let image_ref = resolve_image_ref(args.image.clone(), &snapshot, &git_sha_base)?;
     let config_json = serde_json::to_string(&snapshot)?;
+    let conflicts =
+        check_domain_conflicts(storage, proxy, &app.name, &snapshot.domains, args.steal)?;
 
-    let runtime = if args.record_only {
-        None
-    } else {
-        Some(Runtime::detect()?)
-    };
+    if !args.record_only {
+        runtime.available()?;
+    }
 
     let image_digest = if args.record_only || args.skip_pull {
         args.clone().image_digest.unwrap_or_else(|| {
@@ -89,16 +103,19 @@ pub fn handle_deploy(storage: &mut Storage, proxy: &CaddyFile, args: DeployArgs)
     } else {
         match args.clone().image_digest {
             Some(digest) => digest.clone(),
-            None => runtime
-                .as_ref()
-                .context("runtime required for image pull")?
-                .pull_image(&image_ref)?,
+            None => runtime.pull_image(&image_ref)?,
         }
     };
 
     let git_sha = resolve_git_sha(args.git_sha.clone(), Some(git_sha_base.clone()), &image_ref)?;
     if args.dry_run {
         print_deploy_plan(&app.name, &snapshot, &image_ref, &git_sha, &args.clone())?;
+        for conflict in &conflicts {
+            println!(
+                "would move {} from {} (--steal)",
+                conflict.owner_host, conflict.owner
+            );
+        }
         return Ok(());
     }
 
@@ -131,21 +148,32 @@ pub fn handle_deploy(storage: &mut Storage, proxy: &CaddyFile, args: DeployArgs)
     tx.commit()?;
 
     if args.record_only {
+        route_with_steal(storage, proxy, &app.name, None, &conflicts)?;
         let tx = storage.transaction()?;
         Storage::set_current_release(&tx, &app.id, &release_id)?;
         tx.commit()?;
+        storage.clear_stolen_domains(&app.id)?;
         storage.set_release_status(&release_id, "active")?;
         storage.update_deployment_status(&deployment_id, "succeeded", None)?;
-        if let Err(err) = enforce_retention(storage, &app, &snapshot) {
+        if let Err(err) = enforce_retention(storage, runtime, &app, &snapshot) {
             eprintln!("warning: retention failed: {}", err);
         }
         println!("recorded release {} for {}", release_id, app.name);
         return Ok(());
     }
 
-    let runtime = runtime.context("runtime required for deploy")?;
     let container_name = app_container_name(&app.name, &release_id);
-    let start_result = start_app_quadlet(&runtime, &app.name, &release_id, &snapshot, &image_ref);
+    let start_result = wait_for_addons(runtime, &snapshot).and_then(|()| {
+        start_app_quadlet(
+            runtime,
+            host_config,
+            &app.name,
+            &release_id,
+            &snapshot,
+            &image_ref,
+            proxy.container_name(),
+        )
+    });
     if let Err(err) = start_result {
         storage.set_release_status(&release_id, "failed")?;
         storage.update_deployment_status(&deployment_id, "failed", Some(&err.to_string()))?;
@@ -162,26 +190,36 @@ pub fn handle_deploy(storage: &mut Storage, proxy: &CaddyFile, args: DeployArgs)
         return Err(err);
     }
 
-    if !args.skip_proxy {
-        if let Err(err) = proxy.upsert_route(&app.name, &release_id, &snapshot) {
-            let _ = stop_app_release(storage, &app.name, &release_id);
-            storage.set_release_status(&release_id, "failed")?;
-            storage.update_deployment_status(&deployment_id, "failed", Some(&err.to_string()))?;
-            record_proxy_error(storage, &app.name, &release_id, "deploy", &err);
-            return Err(err);
-        }
+    if let Err(err) = route_release(
+        storage,
+        proxy,
+        &app,
+        &release_id,
+        &snapshot,
+        args.skip_proxy,
+        &conflicts,
+    ) {
+        let _ = stop_app_release(storage, &app.name, &release_id);
+        storage.set_release_status(&release_id, "failed")?;
+        storage.update_deployment_status(&deployment_id, "failed", Some(&err.to_string()))?;
+        record_proxy_error(storage, &app.name, &release_id, "deploy", &err);
+        return Err(err);
     }
 
     let tx = storage.transaction()?;
     Storage::set_current_release(&tx, &app.id, &release_id)?;
     tx.commit()?;
+    storage.clear_stolen_domains(&app.id)?;
     storage.set_release_status(&release_id, "active")?;
     storage.update_deployment_status(&deployment_id, "succeeded", None)?;
+    if snapshot.deploy.private_network {
+        sync_addon_shared_networks(storage, runtime, &snapshot);
+    }
 
     if let Some(old_release_id) = from_release_id {
         let _ = stop_app_release(storage, &app.name, &old_release_id);
     }
-    if let Err(err) = enforce_retention(storage, &app, &snapshot) {
+    if let Err(err) = enforce_retention(storage, runtime, &app, &snapshot) {
         eprintln!("warning: retention failed: {}", err);
     }
 
@@ -269,21 +307,42 @@ fn extract_image_tag(image_ref: &str) -> Option<String> {
 
 fn start_app_quadlet(
     runtime: &Runtime,
+    host_config: &Path,
     app_name: &str,
     release_id: &str,
     snapshot: &crate::config::ConfigSnapshot,
     image_ref: &str,
+    caddy: &str,
 ) -> Result<()> {
-    runtime.ensure_deep_network()?;
+    if snapshot.deploy.private_network {
+        attach_app_network(runtime, app_name, snapshot)?;
+        attach_to_network(runtime, &app_network_name(app_name), caddy)?;
+    } else {
+        runtime.ensure_deep_network()?;
+        // Addons used only by private apps are off deep-net; a shared app needs them on it.
+        for addon in snapshot.addons.iter().filter(|addon| {
+            addon
+                .config
+                .get("network")
+                .and_then(serde_json::Value::as_str)
+                .is_none_or(|network| network == NETWORK_NAME)
+        }) {
+            attach_to_network(runtime, NETWORK_NAME, &format!("deep-addon-{}", addon.name))?;
+        }
+    }
+    for volume in snapshot.volumes.iter().filter(|volume| volume.is_named()) {
+        runtime.ensure_volume(&volume.volume_name(app_name), app_name, &volume.name)?;
+    }
     let quadlet_dir = snapshot
         .deploy
         .quadlet_dir
         .clone()
         .unwrap_or_else(default_quadlet_dir);
-    let unit_name = format!("deep-app-{}-{}", app_name, release_id);
+    let unit_name = app_container_name(app_name, release_id);
     write_app_quadlet(
+        runtime,
+        host_config,
         &quadlet_dir,
-        &unit_name,
         image_ref,
         snapshot,
         app_name,
@@ -298,33 +357,142 @@ fn start_app_quadlet(
 }
 
 pub(crate) fn write_app_quadlet(
+    runtime: &Runtime,
+    host_config: &Path,
     quadlet_dir: &str,
-    unit_name: &str,
     image_ref: &str,
     snapshot: &crate::config::ConfigSnapshot,
     app_name: &str,
     release_id: &str,
 ) -> Result<()> {
+    let unit_name = app_container_name(app_name, release_id);
     let mut env_lines = Vec::new();
     for (key, value) in &snapshot.env {
         env_lines.push(format!("Environment={}={}", key, value));
     }
     env_lines.push(format!("Environment=PORT={}", snapshot.port));
-    let quadlet_path = std::path::Path::new(quadlet_dir).join(format!("{}.container", unit_name));
-    std::fs::create_dir_all(quadlet_dir)?;
+    let logging = load_host_config(host_config)?.logging;
     let template = include_str!("../../templates/app.container");
     let contents = template
         .replace("{{app}}", app_name)
         .replace("{{release}}", release_id)
         .replace("{{image}}", image_ref)
+        .replace("{{dependencies}}", &addon_dependency_lines(snapshot))
+        .replace(
+            "{{networks}}",
+            &network_lines(&app_networks(app_name, snapshot)),
+        )
+        .replace("{{volumes}}", &volume_lines(app_name, snapshot))
         .replace("{{env}}", &env_lines.join("\n"))
-        .replace("{{health}}", &health_lines_for_snapshot(snapshot));
-    std::fs::write(&quadlet_path, contents)?;
+        .replace("{{health}}", &health_lines_for_snapshot(snapshot))
+        .replace("{{resources}}", &snapshot.resources.quadlet_lines())
+        .replace("{{logging}}", &logging.quadlet_lines())
+        .replace("{{auth}}", &crate::registry::auth_line(runtime.auth_file()));
+    write_unit(runtime, quadlet_dir, &unit_name, &contents)?;
+    Ok(())
+}
+
+/// Networks an app container joins: its private network, or the shared one.
+fn app_networks(app_name: &str, snapshot: &crate::config::ConfigSnapshot) -> Vec<String> {
+    if snapshot.deploy.private_network {
+        vec![app_network_name(app_name)]
+    } else {
+        vec![NETWORK_NAME.to_string()]
+    }
+}
+
+/// Create a private app's network and attach its bound addons to it.
+pub(crate) fn attach_app_network(
+    runtime: &Runtime,
+    app_name: &str,
+    snapshot: &crate::config::ConfigSnapshot,
+) -> Result<()> {
+    let network = app_network_name(app_name);
+    runtime.ensure_networks(std::slice::from_ref(&network))?;
+    for addon in &snapshot.addons {
+        attach_to_network(runtime, &network, &format!("deep-addon-{}", addon.name))?;
+    }
+    Ok(())
+}
+
+/// Attach a container to a network now and in its unit, so restarts keep it.
+pub(crate) fn attach_to_network(runtime: &Runtime, network: &str, container: &str) -> Result<()> {
+    if let Some(path) = find_unit(runtime, container) {
+        crate::units::add_network(&path, network)?;
+    }
+    runtime.connect_network(network, container)?;
+    Ok(())
+}
+
+/// Take bound addons off deep-net once only private apps use them; failures only warn.
+fn sync_addon_shared_networks(
+    storage: &Storage,
+    runtime: &Runtime,
+    snapshot: &crate::config::ConfigSnapshot,
+) {
+    for addon in &snapshot.addons {
+        if let Err(err) = crate::cli::addons::sync_shared_network(storage, runtime, &addon.name) {
+            eprintln!(
+                "warning: failed to update the networks of addon {}: {:#}",
+                addon.name, err
+            );
+        }
+    }
+}
+
+/// Detach a container from a network now and in its unit.
+pub(crate) fn detach_from_network(runtime: &Runtime, network: &str, container: &str) -> Result<()> {
+    if let Some(path) = find_unit(runtime, container) {
+        crate::units::remove_network(&path, network)?;
+    }
+    runtime.disconnect_network(network, container)?;
+    Ok(())
+}
+
+/// Order the app after its bound addons so a reboot starts them first.
+fn addon_dependency_lines(snapshot: &crate::config::ConfigSnapshot) -> String {
+    snapshot
+        .addons
+        .iter()
+        .map(|addon| {
+            let unit = format!("deep-addon-{}.service", addon.name);
+            format!("After={}\nRequires={}", unit, unit)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Wait for every bound addon to report healthy, up to `[deploy] addon_wait_secs`.
+fn wait_for_addons(runtime: &Runtime, snapshot: &crate::config::ConfigSnapshot) -> Result<()> {
+    let Some(secs) = snapshot.deploy.addon_wait_secs else {
+        return Ok(());
+    };
+    for addon in &snapshot.addons {
+        let container = format!("deep-addon-{}", addon.name);
+        runtime
+            .wait_for_container_health(&container, std::time::Duration::from_secs(secs))
+            .with_context(|| format!("addon {} is not ready", addon.name))?;
+    }
     Ok(())
 }
 
+fn volume_lines(app_name: &str, snapshot: &crate::config::ConfigSnapshot) -> String {
+    snapshot
+        .volumes
+        .iter()
+        .map(|volume| volume.quadlet_line(app_name))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Roll back to a previous release for an app.
-pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackArgs) -> Result<()> {
+pub fn handle_rollback(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    host_config: &Path,
+    args: RollbackArgs,
+) -> Result<()> {
     let app_row = require_app(storage, &args.app)?;
     let release = storage
         .get_release_by_id(&args.release_id)?
@@ -339,9 +507,17 @@ pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackA
     let snapshot: crate::config::ConfigSnapshot =
         serde_json::from_str(&release.config_json).context("invalid release config")?;
     let healthcheck = snapshot.healthcheck.clone();
+    let conflicts =
+        check_domain_conflicts(storage, proxy, &app_row.name, &snapshot.domains, args.steal)?;
 
     if args.dry_run {
         print_rollback_plan(&app_row.name, &args.release_id, &snapshot)?;
+        for conflict in &conflicts {
+            println!(
+                "would move {} from {} (--steal)",
+                conflict.owner_host, conflict.owner
+            );
+        }
         return Ok(());
     }
 
@@ -359,14 +535,16 @@ pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackA
     )?;
     tx.commit()?;
 
-    let runtime = Runtime::detect()?;
+    runtime.available()?;
     let container_name = app_container_name(&app_row.name, &args.release_id);
     if let Err(err) = start_app_quadlet(
-        &runtime,
+        runtime,
+        host_config,
         &app_row.name,
         &args.release_id,
         &snapshot,
         &release.image_ref,
+        proxy.container_name(),
     ) {
         storage.update_deployment_status(&deployment_id, "failed", Some(&err.to_string()))?;
         return Err(err);
@@ -379,7 +557,15 @@ pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackA
         return Err(err);
     }
 
-    if let Err(err) = proxy.upsert_route(&app_row.name, &args.release_id, &snapshot) {
+    if let Err(err) = route_release(
+        storage,
+        proxy,
+        &app_row,
+        &args.release_id,
+        &snapshot,
+        false,
+        &conflicts,
+    ) {
         let _ = stop_app_release(storage, &app_row.name, &args.release_id);
         storage.update_deployment_status(&deployment_id, "failed", Some(&err.to_string()))?;
         record_proxy_error(storage, &app_row.name, &args.release_id, "rollback", &err);
@@ -389,15 +575,19 @@ pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackA
     let tx = storage.transaction()?;
     Storage::set_current_release(&tx, &app_row.id, &args.release_id)?;
     tx.commit()?;
+    storage.clear_stolen_domains(&app_row.id)?;
     storage.set_release_status(&args.release_id, "active")?;
     storage.update_deployment_status(&deployment_id, "succeeded", None)?;
+    if snapshot.deploy.private_network {
+        sync_addon_shared_networks(storage, runtime, &snapshot);
+    }
 
     if let Some(old_release_id) = from_release_id {
         if old_release_id != args.release_id {
             let _ = stop_app_release(storage, &app_row.name, &old_release_id);
         }
     }
-    if let Err(err) = enforce_retention(storage, &app_row, &snapshot) {
+    if let Err(err) = enforce_retention(storage, runtime, &app_row, &snapshot) {
         eprintln!("warning: retention failed: {}", err);
     }
 
@@ -405,6 +595,31 @@ pub fn handle_rollback(storage: &mut Storage, proxy: &CaddyFile, args: RollbackA
     Ok(())
 }
 
+/// Route a started release, taking any conflicting hosts in the same Caddyfile edit.
+fn route_release(
+    storage: &mut Storage,
+    proxy: &CaddyFile,
+    app: &AppRow,
+    release_id: &str,
+    snapshot: &crate::config::ConfigSnapshot,
+    skip_proxy: bool,
+    conflicts: &[DomainConflict],
+) -> Result<()> {
+    let block = if skip_proxy {
+        None
+    } else {
+        let extras = route_extras_for_app(storage, &app.id)?;
+        Some(render_app_block(
+            &app.name,
+            release_id,
+            snapshot,
+            &extras,
+            proxy.certs_dir(),
+        )?)
+    };
+    route_with_steal(storage, proxy, &app.name, block, conflicts)
+}
+
 fn stop_app_release(storage: &mut Storage, app_name: &str, release_id: &str) -> Result<()> {
     let release = storage.get_release_by_id(release_id)?;
     if let Some(release) = release {
@@ -416,6 +631,9 @@ fn stop_app_release(storage: &mut Storage, app_name: &str, release_id: &str) ->
                 addons: Vec::new(),
                 healthcheck: crate::config::HealthcheckConfig::default(),
                 deploy: crate::config::DeployConfig::default(),
+                proxy: crate::config::ProxyConfig::default(),
+                resources: Default::default(),
+                volumes: Vec::new(),
             });
         let unit_name = app_container_name(app_name, release_id);
         let quadlet_dir = snapshot
@@ -440,69 +658,6 @@ pub(crate) fn apply_addon_env(snapshot: &mut crate::config::ConfigSnapshot) {
     }
 }
 
-fn enforce_retention(
-    storage: &mut Storage,
-    app: &crate::db::AppRow,
-    snapshot: &crate::config::ConfigSnapshot,
-) -> Result<()> {
-    let retain = snapshot.deploy.retain.max(1) as usize;
-    let releases = storage.list_releases(&app.id)?;
-    if releases.len() <= retain {
-        return Ok(());
-    }
-    let current_id = storage.current_release_id(&app.id)?;
-    let mut keep: HashSet<String> = HashSet::new();
-    if let Some(current_id) = current_id {
-        keep.insert(current_id);
-    }
-    for release in &releases {
-        if keep.len() >= retain {
-            break;
-        }
-        keep.insert(release.id.clone());
-    }
-    for release in releases {
-        if keep.contains(&release.id) {
-            continue;
-        }
-        prune_release(storage, app, &release)?;
-    }
-    Ok(())
-}
-
-fn prune_release(
-    storage: &mut Storage,
-    app: &crate::db::AppRow,
-    release: &ReleaseRow,
-) -> Result<()> {
-    let snapshot: crate::config::ConfigSnapshot = serde_json::from_str(&release.config_json)
-        .unwrap_or(crate::config::ConfigSnapshot {
-            env: Default::default(),
-            port: 0,
-            domains: Vec::new(),
-            addons: Vec::new(),
-            healthcheck: crate::config::HealthcheckConfig::default(),
-            deploy: crate::config::DeployConfig::default(),
-        });
-    let unit_name = app_container_name(&app.name, &release.id);
-    let quadlet_dir = snapshot
-        .deploy
-        .quadlet_dir
-        .clone()
-        .unwrap_or_else(default_quadlet_dir);
-    let unit = format!("{}.service", unit_name);
-    let _ = systemctl_for_dir(&quadlet_dir, &["stop", &unit]);
-    let _ = systemctl_for_dir(&quadlet_dir, &["disable", &unit]);
-    let quadlet_path = std::path::Path::new(&quadlet_dir).join(format!("{}.container", unit_name));
-    let _ = std::fs::remove_file(&quadlet_path);
-    let _ = systemctl_for_dir(&quadlet_dir, &["daemon-reload"]);
-
-    storage.delete_deployments_for_release(&release.id)?;
-    storage.delete_release(&release.id)?;
-    println!("pruned release {} for {}", release.id, app.name);
-    Ok(())
-}
-
 fn print_deploy_plan(
     app_name: &str,
     snapshot: &crate::config::ConfigSnapshot,
@@ -531,6 +686,26 @@ fn print_deploy_plan(
     } else {
         println!("image_digest=would resolve via podman pull");
     }
+    if let Some(secs) = snapshot.deploy.addon_wait_secs
+        && !snapshot.addons.is_empty()
+    {
+        let names: Vec<&str> = snapshot
+            .addons
+            .iter()
+            .map(|addon| addon.name.as_str())
+            .collect();
+        println!(
+            "would wait up to {}s for addons: {}",
+            secs,
+            names.join(", ")
+        );
+    }
+    if snapshot.deploy.private_network {
+        println!(
+            "would attach caddy and bound addons to {}",
+            app_network_name(app_name)
+        );
+    }
     println!("would create quadlet: deep-app-{}-<release_id>", app_name);
     println!("would healthcheck container on port {}", snapshot.port);
     if args.skip_proxy {
@@ -593,16 +768,33 @@ mod tests {
             addons: Vec::new(),
             healthcheck: crate::config::HealthcheckConfig::default(),
             deploy: crate::config::DeployConfig::default(),
+            proxy: crate::config::ProxyConfig::default(),
+            resources: Default::default(),
+            volumes: Vec::new(),
         };
         snapshot.env.insert("FOO".to_string(), "bar".to_string());
         snapshot.healthcheck.command = Some("curl -f http://localhost:4321/health".to_string());
         snapshot.healthcheck.interval_ms = 1500;
         snapshot.healthcheck.timeout_ms = 2500;
         snapshot.healthcheck.retries = 3;
+        snapshot.resources.memory = Some("512m".to_string());
+        snapshot.resources.memory_swap = Some("1g".to_string());
+        snapshot.resources.shm_size = Some("64m".to_string());
+        snapshot.volumes.push(crate::config::VolumeConfig {
+            name: "data".to_string(),
+            path: "/data".to_string(),
+            host_path: None,
+        });
+        snapshot.addons.push(crate::config::AddonSnapshot {
+            name: "pg".to_string(),
+            kind: "postgres".to_string(),
+            config: serde_json::json!({}),
+        });
 
         write_app_quadlet(
+            &Runtime::with_engine(std::sync::Arc::new(crate::runtime::Podman)),
+            &dir.path().join("deep.toml"),
             quadlet_dir.to_string_lossy().as_ref(),
-            "deep-app-app-r1",
             "ghcr.io/me/app:latest",
             &snapshot,
             "app",
@@ -619,6 +811,13 @@ mod tests {
         assert!(contents.contains("HealthInterval=1500ms"));
         assert!(contents.contains("HealthTimeout=2500ms"));
         assert!(contents.contains("HealthRetries=3"));
+        assert!(contents.contains("Memory=512m"));
+        assert!(contents.contains("PodmanArgs=--memory-swap=1g"));
+        assert!(contents.contains("ShmSize=64m"));
+        assert!(contents.contains("Volume=deep-vol-app-data:/data"));
+        let unit_section = contents.split("[Container]").next().unwrap_or_default();
+        assert!(unit_section.contains("After=deep-addon-pg.service"));
+        assert!(unit_section.contains("Requires=deep-addon-pg.service"));
         Ok(())
     }
 }
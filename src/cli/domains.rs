@@ -0,0 +1,226 @@
+//! Domain ownership checks across apps and Caddy blocks.
+
+use anyhow::{Result, bail};
+
+use crate::cli::{current_release_snapshot, route_extras_for_app};
+use crate::db::Storage;
+use crate::proxy::{CaddyFile, render_app_block};
+
+#[derive(Debug, Clone)]
+/// A requested host that overlaps with a host owned by another app.
+pub(crate) struct DomainConflict {
+    pub requested: String,
+    pub owner: String,
+    pub owner_host: String,
+    /// Whether the owner only exists as a Caddy block (no current release).
+    pub block_only: bool,
+}
+
+/// Find hosts owned by other apps (current release or Caddy block) that overlap `domains`.
+pub(crate) fn find_domain_conflicts(
+    storage: &Storage,
+    proxy: &CaddyFile,
+    app_name: &str,
+    domains: &[String],
+) -> Result<Vec<DomainConflict>> {
+    let mut conflicts = Vec::new();
+    let mut seen_owners = Vec::new();
+    for app in storage.list_apps()? {
+        if app.name == app_name {
+            continue;
+        }
+        let Some((_, snapshot)) = current_release_snapshot(storage, &app)? else {
+            continue;
+        };
+        seen_owners.push(app.name.clone());
+        collect_conflicts(&mut conflicts, domains, &app.name, &snapshot.domains, false);
+    }
+    for route in proxy.list_routes()? {
+        if route.app == app_name || seen_owners.contains(&route.app) {
+            continue;
+        }
+        collect_conflicts(&mut conflicts, domains, &route.app, &route.hosts, true);
+    }
+    Ok(conflicts)
+}
+
+/// Fail on conflicting hosts unless `steal` is set; returns the conflicts to move.
+pub(crate) fn check_domain_conflicts(
+    storage: &Storage,
+    proxy: &CaddyFile,
+    app_name: &str,
+    domains: &[String],
+    steal: bool,
+) -> Result<Vec<DomainConflict>> {
+    let conflicts = find_domain_conflicts(storage, proxy, app_name, domains)?;
+    match conflicts.first() {
+        Some(conflict) if !steal => bail!(
+            "domain conflict: {} overlaps {} owned by app {}; pass --steal to move it to {}",
+            conflict.requested,
+            conflict.owner_host,
+            conflict.owner,
+            app_name
+        ),
+        _ => Ok(conflicts),
+    }
+}
+
+/// Write `block` as `app_name`'s route and move conflicting hosts away from their
+/// owners in the same Caddyfile edit, so a failed reload leaves every app routed.
+///
+/// Moved hosts are recorded against the owner instead of editing its release.
+pub(crate) fn route_with_steal(
+    storage: &mut Storage,
+    proxy: &CaddyFile,
+    app_name: &str,
+    block: Option<String>,
+    conflicts: &[DomainConflict],
+) -> Result<()> {
+    let mut upserts: Vec<(String, String)> = block
+        .map(|block| (app_name.to_string(), block))
+        .into_iter()
+        .collect();
+    let mut removals = Vec::new();
+    let mut moved = Vec::new();
+    let mut owners: Vec<&str> = conflicts.iter().map(|c| c.owner.as_str()).collect();
+    owners.sort();
+    owners.dedup();
+    for owner in owners {
+        let stolen: Vec<&DomainConflict> = conflicts.iter().filter(|c| c.owner == owner).collect();
+        let hosts: Vec<String> = stolen.iter().map(|c| c.owner_host.clone()).collect();
+        if stolen.iter().any(|c| c.block_only) {
+            removals.push(owner.to_string());
+            moved.push((owner, None, hosts));
+        } else if let Some(app) = storage.get_app_by_name(owner)?
+            && let Some((release, mut snapshot)) = current_release_snapshot(storage, &app)?
+        {
+            snapshot.domains.retain(|domain| !hosts.contains(domain));
+            if snapshot.domains.is_empty() {
+                removals.push(app.name.clone());
+            } else {
+                let extras = route_extras_for_app(storage, &app.id)?;
+                let block = render_app_block(
+                    &app.name,
+                    &release.id,
+                    &snapshot,
+                    &extras,
+                    proxy.certs_dir(),
+                )?;
+                upserts.push((app.name.clone(), block));
+            }
+            moved.push((owner, Some(app.id), hosts));
+        }
+    }
+    if upserts.is_empty() && removals.is_empty() {
+        return Ok(());
+    }
+    proxy.apply_blocks(&upserts, &removals)?;
+
+    for (owner, app_id, hosts) in moved {
+        match app_id {
+            Some(app_id) => {
+                for host in &hosts {
+                    storage.add_stolen_domain(&app_id, host, app_name)?;
+                }
+                eprintln!(
+                    "warning: moved {} from {} to {}; update {}'s app.toml before its next deploy",
+                    hosts.join(","),
+                    owner,
+                    app_name,
+                    owner
+                );
+            }
+            None => println!(
+                "removed stale Caddy block for {} ({})",
+                owner,
+                hosts.join(",")
+            ),
+        }
+        let payload = serde_json::json!({
+            "from": owner,
+            "to": app_name,
+            "hosts": hosts,
+        });
+        let _ = storage.insert_event("domain_stolen", &payload.to_string());
+    }
+    Ok(())
+}
+
+fn collect_conflicts(
+    conflicts: &mut Vec<DomainConflict>,
+    requested: &[String],
+    owner: &str,
+    owner_hosts: &[String],
+    block_only: bool,
+) {
+    for host in requested {
+        for owner_host in owner_hosts {
+            if hosts_overlap(host, owner_host) {
+                conflicts.push(DomainConflict {
+                    requested: host.clone(),
+                    owner: owner.to_string(),
+                    owner_host: owner_host.clone(),
+                    block_only,
+                });
+            }
+        }
+    }
+}
+
+/// Check whether two site addresses can match the same request host.
+///
+/// Wildcards follow Caddy semantics and match exactly one label.
+pub(crate) fn hosts_overlap(a: &str, b: &str) -> bool {
+    let a = normalize_host(a);
+    let b = normalize_host(b);
+    if a == b {
+        return true;
+    }
+    wildcard_matches(&a, &b) || wildcard_matches(&b, &a)
+}
+
+fn wildcard_matches(pattern: &str, host: &str) -> bool {
+    let Some(suffix) = pattern.strip_prefix('*') else {
+        return false;
+    };
+    if let Some(other_suffix) = host.strip_prefix('*') {
+        return other_suffix == suffix;
+    }
+    match host.strip_suffix(suffix) {
+        Some(label) => !label.is_empty() && !label.contains('.'),
+        None => false,
+    }
+}
+
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host
+        .trim()
+        .trim_start_matches("http://")
+        .trim_start_matches("https://");
+    let host = host.split('/').next().unwrap_or(host);
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_handles_exact_wildcard_and_scheme() {
+        assert!(hosts_overlap("api.example.com", "API.example.com"));
+        assert!(hosts_overlap(
+            "http://api.example.com",
+            "api.example.com:443"
+        ));
+        assert!(hosts_overlap("*.example.com", "api.example.com"));
+        assert!(hosts_overlap("api.example.com", "*.example.com"));
+        assert!(hosts_overlap("*.example.com", "*.example.com"));
+        assert!(!hosts_overlap("*.example.com", "example.com"));
+        assert!(!hosts_overlap("*.example.com", "a.b.example.com"));
+        assert!(!hosts_overlap("api.example.com", "www.example.com"));
+    }
+}
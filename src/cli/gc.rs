@@ -0,0 +1,465 @@
+//! Disk reclamation: images no retained release uses, stopped app containers,
+//! dangling app volumes, unit files of releases that no longer exist and the
+//! private networks of removed apps.
+
+use anyhow::Result;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::cli::deploy::detach_from_network;
+use crate::config::ConfigSnapshot;
+use crate::db::{AppRow, ReleaseRow, Storage};
+use crate::runtime::{ContainerInfo, Runtime, app_container_name, app_network_name, format_bytes};
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+use crate::units::{remove_unit, unit_names, unit_path};
+
+const APP_PREFIX: &str = "deep-app-";
+
+#[derive(Debug, Clone, PartialEq)]
+/// Something `deep host gc` can remove.
+pub(crate) enum Garbage {
+    Container(String),
+    Image(String),
+    Volume(String),
+    Unit {
+        dir: String,
+        name: String,
+    },
+    /// A removed app's network and the containers still attached to it.
+    Network {
+        name: String,
+        attached: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A removable item and the bytes it frees, when known.
+pub(crate) struct GcItem {
+    pub garbage: Garbage,
+    pub size: Option<u64>,
+}
+
+impl GcItem {
+    pub(crate) fn columns(&self, action: &str) -> Vec<String> {
+        let (kind, name) = match &self.garbage {
+            Garbage::Container(name) => ("container", name.clone()),
+            Garbage::Image(reference) => ("image", reference.clone()),
+            Garbage::Volume(name) => ("volume", name.clone()),
+            Garbage::Unit { dir, name } => ("unit", format!("{}/{}", dir, name)),
+            Garbage::Network { name, .. } => ("network", name.clone()),
+        };
+        let size = self
+            .size
+            .map(format_bytes)
+            .unwrap_or_else(|| "-".to_string());
+        vec![kind.to_string(), name, size, action.to_string()]
+    }
+}
+
+/// Image references (tags and digests) of every release still on record.
+pub(crate) fn retained_images(storage: &Storage) -> Result<HashSet<String>> {
+    let mut images = HashSet::new();
+    for release in all_releases(storage)? {
+        images.insert(release.image_ref);
+        if !release.image_digest.is_empty() {
+            images.insert(release.image_digest);
+        }
+    }
+    Ok(images)
+}
+
+/// Remove the images of pruned releases that no retained release of any app uses.
+///
+/// Images still used by a container are kept; the engine refuses to remove them.
+pub(crate) fn remove_pruned_images(
+    storage: &Storage,
+    runtime: &Runtime,
+    pruned: &[ReleaseRow],
+) -> Result<()> {
+    let retained = retained_images(storage)?;
+    let mut seen = HashSet::new();
+    for release in pruned {
+        let reference = release_image(release);
+        if reference.is_empty() || retained.contains(reference) || !seen.insert(reference) {
+            continue;
+        }
+        match runtime.remove_image(reference) {
+            Ok(()) => println!("removed image {}", reference),
+            Err(err) => eprintln!("warning: kept image {}: {}", reference, err),
+        }
+    }
+    Ok(())
+}
+
+/// Directories that may hold app units: the default one and any a release set.
+pub(crate) fn unit_dirs(storage: &Storage) -> Result<Vec<String>> {
+    let mut dirs = BTreeSet::from([default_quadlet_dir()]);
+    for release in all_releases(storage)? {
+        if let Some(dir) =
+            release_snapshot(&release).and_then(|snapshot| snapshot.deploy.quadlet_dir)
+        {
+            dirs.insert(dir);
+        }
+    }
+    Ok(dirs.into_iter().collect())
+}
+
+/// Everything that can go, in the order it has to be removed.
+pub(crate) fn find_garbage(
+    storage: &Storage,
+    runtime: &Runtime,
+    unit_dirs: &[String],
+) -> Result<Vec<GcItem>> {
+    let apps = storage.list_apps()?;
+    let mut current = HashSet::new();
+    let mut known_units = HashSet::new();
+    let mut declared_volumes = HashSet::new();
+    for app in &apps {
+        if let Some(release_id) = storage.current_release_id(&app.id)? {
+            current.insert(app_container_name(&app.name, &release_id));
+        }
+        for release in storage.list_releases(&app.id)? {
+            known_units.insert(app_container_name(&app.name, &release.id));
+            let volumes = release_snapshot(&release)
+                .map(|snapshot| snapshot.volumes)
+                .unwrap_or_default();
+            for volume in volumes.iter().filter(|volume| volume.is_named()) {
+                declared_volumes.insert(volume.volume_name(&app.name));
+            }
+        }
+    }
+    let mut items = Vec::new();
+    let containers = runtime.list_containers()?;
+    let networks = orphan_networks(runtime, &apps, &containers)?;
+    let local_images = runtime.list_images()?;
+    let app_repositories: Vec<String> = local_images
+        .iter()
+        .filter(|image| {
+            !image.repository.is_empty()
+                && containers.iter().any(|container| {
+                    container.name.starts_with(APP_PREFIX) && container.uses(image)
+                })
+        })
+        .map(|image| image.repository.clone())
+        .collect();
+
+    // A stopped current release keeps its logs for debugging.
+    let (stopped, in_use): (Vec<ContainerInfo>, Vec<ContainerInfo>) =
+        containers.into_iter().partition(|container| {
+            container.name.starts_with(APP_PREFIX)
+                && container.is_stopped()
+                && !current.contains(&container.name)
+        });
+    for container in stopped {
+        items.push(GcItem {
+            size: runtime.container_size(&container.name),
+            garbage: Garbage::Container(container.name),
+        });
+    }
+
+    // Candidates: repositories of retained releases or of images any deep-app-*
+    // container ran, plus images the git hook labeled with `deep.app`.
+    let retained = retained_images(storage)?;
+    let mut repositories: HashSet<String> = retained
+        .iter()
+        .map(|reference| image_repository(reference).to_string())
+        .collect();
+    repositories.extend(app_repositories);
+    let labeled: HashSet<String> = runtime
+        .labeled_images("deep.app")?
+        .into_iter()
+        .map(|image| image.id)
+        .collect();
+    let mut images = BTreeSet::new();
+    for image in local_images {
+        let referenced = [image.tagged_ref(), image.digest_ref()]
+            .iter()
+            .flatten()
+            .any(|reference| retained.contains(reference));
+        let candidate = repositories.contains(&image.repository) || labeled.contains(&image.id);
+        if !candidate || referenced || in_use.iter().any(|container| container.uses(&image)) {
+            continue;
+        }
+        images.insert(image.reference());
+    }
+    for reference in images {
+        items.push(GcItem {
+            size: runtime.image_size(&reference),
+            garbage: Garbage::Image(reference),
+        });
+    }
+
+    for volume in runtime.dangling_volumes()? {
+        if declared_volumes.contains(&volume) {
+            continue;
+        }
+        items.push(GcItem {
+            size: runtime.volume_size(&volume),
+            garbage: Garbage::Volume(volume),
+        });
+    }
+
+    for dir in unit_dirs {
+        for name in unit_names(runtime, dir, APP_PREFIX)? {
+            if known_units.contains(&name) {
+                continue;
+            }
+            let size = std::fs::metadata(unit_path(runtime, dir, &name))
+                .ok()
+                .map(|metadata| metadata.len());
+            items.push(GcItem {
+                garbage: Garbage::Unit {
+                    dir: dir.clone(),
+                    name,
+                },
+                size,
+            });
+        }
+    }
+
+    // Last: the engine refuses to remove a network while a container uses it.
+    items.extend(networks.into_iter().map(|garbage| GcItem {
+        garbage,
+        size: None,
+    }));
+    Ok(items)
+}
+
+/// Labeled app networks whose app no longer exists.
+fn orphan_networks(
+    runtime: &Runtime,
+    apps: &[AppRow],
+    containers: &[ContainerInfo],
+) -> Result<Vec<Garbage>> {
+    let known: HashSet<String> = apps.iter().map(|app| app_network_name(&app.name)).collect();
+    let mut networks = Vec::new();
+    for name in runtime.app_networks()? {
+        if known.contains(&name) {
+            continue;
+        }
+        let attached = containers
+            .iter()
+            .filter(|container| {
+                runtime
+                    .container_networks(&container.name)
+                    .is_ok_and(|networks| networks.contains(&name))
+            })
+            .map(|container| container.name.clone())
+            .collect();
+        networks.push(Garbage::Network { name, attached });
+    }
+    Ok(networks)
+}
+
+/// Remove one item; units are stopped and disabled first.
+pub(crate) fn remove_garbage(runtime: &Runtime, garbage: &Garbage) -> Result<()> {
+    match garbage {
+        Garbage::Container(name) => runtime.remove_container(name),
+        Garbage::Image(reference) => runtime.remove_image(reference),
+        Garbage::Volume(name) => runtime.remove_volume(name),
+        Garbage::Unit { dir, name } => {
+            let unit = format!("{}.service", name);
+            let _ = systemctl_for_dir(dir, &["stop", &unit]);
+            let _ = systemctl_for_dir(dir, &["disable", &unit]);
+            remove_unit(runtime, dir, name)?;
+            systemctl_for_dir(dir, &["daemon-reload"])
+        }
+        Garbage::Network { name, attached } => {
+            for container in attached {
+                detach_from_network(runtime, name, container)?;
+            }
+            runtime.remove_network(name)
+        }
+    }
+}
+
+fn all_releases(storage: &Storage) -> Result<Vec<ReleaseRow>> {
+    let mut releases = Vec::new();
+    for app in storage.list_apps()? {
+        releases.extend(storage.list_releases(&app.id)?);
+    }
+    Ok(releases)
+}
+
+fn release_snapshot(release: &ReleaseRow) -> Option<ConfigSnapshot> {
+    serde_json::from_str(&release.config_json).ok()
+}
+
+/// The image a release ran: its digest when it was pulled, else its reference.
+fn release_image(release: &ReleaseRow) -> &str {
+    if release.image_digest.contains('@') {
+        &release.image_digest
+    } else {
+        &release.image_ref
+    }
+}
+
+/// Repository part of `repo:tag` or `repo@digest`.
+fn image_repository(reference: &str) -> &str {
+    let reference = reference.split('@').next().unwrap_or(reference);
+    match reference.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => reference,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeployConfig, VolumeConfig};
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, insert_release, release_row, test_podman,
+    };
+    use std::sync::Arc;
+
+    /// Engine listings for one app `web` (releases r0-r2), an addon and a removed app `old`.
+    fn engine_runner() -> Arc<TestRunner> {
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(
+            &["ps -a"],
+            0,
+            "deep-app-web-r1\tghcr.io/me/web:r1\texited\n\
+             deep-app-web-r2\tghcr.io/me/web@sha256:bbb\texited\n\
+             deep-addon-pg\tpostgres:16\trunning\n\
+             deep-app-old-r9\tghcr.io/me/old:v2\trunning\n",
+            "",
+        );
+        runner.add_rule(
+            &["image ls --filter"],
+            0,
+            "eee555eee555\tlocal/api\tsha1\t<none>\n",
+            "",
+        );
+        runner.add_rule(
+            &["image ls"],
+            0,
+            "aaa111aaa111\tghcr.io/me/web\tr1\tsha256:aaa\n\
+             bbb222bbb222\tghcr.io/me/web\t<none>\tsha256:bbb\n\
+             ccc333ccc333\tghcr.io/me/web\tr0\t<none>\n\
+             ddd444ddd444\tpostgres\t16\tsha256:ddd\n\
+             eee555eee555\tlocal/api\tsha1\t<none>\n\
+             fff666fff666\tghcr.io/me/old\tv1\t<none>\n\
+             aaa777aaa777\tghcr.io/me/old\tv2\t<none>\n\
+             bbb888bbb888\tnginx\tlatest\t<none>\n",
+            "",
+        );
+        runner.add_rule(
+            &["volume ls"],
+            0,
+            "deep-vol-web-data\ndeep-vol-old-data\n",
+            "",
+        );
+        runner.add_rule(&["container inspect"], 0, "4096\n", "");
+        runner.add_rule(
+            &["network ls"],
+            0,
+            "deep-app-web-net\ndeep-app-gone-net\n",
+            "",
+        );
+        runner.add_rule(
+            &["inspect --format {{.State.Running}} deep-addon-pg"],
+            0,
+            "true\n",
+            "",
+        );
+        runner.add_rule(
+            &["inspect --format", "deep-addon-pg"],
+            0,
+            "deep-net deep-app-gone-net \n",
+            "",
+        );
+        runner.add_rule(&["image inspect"], 0, "52428800\n", "");
+        runner
+    }
+
+    #[test]
+    fn gc_finds_unreferenced_images_stopped_containers_volumes_and_units() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = engine_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let runtime = test_podman();
+        let quadlet_dir = temp.path().join("quadlets");
+        std::fs::create_dir_all(&quadlet_dir)?;
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let app = storage.create_app("web", "/srv/web")?;
+        let snapshot = ConfigSnapshot {
+            deploy: DeployConfig {
+                quadlet_dir: Some(quadlet_dir.to_string_lossy().to_string()),
+                ..DeployConfig::default()
+            },
+            volumes: vec![VolumeConfig {
+                name: "data".to_string(),
+                path: "/data".to_string(),
+                host_path: None,
+            }],
+            ..base_snapshot()
+        };
+        let r1 = ReleaseRow {
+            image_ref: "ghcr.io/me/web:r1".to_string(),
+            image_digest: String::new(),
+            ..release_row(&app.id, "r1", &snapshot)
+        };
+        let r2 = ReleaseRow {
+            image_ref: "ghcr.io/me/web:r2".to_string(),
+            image_digest: "ghcr.io/me/web@sha256:bbb".to_string(),
+            ..release_row(&app.id, "r2", &snapshot)
+        };
+        insert_release(&mut storage, &r1)?;
+        insert_current_release(&mut storage, &r2)?;
+        std::fs::write(
+            quadlet_dir.join("deep-app-web-r2.container"),
+            "[Container]\n",
+        )?;
+        std::fs::write(
+            quadlet_dir.join("deep-app-web-r0.container"),
+            "[Container]\n",
+        )?;
+
+        // r1 is pruned: its tag goes unless another retained release uses it.
+        storage.delete_release("r1")?;
+        remove_pruned_images(&storage, &runtime, &[r1])?;
+        let dirs = vec![quadlet_dir.to_string_lossy().to_string()];
+        let items = find_garbage(&storage, &runtime, &dirs)?;
+        let rows: Vec<Vec<String>> = items.iter().map(|item| item.columns("-")).collect();
+        let unit = format!("{}/deep-app-web-r0", quadlet_dir.display());
+        assert_eq!(
+            rows,
+            [
+                ["container", "deep-app-web-r1", "4.0KiB", "-"],
+                ["image", "ghcr.io/me/old:v1", "50.0MiB", "-"],
+                ["image", "ghcr.io/me/web:r0", "50.0MiB", "-"],
+                ["image", "ghcr.io/me/web:r1", "50.0MiB", "-"],
+                ["image", "local/api:sha1", "50.0MiB", "-"],
+                ["volume", "deep-vol-old-data", "-", "-"],
+                ["unit", unit.as_str(), "12B", "-"],
+                ["network", "deep-app-gone-net", "-", "-"],
+            ]
+        );
+
+        for item in &items {
+            remove_garbage(&runtime, &item.garbage)?;
+        }
+        assert!(!quadlet_dir.join("deep-app-web-r0.container").exists());
+        assert!(quadlet_dir.join("deep-app-web-r2.container").exists());
+        let calls = runner.calls();
+        for expected in [
+            "podman rmi ghcr.io/me/web:r1",
+            "podman rm deep-app-web-r1",
+            "podman rmi ghcr.io/me/web:r0",
+            "podman volume rm deep-vol-old-data",
+            "podman network disconnect deep-app-gone-net deep-addon-pg",
+            "podman network rm deep-app-gone-net",
+        ] {
+            assert!(calls.iter().any(|call| call == expected), "{}", expected);
+        }
+        assert_eq!(
+            image_repository("registry:5000/me/web:r1"),
+            "registry:5000/me/web"
+        );
+        assert_eq!(
+            image_repository("registry:5000/me/web"),
+            "registry:5000/me/web"
+        );
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use std::path::{Path, PathBuf};
 
 use crate::config::load_app_config;
 use crate::db::Storage;
+use crate::registry::DEFAULT_AUTH_FILE;
+use crate::runtime::Runtime;
 
 #[derive(Subcommand, Debug)]
 /// Git hook maintenance commands.
@@ -42,7 +44,7 @@ pub enum GitCommand {
 }
 
 /// Handle git hook related commands.
-pub fn handle(storage: &mut Storage, command: GitCommand) -> Result<()> {
+pub fn handle(storage: &mut Storage, runtime: &Runtime, command: GitCommand) -> Result<()> {
     match command {
         GitCommand::UpdateHook {
             app,
@@ -51,24 +53,26 @@ pub fn handle(storage: &mut Storage, command: GitCommand) -> Result<()> {
             image_template,
             dockerfile,
             deep_bin,
-        } => handle_update_hook(
-            storage,
-            &app,
-            repos_dir,
-            repo_path,
-            image_template,
-            &dockerfile,
-            &deep_bin,
-        ),
+        } => {
+            let repo_path = hook_repo_path(storage, &app, repos_dir, repo_path)?;
+            handle_update_hook(
+                runtime,
+                &repo_path,
+                &app,
+                image_template,
+                &dockerfile,
+                &deep_bin,
+            )
+        }
     }
 }
 
 /// Initialize a bare repo and install the post-receive hook for an app.
 pub fn init_repo_for_app(
     storage: &mut Storage,
+    runtime: &Runtime,
     app: &str,
-    repos_dir: PathBuf,
-    repo_path: Option<PathBuf>,
+    repo_path: PathBuf,
     image_template: Option<String>,
     dockerfile: &str,
     deep_bin: &str,
@@ -77,7 +81,6 @@ pub fn init_repo_for_app(
         .get_app_by_name(app)?
         .with_context(|| format!("app {} not found; create it first", app))?;
     let image_template = image_template.or_else(|| load_image_template(&app_row.repo_path, app));
-    let repo_path = repo_path.unwrap_or_else(|| repos_dir.join(format!("{}.git", app)));
     if let Some(parent) = repo_path.parent() {
         std::fs::create_dir_all(parent)
             .with_context(|| format!("failed to create {}", parent.display()))?;
@@ -85,6 +88,7 @@ pub fn init_repo_for_app(
 
     init_bare_repo(&repo_path)?;
     write_post_receive(
+        runtime,
         &repo_path,
         app,
         image_template.as_deref(),
@@ -104,6 +108,7 @@ fn init_bare_repo(path: &Path) -> Result<()> {
 }
 
 fn write_post_receive(
+    runtime: &Runtime,
     repo_path: &Path,
     app: &str,
     image_template: Option<&str>,
@@ -114,14 +119,27 @@ fn write_post_receive(
     std::fs::create_dir_all(&hook_dir)?;
     let hook_path = hook_dir.join("post-receive");
     let image_template = image_template.unwrap_or("ghcr.io/me/{{app}}:{{sha}}");
+    // The hook builds with the engine deep deploys with, so the image is in its store.
+    let program = runtime.program();
+    // Podman and Docker read these for the base image pull during the build.
+    let auth_dir = Path::new(DEFAULT_AUTH_FILE)
+        .parent()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
     let build_block = format!(
         r#"
 tmpdir=$(mktemp -d)
 trap 'rm -rf "$tmpdir"' EXIT
 git --work-tree "$tmpdir" checkout -f "$newrev"
-podman build -t "$image" -f "{dockerfile}" "$tmpdir"
+if [ -f "{authfile}" ]; then
+  export REGISTRY_AUTH_FILE="{authfile}" DOCKER_CONFIG="{authdir}"
+fi
+{program} build --label "deep.app=$app" -t "$image" -f "{dockerfile}" "$tmpdir"
 "#,
-        dockerfile = dockerfile
+        program = program,
+        dockerfile = dockerfile,
+        authfile = DEFAULT_AUTH_FILE,
+        authdir = auth_dir,
     );
 
     let script = format!(
@@ -151,14 +169,32 @@ image=$(printf "%s" "$image_template" | sed "s/{{{{app}}}}/$app/g" | sed "s/{{{{
 }
 
 fn handle_update_hook(
-    storage: &mut Storage,
+    runtime: &Runtime,
+    repo_path: &Path,
     app: &str,
-    repos_dir: PathBuf,
-    repo_path: Option<PathBuf>,
     image_template: Option<String>,
     dockerfile: &str,
     deep_bin: &str,
 ) -> Result<()> {
+    write_post_receive(
+        runtime,
+        repo_path,
+        app,
+        image_template.as_deref(),
+        dockerfile,
+        deep_bin,
+    )?;
+    println!("updated hook for {}", repo_path.display());
+    Ok(())
+}
+
+/// The app's existing repo: `--repo-path`, else the recorded path, else `<repos_dir>/<app>.git`.
+fn hook_repo_path(
+    storage: &mut Storage,
+    app: &str,
+    repos_dir: PathBuf,
+    repo_path: Option<PathBuf>,
+) -> Result<PathBuf> {
     let app_row = storage
         .get_app_by_name(app)?
         .with_context(|| format!("app {} not found; create it first", app))?;
@@ -172,15 +208,7 @@ fn handle_update_hook(
     if !repo_path.exists() {
         bail!("repo path {} does not exist", repo_path.display());
     }
-    write_post_receive(
-        &repo_path,
-        app,
-        image_template.as_deref(),
-        dockerfile,
-        deep_bin,
-    )?;
-    println!("updated hook for {}", repo_path.display());
-    Ok(())
+    Ok(repo_path)
 }
 
 fn load_image_template(repo_path: &str, app: &str) -> Option<String> {
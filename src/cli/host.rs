@@ -1,11 +1,15 @@
 use anyhow::{Context, Result, bail};
 use clap::Subcommand;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::cli::gc::{Garbage, find_garbage, remove_garbage, unit_dirs};
+use crate::cli::print_table;
+use crate::config::load_host_config;
 use crate::db::Storage;
 use crate::proxy::CaddyFile;
-use crate::runtime::Runtime;
+use crate::runtime::{NETWORK_NAME, Runtime, format_bytes, network_lines};
 use crate::systemd::{systemctl_active_any, systemctl_any, systemctl_for_dir};
+use crate::units::{unit_path, write_unit};
 
 #[derive(Subcommand, Debug)]
 /// Host management commands.
@@ -91,6 +95,19 @@ pub enum HostCommand {
             help = "Caddy config directory"
         )]
         config_dir: PathBuf,
+        #[arg(
+            short = 'T',
+            long,
+            help = "Custom TLS certificates directory (default: [caddy] certs_dir in deep.toml)"
+        )]
+        certs_dir: Option<PathBuf>,
+        #[arg(
+            short = 'L',
+            long,
+            default_value = "/srv/deep/caddy/logs",
+            help = "Access log directory"
+        )]
+        logs_dir: PathBuf,
         #[arg(
             short = 'q',
             long,
@@ -129,10 +146,43 @@ pub enum HostCommand {
         )]
         name: String,
     },
+    /// Remove unused images, stopped app containers, dangling volumes and orphan units
+    Gc {
+        #[arg(
+            short = 'D',
+            long,
+            help = "Report what would be removed without removing it"
+        )]
+        dry_run: bool,
+        #[arg(
+            short = 'P',
+            long,
+            help = "Also remove dangling app volumes (deletes their data)"
+        )]
+        purge_volumes: bool,
+    },
+    /// Forward journald entries of deep units to the [logging.ship] sink in the host config
+    #[command(alias = "lg")]
+    Logship {
+        #[arg(short = 'o', long, help = "Ship pending entries once and exit")]
+        once: bool,
+        #[arg(
+            short = 's',
+            long,
+            help = "Read the system journal instead of the user journal"
+        )]
+        system: bool,
+    },
 }
 
 /// Handle host subcommands.
-pub fn handle(storage: &mut Storage, proxy: &CaddyFile, command: HostCommand) -> Result<()> {
+pub fn handle(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    host_config: &Path,
+    command: HostCommand,
+) -> Result<()> {
     match command {
         HostCommand::Init {
             data_dir,
@@ -150,7 +200,7 @@ pub fn handle(storage: &mut Storage, proxy: &CaddyFile, command: HostCommand) ->
             skip_caddy_check,
             dry_run,
         } => handle_init(
-            storage,
+            runtime,
             proxy,
             data_dir,
             repos_dir,
@@ -167,35 +217,101 @@ pub fn handle(storage: &mut Storage, proxy: &CaddyFile, command: HostCommand) ->
             skip_caddy_check,
             dry_run,
         ),
-        HostCommand::Status => handle_status(storage, proxy),
+        HostCommand::Status => handle_status(storage, runtime, proxy),
         HostCommand::StartCaddy {
             image,
             name,
             data_dir,
             config_dir,
+            certs_dir,
+            logs_dir,
             quadlet_dir,
             http_port,
             https_port,
             system,
             user,
-        } => handle_caddy_start(
-            data_dir,
-            config_dir,
-            quadlet_dir,
-            image,
-            name,
-            http_port,
-            https_port,
-            system,
-            user,
-        ),
+        } => {
+            let quadlet_dir = if quadlet_dir.as_os_str().is_empty() {
+                select_quadlet_dir(system, user, http_port, https_port)?
+            } else {
+                quadlet_dir
+            };
+            let certs_dir = certs_dir.unwrap_or_else(|| proxy.certs_dir().to_path_buf());
+            warn_certs_dir_mismatch(&certs_dir, proxy);
+            handle_caddy_start(
+                runtime,
+                CaddyDirs {
+                    data: data_dir,
+                    config: config_dir,
+                    certs: certs_dir,
+                    logs: logs_dir,
+                },
+                quadlet_dir,
+                image,
+                name,
+                http_port,
+                https_port,
+            )
+        }
         HostCommand::StopCaddy { name } => handle_caddy_stop(name),
         HostCommand::RestartCaddy { name } => handle_caddy_restart(name),
+        HostCommand::Gc {
+            dry_run,
+            purge_volumes,
+        } => handle_gc(storage, runtime, dry_run, purge_volumes),
+        HostCommand::Logship { once, system } => handle_logship(host_config, once, system),
+    }
+}
+
+fn handle_logship(host_config: &Path, once: bool, system: bool) -> Result<()> {
+    let ship = load_host_config(host_config)?
+        .logging
+        .ship
+        .with_context(|| format!("no [logging.ship] section in {}", host_config.display()))?;
+    loop {
+        match crate::logship::ship_once(&ship, !system) {
+            Ok(count) if count > 0 => println!("shipped {} entries", count),
+            Ok(_) => {}
+            Err(err) if !once => eprintln!("warning: log shipping failed: {}", err),
+            Err(err) => return Err(err),
+        }
+        if once {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_secs(ship.interval_secs.max(1)));
+    }
+}
+
+/// Host directories mounted into the Caddy container.
+struct CaddyDirs {
+    data: PathBuf,
+    config: PathBuf,
+    certs: PathBuf,
+    logs: PathBuf,
+}
+
+impl CaddyDirs {
+    fn under(data_dir: &std::path::Path) -> Self {
+        let caddy_dir = data_dir.join("caddy");
+        Self {
+            data: caddy_dir.join("data"),
+            config: caddy_dir.join("config"),
+            certs: caddy_dir.join("certs"),
+            logs: caddy_dir.join("logs"),
+        }
+    }
+
+    fn create_all(&self) -> Result<()> {
+        for dir in [&self.data, &self.config, &self.certs, &self.logs] {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        Ok(())
     }
 }
 
 fn handle_init(
-    _storage: &mut Storage,
+    runtime: &Runtime,
     proxy: &CaddyFile,
     data_dir: PathBuf,
     repos_dir: Option<PathBuf>,
@@ -214,8 +330,8 @@ fn handle_init(
 ) -> Result<()> {
     let repos_dir = repos_dir.unwrap_or_else(|| data_dir.join("repos"));
     let db_path = db.unwrap_or_else(|| data_dir.join("deep.db"));
-    let caddy_data_dir = data_dir.join("caddy").join("data");
-    let caddy_config_dir = data_dir.join("caddy").join("config");
+    let caddy_dirs = CaddyDirs::under(&data_dir);
+    warn_certs_dir_mismatch(&caddy_dirs.certs, proxy);
     let quadlet_dir = if skip_caddy_quadlet {
         None
     } else {
@@ -224,6 +340,7 @@ fn handle_init(
 
     if dry_run {
         print_host_init_plan(
+            runtime,
             &data_dir,
             &repos_dir,
             &db_path,
@@ -236,7 +353,7 @@ fn handle_init(
             skip_caddy_start,
             skip_network,
             skip_caddy_check,
-        );
+        )?;
         return Ok(());
     }
 
@@ -254,24 +371,20 @@ fn handle_init(
     }
 
     if !skip_network {
-        let runtime = Runtime::detect()?;
         runtime.ensure_deep_network()?;
     }
 
     if !skip_caddy_quadlet {
         let quadlet_dir = quadlet_dir.expect("quadlet_dir set when not skipping");
-        std::fs::create_dir_all(&caddy_data_dir)
-            .with_context(|| format!("failed to create {}", caddy_data_dir.display()))?;
-        std::fs::create_dir_all(&caddy_config_dir)
-            .with_context(|| format!("failed to create {}", caddy_config_dir.display()))?;
+        caddy_dirs.create_all()?;
         std::fs::create_dir_all(&quadlet_dir)
             .with_context(|| format!("failed to create {}", quadlet_dir.display()))?;
         write_caddy_quadlet(
+            runtime,
             &quadlet_dir,
             &caddy_name,
             &caddy_image,
-            &caddy_data_dir,
-            &caddy_config_dir,
+            &caddy_dirs,
             http_port,
             https_port,
         )?;
@@ -298,9 +411,8 @@ fn handle_init(
     Ok(())
 }
 
-fn handle_status(storage: &mut Storage, proxy: &CaddyFile) -> Result<()> {
+fn handle_status(storage: &mut Storage, runtime: &Runtime, proxy: &CaddyFile) -> Result<()> {
     let db_ok = storage.ping().is_ok();
-    let runtime = Runtime::detect()?;
     let net_ok = runtime.deep_network_exists();
     let caddy_ok = proxy.list_routes().is_ok() && systemctl_active_any(proxy.container_name())?;
 
@@ -320,34 +432,75 @@ fn handle_status(storage: &mut Storage, proxy: &CaddyFile) -> Result<()> {
     Ok(())
 }
 
+fn handle_gc(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    dry_run: bool,
+    purge_volumes: bool,
+) -> Result<()> {
+    let items = find_garbage(storage, runtime, &unit_dirs(storage)?)?;
+    if items.is_empty() {
+        println!("nothing to reclaim");
+        return Ok(());
+    }
+    let mut freed = 0;
+    let mut rows = Vec::new();
+    for item in &items {
+        // Volumes hold app data; they only go when asked for explicitly.
+        let action = if matches!(item.garbage, Garbage::Volume(_)) && !purge_volumes {
+            "kept (--purge-volumes)".to_string()
+        } else if dry_run {
+            "would remove".to_string()
+        } else {
+            match remove_garbage(runtime, &item.garbage) {
+                Ok(()) => "removed".to_string(),
+                Err(err) => format!("error: {:#}", err),
+            }
+        };
+        if action == "would remove" || action == "removed" {
+            freed += item.size.unwrap_or(0);
+        }
+        rows.push(item.columns(&action));
+    }
+    print_table(&["KIND", "NAME", "SIZE", "ACTION"], &rows);
+    if dry_run {
+        println!("reclaimable: {}", format_bytes(freed));
+    } else {
+        println!("reclaimed: {}", format_bytes(freed));
+    }
+    Ok(())
+}
+
+/// Custom TLS paths are rendered relative to the configured certs dir, so it must match the mount.
+fn warn_certs_dir_mismatch(mounted: &Path, proxy: &CaddyFile) {
+    if mounted != proxy.certs_dir() {
+        eprintln!(
+            "warning: Caddy mounts {} at /certs but TLS paths resolve against {}; set [caddy] certs_dir = \"{}\" in deep.toml",
+            mounted.display(),
+            proxy.certs_dir().display(),
+            mounted.display()
+        );
+    }
+}
+
 fn handle_caddy_start(
-    data_dir: PathBuf,
-    config_dir: PathBuf,
+    runtime: &Runtime,
+    dirs: CaddyDirs,
     quadlet_dir: PathBuf,
     image: String,
     name: String,
     http_port: u16,
     https_port: u16,
-    system: bool,
-    user: bool,
 ) -> Result<()> {
-    std::fs::create_dir_all(&data_dir)
-        .with_context(|| format!("failed to create {}", data_dir.display()))?;
-    std::fs::create_dir_all(&config_dir)
-        .with_context(|| format!("failed to create {}", config_dir.display()))?;
-    let quadlet_dir = if quadlet_dir.as_os_str().is_empty() {
-        select_quadlet_dir(system, user, http_port, https_port)?
-    } else {
-        quadlet_dir
-    };
+    dirs.create_all()?;
     std::fs::create_dir_all(&quadlet_dir)
         .with_context(|| format!("failed to create {}", quadlet_dir.display()))?;
     write_caddy_quadlet(
+        runtime,
         &quadlet_dir,
         &name,
         &image,
-        &data_dir,
-        &config_dir,
+        &dirs,
         http_port,
         https_port,
     )?;
@@ -373,24 +526,34 @@ fn handle_caddy_restart(name: String) -> Result<()> {
 }
 
 fn write_caddy_quadlet(
+    runtime: &Runtime,
     quadlet_dir: &PathBuf,
     name: &str,
     image: &str,
-    data_dir: &PathBuf,
-    config_dir: &PathBuf,
+    dirs: &CaddyDirs,
     http_port: u16,
     https_port: u16,
 ) -> Result<()> {
-    let quadlet_path = quadlet_dir.join(format!("{}.container", name));
+    // Caddy proxies to private apps too, so it joins every app network that exists.
+    let mut networks = vec![NETWORK_NAME.to_string()];
+    networks.extend(runtime.app_networks()?);
     let template = include_str!("../../templates/caddy.container");
     let contents = template
         .replace("{{image}}", image)
+        .replace("{{networks}}", &network_lines(&networks))
         .replace("{{name}}", name)
         .replace("{{http_port}}", &http_port.to_string())
         .replace("{{https_port}}", &https_port.to_string())
-        .replace("{{data_dir}}", data_dir.to_string_lossy().as_ref())
-        .replace("{{config_dir}}", config_dir.to_string_lossy().as_ref());
-    std::fs::write(&quadlet_path, contents)?;
+        .replace("{{data_dir}}", dirs.data.to_string_lossy().as_ref())
+        .replace("{{config_dir}}", dirs.config.to_string_lossy().as_ref())
+        .replace("{{certs_dir}}", dirs.certs.to_string_lossy().as_ref())
+        .replace("{{logs_dir}}", dirs.logs.to_string_lossy().as_ref());
+    write_unit(
+        runtime,
+        quadlet_dir.to_string_lossy().as_ref(),
+        name,
+        &contents,
+    )?;
     Ok(())
 }
 
@@ -450,6 +613,7 @@ fn user_can_bind_low_ports(port: u16) -> bool {
 }
 
 fn print_host_init_plan(
+    runtime: &Runtime,
     data_dir: &PathBuf,
     repos_dir: &PathBuf,
     db_path: &PathBuf,
@@ -462,9 +626,8 @@ fn print_host_init_plan(
     skip_caddy_start: bool,
     skip_network: bool,
     skip_caddy_check: bool,
-) {
-    let caddy_data_dir = data_dir.join("caddy").join("data");
-    let caddy_config_dir = data_dir.join("caddy").join("config");
+) -> Result<()> {
+    let caddy_dirs = CaddyDirs::under(data_dir);
     println!("dry-run: host init");
     println!("data_dir={}", data_dir.display());
     println!("repos_dir={}", repos_dir.display());
@@ -472,7 +635,7 @@ fn print_host_init_plan(
     if skip_network {
         println!("would skip network creation");
     } else {
-        println!("would ensure podman network deep-net");
+        println!("would ensure {} network deep-net", runtime.program());
     }
     if skip_caddy_quadlet {
         println!("would skip caddy quadlet creation");
@@ -481,15 +644,15 @@ fn print_host_init_plan(
         println!("caddy_name={}", caddy_name);
         println!("caddy_image={}", caddy_image);
         println!("caddy_ports={}/{}", http_port, https_port);
-        println!("caddy_data_dir={}", caddy_data_dir.display());
-        println!("caddy_config_dir={}", caddy_config_dir.display());
+        println!("caddy_data_dir={}", caddy_dirs.data.display());
+        println!("caddy_config_dir={}", caddy_dirs.config.display());
+        println!("caddy_certs_dir={}", caddy_dirs.certs.display());
+        println!("caddy_logs_dir={}", caddy_dirs.logs.display());
         if let Some(dir) = quadlet_dir {
             println!("quadlet_dir={}", dir);
             println!(
-                "would write quadlet: {}",
-                std::path::Path::new(&dir)
-                    .join(format!("{}.container", caddy_name))
-                    .display()
+                "would write unit: {}",
+                unit_path(runtime, &dir, caddy_name).display()
             );
         }
         if skip_caddy_start {
@@ -503,4 +666,5 @@ fn print_host_init_plan(
     } else {
         println!("would validate caddyfile accessibility");
     }
+    Ok(())
 }
@@ -5,6 +5,7 @@ use clap::Subcommand;
 use std::path::PathBuf;
 
 use crate::runner;
+use crate::runtime::Runtime;
 #[derive(Subcommand, Debug)]
 /// Image workflow commands.
 pub enum ImageCommand {
@@ -46,7 +47,7 @@ pub enum ImageCommand {
 }
 
 /// Handle image workflow subcommands.
-pub fn handle(command: ImageCommand) -> Result<()> {
+pub fn handle(runtime: &Runtime, command: ImageCommand) -> Result<()> {
     match command {
         ImageCommand::Publish {
             image_prefix,
@@ -57,9 +58,13 @@ pub fn handle(command: ImageCommand) -> Result<()> {
             no_push,
             dry_run,
         } => publish_image(
+            runtime,
             &image_prefix,
-            tags,
-            &git_ref,
+            if tags.is_empty() {
+                default_tags(&git_ref)
+            } else {
+                tags
+            },
             &dockerfile,
             &context,
             no_push,
@@ -68,20 +73,21 @@ pub fn handle(command: ImageCommand) -> Result<()> {
     }
 }
 
+/// The commit `git_ref` points at plus `latest`.
+fn default_tags(git_ref: &str) -> Vec<String> {
+    let sha = resolve_git_ref(git_ref).unwrap_or_else(|_| "unknown".to_string());
+    vec![sha, "latest".to_string()]
+}
+
 fn publish_image(
+    runtime: &Runtime,
     image_prefix: &str,
-    mut tags: Vec<String>,
-    git_ref: &str,
+    tags: Vec<String>,
     dockerfile: &str,
     context: &PathBuf,
     no_push: bool,
     dry_run: bool,
 ) -> Result<()> {
-    if tags.is_empty() {
-        let sha = resolve_git_ref(git_ref).unwrap_or_else(|_| "unknown".to_string());
-        tags.push(sha);
-        tags.push("latest".to_string());
-    }
     let primary = tags
         .get(0)
         .context("at least one tag is required")?
@@ -106,22 +112,24 @@ fn publish_image(
         return Ok(());
     }
 
-    run_podman(&[
+    let context = context.to_string_lossy();
+    let build = [
         "build",
         "-t",
         &primary_ref,
         "-f",
         dockerfile,
-        context.to_string_lossy().as_ref(),
-    ])?;
+        context.as_ref(),
+    ];
+    run_podman(runtime, runtime.authed(strings(&build)))?;
 
     for extra in all_refs.iter().skip(1) {
-        run_podman(&["tag", &primary_ref, extra])?;
+        run_podman(runtime, strings(&["tag", &primary_ref, extra]))?;
     }
 
     if !no_push {
         for image in all_refs {
-            run_podman(&["push", &image])?;
+            run_podman(runtime, runtime.authed(strings(&["push", &image])))?;
         }
     }
 
@@ -138,69 +146,45 @@ fn resolve_git_ref(reference: &str) -> Result<String> {
     Ok(commit.id().to_string())
 }
 
-fn run_podman(args: &[&str]) -> Result<()> {
-    let status = runner::run_status("podman", args)
-        .with_context(|| format!("failed to run podman {:?}", args))?;
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn run_podman(runtime: &Runtime, args: Vec<String>) -> Result<()> {
+    let program = runtime.program();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let status = runner::run_status(program, &args)
+        .with_context(|| format!("failed to run {} {:?}", program, args))?;
     if status.success() {
         Ok(())
     } else {
-        bail!("podman failed: {:?}", args)
+        bail!("{} failed: {:?}", program, args)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::runner::{Runner, set_runner_for_tests};
-    use std::process::{ExitStatus, Output};
-    use std::sync::{Arc, Mutex};
-
-    #[derive(Default)]
-    struct RecordingRunner {
-        commands: Mutex<Vec<String>>,
-    }
-
-    impl Runner for RecordingRunner {
-        fn output(&self, program: &str, args: &[&str]) -> anyhow::Result<Output> {
-            let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-            let cmdline = format!("{} {}", program, args_joined);
-            self.commands.lock().expect("commands lock").push(cmdline);
-            Ok(Output {
-                status: exit_status(0),
-                stdout: Vec::new(),
-                stderr: Vec::new(),
-            })
-        }
-    }
-
-    #[cfg(unix)]
-    fn exit_status(code: i32) -> ExitStatus {
-        use std::os::unix::process::ExitStatusExt;
-        ExitStatus::from_raw(code << 8)
-    }
-
-    #[cfg(windows)]
-    fn exit_status(code: i32) -> ExitStatus {
-        use std::os::windows::process::ExitStatusExt;
-        ExitStatus::from_raw(code as u32)
-    }
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{TestRunner, test_podman};
+    use std::sync::Arc;
 
     #[test]
     fn publish_image_runs_build_tag_push() -> Result<()> {
-        let runner = Arc::new(RecordingRunner::default());
+        let runner = Arc::new(TestRunner::default());
         let guard = set_runner_for_tests(runner.clone());
 
         publish_image(
+            &test_podman(),
             "ghcr.io/me/app",
             vec!["v1".to_string(), "latest".to_string()],
-            "HEAD",
             "Dockerfile",
             &PathBuf::from("."),
             false,
             false,
         )?;
 
-        let commands = runner.commands.lock().expect("commands lock").clone();
+        let commands = runner.calls();
         drop(guard);
 
         assert!(commands.iter().any(|cmd| cmd.contains("podman build")));
@@ -226,18 +210,18 @@ mod tests {
         let previous_dir = std::env::current_dir()?;
         std::env::set_current_dir(temp.path())?;
 
-        let runner = Arc::new(RecordingRunner::default());
+        let runner = Arc::new(TestRunner::default());
         let guard = set_runner_for_tests(runner.clone());
         publish_image(
+            &test_podman(),
             "ghcr.io/me/app",
-            Vec::new(),
-            "HEAD",
+            default_tags("HEAD"),
             "Dockerfile",
             &PathBuf::from("."),
             false,
             false,
         )?;
-        let commands = runner.commands.lock().expect("commands lock").clone();
+        let commands = runner.calls();
         drop(guard);
 
         std::env::set_current_dir(previous_dir)?;
@@ -1,9 +1,18 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::Args;
+use serde_json::Value;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::cli::require_app;
+use crate::config::ConfigSnapshot;
 use crate::db::Storage;
-use crate::runtime::{Runtime, app_container_name};
+use crate::runtime::{LogOptions, LogTime, Runtime, app_container_name, parse_rfc3339_unix};
+use crate::systemd::{default_quadlet_dir, is_system_dir, journal_logs};
+
+/// The process type every release runs; releases have a single container today.
+const WEB_PROCESS: &str = "web";
 
 #[derive(Args, Debug)]
 #[command(about = "Tail logs for the current release")]
@@ -13,16 +22,561 @@ pub struct LogsArgs {
     pub app: String,
     #[arg(short = 'f', long, help = "Follow log output")]
     pub follow: bool,
+    #[arg(
+        short = 'a',
+        long,
+        help = "Show Caddy access logs instead of container logs"
+    )]
+    pub access: bool,
+    #[arg(
+        short = 's',
+        long,
+        requires = "access",
+        help = "Only show access entries with this status (e.g. 404, 5xx)"
+    )]
+    pub status: Option<String>,
+    #[arg(
+        short = 'p',
+        long,
+        requires = "access",
+        help = "Only show access entries whose path starts with this prefix"
+    )]
+    pub path: Option<String>,
+    #[arg(
+        short = 'S',
+        long,
+        help = "Only show entries newer than a duration (e.g. 30s, 15m, 2h, 1d) or a timestamp"
+    )]
+    pub since: Option<String>,
+    #[arg(
+        short = 'U',
+        long,
+        conflicts_with = "access",
+        help = "Only show entries older than a duration or a timestamp"
+    )]
+    pub until: Option<String>,
+    #[arg(
+        short = 'n',
+        long,
+        conflicts_with = "access",
+        help = "Number of lines to show from the end"
+    )]
+    pub tail: Option<u32>,
+    #[arg(
+        short = 'r',
+        long,
+        conflicts_with = "access",
+        help = "Release ID (defaults to the current release)"
+    )]
+    pub release: Option<String>,
+    #[arg(
+        short = 'P',
+        long,
+        default_value = WEB_PROCESS,
+        conflicts_with = "access",
+        help = "Process type"
+    )]
+    pub process: String,
+    #[arg(short = 't', long, conflicts_with = "access", help = "Show timestamps")]
+    pub timestamps: bool,
+    #[arg(short = 'g', long, help = "Only show lines containing this text")]
+    pub grep: Option<String>,
+    #[arg(
+        short = 'L',
+        long,
+        default_value = "/srv/deep/caddy/logs",
+        help = "Caddy access log directory"
+    )]
+    pub logs_dir: PathBuf,
 }
 
 /// Handle log streaming for the current release.
-pub fn handle(storage: &mut Storage, args: LogsArgs) -> Result<()> {
+pub fn handle(storage: &mut Storage, runtime: &Runtime, args: LogsArgs) -> Result<()> {
+    let grep = args.grep.clone();
+    let matches = |line: &str| grep.as_deref().is_none_or(|needle| line.contains(needle));
+    let mut sink = |line: &str| {
+        if matches(line) {
+            println!("{}", line);
+        }
+    };
+    if args.access {
+        let app_row = require_app(storage, &args.app)?;
+        return handle_access(&app_row.name, &args, &mut sink);
+    }
+    // stderr stays on stderr so piping the logs never mixes in engine errors.
+    let mut err_sink = |line: &str| {
+        if matches(line) {
+            eprintln!("{}", line);
+        }
+    };
+    stream_logs(storage, runtime, &args, &mut sink, &mut err_sink)
+}
+
+/// Stream container logs, falling back to journald when the container is gone.
+fn stream_logs(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    args: &LogsArgs,
+    sink: &mut dyn FnMut(&str),
+    err_sink: &mut dyn FnMut(&str),
+) -> Result<()> {
     let app_row = require_app(storage, &args.app)?;
-    let release_id = storage
-        .current_release_id(&app_row.id)?
-        .context("no current release set")?;
-    let runtime = Runtime::detect()?;
-    let container_name = app_container_name(&app_row.name, &release_id);
-    runtime.logs(&container_name, args.follow)?;
-    Ok(())
+    if args.process != WEB_PROCESS {
+        bail!(
+            "unknown process type {}; releases only run a {} process",
+            args.process,
+            WEB_PROCESS
+        );
+    }
+    let release_id = match &args.release {
+        Some(release_id) => release_id.clone(),
+        None => storage
+            .current_release_id(&app_row.id)?
+            .context("no current release set")?,
+    };
+    let release = storage
+        .get_release_by_id(&release_id)?
+        .filter(|release| release.app_id == app_row.id)
+        .with_context(|| format!("release {} not found for app {}", release_id, app_row.name))?;
+    let options = LogOptions {
+        follow: args.follow,
+        timestamps: args.timestamps,
+        since: args.since.as_deref().map(parse_log_time),
+        until: args.until.as_deref().map(parse_log_time),
+        tail: args.tail,
+    };
+    let container_name = app_container_name(&app_row.name, &release.id);
+    if runtime.container_exists(&container_name) {
+        return runtime.logs(&container_name, &options, sink, err_sink);
+    }
+    let quadlet_dir = serde_json::from_str::<ConfigSnapshot>(&release.config_json)
+        .ok()
+        .and_then(|snapshot| snapshot.deploy.quadlet_dir)
+        .unwrap_or_else(default_quadlet_dir);
+    let unit = format!("{}.service", container_name);
+    eprintln!(
+        "container {} not found; reading journald logs for {}",
+        container_name, unit
+    );
+    journal_logs(
+        &unit,
+        !is_system_dir(&quadlet_dir),
+        &options,
+        sink,
+        err_sink,
+    )
+}
+
+pub(crate) fn parse_log_time(value: &str) -> LogTime {
+    match parse_window(value) {
+        Ok(seconds) => LogTime::Ago(seconds),
+        Err(_) => LogTime::At(value.to_string()),
+    }
+}
+
+/// Print (and optionally follow) the app's JSON access log written by Caddy.
+fn handle_access(app: &str, args: &LogsArgs, sink: &mut dyn FnMut(&str)) -> Result<()> {
+    let path = args.logs_dir.join(format!("{}.log", app));
+    if !path.exists() {
+        bail!(
+            "no access log at {}; deploy the app to write its Caddy block",
+            path.display()
+        );
+    }
+    let since_ts = args.since.as_deref().map(access_since).transpose()?;
+    let filter = AccessFilter {
+        status: args
+            .status
+            .as_deref()
+            .map(StatusFilter::parse)
+            .transpose()?,
+        path: args.path.clone(),
+        since_ts,
+    };
+    let mut offset = print_access_entries(&path, 0, &filter, sink)?;
+    if !args.follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let len = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        if len < offset {
+            // Caddy rotated the file; start over on the new one.
+            offset = 0;
+        }
+        if len > offset {
+            offset = print_access_entries(&path, offset, &filter, sink)?;
+        }
+    }
+}
+
+/// Emit complete entries after `offset` and return the offset after the last full line.
+fn print_access_entries(
+    path: &Path,
+    offset: u64,
+    filter: &AccessFilter,
+    sink: &mut dyn FnMut(&str),
+) -> Result<u64> {
+    let (lines, next) = read_complete_lines(path, offset)?;
+    for line in lines {
+        let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if filter.matches(&entry) {
+            sink(&format_access_entry(&entry));
+        }
+    }
+    Ok(next)
+}
+
+fn read_complete_lines(path: &Path, offset: u64) -> Result<(Vec<String>, u64)> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    let Some(end) = buf.iter().rposition(|byte| *byte == b'\n') else {
+        return Ok((Vec::new(), offset));
+    };
+    let text = String::from_utf8_lossy(&buf[..end]);
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok((lines, offset + end as u64 + 1))
+}
+
+#[derive(Debug, PartialEq)]
+enum StatusFilter {
+    Exact(u64),
+    Class(u64),
+}
+
+impl StatusFilter {
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        if let Some(class) = value.strip_suffix("xx")
+            && let Ok(class @ 1..=5) = class.parse::<u64>()
+        {
+            return Ok(StatusFilter::Class(class));
+        }
+        match value.parse::<u64>() {
+            Ok(code @ 100..=599) => Ok(StatusFilter::Exact(code)),
+            _ => bail!(
+                "invalid status filter {}; use a code (404) or class (5xx)",
+                value
+            ),
+        }
+    }
+
+    fn matches(&self, status: u64) -> bool {
+        match self {
+            StatusFilter::Exact(code) => status == *code,
+            StatusFilter::Class(class) => status / 100 == *class,
+        }
+    }
+}
+
+struct AccessFilter {
+    status: Option<StatusFilter>,
+    path: Option<String>,
+    since_ts: Option<f64>,
+}
+
+impl AccessFilter {
+    fn matches(&self, entry: &Value) -> bool {
+        if let Some(status) = &self.status
+            && !status.matches(entry["status"].as_u64().unwrap_or(0))
+        {
+            return false;
+        }
+        if let Some(prefix) = &self.path {
+            let uri = entry["request"]["uri"].as_str().unwrap_or("");
+            let path = uri.split('?').next().unwrap_or("");
+            if !path.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(since) = self.since_ts
+            && entry["ts"].as_f64().is_some_and(|ts| ts < since)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// Unix time for an access-log `--since`: a window back from now or a timestamp.
+fn access_since(value: &str) -> Result<f64> {
+    match parse_log_time(value) {
+        LogTime::Ago(seconds) => Ok(unix_now() - seconds as f64),
+        LogTime::At(timestamp) => parse_timestamp(&timestamp)
+            .map(|ts| ts as f64)
+            .with_context(|| {
+                format!(
+                    "invalid --since {}; use a duration (e.g. 15m) or a timestamp like 2024-05-01 10:00",
+                    value
+                )
+            }),
+    }
+}
+
+/// Parse `YYYY-MM-DD[T ]HH:MM[:SS][offset]`; timestamps without an offset are UTC.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = value.trim().replacen(' ', "T", 1);
+    parse_rfc3339_unix(&value).or_else(|| {
+        let (date, rest) = value.split_once('T')?;
+        let offset_at = rest.find(['Z', 'z', '+', '-']).unwrap_or(rest.len());
+        let (clock, offset) = rest.split_at(offset_at);
+        parse_rfc3339_unix(&format!("{}T{}:00{}", date, clock, offset))
+    })
+}
+
+/// Parse a window like `90`, `30s`, `15m`, `2h` or `1d` into seconds.
+fn parse_window(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid duration {}", value))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => bail!("invalid duration {}; use s, m, h or d", value),
+    };
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("duration {} is too large", value))
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Render a Caddy JSON access entry as a single human-readable line.
+fn format_access_entry(entry: &Value) -> String {
+    let request = &entry["request"];
+    let ts = entry["ts"]
+        .as_f64()
+        .map(format_unix_ts)
+        .unwrap_or_else(|| entry["ts"].as_str().unwrap_or("-").to_string());
+    let duration_ms = entry["duration"].as_f64().unwrap_or(0.0) * 1_000.0;
+    let client = request["client_ip"]
+        .as_str()
+        .or_else(|| request["remote_ip"].as_str())
+        .unwrap_or("-");
+    format!(
+        "{} {} {} {}{} {:.1}ms {}B {}",
+        ts,
+        entry["status"].as_u64().unwrap_or(0),
+        request["method"].as_str().unwrap_or("-"),
+        request["host"].as_str().unwrap_or(""),
+        request["uri"].as_str().unwrap_or("-"),
+        duration_ms,
+        entry["size"].as_u64().unwrap_or(0),
+        client
+    )
+}
+
+fn format_unix_ts(ts: f64) -> String {
+    let fmt = time::format_description::well_known::Rfc3339;
+    time::OffsetDateTime::from_unix_timestamp(ts as i64)
+        .ok()
+        .and_then(|datetime| datetime.format(&fmt).ok())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeployConfig;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, insert_release, release_row, test_podman,
+    };
+    use std::sync::Arc;
+
+    fn logs_runner(missing_container: bool) -> Arc<TestRunner> {
+        let runner = Arc::new(TestRunner::default());
+        if missing_container {
+            runner.add_rule(&["inspect"], 1, "", "");
+        }
+        runner.add_rule(
+            &["podman logs"],
+            0,
+            "booting\nlistening on :3000\n",
+            "warning: slow start\n",
+        );
+        runner.add_rule(&["journalctl"], 0, "old release output\n", "");
+        runner
+    }
+
+    fn logs_args(release: Option<&str>) -> LogsArgs {
+        LogsArgs {
+            app: "app".to_string(),
+            follow: false,
+            access: false,
+            status: None,
+            path: None,
+            since: Some("15m".to_string()),
+            until: None,
+            tail: Some(50),
+            release: release.map(str::to_string),
+            process: WEB_PROCESS.to_string(),
+            timestamps: true,
+            grep: None,
+            logs_dir: PathBuf::from("/srv/deep/caddy/logs"),
+        }
+    }
+
+    fn seed_releases(storage: &mut Storage) -> Result<()> {
+        let app = storage.create_app("app", "/tmp")?;
+        let snapshot = ConfigSnapshot {
+            deploy: DeployConfig {
+                quadlet_dir: Some("/home/deep/.config/containers/systemd".to_string()),
+                ..DeployConfig::default()
+            },
+            ..base_snapshot()
+        };
+        insert_release(storage, &release_row(&app.id, "r1", &snapshot))?;
+        insert_current_release(storage, &release_row(&app.id, "r2", &snapshot))
+    }
+
+    #[test]
+    fn logs_stream_from_container_or_journald() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        seed_releases(&mut storage)?;
+
+        let runner = logs_runner(false);
+        let guard = set_runner_for_tests(runner.clone());
+        let mut lines = Vec::new();
+        let mut errors = Vec::new();
+        stream_logs(
+            &mut storage,
+            &test_podman(),
+            &logs_args(None),
+            &mut |line| lines.push(line.to_string()),
+            &mut |line| errors.push(line.to_string()),
+        )?;
+        drop(guard);
+        assert_eq!(lines, vec!["booting", "listening on :3000"]);
+        assert_eq!(errors, vec!["warning: slow start"]);
+        let commands = runner.calls();
+        assert!(
+            commands
+                .iter()
+                .any(|cmd| cmd == "podman logs -t --since 900s --tail 50 deep-app-app-r2")
+        );
+
+        let runner = logs_runner(true);
+        let _guard = set_runner_for_tests(runner.clone());
+        let mut lines = Vec::new();
+        stream_logs(
+            &mut storage,
+            &test_podman(),
+            &logs_args(Some("r1")),
+            &mut |line| lines.push(line.to_string()),
+            &mut |_| {},
+        )?;
+        assert_eq!(lines, vec!["old release output"]);
+        let commands = runner.calls();
+        assert!(commands.iter().any(|cmd| cmd
+            == "journalctl --user -u deep-app-app-r1.service --no-pager --since=-900s -n 50"));
+
+        assert!(
+            stream_logs(
+                &mut storage,
+                &test_podman(),
+                &logs_args(Some("nope")),
+                &mut |_| {},
+                &mut |_| {}
+            )
+            .is_err()
+        );
+        let mut args = logs_args(None);
+        args.process = "worker".to_string();
+        assert!(
+            stream_logs(
+                &mut storage,
+                &test_podman(),
+                &args,
+                &mut |_| {},
+                &mut |_| {}
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    fn entry(status: u64, uri: &str, ts: f64) -> Value {
+        serde_json::json!({
+            "ts": ts,
+            "status": status,
+            "duration": 0.0123,
+            "size": 512,
+            "request": {
+                "method": "GET",
+                "host": "app.example.com",
+                "uri": uri,
+                "remote_ip": "203.0.113.7"
+            }
+        })
+    }
+
+    #[test]
+    fn access_filter_matches_status_path_and_window() -> Result<()> {
+        let filter = AccessFilter {
+            status: Some(StatusFilter::parse("5xx")?),
+            path: Some("/api".to_string()),
+            since_ts: Some(1_700_000_000.0),
+        };
+        assert!(filter.matches(&entry(502, "/api/users?page=2", 1_700_000_100.0)));
+        assert!(!filter.matches(&entry(404, "/api/users", 1_700_000_100.0)));
+        assert!(!filter.matches(&entry(500, "/static/app.js", 1_700_000_100.0)));
+        assert!(!filter.matches(&entry(500, "/api", 1_600_000_000.0)));
+
+        assert_eq!(StatusFilter::parse("404")?, StatusFilter::Exact(404));
+        assert!(StatusFilter::parse("9xx").is_err());
+        assert!(StatusFilter::parse("abc").is_err());
+        assert_eq!(parse_window("15m")?, 900);
+        assert_eq!(parse_window("90")?, 90);
+        assert!(parse_window("2w").is_err());
+        assert!(parse_window("18446744073709551615d").is_err());
+        assert_eq!(access_since("2023-11-14T22:13:20Z")?, 1_700_000_000.0);
+        assert_eq!(access_since("2023-11-14 22:13:20")?, 1_700_000_000.0);
+        assert_eq!(access_since("2023-11-14 22:13")?, 1_699_999_980.0);
+        assert!(access_since("yesterday").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn access_entries_format_and_skip_partial_lines() -> Result<()> {
+        let line = format_access_entry(&entry(200, "/health", 1_700_000_000.0));
+        assert_eq!(
+            line,
+            "2023-11-14T22:13:20Z 200 GET app.example.com/health 12.3ms 512B 203.0.113.7"
+        );
+
+        let temp = tempfile::TempDir::new()?;
+        let path = temp.path().join("app.log");
+        let first = entry(200, "/", 1.0).to_string();
+        std::fs::write(&path, format!("{}\n{{\"ts\":", first))?;
+        let (lines, offset) = read_complete_lines(&path, 0)?;
+        assert_eq!(lines, vec![first.clone()]);
+        assert_eq!(offset, first.len() as u64 + 1);
+        let (lines, next) = read_complete_lines(&path, offset)?;
+        assert!(lines.is_empty());
+        assert_eq!(next, offset);
+        Ok(())
+    }
 }
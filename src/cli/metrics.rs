@@ -0,0 +1,415 @@
+//! Container resource stats and a Prometheus exporter.
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cli::{available_runtime, print_table, require_app};
+use crate::db::{DeploymentStatsRow, Storage};
+use crate::runtime::{ContainerStats, Runtime};
+
+/// How long one client may take to send its request or read the response.
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+const HEADERS: [&str; 7] = ["KIND", "NAME", "RELEASE", "CPU", "MEM", "MEM%", "PIDS"];
+
+#[derive(Subcommand, Debug)]
+pub enum MetricsCommand {
+    /// Serve Prometheus metrics over HTTP
+    #[command(alias = "s")]
+    Serve {
+        #[arg(
+            short = 'l',
+            long,
+            default_value = "127.0.0.1:9900",
+            help = "Address to listen on"
+        )]
+        listen: String,
+    },
+}
+
+/// Stats for one deep-managed container, mapped back to its app or addon.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ServiceStats {
+    pub kind: &'static str,
+    pub name: String,
+    pub release: Option<String>,
+    pub stats: ContainerStats,
+}
+
+impl ServiceStats {
+    /// Map `deep-app-<app>-<release>` and `deep-addon-<name>` containers; others are skipped.
+    pub(crate) fn from_stats(stats: ContainerStats) -> Option<Self> {
+        if let Some(rest) = stats.name.strip_prefix("deep-app-") {
+            let (app, release) = rest.rsplit_once('-')?;
+            return Some(Self {
+                kind: "app",
+                name: app.to_string(),
+                release: Some(release.to_string()),
+                stats,
+            });
+        }
+        let addon = stats.name.strip_prefix("deep-addon-")?.to_string();
+        Some(Self {
+            kind: "addon",
+            name: addon,
+            release: None,
+            stats,
+        })
+    }
+}
+
+/// Print live resource usage for app and addon containers.
+pub fn handle_stats(storage: &mut Storage, runtime: &Runtime, app: Option<String>) -> Result<()> {
+    if let Some(app) = &app {
+        require_app(storage, app)?;
+    }
+    let services = collect_services(runtime)?;
+    let rows: Vec<Vec<String>> = services
+        .into_iter()
+        .filter(|service| match &app {
+            Some(app) => service.kind == "app" && &service.name == app,
+            None => true,
+        })
+        .map(|service| {
+            let dash = |value: &str| {
+                if value.is_empty() {
+                    "-".to_string()
+                } else {
+                    value.to_string()
+                }
+            };
+            vec![
+                service.kind.to_string(),
+                service.name,
+                service.release.unwrap_or_else(|| "-".to_string()),
+                dash(&service.stats.cpu_percent),
+                dash(&service.stats.mem_usage),
+                dash(&service.stats.mem_percent),
+                dash(&service.stats.pids),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        match &app {
+            Some(app) => println!("no running containers for {}", app),
+            None => println!("no running deep containers"),
+        }
+        return Ok(());
+    }
+    print_table(&HEADERS, &rows);
+    Ok(())
+}
+
+/// Dispatch metrics subcommands.
+pub fn handle(storage: &mut Storage, runtime: &Runtime, command: MetricsCommand) -> Result<()> {
+    match command {
+        MetricsCommand::Serve { listen } => {
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("failed to listen on {}", listen))?;
+            let runtime = available_runtime(runtime, "container metrics unavailable");
+            println!("serving metrics on http://{}/metrics", listen);
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| serve_connection(storage, runtime, stream));
+                if let Err(err) = result {
+                    eprintln!("warning: metrics request failed: {}", err);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn collect_services(runtime: &Runtime) -> Result<Vec<ServiceStats>> {
+    let mut services: Vec<ServiceStats> = runtime
+        .container_stats(&[])?
+        .into_iter()
+        .filter_map(ServiceStats::from_stats)
+        .collect();
+    services.sort_by(|a, b| (a.kind, &a.name, &a.release).cmp(&(b.kind, &b.name, &b.release)));
+    Ok(services)
+}
+
+/// Answer one HTTP request; only `GET /metrics` is served.
+///
+/// Connections are served one at a time, so an idle client is cut off after
+/// [`CLIENT_TIMEOUT`] instead of blocking every later scrape.
+fn serve_connection(storage: &Storage, runtime: Option<&Runtime>, stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = if method != "GET" {
+        ("405 Method Not Allowed", "method not allowed\n".to_string())
+    } else if path == "/metrics" {
+        ("200 OK", collect_metrics(storage, runtime)?)
+    } else {
+        ("404 Not Found", "not found; try /metrics\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    (&stream).write_all(response.as_bytes())?;
+    Ok(())
+}
+
+fn collect_metrics(storage: &Storage, runtime: Option<&Runtime>) -> Result<String> {
+    let services = match runtime.map(collect_services) {
+        Some(Ok(services)) => Some(services),
+        Some(Err(err)) => {
+            eprintln!("warning: podman stats failed: {}", err);
+            None
+        }
+        None => None,
+    };
+    let deployments = storage.deployment_stats()?;
+    Ok(render_metrics(services.as_deref(), &deployments))
+}
+
+/// Render container gauges and deployment counters in Prometheus text format.
+/// `services` is `None` when container stats could not be collected.
+pub(crate) fn render_metrics(
+    services: Option<&[ServiceStats]>,
+    deployments: &[DeploymentStatsRow],
+) -> String {
+    let mut out = String::new();
+    metric_header(
+        &mut out,
+        "deep_container_stats_up",
+        "gauge",
+        "Whether container stats were collected from the runtime.",
+    );
+    out.push_str(&format!(
+        "deep_container_stats_up {}\n",
+        u8::from(services.is_some())
+    ));
+
+    let services = services.unwrap_or_default();
+    type Extract = fn(&ContainerStats) -> Option<f64>;
+    let gauges: [(&str, &str, Extract); 5] = [
+        (
+            "deep_container_cpu_percent",
+            "CPU usage of the container in percent of one core.",
+            |stats| parse_percent(&stats.cpu_percent),
+        ),
+        (
+            "deep_container_memory_bytes",
+            "Memory used by the container.",
+            |stats| parse_bytes(stats.mem_usage.split('/').next()?),
+        ),
+        (
+            "deep_container_memory_limit_bytes",
+            "Memory limit of the container.",
+            |stats| parse_bytes(stats.mem_usage.split('/').nth(1)?),
+        ),
+        (
+            "deep_container_memory_percent",
+            "Memory used by the container in percent of its limit.",
+            |stats| parse_percent(&stats.mem_percent),
+        ),
+        (
+            "deep_container_pids",
+            "Number of processes in the container.",
+            |stats| stats.pids.trim().parse().ok(),
+        ),
+    ];
+    for (name, help, extract) in gauges {
+        metric_header(&mut out, name, "gauge", help);
+        for service in services {
+            let Some(value) = extract(&service.stats) else {
+                continue;
+            };
+            out.push_str(&format!(
+                "{}{{kind=\"{}\",name=\"{}\",release=\"{}\"}} {}\n",
+                name,
+                service.kind,
+                escape_label(&service.name),
+                escape_label(service.release.as_deref().unwrap_or("")),
+                value
+            ));
+        }
+    }
+
+    metric_header(
+        &mut out,
+        "deep_deployments",
+        "gauge",
+        "Deployments currently recorded per app and status; pruning releases lowers it.",
+    );
+    for row in deployments {
+        out.push_str(&format!(
+            "deep_deployments{{app=\"{}\",status=\"{}\"}} {}\n",
+            escape_label(&row.app),
+            escape_label(&row.status),
+            row.count
+        ));
+    }
+    metric_header(
+        &mut out,
+        "deep_deployment_duration_seconds",
+        "summary",
+        "Time from deployment start to its final status.",
+    );
+    for row in deployments.iter().filter(|row| row.finished_count > 0) {
+        let labels = format!(
+            "app=\"{}\",status=\"{}\"",
+            escape_label(&row.app),
+            escape_label(&row.status)
+        );
+        out.push_str(&format!(
+            "deep_deployment_duration_seconds_sum{{{}}} {}\n",
+            labels, row.duration_secs_sum
+        ));
+        out.push_str(&format!(
+            "deep_deployment_duration_seconds_count{{{}}} {}\n",
+            labels, row.finished_count
+        ));
+    }
+    out
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, kind
+    ));
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn parse_percent(value: &str) -> Option<f64> {
+    value.trim().trim_end_matches('%').trim().parse().ok()
+}
+
+/// Parse sizes like `12.5MB`, `1.2GiB` or `512kB` into bytes.
+fn parse_bytes(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value
+        .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let factor = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some(number * factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{TestRunner, test_podman};
+    use std::io::Read;
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_bytes_handles_decimal_and_binary_units() {
+        assert_eq!(parse_bytes("12.5MB"), Some(12_500_000.0));
+        assert_eq!(parse_bytes(" 1GiB "), Some(1024.0 * 1024.0 * 1024.0));
+        assert_eq!(parse_bytes("512kB"), Some(512_000.0));
+        assert_eq!(parse_bytes("0B"), Some(0.0));
+        assert_eq!(parse_bytes("lots"), None);
+        assert_eq!(parse_percent("2.44%"), Some(2.44));
+    }
+
+    #[test]
+    fn metrics_endpoint_serves_containers_and_deployments() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(
+            &["stats --no-stream"],
+            0,
+            r#"[
+  {"name":"deep-app-web-r1","cpu_percent":"1.50%","mem_usage":"12.5MB / 512MB","mem_percent":"2.44%","pids":"4"},
+  {"name":"deep-addon-db","cpu_percent":"0.20%","mem_usage":"64MiB / 1GiB","mem_percent":"6.25%","pids":"9"},
+  {"name":"unrelated","cpu_percent":"9.00%","mem_usage":"1MB / 1GB","mem_percent":"0.10%","pids":"1"}
+]"#,
+            "",
+        );
+        let _guard = set_runner_for_tests(runner);
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        for (id, status) in [("d1", "succeeded"), ("d2", "succeeded"), ("d3", "failed")] {
+            let tx = storage.transaction()?;
+            Storage::insert_deployment(&tx, id, &web.id, None, None, "pending", None)?;
+            tx.commit()?;
+            storage.update_deployment_status(id, status, None)?;
+        }
+
+        let runtime = test_podman();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let client = std::thread::spawn(move || -> Result<String> {
+            let mut stream = TcpStream::connect(addr)?;
+            stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        });
+        let (stream, _) = listener.accept()?;
+        serve_connection(&storage, Some(&runtime), stream)?;
+        let response = client.join().expect("client thread")?;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("deep_container_stats_up 1\n"));
+        assert!(response.contains(
+            "deep_container_cpu_percent{kind=\"app\",name=\"web\",release=\"r1\"} 1.5\n"
+        ));
+        assert!(response.contains(
+            "deep_container_memory_bytes{kind=\"addon\",name=\"db\",release=\"\"} 67108864\n"
+        ));
+        assert!(response.contains(
+            "deep_container_memory_limit_bytes{kind=\"app\",name=\"web\",release=\"r1\"} 512000000\n"
+        ));
+        assert!(
+            response.contains("deep_container_pids{kind=\"addon\",name=\"db\",release=\"\"} 9\n")
+        );
+        assert!(!response.contains("unrelated"));
+        assert!(response.contains("deep_deployments{app=\"web\",status=\"succeeded\"} 2\n"));
+        assert!(response.contains("deep_deployments{app=\"web\",status=\"failed\"} 1\n"));
+        assert!(response.contains(
+            "deep_deployment_duration_seconds_count{app=\"web\",status=\"succeeded\"} 2\n"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn render_metrics_reports_missing_runtime() {
+        let out = render_metrics(None, &[]);
+        assert!(out.contains("deep_container_stats_up 0\n"));
+        assert!(out.contains("# TYPE deep_deployments gauge\n"));
+    }
+}
@@ -3,19 +3,28 @@
 mod addons;
 mod apps;
 pub mod deploy;
+mod domains;
+mod gc;
 pub mod git;
 mod host;
 mod image;
 mod logs;
+mod metrics;
 mod proxy;
+mod ps;
+mod registry;
 mod releases;
+mod retention;
+mod volumes;
 
 use anyhow::{Context, Result, bail};
 use clap::{Args, Parser, Subcommand};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::db::{AppRow, Storage};
-use crate::proxy::CaddyFile;
+use crate::config::{ConfigSnapshot, load_host_config};
+use crate::db::{AppRow, ReleaseRow, Storage};
+use crate::proxy::{BasicAuthUser, CaddyFile, RouteExtras};
+use crate::runtime::{Runtime, host_runtime};
 
 #[derive(Parser, Debug)]
 #[command(name = "deep", version, about = "Deep micro-PaaS CLI")]
@@ -55,6 +64,18 @@ struct ProxyArgs {
     caddy_container: String,
 }
 
+#[derive(Args, Debug, Clone)]
+/// Host configuration options.
+struct HostConfigArgs {
+    #[arg(
+        short = 'H',
+        long,
+        default_value = crate::config::DEFAULT_HOST_CONFIG,
+        help = "Host config (deep.toml) path"
+    )]
+    host_config: PathBuf,
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Manage apps
@@ -62,6 +83,10 @@ enum Command {
     Apps {
         #[command(flatten)]
         db: DbArgs,
+        #[command(flatten)]
+        proxy: ProxyArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: apps::AppsCommand,
     },
@@ -73,6 +98,8 @@ enum Command {
         #[command(flatten)]
         proxy: ProxyArgs,
         #[command(flatten)]
+        host: HostConfigArgs,
+        #[command(flatten)]
         args: deploy::DeployArgs,
     },
     /// Inspect releases
@@ -80,6 +107,8 @@ enum Command {
     Releases {
         #[command(flatten)]
         db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: releases::ReleasesCommand,
     },
@@ -91,6 +120,8 @@ enum Command {
         #[command(flatten)]
         proxy: ProxyArgs,
         #[command(flatten)]
+        host: HostConfigArgs,
+        #[command(flatten)]
         args: deploy::RollbackArgs,
     },
     /// Stream logs for the current release
@@ -99,24 +130,70 @@ enum Command {
         #[command(flatten)]
         db: DbArgs,
         #[command(flatten)]
+        host: HostConfigArgs,
+        #[command(flatten)]
         args: logs::LogsArgs,
     },
+    /// Manage persistent app volumes
+    #[command(alias = "v")]
+    Volumes {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
+        #[command(subcommand)]
+        command: volumes::VolumesCommand,
+    },
     /// Manage addons and bindings
     #[command(alias = "ad")]
     Addons {
         #[command(flatten)]
         db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: addons::AddonsCommand,
     },
     /// Inspect and validate proxy routes
     #[command(alias = "p")]
     Proxy {
+        #[command(flatten)]
+        db: DbArgs,
         #[command(flatten)]
         proxy: ProxyArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: proxy::ProxyCommand,
     },
+    /// Show apps, addons and Caddy with live container state
+    Ps {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        proxy: ProxyArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
+    },
+    /// Show CPU, memory and process usage of app and addon containers
+    Stats {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
+        #[arg(help = "Only show containers of this app")]
+        app: Option<String>,
+    },
+    /// Export container and deployment metrics
+    #[command(alias = "m")]
+    Metrics {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
+        #[command(subcommand)]
+        command: metrics::MetricsCommand,
+    },
     /// Host setup and health checks
     #[command(alias = "h")]
     Host {
@@ -124,6 +201,8 @@ enum Command {
         db: DbArgs,
         #[command(flatten)]
         proxy: ProxyArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: host::HostCommand,
     },
@@ -132,62 +211,155 @@ enum Command {
     Git {
         #[command(flatten)]
         db: DbArgs,
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: git::GitCommand,
     },
     /// Build and publish images (laptop workflow)
     #[command(alias = "i")]
     Image {
+        #[command(flatten)]
+        host: HostConfigArgs,
         #[command(subcommand)]
         command: image::ImageCommand,
     },
+    /// Manage registry credentials for pulls, builds and pushes
+    #[command(alias = "reg")]
+    Registry {
+        #[command(subcommand)]
+        command: registry::RegistryCommand,
+    },
 }
 
 /// Entry point for the CLI.
 pub fn run() -> Result<()> {
-    let cli = Cli::parse();
+    dispatch(Cli::parse())
+}
 
+/// Run a parsed command line.
+fn dispatch(cli: Cli) -> Result<()> {
     match cli.command {
-        Command::Apps { db, command } => {
+        Command::Apps {
+            db,
+            proxy,
+            host,
+            command,
+        } => {
+            let mut storage = Storage::open(&db.db)?;
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            apps::handle(&mut storage, &runtime, &proxy, command)
+        }
+        Command::Deploy {
+            db,
+            proxy,
+            host,
+            args,
+        } => {
             let mut storage = Storage::open(&db.db)?;
-            apps::handle(&mut storage, command)
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            deploy::handle_deploy(&mut storage, &runtime, &proxy, &host.host_config, args)
         }
-        Command::Deploy { db, proxy, args } => {
+        Command::Releases { db, host, command } => {
             let mut storage = Storage::open(&db.db)?;
-            let proxy = CaddyFile::new(proxy.caddyfile, proxy.caddy_container);
-            deploy::handle_deploy(&mut storage, &proxy, args)
+            let runtime = host_runtime(&host.host_config)?;
+            releases::handle(&mut storage, &runtime, command)
         }
-        Command::Releases { db, command } => {
+        Command::Rollback {
+            db,
+            proxy,
+            host,
+            args,
+        } => {
             let mut storage = Storage::open(&db.db)?;
-            releases::handle(&mut storage, command)
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            deploy::handle_rollback(&mut storage, &runtime, &proxy, &host.host_config, args)
         }
-        Command::Rollback { db, proxy, args } => {
+        Command::Logs { db, host, args } => {
             let mut storage = Storage::open(&db.db)?;
-            let proxy = CaddyFile::new(proxy.caddyfile, proxy.caddy_container);
-            deploy::handle_rollback(&mut storage, &proxy, args)
+            let runtime = host_runtime(&host.host_config)?;
+            logs::handle(&mut storage, &runtime, args)
         }
-        Command::Logs { db, args } => {
+        Command::Volumes { db, host, command } => {
             let mut storage = Storage::open(&db.db)?;
-            logs::handle(&mut storage, args)
+            let runtime = host_runtime(&host.host_config)?;
+            volumes::handle(&mut storage, &runtime, command)
         }
-        Command::Addons { db, command } => {
+        Command::Addons { db, host, command } => {
             let mut storage = Storage::open(&db.db)?;
-            addons::handle(&mut storage, command)
+            let runtime = host_runtime(&host.host_config)?;
+            addons::handle(&mut storage, &runtime, &host.host_config, command)
         }
-        Command::Proxy { proxy, command } => {
-            let proxy = CaddyFile::new(proxy.caddyfile, proxy.caddy_container);
-            proxy::handle(&proxy, command)
+        Command::Proxy {
+            db,
+            proxy,
+            host,
+            command,
+        } => {
+            let mut storage = Storage::open(&db.db)?;
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            proxy::handle(&mut storage, &runtime, &proxy, command)
+        }
+        Command::Ps { db, proxy, host } => {
+            let mut storage = Storage::open(&db.db)?;
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            ps::handle(&mut storage, &runtime, &proxy)
         }
-        Command::Host { db, proxy, command } => {
+        Command::Stats { db, host, app } => {
             let mut storage = Storage::open(&db.db)?;
-            let proxy = CaddyFile::new(proxy.caddyfile, proxy.caddy_container);
-            host::handle(&mut storage, &proxy, command)
+            let runtime = host_runtime(&host.host_config)?;
+            metrics::handle_stats(&mut storage, &runtime, app)
         }
-        Command::Git { db, command } => {
+        Command::Metrics { db, host, command } => {
             let mut storage = Storage::open(&db.db)?;
-            git::handle(&mut storage, command)
+            let runtime = host_runtime(&host.host_config)?;
+            metrics::handle(&mut storage, &runtime, command)
+        }
+        Command::Host {
+            db,
+            proxy,
+            host,
+            command,
+        } => {
+            let mut storage = Storage::open(&db.db)?;
+            let runtime = host_runtime(&host.host_config)?;
+            let proxy = caddy_file(proxy, &runtime, &host.host_config)?;
+            host::handle(&mut storage, &runtime, &proxy, &host.host_config, command)
+        }
+        Command::Git { db, host, command } => {
+            let mut storage = Storage::open(&db.db)?;
+            let runtime = host_runtime(&host.host_config)?;
+            git::handle(&mut storage, &runtime, command)
+        }
+        Command::Image { host, command } => {
+            let runtime = host_runtime(&host.host_config)?;
+            image::handle(&runtime, command)
+        }
+        Command::Registry { command } => registry::handle(command),
+    }
+}
+
+fn caddy_file(args: ProxyArgs, runtime: &Runtime, host_config: &Path) -> Result<CaddyFile> {
+    let certs_dir = load_host_config(host_config)?.caddy.certs_dir;
+    Ok(
+        CaddyFile::new(args.caddyfile, args.caddy_container, runtime.clone())
+            .with_certs_dir(certs_dir),
+    )
+}
+
+/// The runtime when its engine is installed; otherwise warn with the reason and what is skipped.
+fn available_runtime<'a>(runtime: &'a Runtime, skipped: &str) -> Option<&'a Runtime> {
+    match runtime.available() {
+        Ok(()) => Some(runtime),
+        Err(err) => {
+            eprintln!("warning: {}; {}", err, skipped);
+            None
         }
-        Command::Image { command } => image::handle(command),
     }
 }
 
@@ -197,6 +369,113 @@ fn require_app(storage: &mut Storage, name: &str) -> Result<AppRow> {
         .with_context(|| format!("app {} not found", name))
 }
 
+/// Ask for confirmation on a terminal; `yes` skips the prompt.
+fn confirm(prompt: &str, yes: bool) -> Result<bool> {
+    use std::io::{BufRead, IsTerminal, Write};
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("{} Pass --yes to confirm non-interactively.", prompt);
+    }
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES"))
+}
+
+/// Read one line from stdin as a password, prompting on stderr.
+///
+/// On a terminal the typed password is not echoed.
+fn read_password_from_stdin() -> Result<String> {
+    use std::io::IsTerminal;
+    eprint!("password: ");
+    if !std::io::stdin().is_terminal() {
+        return read_stdin_line();
+    }
+    let echo = EchoOff::new()?;
+    let password = read_stdin_line();
+    drop(echo);
+    // The newline typed after the password was not echoed either.
+    eprintln!();
+    password
+}
+
+/// Turns terminal echo off on stdin until dropped.
+struct EchoOff {
+    #[cfg(unix)]
+    saved: libc::termios,
+}
+
+impl EchoOff {
+    #[cfg(unix)]
+    fn new() -> Result<Self> {
+        // SAFETY: tcgetattr fills the zeroed termios for fd 0, which is a terminal here.
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("failed to read terminal mode");
+        }
+        let mut quiet = saved;
+        quiet.c_lflag &= !libc::ECHO;
+        // SAFETY: `quiet` is a valid termios derived from the current one.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("failed to turn off echo");
+        }
+        Ok(Self { saved })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> Result<Self> {
+        Ok(Self {})
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        // SAFETY: restores the mode read in `new`.
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Read one line from stdin without a prompt, for piped secrets.
+fn read_stdin_line() -> Result<String> {
+    use std::io::BufRead;
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("failed to read password from stdin")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Print rows as left-aligned columns separated by two spaces.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (index, cell) in row.iter().enumerate() {
+            if let Some(width) = widths.get_mut(index) {
+                *width = (*width).max(cell.len());
+            }
+        }
+    }
+    let format_row = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| format!("{:width$}", cell, width = widths[index]))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
 fn now_rfc3339() -> String {
     let fmt = time::format_description::well_known::Rfc3339;
     time::OffsetDateTime::now_utc()
@@ -220,6 +499,57 @@ fn record_proxy_error(
     let _ = storage.insert_event("proxy_error", &payload.to_string());
 }
 
+fn route_extras_for_app(storage: &Storage, app_id: &str) -> Result<RouteExtras> {
+    let auth_users = storage
+        .list_proxy_users(app_id)?
+        .into_iter()
+        .map(|user| BasicAuthUser {
+            username: user.username,
+            password_hash: user.password_hash,
+        })
+        .collect();
+    Ok(RouteExtras { auth_users })
+}
+
+fn current_release_snapshot(
+    storage: &Storage,
+    app_row: &AppRow,
+) -> Result<Option<(ReleaseRow, ConfigSnapshot)>> {
+    let Some(release_id) = storage.current_release_id(&app_row.id)? else {
+        return Ok(None);
+    };
+    let release = storage
+        .get_release_by_id(&release_id)?
+        .context("current release missing")?;
+    let mut snapshot: ConfigSnapshot =
+        serde_json::from_str(&release.config_json).context("invalid release config")?;
+    // Hosts moved to another app with `--steal` stay in the release but leave the route.
+    let stolen = storage.stolen_domains(&app_row.id)?;
+    snapshot.domains.retain(|domain| !stolen.contains(domain));
+    Ok(Some((release, snapshot)))
+}
+
+fn refresh_app_route(storage: &mut Storage, proxy: &CaddyFile, app_row: &AppRow) -> Result<()> {
+    let Some((release, snapshot)) = current_release_snapshot(storage, app_row)? else {
+        println!(
+            "no current release for {}; changes apply on next deploy",
+            app_row.name
+        );
+        return Ok(());
+    };
+    let release_id = release.id;
+    if snapshot.domains.is_empty() {
+        return Ok(());
+    }
+    let extras = route_extras_for_app(storage, &app_row.id)?;
+    if let Err(err) = proxy.upsert_route_with_extras(&app_row.name, &release_id, &snapshot, &extras)
+    {
+        record_proxy_error(storage, &app_row.name, &release_id, "refresh", &err);
+        return Err(err);
+    }
+    Ok(())
+}
+
 fn resolve_config_path(
     args_config: &Option<PathBuf>,
     repo_path: &str,
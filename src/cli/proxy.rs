@@ -1,7 +1,15 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use clap::Subcommand;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
-use crate::proxy::CaddyFile;
+use crate::cli::domains::normalize_host;
+use crate::cli::{available_runtime, current_release_snapshot, route_extras_for_app};
+use crate::db::Storage;
+use crate::proxy::{
+    CaddyFile, RouteStatus, parse_caddyfile_routes, rebuild_caddyfile, render_app_block,
+    unified_diff,
+};
+use crate::runtime::Runtime;
 
 #[derive(Subcommand, Debug)]
 /// Proxy-related commands.
@@ -9,10 +17,31 @@ pub enum ProxyCommand {
     /// List and validate configured routes
     #[command(alias = "st")]
     Status,
+    /// Compare Caddy blocks with the database and report drift
+    #[command(alias = "ck")]
+    Check {
+        #[arg(
+            short = 'F',
+            long,
+            help = "Regenerate drifted blocks and remove orphans"
+        )]
+        fix: bool,
+    },
+    /// Regenerate every deep-managed block from the database
+    #[command(alias = "rb")]
+    Rebuild {
+        #[arg(short = 'D', long, help = "Print a unified diff without writing")]
+        dry_run: bool,
+    },
 }
 
 /// Handle proxy subcommands.
-pub fn handle(proxy: &CaddyFile, command: ProxyCommand) -> Result<()> {
+pub fn handle(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    command: ProxyCommand,
+) -> Result<()> {
     match command {
         ProxyCommand::Status => {
             let routes = proxy.list_routes()?;
@@ -51,5 +80,277 @@ pub fn handle(proxy: &CaddyFile, command: ProxyCommand) -> Result<()> {
             }
             Ok(())
         }
+        ProxyCommand::Check { fix } => handle_check(storage, runtime, proxy, fix),
+        ProxyCommand::Rebuild { dry_run } => handle_rebuild(storage, proxy, dry_run),
+    }
+}
+
+fn handle_rebuild(storage: &mut Storage, proxy: &CaddyFile, dry_run: bool) -> Result<()> {
+    let mut blocks = Vec::new();
+    for app in storage.list_apps()? {
+        let Some((release, snapshot)) = current_release_snapshot(storage, &app)? else {
+            continue;
+        };
+        if snapshot.domains.is_empty() {
+            continue;
+        }
+        let extras = route_extras_for_app(storage, &app.id)?;
+        let block = render_app_block(
+            &app.name,
+            &release.id,
+            &snapshot,
+            &extras,
+            proxy.certs_dir(),
+        )
+        .with_context(|| format!("failed to render block for app {}", app.name))?;
+        blocks.push((app.name, block));
+    }
+    blocks.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let contents = proxy.read_contents()?;
+    let updated = rebuild_caddyfile(&contents, &blocks)
+        .with_context(|| format!("failed to rebuild {}", proxy.path().display()))?;
+    if updated == contents {
+        println!("caddyfile up to date ({} app block(s))", blocks.len());
+        return Ok(());
+    }
+    if dry_run {
+        let path = proxy.path().display().to_string();
+        print!(
+            "{}",
+            unified_diff(&path, &format!("{} (rebuilt)", path), &contents, &updated)
+        );
+        return Ok(());
+    }
+    proxy.replace_contents(&contents, &updated)?;
+    println!(
+        "caddyfile rebuilt with {} app block(s); previous version saved to {}",
+        blocks.len(),
+        proxy.path().with_extension("bak").display()
+    );
+    Ok(())
+}
+
+fn handle_check(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+    fix: bool,
+) -> Result<()> {
+    let routes = proxy.list_routes()?;
+    let runtime = available_runtime(runtime, "skipping container state checks");
+
+    let mut upserts: Vec<(String, String)> = Vec::new();
+    let mut removals: Vec<String> = Vec::new();
+    let mut manual = 0;
+    let mut expected_apps = HashSet::new();
+
+    for app in storage.list_apps()? {
+        let Some((release, snapshot)) = current_release_snapshot(storage, &app)? else {
+            continue;
+        };
+        if snapshot.domains.is_empty() {
+            continue;
+        }
+        expected_apps.insert(app.name.clone());
+        let extras = route_extras_for_app(storage, &app.id)?;
+        let block = match render_app_block(
+            &app.name,
+            &release.id,
+            &snapshot,
+            &extras,
+            proxy.certs_dir(),
+        ) {
+            Ok(block) => block,
+            Err(err) => {
+                println!("invalid  {}  {}", app.name, err);
+                manual += 1;
+                continue;
+            }
+        };
+        let expected = parse_caddyfile_routes(&block);
+        let actual: Vec<&RouteStatus> = routes.iter().filter(|r| r.app == app.name).collect();
+        let drift = match (expected.first(), actual.as_slice()) {
+            (_, []) => {
+                println!("missing  {}  no block for release {}", app.name, release.id);
+                true
+            }
+            (Some(expected), [actual]) => {
+                let mut drift = false;
+                if host_set(&expected.hosts) != host_set(&actual.hosts) {
+                    println!(
+                        "drift  {}  hosts expected={} actual={}",
+                        app.name,
+                        expected.hosts.join(","),
+                        actual.hosts.join(",")
+                    );
+                    drift = true;
+                }
+                if expected.upstreams != actual.upstreams {
+                    println!(
+                        "drift  {}  upstream expected={} actual={}",
+                        app.name,
+                        expected.upstreams.join(","),
+                        actual.upstreams.join(",")
+                    );
+                    drift = true;
+                }
+                drift
+            }
+            (_, many) => {
+                println!("duplicate-block  {}  {} blocks", app.name, many.len());
+                true
+            }
+        };
+        if drift {
+            upserts.push((app.name.clone(), block));
+        }
+    }
+
+    for route in &routes {
+        if !expected_apps.contains(&route.app) && !removals.contains(&route.app) {
+            println!(
+                "orphan  {}  no app with a current release and domains",
+                route.app
+            );
+            removals.push(route.app.clone());
+        }
+    }
+
+    let mut host_owners: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for route in &routes {
+        for host in &route.hosts {
+            host_owners
+                .entry(normalize_host(host))
+                .or_default()
+                .insert(route.app.clone());
+        }
+    }
+    for (host, owners) in &host_owners {
+        if owners.len() > 1 {
+            let owners: Vec<&str> = owners.iter().map(String::as_str).collect();
+            println!("duplicate-host  {}  apps={}", host, owners.join(","));
+            manual += 1;
+        }
+    }
+
+    if let Some(runtime) = runtime {
+        for route in &routes {
+            let regenerated = upserts.iter().any(|(app, _)| app == &route.app);
+            if regenerated || removals.contains(&route.app) {
+                continue;
+            }
+            for upstream in &route.upstreams {
+                let container = upstream.split(':').next().unwrap_or(upstream);
+                if !runtime.container_running(container) {
+                    println!("stopped  {}  {}", route.app, container);
+                    manual += 1;
+                }
+            }
+        }
+    }
+
+    let fixable = upserts.len() + removals.len();
+    if fixable == 0 && manual == 0 {
+        println!("proxy check: ok ({} block(s))", routes.len());
+        return Ok(());
+    }
+    if fix && fixable > 0 {
+        proxy.apply_blocks(&upserts, &removals)?;
+        println!(
+            "fixed: regenerated {} block(s), removed {} orphan block(s)",
+            upserts.len(),
+            removals.len()
+        );
+        if manual > 0 {
+            bail!("proxy check: {} issue(s) need manual attention", manual);
+        }
+        return Ok(());
+    }
+    bail!("proxy check found {} issue(s)", fixable + manual)
+}
+
+fn host_set(hosts: &[String]) -> BTreeSet<String> {
+    hosts.iter().map(|host| host.trim().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigSnapshot;
+    use crate::db::ReleaseRow;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, release_row, test_podman,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn check_fix_regenerates_drifted_blocks_and_removes_orphans() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let caddyfile = temp.path().join("Caddyfile");
+        std::fs::write(
+            &caddyfile,
+            "{\n    email ops@example.com\n}\n\
+             # deep:app:app\nold.example.com {\n    reverse_proxy deep-app-app-r1:3000\n}\n# deep:end\n\
+             # deep:app:ghost\nghost.example.com {\n    reverse_proxy deep-app-ghost-r9:3000\n}\n# deep:end\n",
+        )?;
+
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(&["{{.State.Running}}"], 0, "true", "");
+        let _guard = set_runner_for_tests(runner);
+
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        seed_current_release(&mut storage, "app", "r2")?;
+
+        let proxy = CaddyFile::new(caddyfile.clone(), "deep-caddy".to_string(), test_podman());
+        assert!(handle_check(&mut storage, &test_podman(), &proxy, false).is_err());
+        handle_check(&mut storage, &test_podman(), &proxy, true)?;
+
+        let contents = std::fs::read_to_string(&caddyfile)?;
+        assert!(contents.contains("email ops@example.com"));
+        assert!(contents.contains("app.example.com {"));
+        assert!(contents.contains("reverse_proxy deep-app-app-r2:3000"));
+        assert!(!contents.contains("deep:app:ghost"));
+        handle_check(&mut storage, &test_podman(), &proxy, false)?;
+        Ok(())
+    }
+
+    fn seed_current_release(storage: &mut Storage, name: &str, release_id: &str) -> Result<()> {
+        let app = storage.create_app(name, "/tmp")?;
+        let snapshot = ConfigSnapshot {
+            domains: vec![format!("{}.example.com", name)],
+            ..base_snapshot()
+        };
+        let release = ReleaseRow {
+            created_at: "2024-01-02T00:00:00Z".to_string(),
+            ..release_row(&app.id, release_id, &snapshot)
+        };
+        insert_current_release(storage, &release)
+    }
+
+    #[test]
+    fn rebuild_recreates_lost_caddyfile_and_dry_run_leaves_it_alone() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let caddyfile = temp.path().join("Caddyfile");
+        let runner = Arc::new(TestRunner::default());
+        let _guard = set_runner_for_tests(runner);
+
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        seed_current_release(&mut storage, "web", "r1")?;
+        seed_current_release(&mut storage, "api", "r7")?;
+        let proxy = CaddyFile::new(caddyfile.clone(), "deep-caddy".to_string(), test_podman());
+
+        handle_rebuild(&mut storage, &proxy, true)?;
+        assert!(!caddyfile.exists());
+
+        handle_rebuild(&mut storage, &proxy, false)?;
+        let contents = std::fs::read_to_string(&caddyfile)?;
+        let routes = parse_caddyfile_routes(&contents);
+        let apps: Vec<&str> = routes.iter().map(|route| route.app.as_str()).collect();
+        assert_eq!(apps, vec!["api", "web"]);
+        assert!(contents.contains("reverse_proxy deep-app-api-r7:3000"));
+        assert!(caddyfile.with_extension("bak").exists());
+        Ok(())
     }
 }
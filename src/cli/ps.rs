@@ -0,0 +1,187 @@
+//! Host-wide status table for apps, addons and Caddy.
+
+use anyhow::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::{available_runtime, current_release_snapshot, print_table};
+use crate::db::Storage;
+use crate::proxy::CaddyFile;
+use crate::runtime::{ContainerState, Runtime, app_container_name, format_uptime};
+use crate::systemd::{default_quadlet_dir, unit_state_any, unit_state_for_dir};
+
+const HEADERS: [&str; 8] = [
+    "KIND", "NAME", "VERSION", "UNIT", "STATE", "HEALTH", "RESTARTS", "UPTIME",
+];
+
+/// systemd and container state for one deep-managed service.
+pub(crate) struct ServiceStatus {
+    pub unit_state: String,
+    pub container: Option<ContainerState>,
+}
+
+impl ServiceStatus {
+    /// Probe a container; without a runtime only the unit state is known.
+    pub(crate) fn probe(runtime: Option<&Runtime>, container: &str, unit_state: String) -> Self {
+        Self {
+            unit_state,
+            container: runtime.and_then(|runtime| runtime.container_state(container)),
+        }
+    }
+
+    /// State, health, restart count and uptime columns.
+    pub(crate) fn columns(&self, now_unix: i64) -> [String; 4] {
+        let Some(state) = &self.container else {
+            return [
+                "missing".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+            ];
+        };
+        [
+            state.status.clone(),
+            state.health.clone().unwrap_or_else(|| "-".to_string()),
+            state.restart_count.to_string(),
+            state
+                .uptime_secs(now_unix)
+                .map(format_uptime)
+                .unwrap_or_else(|| "-".to_string()),
+        ]
+    }
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Print the host-wide table.
+pub fn handle(storage: &mut Storage, runtime: &Runtime, proxy: &CaddyFile) -> Result<()> {
+    let rows = ps_rows(storage, runtime, proxy)?;
+    print_table(&HEADERS, &rows);
+    Ok(())
+}
+
+fn ps_rows(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    proxy: &CaddyFile,
+) -> Result<Vec<Vec<String>>> {
+    let runtime = available_runtime(runtime, "container state unavailable");
+    let now = unix_now();
+    let mut rows = Vec::new();
+    let mut push = |kind: &str, name: &str, version: &str, status: ServiceStatus| {
+        let mut row = vec![
+            kind.to_string(),
+            name.to_string(),
+            version.to_string(),
+            status.unit_state.clone(),
+        ];
+        row.extend(status.columns(now));
+        rows.push(row);
+    };
+
+    for app in storage.list_apps()? {
+        let Some((release, snapshot)) = current_release_snapshot(storage, &app)? else {
+            push(
+                "app",
+                &app.name,
+                "-",
+                ServiceStatus {
+                    unit_state: "no-release".to_string(),
+                    container: None,
+                },
+            );
+            continue;
+        };
+        let container = app_container_name(&app.name, &release.id);
+        let quadlet_dir = snapshot
+            .deploy
+            .quadlet_dir
+            .clone()
+            .unwrap_or_else(default_quadlet_dir);
+        let unit_state = unit_state_for_dir(&quadlet_dir, &format!("{}.service", container));
+        let status = ServiceStatus::probe(runtime, &container, unit_state);
+        push("app", &app.name, &release.id, status);
+    }
+
+    let addon_dir = default_quadlet_dir();
+    for addon in storage.list_addons()? {
+        let container = format!("deep-addon-{}", addon.name);
+        let image = serde_json::from_str::<serde_json::Value>(&addon.config_json)
+            .ok()
+            .and_then(|config| config["image"].as_str().map(str::to_string))
+            .unwrap_or_else(|| "-".to_string());
+        let unit_state = unit_state_for_dir(&addon_dir, &format!("{}.service", container));
+        let status = ServiceStatus::probe(runtime, &container, unit_state);
+        push("addon", &addon.name, &image, status);
+    }
+
+    let caddy = proxy.container_name();
+    let unit_state = unit_state_any(&format!("{}.service", caddy));
+    let status = ServiceStatus::probe(runtime, caddy, unit_state);
+    push("proxy", caddy, "-", status);
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ReleaseRow;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, release_row, test_podman,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn ps_rows_cover_apps_addons_and_caddy() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(&["is-active deep-app-web-r1"], 0, "active", "");
+        runner.add_rule(&["is-active"], 0, "inactive", "");
+        runner.add_rule(
+            &["inspect deep-app-web-r1"],
+            0,
+            r#"[{"State":{"Status":"running","Health":{"Status":"healthy"},"StartedAt":"2000-01-01T00:00:00Z"},"RestartCount":1}]"#,
+            "",
+        );
+        runner.add_rule(&["inspect deep-addon"], 1, "", "");
+        let _guard = set_runner_for_tests(runner);
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        storage.create_app("idle", "/tmp")?;
+        storage.create_addon("db", "postgres", r#"{"image":"postgres:16"}"#)?;
+        let release = ReleaseRow {
+            image_ref: "ghcr.io/me/web:latest".to_string(),
+            image_digest: "ghcr.io/me/web@sha256:deadbeef".to_string(),
+            ..release_row(&web.id, "r1", &base_snapshot())
+        };
+        insert_current_release(&mut storage, &release)?;
+
+        let proxy = CaddyFile::new(
+            temp.path().join("Caddyfile"),
+            "deep-caddy".to_string(),
+            test_podman(),
+        );
+        let rows = ps_rows(&mut storage, &test_podman(), &proxy)?;
+        let names: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|row| (row[0].as_str(), row[1].as_str()))
+            .collect();
+        assert!(names.contains(&("app", "web")));
+        assert!(names.contains(&("app", "idle")));
+        assert!(names.contains(&("addon", "db")));
+        assert!(names.contains(&("proxy", "deep-caddy")));
+
+        let web_row = rows.iter().find(|row| row[1] == "web").expect("web row");
+        assert_eq!(&web_row[2..7], ["r1", "active", "running", "healthy", "1"]);
+        let idle_row = rows.iter().find(|row| row[1] == "idle").expect("idle row");
+        assert_eq!(idle_row[3], "no-release");
+        let db_row = rows.iter().find(|row| row[1] == "db").expect("db row");
+        assert_eq!(&db_row[2..5], ["postgres:16", "inactive", "missing"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Registry logins stored in deep's auth file.
+
+use anyhow::{Result, bail};
+use clap::Subcommand;
+use std::path::Path;
+
+use crate::cli::{print_table, read_password_from_stdin, read_stdin_line};
+use crate::registry::{DEFAULT_AUTH_FILE, list, login, logout};
+
+#[derive(Subcommand, Debug)]
+/// Registry credential commands.
+pub enum RegistryCommand {
+    /// Store credentials for a registry
+    #[command(alias = "in")]
+    Login {
+        #[arg(help = "Registry host, e.g. ghcr.io")]
+        registry: String,
+        #[arg(short = 'u', long, help = "Username")]
+        username: String,
+        // No argv form: a password there shows up in the process list and shell history.
+        #[arg(
+            short = 's',
+            long,
+            help = "Read the password or token from stdin without prompting"
+        )]
+        password_stdin: bool,
+    },
+    /// Remove stored credentials for a registry
+    #[command(alias = "out")]
+    Logout {
+        #[arg(help = "Registry host")]
+        registry: String,
+    },
+    /// List registries with stored credentials
+    #[command(alias = "ls")]
+    List,
+}
+
+/// Handle registry subcommands.
+pub fn handle(command: RegistryCommand) -> Result<()> {
+    let path = Path::new(DEFAULT_AUTH_FILE);
+    match command {
+        RegistryCommand::Login {
+            registry,
+            username,
+            password_stdin,
+        } => {
+            let password = if password_stdin {
+                read_stdin_line()?
+            } else {
+                read_password_from_stdin()?
+            };
+            if password.is_empty() {
+                bail!("empty password");
+            }
+            login(path, &registry, &username, &password)?;
+            println!("logged in to {} as {}", registry, username);
+            println!(
+                "units written before this login pick it up on the next deploy or addon upgrade"
+            );
+        }
+        RegistryCommand::Logout { registry } => {
+            if logout(path, &registry)? {
+                println!("logged out of {}", registry);
+            } else {
+                println!("no credentials stored for {}", registry);
+            }
+        }
+        RegistryCommand::List => {
+            let logins = list(path)?;
+            if logins.is_empty() {
+                println!("no registry credentials stored");
+                return Ok(());
+            }
+            let rows: Vec<Vec<String>> = logins
+                .into_iter()
+                .map(|login| vec![login.registry, login.username])
+                .collect();
+            print_table(&["REGISTRY", "USERNAME"], &rows);
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,10 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::Subcommand;
 
-use crate::cli::require_app;
-use crate::db::Storage;
+use crate::cli::retention::{prune_releases, releases_to_prune};
+use crate::cli::{confirm, current_release_snapshot, require_app};
+use crate::db::{ReleaseRow, Storage};
+use crate::runtime::Runtime;
 
 #[derive(Subcommand, Debug)]
 /// Release-related commands.
@@ -19,10 +21,29 @@ pub enum ReleasesCommand {
         #[arg(help = "App name")]
         app: String,
     },
+    /// Keep a release through retention until it is unpinned
+    Pin {
+        #[arg(help = "Release id")]
+        release_id: String,
+    },
+    /// Let retention prune a pinned release again
+    Unpin {
+        #[arg(help = "Release id")]
+        release_id: String,
+    },
+    /// Apply the current release's retention settings now
+    Prune {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(short = 'D', long, help = "Show what would be pruned without pruning")]
+        dry_run: bool,
+        #[arg(short = 'y', long, help = "Skip the confirmation prompt")]
+        yes: bool,
+    },
 }
 
 /// Handle release subcommands.
-pub fn handle(storage: &mut Storage, command: ReleasesCommand) -> Result<()> {
+pub fn handle(storage: &mut Storage, runtime: &Runtime, command: ReleasesCommand) -> Result<()> {
     match command {
         ReleasesCommand::List { app } => {
             let app_row = require_app(storage, &app)?;
@@ -31,10 +52,16 @@ pub fn handle(storage: &mut Storage, command: ReleasesCommand) -> Result<()> {
                 println!("no releases for {}", app);
                 return Ok(());
             }
+            let pinned = storage.pinned_release_ids(&app_row.id)?;
             for release in releases {
+                let marker = if pinned.contains(&release.id) {
+                    "  pinned"
+                } else {
+                    ""
+                };
                 println!(
-                    "{}  {}  {}  {}",
-                    release.id, release.status, release.git_sha, release.image_ref
+                    "{}  {}  {}  {}{}",
+                    release.id, release.status, release.git_sha, release.image_ref, marker
                 );
             }
             Ok(())
@@ -53,5 +80,155 @@ pub fn handle(storage: &mut Storage, command: ReleasesCommand) -> Result<()> {
             );
             Ok(())
         }
+        ReleasesCommand::Pin { release_id } => {
+            require_release(storage, &release_id)?;
+            if storage.pin_release(&release_id)? {
+                println!("pinned release {}", release_id);
+            } else {
+                println!("release {} is already pinned", release_id);
+            }
+            Ok(())
+        }
+        ReleasesCommand::Unpin { release_id } => {
+            require_release(storage, &release_id)?;
+            if storage.unpin_release(&release_id)? {
+                println!("unpinned release {}", release_id);
+            } else {
+                println!("release {} is not pinned", release_id);
+            }
+            Ok(())
+        }
+        ReleasesCommand::Prune { app, dry_run, yes } => {
+            let app_row = require_app(storage, &app)?;
+            let deploy = current_release_snapshot(storage, &app_row)?
+                .map(|(_, snapshot)| snapshot.deploy)
+                .unwrap_or_default();
+            let releases = releases_to_prune(storage, &app_row, &deploy)?;
+            if releases.is_empty() {
+                println!("nothing to prune for {}", app);
+                return Ok(());
+            }
+            for release in &releases {
+                println!("{}", prune_line(release));
+            }
+            if dry_run {
+                return Ok(());
+            }
+            let prompt = format!(
+                "Prune {} release(s) of {} with their units and images?",
+                releases.len(),
+                app
+            );
+            if !confirm(&prompt, yes)? {
+                bail!("aborted");
+            }
+            prune_releases(storage, runtime, &app_row, &releases)
+        }
+    }
+}
+
+fn require_release(storage: &Storage, release_id: &str) -> Result<ReleaseRow> {
+    storage
+        .get_release_by_id(release_id)?
+        .with_context(|| format!("release {} not found", release_id))
+}
+
+fn prune_line(release: &ReleaseRow) -> String {
+    format!(
+        "would prune {}  {}  {}  {}",
+        release.id, release.status, release.created_at, release.image_ref
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, dispatch};
+    use crate::config::{ConfigSnapshot, DeployConfig};
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, insert_release, release_row,
+    };
+    use clap::Parser;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn deep(dir: &Path, args: &[&str]) -> Result<()> {
+        let db = dir.join("deep.db");
+        let host_config = dir.join("deep.toml");
+        let mut argv = vec![
+            "deep",
+            "releases",
+            "--db",
+            db.to_str().expect("utf-8 path"),
+            "--host-config",
+            host_config.to_str().expect("utf-8 path"),
+        ];
+        argv.extend_from_slice(args);
+        dispatch(Cli::try_parse_from(argv)?)
+    }
+
+    #[test]
+    fn pin_and_unpin_reject_unknown_release_ids() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let _guard = set_runner_for_tests(Arc::new(TestRunner::default()));
+        Storage::open(&temp.path().join("deep.db"))?;
+        for command in ["pin", "unpin"] {
+            let err = deep(temp.path(), &[command, "r404"]).unwrap_err();
+            assert_eq!(err.to_string(), "release r404 not found");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prune_dry_run_lists_releases_without_pruning() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = Arc::new(TestRunner::default());
+        let _guard = set_runner_for_tests(runner.clone());
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let app = storage.create_app("web", "/tmp")?;
+        let snapshot = ConfigSnapshot {
+            deploy: DeployConfig {
+                retain: 1,
+                ..DeployConfig::default()
+            },
+            ..base_snapshot()
+        };
+        insert_release(&mut storage, &release_row(&app.id, "r1", &snapshot))?;
+        let current = ReleaseRow {
+            created_at: "2024-01-02T00:00:00Z".to_string(),
+            ..release_row(&app.id, "r2", &snapshot)
+        };
+        insert_current_release(&mut storage, &current)?;
+
+        // The dry run returns before the confirmation prompt.
+        deep(temp.path(), &["prune", "web", "--dry-run"])?;
+        assert_eq!(storage.list_releases(&app.id)?.len(), 2);
+        assert!(
+            runner
+                .calls()
+                .iter()
+                .all(|call| !call.contains("rm") && !call.contains("stop")),
+            "{:?}",
+            runner.calls()
+        );
+
+        let lines: Vec<String> = releases_to_prune(&storage, &app, &snapshot.deploy)?
+            .iter()
+            .map(prune_line)
+            .collect();
+        assert_eq!(
+            lines,
+            ["would prune r1  active  2024-01-01T00:00:00Z  ghcr.io/me/app:latest"]
+        );
+
+        deep(temp.path(), &["prune", "web", "--yes"])?;
+        let ids: Vec<String> = storage
+            .list_releases(&app.id)?
+            .into_iter()
+            .map(|release| release.id)
+            .collect();
+        assert_eq!(ids, ["r2"]);
+        Ok(())
     }
 }
@@ -0,0 +1,273 @@
+//! Release retention: which releases an app keeps, and pruning the rest.
+//!
+//! The current and pinned releases are always kept, and so are pending releases
+//! newer than the current one (a deploy may still be running). Active and failed
+//! releases fill separate quotas (`retain`, `retain_failed`), newest first, so a
+//! run of failed deploys cannot push out good rollback targets. Past the quotas,
+//! `retain_days` and `keep_tagged` keep releases by age and by image tag.
+
+use anyhow::Result;
+use std::collections::HashSet;
+
+use crate::cli::gc::remove_pruned_images;
+use crate::cli::ps::unix_now;
+use crate::config::{ConfigSnapshot, DeployConfig};
+use crate::db::{AppRow, ReleaseRow, Storage};
+use crate::runtime::{Runtime, app_container_name, parse_rfc3339_unix};
+use crate::systemd::{default_quadlet_dir, systemctl_for_dir};
+use crate::units::remove_unit;
+
+const ACTIVE: &str = "active";
+const FAILED: &str = "failed";
+const PENDING: &str = "pending";
+
+/// Prune the releases `snapshot`'s retention settings let go, then their images.
+pub(crate) fn enforce_retention(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    app: &AppRow,
+    snapshot: &ConfigSnapshot,
+) -> Result<()> {
+    let pruned = releases_to_prune(storage, app, &snapshot.deploy)?;
+    prune_releases(storage, runtime, app, &pruned)
+}
+
+/// Releases of `app` that `deploy`'s retention settings let go, newest first.
+pub(crate) fn releases_to_prune(
+    storage: &Storage,
+    app: &AppRow,
+    deploy: &DeployConfig,
+) -> Result<Vec<ReleaseRow>> {
+    let releases = storage.list_releases(&app.id)?;
+    let current = storage.current_release_id(&app.id)?;
+    let pinned = storage.pinned_release_ids(&app.id)?;
+    Ok(select_prunable(
+        releases,
+        current.as_deref(),
+        &pinned,
+        deploy,
+        unix_now(),
+    ))
+}
+
+/// Stop and remove each release's unit and record, then images nothing retained uses.
+pub(crate) fn prune_releases(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    app: &AppRow,
+    releases: &[ReleaseRow],
+) -> Result<()> {
+    if releases.is_empty() {
+        return Ok(());
+    }
+    for release in releases {
+        prune_release(storage, runtime, app, release)?;
+    }
+    remove_pruned_images(storage, runtime, releases)
+}
+
+/// Apply the policy to `releases` (newest first) as of `now` (unix seconds).
+fn select_prunable(
+    releases: Vec<ReleaseRow>,
+    current: Option<&str>,
+    pinned: &HashSet<String>,
+    deploy: &DeployConfig,
+    now: i64,
+) -> Vec<ReleaseRow> {
+    let retain = deploy.retain.max(1) as usize;
+    let retain_failed = deploy.retain_failed as usize;
+    let keep_after = deploy
+        .retain_days
+        .map(|days| now - i64::from(days) * 86_400);
+    // The current release takes one of the `retain` slots.
+    let mut kept = usize::from(current.is_some());
+    let mut kept_failed = 0;
+    // Releases come newest first, so everything before the current one is newer.
+    let mut newer_than_current = true;
+    let mut prunable = Vec::new();
+    for release in releases {
+        if Some(release.id.as_str()) == current {
+            newer_than_current = false;
+            continue;
+        }
+        if pinned.contains(&release.id) || (newer_than_current && release.status == PENDING) {
+            continue;
+        }
+        if release.status == FAILED {
+            if kept_failed < retain_failed {
+                kept_failed += 1;
+                continue;
+            }
+        } else if release.status == ACTIVE && kept < retain {
+            kept += 1;
+            continue;
+        }
+        let recent = keep_after.is_some_and(|keep_after| {
+            parse_rfc3339_unix(&release.created_at).is_some_and(|created| created >= keep_after)
+        });
+        if recent || (deploy.keep_tagged && is_tagged(&release)) {
+            continue;
+        }
+        prunable.push(release);
+    }
+    prunable
+}
+
+/// Whether a release's image has a version tag (`1.2`, `v1.2.0-rc.1`) rather than
+/// `latest`, a branch name or a commit SHA.
+fn is_tagged(release: &ReleaseRow) -> bool {
+    let reference = release.image_ref.split('@').next().unwrap_or_default();
+    match reference.rsplit_once(':') {
+        // An all-digit short SHA also matches the pattern.
+        Some((_, tag)) if !tag.contains('/') => {
+            is_version_tag(tag) && !release.git_sha.starts_with(tag)
+        }
+        _ => false,
+    }
+}
+
+/// `v?\d+(\.\d+)*` with an optional `-pre` or `+build` suffix.
+fn is_version_tag(tag: &str) -> bool {
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    let (version, suffix) = match tag.find(['-', '+']) {
+        Some(index) => tag.split_at(index),
+        None => (tag, ""),
+    };
+    let numeric = version
+        .split('.')
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    let suffix_ok = suffix.len() != 1
+        && suffix
+            .chars()
+            .skip(1)
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'));
+    numeric && suffix_ok
+}
+
+fn prune_release(
+    storage: &mut Storage,
+    runtime: &Runtime,
+    app: &AppRow,
+    release: &ReleaseRow,
+) -> Result<()> {
+    let snapshot: ConfigSnapshot =
+        serde_json::from_str(&release.config_json).unwrap_or(ConfigSnapshot {
+            env: Default::default(),
+            port: 0,
+            domains: Vec::new(),
+            addons: Vec::new(),
+            healthcheck: crate::config::HealthcheckConfig::default(),
+            deploy: DeployConfig::default(),
+            proxy: crate::config::ProxyConfig::default(),
+            resources: Default::default(),
+            volumes: Vec::new(),
+        });
+    let unit_name = app_container_name(&app.name, &release.id);
+    let quadlet_dir = snapshot
+        .deploy
+        .quadlet_dir
+        .clone()
+        .unwrap_or_else(default_quadlet_dir);
+    let unit = format!("{}.service", unit_name);
+    let _ = systemctl_for_dir(&quadlet_dir, &["stop", &unit]);
+    let _ = systemctl_for_dir(&quadlet_dir, &["disable", &unit]);
+    let _ = remove_unit(runtime, &quadlet_dir, &unit_name);
+    let _ = systemctl_for_dir(&quadlet_dir, &["daemon-reload"]);
+
+    storage.delete_deployments_for_release(&release.id)?;
+    storage.delete_release(&release.id)?;
+    println!("pruned release {} for {}", release.id, app.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{base_snapshot, release_row};
+
+    fn release(id: &str, day: u32, status: &str, tag: &str) -> ReleaseRow {
+        ReleaseRow {
+            created_at: format!("2024-05-{:02}T12:00:00Z", day),
+            git_sha: "0123456789abcdef".to_string(),
+            image_ref: format!("ghcr.io/me/web:{}", tag),
+            status: status.to_string(),
+            ..release_row("app-id", id, &base_snapshot())
+        }
+    }
+
+    fn pruned(deploy: &DeployConfig, pinned: &[&str]) -> Vec<String> {
+        // Newest first, as `list_releases` returns them.
+        let releases = vec![
+            release("r8", 8, "failed", "0123456"),
+            release("r7", 7, "failed", "0123456"),
+            release("r6", 6, "active", "latest"),
+            release("r5", 5, "active", "0123456"),
+            release("r4", 4, "active", "v1.2.0"),
+            release("r3", 3, "failed", "0123456"),
+            release("r2", 2, "active", "0123456"),
+            release("r1", 1, "active", "v1.0.0"),
+        ];
+        let pinned = pinned.iter().map(|id| id.to_string()).collect();
+        let now = parse_rfc3339_unix("2024-05-08T13:00:00Z").expect("now");
+        select_prunable(releases, Some("r5"), &pinned, deploy, now)
+            .into_iter()
+            .map(|release| release.id)
+            .collect()
+    }
+
+    #[test]
+    fn retention_keeps_quotas_by_status_then_age_tags_and_pins() {
+        let deploy = DeployConfig {
+            retain: 2,
+            ..DeployConfig::default()
+        };
+        // Current r5 and the newest good r6 fill `retain`; r8 fills `retain_failed`.
+        assert_eq!(pruned(&deploy, &[]), ["r7", "r4", "r3", "r2", "r1"]);
+        assert_eq!(pruned(&deploy, &["r2"]), ["r7", "r4", "r3", "r1"]);
+
+        let deploy = DeployConfig {
+            retain: 2,
+            retain_failed: 0,
+            retain_days: Some(3),
+            keep_tagged: true,
+            ..DeployConfig::default()
+        };
+        // Releases from the last three days stay; so do version tags.
+        assert_eq!(pruned(&deploy, &[]), ["r3", "r2"]);
+    }
+
+    #[test]
+    fn retention_counts_only_active_releases_and_keeps_newer_pending() {
+        let deploy = DeployConfig {
+            retain: 2,
+            retain_failed: 0,
+            ..DeployConfig::default()
+        };
+        let releases = vec![
+            release("r5", 5, "pending", "0123456"),
+            release("r4", 4, "active", "0123456"),
+            release("r3", 3, "pending", "0123456"),
+            release("r2", 2, "active", "0123456"),
+            release("r1", 1, "active", "0123456"),
+        ];
+        let now = parse_rfc3339_unix("2024-05-08T13:00:00Z").expect("now");
+        let ids: Vec<String> = select_prunable(releases, Some("r4"), &HashSet::new(), &deploy, now)
+            .into_iter()
+            .map(|release| release.id)
+            .collect();
+        // r5 may still be deploying; the stale r3 does not take r2's slot.
+        assert_eq!(ids, ["r3", "r1"]);
+    }
+
+    #[test]
+    fn version_tags_are_recognized() {
+        for tag in ["1", "v1.2.0", "2024.05.01", "v2.0.0-rc.1", "1.4+build.7"] {
+            assert!(is_version_tag(tag), "{tag}");
+        }
+        for tag in [
+            "latest", "main", "deadbeef", "v", "1.", "1..2", "1.2-", "stable-1",
+        ] {
+            assert!(!is_version_tag(tag), "{tag}");
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Persistent app volumes: listing, inspection, removal and backups.
+
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{current_release_snapshot, print_table, require_app};
+use crate::config::VolumeConfig;
+use crate::db::{AppRow, Storage};
+use crate::runner;
+use crate::runtime::Runtime;
+
+const HEADERS: [&str; 4] = ["NAME", "SOURCE", "MOUNT", "STATE"];
+
+#[derive(Subcommand, Debug)]
+pub enum VolumesCommand {
+    /// List declared and leftover volumes for an app
+    #[command(alias = "ls")]
+    List {
+        #[arg(help = "App name")]
+        app: String,
+    },
+    /// Show details of one volume
+    #[command(alias = "i")]
+    Inspect {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(help = "Volume name from app.toml")]
+        name: String,
+    },
+    /// Remove a named volume and its data
+    #[command(alias = "rm")]
+    Remove {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(help = "Volume name from app.toml")]
+        name: String,
+        #[arg(short = 'y', long, help = "Confirm deleting the volume's data")]
+        yes: bool,
+    },
+    /// Archive volume contents into tar files
+    #[command(alias = "b")]
+    Backup {
+        #[arg(help = "App name")]
+        app: String,
+        #[arg(help = "Volume name (all declared volumes when omitted)")]
+        name: Option<String>,
+        #[arg(
+            short = 'o',
+            long,
+            default_value = "/srv/deep/backups/volumes",
+            help = "Directory for backup archives (a subdirectory per app)"
+        )]
+        output_dir: PathBuf,
+    },
+}
+
+/// Dispatch volume subcommands.
+pub fn handle(storage: &mut Storage, runtime: &Runtime, command: VolumesCommand) -> Result<()> {
+    match command {
+        VolumesCommand::List { app } => {
+            let app_row = require_app(storage, &app)?;
+            let rows = volume_rows(storage, runtime, &app_row)?;
+            if rows.is_empty() {
+                println!("no volumes for {}", app_row.name);
+                return Ok(());
+            }
+            print_table(&HEADERS, &rows);
+            Ok(())
+        }
+        VolumesCommand::Inspect { app, name } => {
+            let app_row = require_app(storage, &app)?;
+            let volume = find_volume(storage, runtime, &app_row, &name)?;
+            if !volume.is_named() {
+                let source = volume.source(&app_row.name);
+                println!("volume {} (bind mount)", volume.name);
+                println!("  host path: {}", source);
+                println!("  mount: {}", volume.path);
+                println!(
+                    "  exists: {}",
+                    if Path::new(&source).exists() {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                );
+                return Ok(());
+            }
+            let output = runtime
+                .inspect_volume(&volume.volume_name(&app_row.name))
+                .with_context(|| format!("volume {} has not been created yet", name))?;
+            print!("{}", output);
+            Ok(())
+        }
+        VolumesCommand::Remove { app, name, yes } => {
+            let app_row = require_app(storage, &app)?;
+            let volume = find_volume(storage, runtime, &app_row, &name)?;
+            if !volume.is_named() {
+                bail!(
+                    "volume {} is a bind mount of {}; deep does not delete host paths",
+                    name,
+                    volume.source(&app_row.name)
+                );
+            }
+            let volume_name = volume.volume_name(&app_row.name);
+            if !yes {
+                bail!(
+                    "removing {} deletes its data; pass --yes to confirm",
+                    volume_name
+                );
+            }
+            runtime.remove_volume(&volume_name).with_context(|| {
+                format!(
+                    "failed to remove {}; stop containers that still mount it",
+                    volume_name
+                )
+            })?;
+            let payload = serde_json::json!({
+                "app": app_row.name,
+                "volume": volume_name,
+            });
+            let _ = storage.insert_event("volume_removed", &payload.to_string());
+            println!("removed volume {}", volume_name);
+            Ok(())
+        }
+        VolumesCommand::Backup {
+            app,
+            name,
+            output_dir,
+        } => {
+            let app_row = require_app(storage, &app)?;
+            let volumes = match name {
+                Some(name) => vec![find_volume(storage, runtime, &app_row, &name)?],
+                None => declared_volumes(storage, &app_row)?,
+            };
+            if volumes.is_empty() {
+                println!("no volumes declared for {}", app_row.name);
+                return Ok(());
+            }
+            let dir = output_dir.join(&app_row.name);
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+            let stamp = backup_stamp();
+            for volume in volumes {
+                let path = backup_volume(runtime, &app_row.name, &volume, &dir, &stamp)?;
+                println!("backed up {} to {}", volume.name, path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// UTC timestamp used in backup file and directory names (`20240501T103000Z`).
+pub(crate) fn backup_stamp() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+fn declared_volumes(storage: &Storage, app_row: &AppRow) -> Result<Vec<VolumeConfig>> {
+    Ok(current_release_snapshot(storage, app_row)?
+        .map(|(_, snapshot)| snapshot.volumes)
+        .unwrap_or_default())
+}
+
+/// Find a volume declared in the app's config, or a leftover one labeled as the app's.
+fn find_volume(
+    storage: &Storage,
+    runtime: &Runtime,
+    app_row: &AppRow,
+    name: &str,
+) -> Result<VolumeConfig> {
+    if let Some(volume) = declared_volumes(storage, app_row)?
+        .into_iter()
+        .find(|volume| volume.name == name)
+    {
+        return Ok(volume);
+    }
+    // Volumes dropped from app.toml keep their data until removed; the owner labels
+    // stop `deep-vol-a-b-c` of app `a-b` from being reached as volume `b-c` of app `a`.
+    let leftover = VolumeConfig {
+        name: name.to_string(),
+        path: "-".to_string(),
+        host_path: None,
+    };
+    let owner = runtime.volume_owner(&leftover.volume_name(&app_row.name))?;
+    if owner != Some((app_row.name.clone(), name.to_string())) {
+        bail!(
+            "volume {} is not declared in {}'s app.toml",
+            name,
+            app_row.name
+        );
+    }
+    Ok(leftover)
+}
+
+fn volume_rows(storage: &Storage, runtime: &Runtime, app_row: &AppRow) -> Result<Vec<Vec<String>>> {
+    let declared = declared_volumes(storage, app_row)?;
+    let mut rows = Vec::new();
+    for volume in &declared {
+        let source = volume.source(&app_row.name);
+        let exists = if volume.is_named() {
+            runtime.volume_exists(&source)
+        } else {
+            Path::new(&source).exists()
+        };
+        rows.push(vec![
+            volume.name.clone(),
+            source,
+            volume.path.clone(),
+            if exists { "present" } else { "missing" }.to_string(),
+        ]);
+    }
+    let prefix = format!("deep-vol-{}-", app_row.name);
+    for existing in runtime.list_app_volumes(&app_row.name)? {
+        if declared
+            .iter()
+            .any(|volume| volume.is_named() && volume.volume_name(&app_row.name) == existing)
+        {
+            continue;
+        }
+        let name = existing
+            .strip_prefix(&prefix)
+            .unwrap_or(&existing)
+            .to_string();
+        rows.push(vec![name, existing, "-".to_string(), "unused".to_string()]);
+    }
+    Ok(rows)
+}
+
+fn backup_volume(
+    runtime: &Runtime,
+    app_name: &str,
+    volume: &VolumeConfig,
+    dir: &Path,
+    stamp: &str,
+) -> Result<PathBuf> {
+    let path = dir.join(format!("{}-{}.tar", volume.name, stamp));
+    let source = volume.source(app_name);
+    if volume.is_named() {
+        runtime
+            .export_volume(&source, &path)
+            .with_context(|| format!("failed to export {}", source))?;
+        return Ok(path);
+    }
+    let target = path.to_string_lossy();
+    let output = runner::run_output("tar", &["-C", &source, "-cf", target.as_ref(), "."])?;
+    if !output.status.success() {
+        bail!(
+            "tar of {} failed: {}",
+            source,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigSnapshot;
+    use crate::db::ReleaseRow;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::{
+        TestRunner, base_snapshot, insert_current_release, release_row, test_podman,
+    };
+    use std::sync::Arc;
+
+    fn volume_runner() -> Arc<TestRunner> {
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(
+            &["volume ls"],
+            0,
+            "deep-vol-web-data\ndeep-vol-web-old\n",
+            "",
+        );
+        runner.add_rule(
+            &["volume inspect deep-vol-web-old"],
+            0,
+            r#"[{"Labels":{"deep.app":"web","deep.volume":"old"}}]"#,
+            "",
+        );
+        runner.add_rule(
+            &["volume inspect deep-vol-web-b-c"],
+            0,
+            r#"[{"Labels":{"deep.app":"web-b","deep.volume":"c"}}]"#,
+            "",
+        );
+        runner.add_rule(&["volume inspect deep-vol-web-cache"], 1, "", "");
+        runner
+    }
+
+    fn seed_release(storage: &mut Storage, app: &AppRow, volumes: Vec<VolumeConfig>) -> Result<()> {
+        let snapshot = ConfigSnapshot {
+            volumes,
+            ..base_snapshot()
+        };
+        let release = ReleaseRow {
+            image_ref: "ghcr.io/me/web:latest".to_string(),
+            image_digest: "ghcr.io/me/web@sha256:deadbeef".to_string(),
+            ..release_row(&app.id, "r1", &snapshot)
+        };
+        insert_current_release(storage, &release)
+    }
+
+    #[test]
+    fn volumes_list_marks_missing_and_unused_and_backup_exports() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = volume_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        let host_dir = temp.path().join("uploads");
+        std::fs::create_dir_all(&host_dir)?;
+        seed_release(
+            &mut storage,
+            &web,
+            vec![
+                VolumeConfig {
+                    name: "data".to_string(),
+                    path: "/var/lib/web".to_string(),
+                    host_path: None,
+                },
+                VolumeConfig {
+                    name: "cache".to_string(),
+                    path: "/cache".to_string(),
+                    host_path: None,
+                },
+                VolumeConfig {
+                    name: "uploads".to_string(),
+                    path: "/uploads".to_string(),
+                    host_path: Some(host_dir.to_string_lossy().to_string()),
+                },
+            ],
+        )?;
+
+        let runtime = test_podman();
+        let rows = volume_rows(&storage, &runtime, &web)?;
+        let states: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|row| (row[0].as_str(), row[3].as_str()))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ("data", "present"),
+                ("cache", "missing"),
+                ("uploads", "present"),
+                ("old", "unused"),
+            ]
+        );
+
+        let out = temp.path().join("backups");
+        handle(
+            &mut storage,
+            &test_podman(),
+            VolumesCommand::Backup {
+                app: "web".to_string(),
+                name: None,
+                output_dir: out.clone(),
+            },
+        )?;
+        let calls = runner.calls();
+        let export = calls
+            .iter()
+            .find(|call| call.contains("volume export"))
+            .expect("volume export call");
+        assert!(export.contains(&out.join("web").to_string_lossy().to_string()));
+        assert!(export.ends_with(" deep-vol-web-data"));
+        assert!(
+            calls
+                .iter()
+                .any(|call| call.starts_with("tar -C ") && call.contains("uploads-"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn volumes_remove_requires_yes_and_refuses_bind_mounts() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let runner = volume_runner();
+        let _guard = set_runner_for_tests(runner.clone());
+        let mut storage = Storage::open(&temp.path().join("deep.db"))?;
+        let web = storage.create_app("web", "/tmp")?;
+        seed_release(
+            &mut storage,
+            &web,
+            vec![VolumeConfig {
+                name: "uploads".to_string(),
+                path: "/uploads".to_string(),
+                host_path: Some("/srv/uploads".to_string()),
+            }],
+        )?;
+        let remove = |name: &str, yes: bool| VolumesCommand::Remove {
+            app: "web".to_string(),
+            name: name.to_string(),
+            yes,
+        };
+
+        let err = handle(&mut storage, &test_podman(), remove("uploads", true)).unwrap_err();
+        assert!(err.to_string().contains("bind mount"));
+        let err = handle(&mut storage, &test_podman(), remove("old", false)).unwrap_err();
+        assert!(err.to_string().contains("--yes"));
+        let err = handle(&mut storage, &test_podman(), remove("b-c", true)).unwrap_err();
+        assert!(err.to_string().contains("not declared"));
+        assert!(
+            test_podman()
+                .ensure_volume("deep-vol-web-b-c", "web", "b-c")
+                .is_err()
+        );
+        handle(&mut storage, &test_podman(), remove("old", true))?;
+        let calls = runner.calls();
+        assert!(
+            calls
+                .iter()
+                .any(|call| call == "podman volume rm deep-vol-web-old")
+        );
+        Ok(())
+    }
+}
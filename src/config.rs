@@ -1,9 +1,12 @@
 //! App configuration and deploy defaults loaded from app.toml.
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Host-level settings shared by every app and addon on the VPS.
+pub const DEFAULT_HOST_CONFIG: &str = "/srv/deep/deep.toml";
 
 #[derive(Debug, Deserialize)]
 /// Top-level app.toml representation.
@@ -15,6 +18,12 @@ pub struct AppConfig {
     pub healthcheck: HealthcheckConfig,
     #[serde(default)]
     pub deploy: DeployConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub resources: ResourcesConfig,
+    #[serde(default)]
+    pub volumes: Vec<VolumeConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,6 +44,12 @@ pub struct ConfigSnapshot {
     pub addons: Vec<AddonSnapshot>,
     pub healthcheck: HealthcheckConfig,
     pub deploy: DeployConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub resources: ResourcesConfig,
+    #[serde(default)]
+    pub volumes: Vec<VolumeConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,6 +107,9 @@ impl AppConfig {
             addons,
             healthcheck: self.healthcheck.clone(),
             deploy: self.deploy.clone(),
+            proxy: self.proxy.clone(),
+            resources: self.resources.clone(),
+            volumes: self.volumes.clone(),
         }
     }
 }
@@ -101,6 +119,252 @@ pub fn load_app_config(path: &Path) -> Result<AppConfig> {
     let raw = std::fs::read_to_string(path)
         .with_context(|| format!("failed to read app config at {}", path.display()))?;
     let cfg: AppConfig = toml::from_str(&raw).with_context(|| "failed to parse app.toml")?;
+    validate_volumes(&cfg.volumes)?;
+    validate_proxy(&cfg.proxy)?;
+    Ok(cfg)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// Persistent volume mounted into every release of an app.
+pub struct VolumeConfig {
+    pub name: String,
+    /// Mount path inside the container.
+    pub path: String,
+    /// Bind-mount this host directory instead of a named Podman volume.
+    pub host_path: Option<String>,
+}
+
+impl VolumeConfig {
+    /// Podman volume name shared by all releases of the app.
+    pub fn volume_name(&self, app_name: &str) -> String {
+        format!("deep-vol-{}-{}", app_name, self.name)
+    }
+
+    /// Volume source: the host path for bind mounts, otherwise the named volume.
+    pub fn source(&self, app_name: &str) -> String {
+        match non_empty(&self.host_path) {
+            Some(host_path) => host_path.to_string(),
+            None => self.volume_name(app_name),
+        }
+    }
+
+    /// Whether this is a named Podman volume (not a bind mount).
+    pub fn is_named(&self) -> bool {
+        non_empty(&self.host_path).is_none()
+    }
+
+    /// Render the quadlet `Volume=` line.
+    pub fn quadlet_line(&self, app_name: &str) -> String {
+        format!("Volume={}:{}", self.source(app_name), self.path)
+    }
+}
+
+/// Reject volumes with unsafe names, relative paths or duplicates.
+pub fn validate_volumes(volumes: &[VolumeConfig]) -> Result<()> {
+    let mut names = std::collections::BTreeSet::new();
+    let mut paths = std::collections::BTreeSet::new();
+    for volume in volumes {
+        if volume.name.is_empty()
+            || !volume
+                .name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        {
+            bail!(
+                "invalid volume name {:?}; use letters, digits, '-' or '_'",
+                volume.name
+            );
+        }
+        if !volume.path.starts_with('/') {
+            bail!("volume {} path must be absolute", volume.name);
+        }
+        if !is_mount_path(&volume.path) {
+            bail!(
+                "volume {} path {:?} must not contain ':' or control characters",
+                volume.name,
+                volume.path
+            );
+        }
+        if let Some(host_path) = non_empty(&volume.host_path) {
+            if !host_path.starts_with('/') {
+                bail!("volume {} host_path must be absolute", volume.name);
+            }
+            if !is_mount_path(host_path) {
+                bail!(
+                    "volume {} host_path {:?} must not contain ':' or control characters",
+                    volume.name,
+                    host_path
+                );
+            }
+        }
+        if !names.insert(volume.name.as_str()) {
+            bail!("duplicate volume name {}", volume.name);
+        }
+        if !paths.insert(volume.path.as_str()) {
+            bail!("volume path {} is mounted twice", volume.path);
+        }
+    }
+    Ok(())
+}
+
+/// `Volume=source:target` splits on ':', and a newline would start a new unit key.
+fn is_mount_path(path: &str) -> bool {
+    !path.contains(':') && !path.chars().any(char::is_control)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+/// Container resource limits for an app or addon.
+pub struct ResourcesConfig {
+    /// Memory limit (e.g. `512m`, `2g`).
+    pub memory: Option<String>,
+    /// Memory plus swap limit; `-1` allows unlimited swap.
+    pub memory_swap: Option<String>,
+    /// CPU quota in cores (e.g. `1.5`).
+    pub cpus: Option<f64>,
+    pub pids_limit: Option<u32>,
+    /// Size of `/dev/shm` (e.g. `256m`).
+    pub shm_size: Option<String>,
+}
+
+impl ResourcesConfig {
+    /// Render the quadlet `[Container]` lines for these limits (empty when unset).
+    pub fn quadlet_lines(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(memory) = non_empty(&self.memory) {
+            lines.push(format!("Memory={}", memory));
+        }
+        if let Some(swap) = non_empty(&self.memory_swap) {
+            lines.push(format!("PodmanArgs=--memory-swap={}", swap));
+        }
+        if let Some(cpus) = self.cpus {
+            lines.push(format!("PodmanArgs=--cpus={}", cpus));
+        }
+        if let Some(pids) = self.pids_limit {
+            lines.push(format!("PidsLimit={}", pids));
+        }
+        if let Some(shm) = non_empty(&self.shm_size) {
+            lines.push(format!("ShmSize={}", shm));
+        }
+        lines.join("\n")
+    }
+
+    /// Whether no limit is configured.
+    pub fn is_empty(&self) -> bool {
+        self == &ResourcesConfig::default()
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+#[derive(Debug, Deserialize, Default)]
+/// Host-level deep.toml representation.
+pub struct HostConfig {
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    #[serde(default)]
+    pub caddy: CaddyConfig,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Host directories mounted into the Caddy container.
+pub struct CaddyConfig {
+    /// Host directory mounted at `/certs`; custom `proxy.tls` paths resolve against it.
+    #[serde(default = "default_caddy_certs_dir")]
+    pub certs_dir: PathBuf,
+}
+
+impl Default for CaddyConfig {
+    fn default() -> Self {
+        Self {
+            certs_dir: default_caddy_certs_dir(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+/// Container engine selection.
+pub struct RuntimeConfig {
+    /// `podman` or `docker`; detected (Podman first) when unset.
+    pub engine: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+/// Container log driver for app/addon quadlets and optional log shipping.
+pub struct LoggingConfig {
+    /// Quadlet `LogDriver=` value (e.g. `journald`, `k8s-file`).
+    pub driver: Option<String>,
+    /// Quadlet `LogOpt=` values (e.g. `tag=deep`).
+    #[serde(default)]
+    pub options: Vec<String>,
+    pub ship: Option<ShipConfig>,
+}
+
+impl LoggingConfig {
+    /// Render the `LogDriver=`/`LogOpt=` quadlet lines (empty when unset).
+    pub fn quadlet_lines(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(driver) = self.driver.as_deref().filter(|d| !d.trim().is_empty()) {
+            lines.push(format!("LogDriver={}", driver.trim()));
+        }
+        for option in &self.options {
+            lines.push(format!("LogOpt={}", option));
+        }
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Destination for `deep host logship`.
+pub enum ShipSink {
+    /// Rotating `<dir>/<app>/<release>.log` files.
+    File,
+    /// Loki-compatible JSON push endpoint.
+    Loki,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Settings for forwarding journald entries of deep units.
+pub struct ShipConfig {
+    pub sink: ShipSink,
+    #[serde(default = "default_ship_dir")]
+    pub dir: PathBuf,
+    #[serde(default = "default_ship_max_size_mb")]
+    pub max_size_mb: u64,
+    #[serde(default = "default_ship_keep")]
+    pub keep: u32,
+    /// Push URL, e.g. `http://127.0.0.1:3100/loki/api/v1/push`.
+    pub url: Option<String>,
+    /// Extra labels attached to every pushed stream.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Unit patterns passed to `journalctl -u`.
+    #[serde(default = "default_ship_units")]
+    pub units: Vec<String>,
+    #[serde(default = "default_ship_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_ship_cursor_file")]
+    pub cursor_file: PathBuf,
+    /// Most entries written or pushed per request.
+    #[serde(default = "default_ship_batch_size")]
+    pub batch_size: usize,
+}
+
+/// Load deep.toml from disk; a missing file yields the defaults.
+pub fn load_host_config(path: &Path) -> Result<HostConfig> {
+    if !path.exists() {
+        return Ok(HostConfig::default());
+    }
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read host config at {}", path.display()))?;
+    let cfg: HostConfig = toml::from_str(&raw).with_context(|| "failed to parse deep.toml")?;
     Ok(cfg)
 }
 
@@ -113,8 +377,22 @@ pub struct DeployConfig {
     pub git_ref: Option<String>,
     pub quadlet_dir: Option<String>,
     pub image_template: Option<String>,
+    /// Successful releases kept, newest first; the current one counts.
     #[serde(default = "default_deploy_retain")]
     pub retain: u32,
+    /// Failed releases kept, newest first, on top of `retain`.
+    #[serde(default = "default_deploy_retain_failed")]
+    pub retain_failed: u32,
+    /// Keep every release younger than this many days, whatever the counts.
+    pub retain_days: Option<u32>,
+    /// Keep releases deployed from a version tag (not `latest` or a commit SHA).
+    #[serde(default)]
+    pub keep_tagged: bool,
+    /// Seconds to wait for bound addons to report healthy before starting the app.
+    pub addon_wait_secs: Option<u64>,
+    /// Run the app on its own `deep-app-<name>-net` instead of the shared deep-net.
+    #[serde(default)]
+    pub private_network: bool,
 }
 
 impl Default for DeployConfig {
@@ -127,10 +405,204 @@ impl Default for DeployConfig {
             quadlet_dir: None,
             image_template: None,
             retain: default_deploy_retain(),
+            retain_failed: default_deploy_retain_failed(),
+            retain_days: None,
+            keep_tagged: false,
+            addon_wait_secs: None,
+            private_network: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Access controls rendered into the app's Caddy block.
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub allow_cidrs: Vec<String>,
+    pub auth: Option<ProxyAuthConfig>,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Per-app Caddy access log written as JSON under the Caddy logs directory.
+pub struct AccessLogConfig {
+    #[serde(default = "default_access_log_enabled")]
+    pub enabled: bool,
+    /// Size at which the log file is rotated (Caddy size syntax, e.g. `10MiB`).
+    #[serde(default = "default_access_log_roll_size")]
+    pub roll_size: String,
+    /// Number of rotated files to keep.
+    #[serde(default = "default_access_log_roll_keep")]
+    pub roll_keep: u32,
+    /// How long rotated files are kept (Caddy duration syntax, e.g. `720h`).
+    #[serde(default = "default_access_log_roll_keep_for")]
+    pub roll_keep_for: String,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_access_log_enabled(),
+            roll_size: default_access_log_roll_size(),
+            roll_keep: default_access_log_roll_keep(),
+            roll_keep_for: default_access_log_roll_keep_for(),
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Basic auth settings; users and password hashes are stored in the database.
+pub struct ProxyAuthConfig {
+    pub realm: Option<String>,
+}
+
+/// Reject proxy values that would break out of the rendered Caddyfile block.
+pub fn validate_proxy(proxy: &ProxyConfig) -> Result<()> {
+    for cidr in &proxy.allow_cidrs {
+        validate_cidr(cidr)?;
+    }
+    if let Some(realm) = proxy.auth.as_ref().and_then(|auth| auth.realm.as_deref())
+        && (realm.is_empty()
+            || !realm
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || " -_.@".contains(ch)))
+    {
+        bail!(
+            "invalid proxy.auth realm {:?}; use letters, digits, spaces or '-', '_', '.', '@'",
+            realm
+        );
+    }
+    let access_log = &proxy.access_log;
+    if !is_caddy_size(&access_log.roll_size) {
+        bail!(
+            "invalid proxy.access_log roll_size {:?}; use a size like 10MiB or 100MB",
+            access_log.roll_size
+        );
+    }
+    if !is_caddy_duration(&access_log.roll_keep_for) {
+        bail!(
+            "invalid proxy.access_log roll_keep_for {:?}; use a duration like 720h or 30d",
+            access_log.roll_keep_for
+        );
+    }
+    Ok(())
+}
+
+/// Caddy size syntax: a number with an optional `B`, `KB`/`KiB`... unit.
+fn is_caddy_size(value: &str) -> bool {
+    let Some(index) = value.find(|ch: char| !ch.is_ascii_digit() && ch != '.') else {
+        return is_decimal(value);
+    };
+    let (number, unit) = value.split_at(index);
+    let unit = unit.strip_prefix(' ').unwrap_or(unit).to_ascii_lowercase();
+    is_decimal(number)
+        && matches!(
+            unit.as_str(),
+            "b" | "k" | "kb" | "kib" | "m" | "mb" | "mib" | "g" | "gb" | "gib" | "t" | "tb" | "tib"
+        )
+}
+
+/// Caddy duration syntax: Go durations (`1h30m`) plus a `d` unit for days.
+fn is_caddy_duration(value: &str) -> bool {
+    let mut rest = value;
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let Some(unit) = ["ns", "us", "µs", "ms", "s", "m", "h", "d"]
+            .into_iter()
+            .filter(|unit| tail.starts_with(unit))
+            .max_by_key(|unit| unit.len())
+        else {
+            return false;
+        };
+        if !is_decimal(number) {
+            return false;
+        }
+        rest = &tail[unit.len()..];
+    }
+    true
+}
+
+fn is_decimal(value: &str) -> bool {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    !whole.is_empty()
+        && !fraction.is_empty()
+        && whole.chars().all(|ch| ch.is_ascii_digit())
+        && fraction.chars().all(|ch| ch.is_ascii_digit())
+}
+
+/// Accept an IP address with an optional prefix length valid for its family.
+pub fn validate_cidr(cidr: &str) -> Result<()> {
+    let (addr, prefix) = match cidr.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (cidr, None),
+    };
+    let addr: std::net::IpAddr = addr
+        .parse()
+        .with_context(|| format!("invalid proxy.allow_cidrs entry {:?}", cidr))?;
+    if let Some(prefix) = prefix {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u8>() {
+            Ok(bits) if bits <= max && !prefix.starts_with('+') => {}
+            _ => bail!(
+                "invalid prefix length in proxy.allow_cidrs entry {:?}; expected 0-{}",
+                cidr,
+                max
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Accept basic auth usernames made of letters, digits and `-`, `_`, `.`, `@`.
+pub fn validate_auth_username(user: &str) -> Result<()> {
+    if user.is_empty()
+        || !user
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_.@".contains(ch))
+    {
+        bail!(
+            "invalid username {:?}; use letters, digits or '-', '_', '.', '@'",
+            user
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+/// How Caddy obtains certificates for an app's domains.
+pub enum TlsMode {
+    /// Automatic HTTPS via ACME.
+    #[default]
+    Auto,
+    /// Certificates issued by Caddy's local CA (LAN/.internal hosts).
+    Internal,
+    /// Certificate and key files mounted into the Caddy container.
+    Custom,
+    /// Plain HTTP (e.g. behind a CDN that terminates TLS).
+    Off,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// TLS settings for an app route.
+pub struct TlsConfig {
+    #[serde(default)]
+    pub mode: TlsMode,
+    /// Certificate path, relative to the Caddy certs directory unless absolute.
+    pub cert: Option<String>,
+    /// Key path, relative to the Caddy certs directory unless absolute.
+    pub key: Option<String>,
+}
+
 fn default_health_kind() -> HealthcheckKind {
     HealthcheckKind::Http
 }
@@ -154,3 +626,55 @@ fn default_health_interval_ms() -> u64 {
 fn default_deploy_retain() -> u32 {
     10
 }
+
+fn default_deploy_retain_failed() -> u32 {
+    1
+}
+
+fn default_caddy_certs_dir() -> PathBuf {
+    PathBuf::from(crate::proxy::DEFAULT_CADDY_CERTS_DIR)
+}
+
+fn default_ship_dir() -> PathBuf {
+    PathBuf::from("/srv/deep/logs")
+}
+
+fn default_ship_max_size_mb() -> u64 {
+    10
+}
+
+fn default_ship_keep() -> u32 {
+    5
+}
+
+fn default_ship_units() -> Vec<String> {
+    vec!["deep-app-*".to_string()]
+}
+
+fn default_ship_interval_secs() -> u64 {
+    5
+}
+
+fn default_ship_cursor_file() -> PathBuf {
+    PathBuf::from("/srv/deep/logs/.ship-cursor")
+}
+
+fn default_ship_batch_size() -> usize {
+    500
+}
+
+fn default_access_log_enabled() -> bool {
+    true
+}
+
+fn default_access_log_roll_size() -> String {
+    "10MiB".to_string()
+}
+
+fn default_access_log_roll_keep() -> u32 {
+    5
+}
+
+fn default_access_log_roll_keep_for() -> String {
+    "720h".to_string()
+}
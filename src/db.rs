@@ -3,6 +3,7 @@
 use anyhow::{Context, Result};
 use rusqlite::{Connection, OptionalExtension, Transaction, params};
 use serde_json::Value;
+use std::collections::HashSet;
 use std::path::Path;
 use time::OffsetDateTime;
 use ulid::Ulid;
@@ -11,6 +12,19 @@ use crate::config::AddonSnapshot;
 
 const MIGRATION_SQL: &str = include_str!("../migrations/001_init.sql");
 const MIGRATION_SQL_2: &str = include_str!("../migrations/002_bindings_config.sql");
+const MIGRATION_SQL_3: &str = include_str!("../migrations/003_proxy_auth_users.sql");
+const MIGRATION_SQL_4: &str = include_str!("../migrations/004_deployment_finished_at.sql");
+const MIGRATION_SQL_5: &str = include_str!("../migrations/005_addon_revisions.sql");
+const MIGRATION_SQL_6: &str = include_str!("../migrations/006_release_pins.sql");
+const MIGRATION_SQL_7: &str = include_str!("../migrations/007_stolen_domains.sql");
+const VERSIONED_MIGRATIONS: &[(i64, &str)] = &[
+    (2, MIGRATION_SQL_2),
+    (3, MIGRATION_SQL_3),
+    (4, MIGRATION_SQL_4),
+    (5, MIGRATION_SQL_5),
+    (6, MIGRATION_SQL_6),
+    (7, MIGRATION_SQL_7),
+];
 
 #[derive(Debug, Clone)]
 /// App row stored in SQLite.
@@ -45,6 +59,58 @@ pub struct AddonRow {
     pub created_at: String,
 }
 
+#[derive(Debug, Clone)]
+/// Binding of an addon to an app, with the app's name.
+pub struct BindingRow {
+    pub app_id: String,
+    pub app_name: String,
+    pub addon_id: String,
+    pub config_json: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone)]
+/// Snapshot of an addon config taken before an upgrade.
+pub struct AddonRevisionRow {
+    pub id: String,
+    pub addon_id: String,
+    pub created_at: String,
+    pub image: String,
+    pub config_json: String,
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+/// Basic auth user row stored in SQLite.
+pub struct ProxyUserRow {
+    pub id: String,
+    pub app_id: String,
+    pub username: String,
+    pub password_hash: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone)]
+/// Audit event row stored in SQLite.
+pub struct EventRow {
+    pub id: String,
+    pub ts: String,
+    pub kind: String,
+    pub payload_json: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Deployment counts and durations for one app and status.
+pub struct DeploymentStatsRow {
+    pub app: String,
+    pub status: String,
+    pub count: i64,
+    /// Sum of start-to-finish durations for deployments that finished.
+    pub duration_secs_sum: f64,
+    /// Number of deployments with a recorded finish time.
+    pub finished_count: i64,
+}
+
 /// SQLite storage wrapper with migrations and helpers.
 pub struct Storage {
     conn: Connection,
@@ -213,6 +279,63 @@ impl Storage {
         Ok(())
     }
 
+    /// Pin a release so retention never prunes it; returns false if it already was.
+    pub fn pin_release(&self, release_id: &str) -> Result<bool> {
+        let changed = self.conn.execute(
+            "INSERT OR IGNORE INTO release_pins(release_id, pinned_at) VALUES(?1, ?2)",
+            params![release_id, now_rfc3339()],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Unpin a release; returns false if it was not pinned.
+    pub fn unpin_release(&self, release_id: &str) -> Result<bool> {
+        let changed = self.conn.execute(
+            "DELETE FROM release_pins WHERE release_id = ?1",
+            params![release_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Ids of an app's pinned releases.
+    pub fn pinned_release_ids(&self, app_id: &str) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT p.release_id FROM release_pins p
+             JOIN releases r ON r.id = p.release_id
+             WHERE r.app_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![app_id], |row| row.get(0))?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
+    /// Record that `host` was moved from an app's route to `taken_by`.
+    pub fn add_stolen_domain(&self, app_id: &str, host: &str, taken_by: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO stolen_domains(app_id, host, taken_by, stolen_at)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![app_id, host, taken_by, now_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Hosts other apps have taken from an app's current route.
+    pub fn stolen_domains(&self, app_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT host FROM stolen_domains WHERE app_id = ?1 ORDER BY host")?;
+        let rows = stmt.query_map(params![app_id], |row| row.get(0))?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
+    /// Forget stolen hosts once an app routes a release that was checked for conflicts.
+    pub fn clear_stolen_domains(&self, app_id: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM stolen_domains WHERE app_id = ?1",
+            params![app_id],
+        )?;
+        Ok(())
+    }
+
     /// Get the current release id for an app.
     pub fn current_release_id(&self, app_id: &str) -> Result<Option<String>> {
         self.conn
@@ -264,7 +387,7 @@ impl Storage {
         Ok(())
     }
 
-    /// Update a deployment status.
+    /// Update a deployment status and stamp its finish time.
     pub fn update_deployment_status(
         &self,
         deployment_id: &str,
@@ -272,12 +395,34 @@ impl Storage {
         error: Option<&str>,
     ) -> Result<()> {
         self.conn.execute(
-            "UPDATE deployments SET status = ?1, error = ?2 WHERE id = ?3",
-            params![status, error, deployment_id],
+            "UPDATE deployments SET status = ?1, error = ?2, finished_at = ?3 WHERE id = ?4",
+            params![status, error, now_rfc3339(), deployment_id],
         )?;
         Ok(())
     }
 
+    /// Aggregate deployment counts and durations per app and status.
+    pub fn deployment_stats(&self) -> Result<Vec<DeploymentStatsRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT apps.name, deployments.status, COUNT(*),
+                    COALESCE(SUM((julianday(deployments.finished_at) - julianday(deployments.created_at)) * 86400.0), 0.0),
+                    COUNT(deployments.finished_at)
+             FROM deployments JOIN apps ON apps.id = deployments.app_id
+             GROUP BY apps.name, deployments.status
+             ORDER BY apps.name, deployments.status",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(DeploymentStatsRow {
+                app: row.get(0)?,
+                status: row.get(1)?,
+                count: row.get(2)?,
+                duration_secs_sum: row.get(3)?,
+                finished_count: row.get(4)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
     /// Remove deployment rows that reference a release.
     pub fn delete_deployments_for_release(&self, release_id: &str) -> Result<()> {
         self.conn.execute(
@@ -372,6 +517,28 @@ impl Storage {
         Ok(())
     }
 
+    /// List the apps bound to an addon.
+    pub fn list_addon_bindings(&self, addon_id: &str) -> Result<Vec<BindingRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT bindings.app_id, apps.name, bindings.addon_id, bindings.config_json,
+                    bindings.created_at
+             FROM bindings
+             INNER JOIN apps ON apps.id = bindings.app_id
+             WHERE bindings.addon_id = ?1
+             ORDER BY apps.name ASC",
+        )?;
+        let rows = stmt.query_map(params![addon_id], |row| {
+            Ok(BindingRow {
+                app_id: row.get(0)?,
+                app_name: row.get(1)?,
+                addon_id: row.get(2)?,
+                config_json: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
     /// Find an addon by name.
     pub fn get_addon_by_name(&self, name: &str) -> Result<Option<AddonRow>> {
         self.conn
@@ -393,6 +560,61 @@ impl Storage {
             .context("failed to query addon")
     }
 
+    /// Record an addon config revision.
+    pub fn insert_addon_revision(
+        &self,
+        addon_id: &str,
+        image: &str,
+        config_json: &str,
+        status: &str,
+    ) -> Result<AddonRevisionRow> {
+        let now = now_rfc3339();
+        let id = Ulid::new().to_string();
+        self.conn.execute(
+            "INSERT INTO addon_revisions(id, addon_id, created_at, image, config_json, status)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, addon_id, now, image, config_json, status],
+        )?;
+        Ok(AddonRevisionRow {
+            id,
+            addon_id: addon_id.to_string(),
+            created_at: now,
+            image: image.to_string(),
+            config_json: config_json.to_string(),
+            status: status.to_string(),
+        })
+    }
+
+    /// Update the status of an addon revision.
+    pub fn update_addon_revision_status(&self, id: &str, status: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE addon_revisions SET status = ?1 WHERE id = ?2",
+            params![status, id],
+        )?;
+        Ok(())
+    }
+
+    /// List revisions for an addon, newest first.
+    pub fn list_addon_revisions(&self, addon_id: &str) -> Result<Vec<AddonRevisionRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, addon_id, created_at, image, config_json, status
+             FROM addon_revisions
+             WHERE addon_id = ?1
+             ORDER BY created_at DESC, id DESC",
+        )?;
+        let rows = stmt.query_map(params![addon_id], |row| {
+            Ok(AddonRevisionRow {
+                id: row.get(0)?,
+                addon_id: row.get(1)?,
+                created_at: row.get(2)?,
+                image: row.get(3)?,
+                config_json: row.get(4)?,
+                status: row.get(5)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
     /// Build addon snapshots for an app, merging binding env overrides.
     pub fn addon_snapshots_for_app(&self, app_id: &str) -> Result<Vec<AddonSnapshot>> {
         let mut stmt = self.conn.prepare(
@@ -417,6 +639,54 @@ impl Storage {
         Ok(rows.filter_map(Result::ok).collect())
     }
 
+    /// Insert or replace a basic auth user for an app.
+    pub fn upsert_proxy_user(
+        &self,
+        app_id: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<()> {
+        let now = now_rfc3339();
+        let id = Ulid::new().to_string();
+        self.conn.execute(
+            "INSERT INTO proxy_auth_users(id, app_id, username, password_hash, created_at)
+             VALUES(?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(app_id, username)
+             DO UPDATE SET password_hash = excluded.password_hash",
+            params![id, app_id, username, password_hash, now],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a basic auth user; returns false when the user did not exist.
+    pub fn remove_proxy_user(&self, app_id: &str, username: &str) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM proxy_auth_users WHERE app_id = ?1 AND username = ?2",
+            params![app_id, username],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// List basic auth users for an app.
+    pub fn list_proxy_users(&self, app_id: &str) -> Result<Vec<ProxyUserRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, app_id, username, password_hash, created_at
+             FROM proxy_auth_users
+             WHERE app_id = ?1
+             ORDER BY username ASC",
+        )?;
+        let rows = stmt.query_map(params![app_id], |row| {
+            Ok(ProxyUserRow {
+                id: row.get(0)?,
+                app_id: row.get(1)?,
+                username: row.get(2)?,
+                password_hash: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
     /// Insert an event for audit/debug purposes.
     pub fn insert_event(&self, kind: &str, payload_json: &str) -> Result<()> {
         let id = Ulid::new().to_string();
@@ -428,6 +698,30 @@ impl Storage {
         Ok(())
     }
 
+    /// List events of one kind, oldest first.
+    pub fn list_events(&self, kind: &str) -> Result<Vec<EventRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, ts, kind, payload_json
+             FROM events
+             WHERE kind = ?1
+             ORDER BY ts ASC, id ASC",
+        )?;
+        let rows = stmt.query_map(params![kind], |row| {
+            Ok(EventRow {
+                id: row.get(0)?,
+                ts: row.get(1)?,
+                kind: row.get(2)?,
+                payload_json: row.get(3)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
+    /// Path of the database file (`None` for in-memory databases).
+    pub fn path(&self) -> Option<&str> {
+        self.conn.path().filter(|path| !path.is_empty())
+    }
+
     /// Test the database connection.
     pub fn ping(&self) -> Result<()> {
         self.conn.execute("SELECT 1", [])?;
@@ -472,19 +766,21 @@ fn migrate(conn: &Connection) -> Result<()> {
             params![now_rfc3339()],
         )?;
     }
-    let exists: Option<i64> = conn
-        .query_row(
-            "SELECT version FROM schema_migrations WHERE version = 2",
-            [],
-            |row| row.get(0),
-        )
-        .optional()?;
-    if exists.is_none() {
-        conn.execute_batch(MIGRATION_SQL_2)?;
-        conn.execute(
-            "INSERT INTO schema_migrations(version, applied_at) VALUES(2, ?1)",
-            params![now_rfc3339()],
-        )?;
+    for (version, sql) in VERSIONED_MIGRATIONS {
+        let exists: Option<i64> = conn
+            .query_row(
+                "SELECT version FROM schema_migrations WHERE version = ?1",
+                params![version],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if exists.is_none() {
+            conn.execute_batch(sql)?;
+            conn.execute(
+                "INSERT INTO schema_migrations(version, applied_at) VALUES(?1, ?2)",
+                params![version, now_rfc3339()],
+            )?;
+        }
     }
     Ok(())
 }
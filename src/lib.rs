@@ -3,7 +3,12 @@
 pub mod cli;
 pub mod config;
 pub mod db;
+pub mod logship;
 pub mod proxy;
+pub mod registry;
 pub mod runner;
 pub mod runtime;
 pub mod systemd;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod units;
@@ -0,0 +1,397 @@
+//! Forward journald entries of deep units to a rotating file tree or a Loki push endpoint.
+
+use anyhow::{Context, Result, bail};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{ShipConfig, ShipSink};
+use crate::runner;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A journald entry emitted by a deep unit.
+pub struct JournalEntry {
+    pub cursor: String,
+    pub unit: String,
+    pub message: String,
+    /// Wall clock time in microseconds since the epoch.
+    pub ts_micros: u64,
+}
+
+impl JournalEntry {
+    /// Parse one line of `journalctl -o json` output.
+    pub fn parse(line: &str) -> Option<Self> {
+        let value: Value = serde_json::from_str(line).ok()?;
+        let cursor = value["__CURSOR"].as_str()?.to_string();
+        let unit = value["CONTAINER_NAME"]
+            .as_str()
+            .map(|name| format!("{}.service", name))
+            .or_else(|| value["_SYSTEMD_USER_UNIT"].as_str().map(str::to_string))
+            .or_else(|| value["_SYSTEMD_UNIT"].as_str().map(str::to_string))?;
+        let message = match &value["MESSAGE"] {
+            Value::String(text) => text.clone(),
+            // journald encodes non-UTF-8 messages as byte arrays.
+            Value::Array(bytes) => {
+                let bytes: Vec<u8> = bytes
+                    .iter()
+                    .filter_map(|byte| byte.as_u64().map(|b| b as u8))
+                    .collect();
+                String::from_utf8_lossy(&bytes).to_string()
+            }
+            _ => return None,
+        };
+        let ts_micros = value["__REALTIME_TIMESTAMP"]
+            .as_str()
+            .and_then(|ts| ts.parse().ok())
+            .unwrap_or(0);
+        Some(Self {
+            cursor,
+            unit,
+            message,
+            ts_micros,
+        })
+    }
+
+    /// Labels derived from the unit name (`app`/`release` or `addon`).
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        let mut labels = BTreeMap::new();
+        let stem = self.unit.trim_end_matches(".service");
+        labels.insert("unit".to_string(), stem.to_string());
+        if let Some(rest) = stem.strip_prefix("deep-app-")
+            && let Some((app, release)) = rest.rsplit_once('-')
+        {
+            labels.insert("app".to_string(), app.to_string());
+            labels.insert("release".to_string(), release.to_string());
+        } else if let Some(addon) = stem.strip_prefix("deep-addon-") {
+            labels.insert("addon".to_string(), addon.to_string());
+        }
+        labels
+    }
+
+    /// Relative path of the file this entry is written to by the file sink.
+    fn file_path(&self) -> PathBuf {
+        let labels = self.labels();
+        match (
+            labels.get("app"),
+            labels.get("release"),
+            labels.get("addon"),
+        ) {
+            (Some(app), Some(release), _) => Path::new(app).join(format!("{}.log", release)),
+            (_, _, Some(addon)) => Path::new("addons").join(format!("{}.log", addon)),
+            _ => PathBuf::from(format!("{}.log", labels["unit"])),
+        }
+    }
+}
+
+/// Read journald entries for the configured units after `cursor`, or only the
+/// newest `tail` entries when given.
+pub fn read_journal(
+    units: &[String],
+    user: bool,
+    cursor: Option<&str>,
+    tail: Option<usize>,
+) -> Result<Vec<JournalEntry>> {
+    let mut args = Vec::new();
+    if user {
+        args.push("--user".to_string());
+    }
+    args.extend([
+        "-o".to_string(),
+        "json".to_string(),
+        "--no-pager".to_string(),
+    ]);
+    for unit in units {
+        args.push("-u".to_string());
+        args.push(unit.clone());
+    }
+    if let Some(cursor) = cursor {
+        args.push(format!("--after-cursor={}", cursor));
+    }
+    if let Some(tail) = tail {
+        args.extend(["-n".to_string(), tail.to_string()]);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output =
+        runner::run_output("journalctl", &args).with_context(|| "failed to run journalctl")?;
+    if !output.status.success() {
+        bail!(
+            "journalctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(JournalEntry::parse)
+        .collect())
+}
+
+/// Ship all new entries once and advance the cursor; returns the number of entries shipped.
+///
+/// Without a cursor (first run, or the file was lost) shipping starts from now
+/// instead of the whole journal. Entries go out in `batch_size` chunks, saving the
+/// cursor after each, so no single write or request grows with the backlog.
+pub fn ship_once(config: &ShipConfig, user: bool) -> Result<usize> {
+    let cursor = fs::read_to_string(&config.cursor_file)
+        .ok()
+        .map(|cursor| cursor.trim().to_string())
+        .filter(|cursor| !cursor.is_empty());
+    let Some(cursor) = cursor else {
+        if let Some(latest) = read_journal(&config.units, user, None, Some(1))?.last() {
+            save_cursor(&config.cursor_file, &latest.cursor)?;
+        }
+        return Ok(0);
+    };
+    let entries = read_journal(&config.units, user, Some(&cursor), None)?;
+    for batch in entries.chunks(config.batch_size.max(1)) {
+        match config.sink {
+            ShipSink::File => write_files(config, batch)?,
+            ShipSink::Loki => push_loki(config, batch)?,
+        }
+        if let Some(last) = batch.last() {
+            save_cursor(&config.cursor_file, &last.cursor)?;
+        }
+    }
+    Ok(entries.len())
+}
+
+fn save_cursor(path: &Path, cursor: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, cursor)
+        .with_context(|| format!("failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Append entries to `<dir>/<app>/<release>.log`, rotating files past `max_size_mb`.
+fn write_files(config: &ShipConfig, entries: &[JournalEntry]) -> Result<()> {
+    let max_bytes = config.max_size_mb.max(1) * 1024 * 1024;
+    for entry in entries {
+        let path = config.dir.join(entry.file_path());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let line = format!("{} {}\n", format_micros(entry.ts_micros), entry.message);
+        let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > max_bytes {
+            rotate(&path, config.keep)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Shift `file.log.N` to `file.log.N+1`, dropping anything past `keep`.
+fn rotate(path: &Path, keep: u32) -> Result<()> {
+    let numbered = |index: u32| PathBuf::from(format!("{}.{}", path.display(), index));
+    if keep == 0 {
+        return fs::remove_file(path)
+            .with_context(|| format!("failed to rotate {}", path.display()));
+    }
+    let _ = fs::remove_file(numbered(keep));
+    for index in (1..keep).rev() {
+        let from = numbered(index);
+        if from.exists() {
+            fs::rename(&from, numbered(index + 1))
+                .with_context(|| format!("failed to rotate {}", from.display()))?;
+        }
+    }
+    fs::rename(path, numbered(1)).with_context(|| format!("failed to rotate {}", path.display()))
+}
+
+fn format_micros(ts_micros: u64) -> String {
+    let fmt = time::format_description::well_known::Rfc3339;
+    time::OffsetDateTime::from_unix_timestamp_nanos(ts_micros as i128 * 1_000)
+        .ok()
+        .and_then(|datetime| datetime.format(&fmt).ok())
+        .unwrap_or_else(|| ts_micros.to_string())
+}
+
+/// Push entries to a Loki-compatible `/loki/api/v1/push` endpoint, one stream per unit.
+fn push_loki(config: &ShipConfig, entries: &[JournalEntry]) -> Result<()> {
+    let url = config
+        .url
+        .as_deref()
+        .context("logging.ship.url is required for the loki sink")?;
+    let mut streams: BTreeMap<BTreeMap<String, String>, Vec<Value>> = BTreeMap::new();
+    for entry in entries {
+        let mut labels = config.labels.clone();
+        labels.extend(entry.labels());
+        streams.entry(labels).or_default().push(serde_json::json!([
+            (entry.ts_micros as u128 * 1_000).to_string(),
+            entry.message
+        ]));
+    }
+    let streams: Vec<Value> = streams
+        .into_iter()
+        .map(|(labels, values)| serde_json::json!({ "stream": labels, "values": values }))
+        .collect();
+    let body = serde_json::json!({ "streams": streams }).to_string();
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .with_context(|| format!("failed to push logs to {}", url))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().unwrap_or_default();
+        bail!(
+            "log push to {} failed with status {}: {}",
+            url,
+            status,
+            text.trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::set_runner_for_tests;
+    use crate::test_support::TestRunner;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn journal_runner(journal: &str) -> Arc<TestRunner> {
+        let runner = Arc::new(TestRunner::default());
+        runner.add_rule(&["journalctl"], 0, journal, "");
+        runner
+    }
+
+    fn journal_line(cursor: &str, unit: &str, message: &str) -> String {
+        serde_json::json!({
+            "__CURSOR": cursor,
+            "__REALTIME_TIMESTAMP": "1700000000000000",
+            "_SYSTEMD_USER_UNIT": unit,
+            "MESSAGE": message,
+        })
+        .to_string()
+    }
+
+    fn ship_config(dir: &Path, sink: ShipSink, url: Option<String>) -> ShipConfig {
+        ShipConfig {
+            sink,
+            dir: dir.join("logs"),
+            max_size_mb: 1,
+            keep: 2,
+            url,
+            labels: BTreeMap::from([("host".to_string(), "vps1".to_string())]),
+            units: vec!["deep-app-*".to_string()],
+            interval_secs: 1,
+            cursor_file: dir.join("cursor"),
+            batch_size: 1,
+        }
+    }
+
+    #[test]
+    fn entries_map_to_labels_and_file_tree() {
+        let entry = JournalEntry::parse(&journal_line(
+            "c1",
+            "deep-app-my-app-01HXYZ.service",
+            "hello",
+        ))
+        .expect("entry");
+        let labels = entry.labels();
+        assert_eq!(labels["app"], "my-app");
+        assert_eq!(labels["release"], "01HXYZ");
+        assert_eq!(entry.file_path(), Path::new("my-app").join("01HXYZ.log"));
+
+        let bytes =
+            r#"{"__CURSOR":"c2","_SYSTEMD_UNIT":"deep-addon-db.service","MESSAGE":[104,105]}"#;
+        let entry = JournalEntry::parse(bytes).expect("byte message");
+        assert_eq!(entry.message, "hi");
+        assert_eq!(entry.file_path(), Path::new("addons").join("db.log"));
+        assert!(JournalEntry::parse(r#"{"MESSAGE":"no cursor"}"#).is_none());
+    }
+
+    #[test]
+    fn file_sink_appends_rotates_and_saves_cursor() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let journal = [
+            journal_line("c1", "deep-app-app-r1.service", "first"),
+            journal_line("c2", "deep-app-app-r1.service", "second"),
+        ]
+        .join("\n");
+        let _guard = set_runner_for_tests(journal_runner(&journal));
+        let config = ship_config(temp.path(), ShipSink::File, None);
+
+        // The first run only remembers where the journal ends.
+        assert_eq!(ship_once(&config, true)?, 0);
+        assert_eq!(fs::read_to_string(&config.cursor_file)?, "c2");
+        assert!(!config.dir.exists());
+
+        fs::write(&config.cursor_file, "c0")?;
+        assert_eq!(ship_once(&config, true)?, 2);
+        let log_path = config.dir.join("app").join("r1.log");
+        let contents = fs::read_to_string(&log_path)?;
+        assert_eq!(
+            contents,
+            "2023-11-14T22:13:20Z first\n2023-11-14T22:13:20Z second\n"
+        );
+        assert_eq!(fs::read_to_string(&config.cursor_file)?, "c2");
+
+        fs::write(&log_path, vec![b'x'; 1024 * 1024])?;
+        ship_once(&config, true)?;
+        assert!(PathBuf::from(format!("{}.1", log_path.display())).exists());
+        assert!(fs::read_to_string(&log_path)?.ends_with("second\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn loki_sink_pushes_streams_to_stub() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/loki/api/v1/push", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> Result<String> {
+            let (stream, _) = listener.accept()?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut length = 0usize;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse()?;
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body)?;
+            (&stream).write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")?;
+            Ok(String::from_utf8(body)?)
+        });
+
+        let temp = tempfile::TempDir::new()?;
+        let journal = journal_line("c9", "deep-app-app-r1.service", "pushed");
+        let _guard = set_runner_for_tests(journal_runner(&journal));
+        let config = ship_config(temp.path(), ShipSink::Loki, Some(url));
+        fs::write(&config.cursor_file, "c0")?;
+        assert_eq!(ship_once(&config, true)?, 1);
+
+        let body: Value = serde_json::from_str(&server.join().expect("stub thread")?)?;
+        let stream = &body["streams"][0];
+        assert_eq!(stream["stream"]["app"], "app");
+        assert_eq!(stream["stream"]["host"], "vps1");
+        assert_eq!(stream["values"][0][0], "1700000000000000000");
+        assert_eq!(stream["values"][0][1], "pushed");
+        assert_eq!(fs::read_to_string(&config.cursor_file)?, "c9");
+        Ok(())
+    }
+}
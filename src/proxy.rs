@@ -2,41 +2,89 @@
 
 use anyhow::{Context, Result, bail};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::config::ConfigSnapshot;
-use crate::runtime::app_container_name;
-use crate::systemd::systemctl_any;
+use crate::config::{ConfigSnapshot, TlsMode, validate_auth_username, validate_proxy};
+use crate::runner;
+use crate::runtime::{Runtime, app_container_name};
+use crate::systemd::{systemctl_any, systemctl_for_dir};
+use crate::units::find_unit;
+
+/// Directory where the Caddyfile directory is mounted inside the Caddy container.
+pub const CADDY_CONFIG_MOUNT: &str = "/etc/caddy";
+
+/// Directory where custom certificates are mounted inside the Caddy container.
+pub const CADDY_CERTS_MOUNT: &str = "/certs";
+
+/// Host directory mounted at [`CADDY_CERTS_MOUNT`] unless `[caddy] certs_dir` says otherwise.
+pub const DEFAULT_CADDY_CERTS_DIR: &str = "/srv/deep/caddy/certs";
+
+/// Directory where per-app access logs are written inside the Caddy container.
+pub const CADDY_LOGS_MOUNT: &str = "/logs";
 
 #[derive(Debug, Clone)]
 /// Caddyfile-based proxy controller.
 pub struct CaddyFile {
     host_path: PathBuf,
     container_name: String,
+    runtime: Runtime,
+    certs_dir: PathBuf,
 }
 
 #[derive(Debug)]
 /// Parsed route information from a Caddyfile.
 pub struct RouteStatus {
     pub id: String,
+    pub app: String,
     pub hosts: Vec<String>,
     pub upstreams: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default)]
+/// Route settings that live outside the release snapshot.
+pub struct RouteExtras {
+    pub auth_users: Vec<BasicAuthUser>,
+}
+
+#[derive(Debug, Clone)]
+/// Basic auth credential rendered into a Caddy block.
+pub struct BasicAuthUser {
+    pub username: String,
+    pub password_hash: String,
+}
+
 impl CaddyFile {
     /// Create a new Caddyfile controller.
-    pub fn new(host_path: PathBuf, container_name: String) -> Self {
+    pub fn new(host_path: PathBuf, container_name: String, runtime: Runtime) -> Self {
         Self {
             host_path,
             container_name,
+            runtime,
+            certs_dir: PathBuf::from(DEFAULT_CADDY_CERTS_DIR),
         }
     }
 
+    /// Use the host certificates directory mounted at [`CADDY_CERTS_MOUNT`].
+    pub fn with_certs_dir(mut self, certs_dir: PathBuf) -> Self {
+        self.certs_dir = certs_dir;
+        self
+    }
+
+    /// Get the host directory mounted at [`CADDY_CERTS_MOUNT`].
+    pub fn certs_dir(&self) -> &Path {
+        &self.certs_dir
+    }
+
     /// Get the configured Caddy service/container name.
     pub fn container_name(&self) -> &str {
         &self.container_name
     }
 
+    /// Get the host path of the Caddyfile.
+    pub fn path(&self) -> &Path {
+        &self.host_path
+    }
+
     /// Upsert a route for an app and reload Caddy with rollback on failure.
     pub fn upsert_route(
         &self,
@@ -44,37 +92,73 @@ impl CaddyFile {
         release_id: &str,
         snapshot: &ConfigSnapshot,
     ) -> Result<()> {
-        if snapshot.domains.is_empty() {
-            bail!("no domains configured for app; cannot update proxy route");
+        self.upsert_route_with_extras(app_name, release_id, snapshot, &RouteExtras::default())
+    }
+
+    /// Upsert a route with database-backed extras (e.g. basic auth users).
+    pub fn upsert_route_with_extras(
+        &self,
+        app_name: &str,
+        release_id: &str,
+        snapshot: &ConfigSnapshot,
+        extras: &RouteExtras,
+    ) -> Result<()> {
+        let block = render_app_block(app_name, release_id, snapshot, extras, &self.certs_dir)?;
+        self.apply_blocks(&[(app_name.to_string(), block)], &[])
+    }
+
+    /// Upsert and remove app blocks in one validated write and reload.
+    pub fn apply_blocks(&self, upserts: &[(String, String)], removals: &[String]) -> Result<()> {
+        let contents = self.read_contents()?;
+        let mut updated = contents.clone();
+        for app in removals {
+            updated = remove_caddyfile_block(&updated, app);
         }
-        let upstream = format!(
-            "{}:{}",
-            app_container_name(app_name, release_id),
-            snapshot.port
-        );
-        let mut contents = String::new();
-        if self.host_path.exists() {
-            contents = fs::read_to_string(&self.host_path).with_context(|| {
-                format!("failed to read caddyfile at {}", self.host_path.display())
-            })?;
+        for (app, block) in upserts {
+            updated = upsert_caddyfile_block(&updated, app, block);
+        }
+        self.replace_contents(&contents, &updated)
+    }
+
+    /// Read the current Caddyfile, or an empty string when it does not exist.
+    pub fn read_contents(&self) -> Result<String> {
+        if !self.host_path.exists() {
+            return Ok(String::new());
+        }
+        fs::read_to_string(&self.host_path)
+            .with_context(|| format!("failed to read caddyfile at {}", self.host_path.display()))
+    }
+
+    /// Validate a new Caddyfile, swap it in atomically, and reload Caddy.
+    ///
+    /// The previous contents are kept in a `.bak` file and restored if the reload fails.
+    pub fn replace_contents(&self, previous: &str, updated: &str) -> Result<()> {
+        if updated.contains(&format!("output file {}/", CADDY_LOGS_MOUNT)) {
+            self.ensure_logs_mount()?;
         }
-        let updated = upsert_caddyfile_block(&contents, app_name, &snapshot.domains, &upstream);
         if let Some(parent) = self.host_path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create {}", parent.display()))?;
         }
+        let temp_path = self.host_path.with_extension("deep-tmp");
+        fs::write(&temp_path, updated)
+            .with_context(|| format!("failed to write caddyfile at {}", temp_path.display()))?;
+        if let Err(err) = self.validate(&temp_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
         let backup_path = self.host_path.with_extension("bak");
-        fs::write(&backup_path, &contents).with_context(|| {
+        fs::write(&backup_path, previous).with_context(|| {
             format!(
                 "failed to write caddyfile backup at {}",
                 backup_path.display()
             )
         })?;
-        fs::write(&self.host_path, updated).with_context(|| {
+        fs::rename(&temp_path, &self.host_path).with_context(|| {
             format!("failed to write caddyfile at {}", self.host_path.display())
         })?;
         if let Err(err) = self.reload() {
-            fs::write(&self.host_path, &contents).with_context(|| {
+            fs::write(&self.host_path, previous).with_context(|| {
                 format!(
                     "failed to restore caddyfile at {}",
                     self.host_path.display()
@@ -92,32 +176,292 @@ impl CaddyFile {
         Ok(())
     }
 
+    /// Add the `/logs` mount to a Caddy quadlet written before access logs existed.
+    ///
+    /// Without it Caddy writes access logs inside the container, where they are
+    /// lost on restart and invisible to `deep logs --access`.
+    fn ensure_logs_mount(&self) -> Result<()> {
+        let Some(path) = find_unit(&self.runtime, &self.container_name) else {
+            return Ok(());
+        };
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if !self.runtime.engine().uses_quadlets() {
+            if !contents.contains(&format!(":{}", CADDY_LOGS_MOUNT)) {
+                eprintln!(
+                    "warning: {} does not mount {}; rerun `deep host start-caddy` to keep access logs",
+                    path.display(),
+                    CADDY_LOGS_MOUNT
+                );
+            }
+            return Ok(());
+        }
+        let Some((updated, logs_dir)) = add_logs_mount(&contents) else {
+            return Ok(());
+        };
+        fs::create_dir_all(&logs_dir)
+            .with_context(|| format!("failed to create {}", logs_dir.display()))?;
+        fs::write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+        let quadlet_dir = path
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default();
+        systemctl_for_dir(&quadlet_dir, &["daemon-reload"])?;
+        systemctl_for_dir(
+            &quadlet_dir,
+            &["restart", &format!("{}.service", self.container_name)],
+        )?;
+        println!(
+            "added {} mount for access logs to {}; restarted {}",
+            CADDY_LOGS_MOUNT,
+            path.display(),
+            self.container_name
+        );
+        Ok(())
+    }
+
+    /// Run `caddy validate` inside the Caddy container against a host file.
+    fn validate(&self, host_file: &Path) -> Result<()> {
+        let file_name = host_file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("invalid caddyfile path")?;
+        let container_path = format!("{}/{}", CADDY_CONFIG_MOUNT, file_name);
+        let output = runner::run_output(
+            self.runtime.program(),
+            &[
+                "exec",
+                &self.container_name,
+                "caddy",
+                "validate",
+                "--config",
+                &container_path,
+                "--adapter",
+                "caddyfile",
+            ],
+        )
+        .with_context(|| "failed to run caddy validate")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let detail = if stderr.trim().is_empty() {
+                stdout.trim().to_string()
+            } else {
+                stderr.trim().to_string()
+            };
+            bail!("caddyfile validation failed: {}", detail);
+        }
+        Ok(())
+    }
+
     /// List routes parsed from the Caddyfile.
     pub fn list_routes(&self) -> Result<Vec<RouteStatus>> {
         if !self.host_path.exists() {
             return Ok(Vec::new());
         }
-        let contents = fs::read_to_string(&self.host_path)
-            .with_context(|| format!("failed to read caddyfile at {}", self.host_path.display()))?;
+        let contents = self.read_contents()?;
         Ok(parse_caddyfile_routes(&contents))
     }
 
+    /// Hash a password with bcrypt using the Caddy binary inside the container.
+    ///
+    /// The password goes through stdin so it never shows up in the process list.
+    pub fn hash_password(&self, plaintext: &str) -> Result<String> {
+        let input = format!("{}\n", plaintext);
+        let output = runner::run_output_with_stdin(
+            self.runtime.program(),
+            &["exec", "-i", &self.container_name, "caddy", "hash-password"],
+            input.as_bytes(),
+        )
+        .with_context(|| "failed to run caddy hash-password")?;
+        if !output.status.success() {
+            bail!(
+                "caddy hash-password failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if hash.is_empty() {
+            bail!("caddy hash-password returned an empty hash");
+        }
+        Ok(hash)
+    }
+
     /// Reload Caddy via systemd.
     pub fn reload(&self) -> Result<()> {
         systemctl_any(&["reload", &format!("{}.service", self.container_name)])
     }
 }
 
-fn upsert_caddyfile_block(contents: &str, app: &str, domains: &[String], upstream: &str) -> String {
+/// Render the deep-managed Caddy block for an app release.
+pub fn render_app_block(
+    app: &str,
+    release_id: &str,
+    snapshot: &ConfigSnapshot,
+    extras: &RouteExtras,
+    certs_dir: &Path,
+) -> Result<String> {
+    if snapshot.domains.is_empty() {
+        bail!("no domains configured for app; cannot update proxy route");
+    }
+    let upstream = format!("{}:{}", app_container_name(app, release_id), snapshot.port);
+    let mut body = Vec::new();
+    let tls = &snapshot.proxy.tls;
+    match tls.mode {
+        TlsMode::Auto | TlsMode::Off => {}
+        TlsMode::Internal => body.push("    tls internal".to_string()),
+        TlsMode::Custom => {
+            let (Some(cert), Some(key)) = (tls.cert.as_deref(), tls.key.as_deref()) else {
+                bail!("proxy.tls mode \"custom\" requires both cert and key");
+            };
+            body.push(format!(
+                "    tls {} {}",
+                container_cert_path(cert, certs_dir)?,
+                container_cert_path(key, certs_dir)?
+            ));
+        }
+    }
+    // Snapshots predate validation, so check again before writing the block.
+    validate_proxy(&snapshot.proxy)?;
+    if !snapshot.proxy.allow_cidrs.is_empty() {
+        body.push(format!(
+            "    @deep_denied not remote_ip {}",
+            snapshot.proxy.allow_cidrs.join(" ")
+        ));
+        body.push("    respond @deep_denied 403".to_string());
+    }
+    if let Some(auth) = &snapshot.proxy.auth {
+        if extras.auth_users.is_empty() {
+            bail!(
+                "proxy.auth is enabled for {} but no users exist; add one with `deep apps auth add {} <user>`",
+                app,
+                app
+            );
+        }
+        match auth.realm.as_deref() {
+            Some(realm) => body.push(format!("    basic_auth bcrypt \"{}\" {{", realm)),
+            None => body.push("    basic_auth {".to_string()),
+        }
+        for user in &extras.auth_users {
+            validate_auth_username(&user.username)?;
+            body.push(format!("        {} {}", user.username, user.password_hash));
+        }
+        body.push("    }".to_string());
+    }
+    let access_log = &snapshot.proxy.access_log;
+    if access_log.enabled {
+        body.push("    log {".to_string());
+        body.push(format!(
+            "        output file {}/{}.log {{",
+            CADDY_LOGS_MOUNT, app
+        ));
+        body.push(format!("            roll_size {}", access_log.roll_size));
+        body.push(format!("            roll_keep {}", access_log.roll_keep));
+        body.push(format!(
+            "            roll_keep_for {}",
+            access_log.roll_keep_for
+        ));
+        body.push("        }".to_string());
+        body.push("        format json".to_string());
+        body.push("    }".to_string());
+    }
+    body.push(format!("    reverse_proxy {}", upstream));
+    let hosts: Vec<String> = if tls.mode == TlsMode::Off {
+        snapshot
+            .domains
+            .iter()
+            .map(|domain| format!("http://{}", domain))
+            .collect()
+    } else {
+        snapshot.domains.clone()
+    };
+    Ok(format!(
+        "# deep:app:{app}\n{hosts} {{\n{body}\n}}\n# deep:end\n",
+        app = app,
+        hosts = hosts.join(", "),
+        body = body.join("\n")
+    ))
+}
+
+/// Map a configured certificate path to where Caddy sees it.
+///
+/// Only the certs directory is mounted into the proxy container, so absolute
+/// paths must sit under it (as a host or container path); anything else is
+/// rejected rather than handed to Caddy as a file it cannot open.
+fn container_cert_path(path: &str, certs_dir: &Path) -> Result<String> {
+    let host_dir = certs_dir.to_string_lossy();
+    let relative = if let Some(rest) = strip_dir(path, host_dir.trim_end_matches('/')) {
+        rest
+    } else if let Some(rest) = strip_dir(path, CADDY_CERTS_MOUNT) {
+        rest
+    } else if path.starts_with('/') {
+        bail!(
+            "certificate {} is outside {}; copy it there so the proxy container can read it",
+            path,
+            certs_dir.display()
+        );
+    } else {
+        path.trim_start_matches("./")
+    };
+    if relative.is_empty() || relative.split('/').any(|part| part == "..") {
+        bail!("invalid certificate path {}", path);
+    }
+    Ok(format!("{}/{}", CADDY_CERTS_MOUNT, relative))
+}
+
+/// Insert a `/logs` volume next to the quadlet's other Caddy volumes.
+///
+/// Returns `None` when the quadlet already mounts `/logs`; otherwise the new
+/// contents and the host directory, a `logs` sibling of the `/data` volume.
+fn add_logs_mount(quadlet: &str) -> Option<(String, PathBuf)> {
+    let mut data_dir = None;
+    let mut insert_at = None;
+    let lines: Vec<&str> = quadlet.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line == "[Container]" {
+            insert_at = insert_at.or(Some(index + 1));
+        }
+        let Some(volume) = line.strip_prefix("Volume=") else {
+            continue;
+        };
+        let mut parts = volume.split(':');
+        let (source, target) = (parts.next()?, parts.next().unwrap_or_default());
+        if target == CADDY_LOGS_MOUNT {
+            return None;
+        }
+        if target == "/data" {
+            data_dir = Path::new(source).parent().map(Path::to_path_buf);
+        }
+        insert_at = Some(index + 1);
+    }
+    let logs_dir = data_dir
+        .unwrap_or_else(|| PathBuf::from("/srv/deep/caddy"))
+        .join("logs");
+    let mut updated: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    updated.insert(
+        insert_at?,
+        format!("Volume={}:{}", logs_dir.display(), CADDY_LOGS_MOUNT),
+    );
+    Some((format!("{}\n", updated.join("\n")), logs_dir))
+}
+
+fn strip_dir<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    path.strip_prefix(dir)?.strip_prefix('/')
+}
+
+fn upsert_caddyfile_block(contents: &str, app: &str, block: &str) -> String {
+    let mut output = remove_caddyfile_block(contents, app);
+    if !output.ends_with('\n') && !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(block);
+    output
+}
+
+fn remove_caddyfile_block(contents: &str, app: &str) -> String {
     let start_marker = format!("# deep:app:{}", app);
     let end_marker = "# deep:end";
-    let block = format!(
-        "{start}\n{hosts} {{\n    reverse_proxy {upstream}\n}}\n{end}\n",
-        start = start_marker,
-        hosts = domains.join(", "),
-        upstream = upstream,
-        end = end_marker
-    );
 
     let mut lines = Vec::new();
     let mut in_block = false;
@@ -139,13 +483,137 @@ fn upsert_caddyfile_block(contents: &str, app: &str, domains: &[String], upstrea
     if !output.ends_with('\n') && !output.is_empty() {
         output.push('\n');
     }
-    output.push_str(&block);
     output
 }
 
-fn parse_caddyfile_routes(contents: &str) -> Vec<RouteStatus> {
+/// Replace every deep-managed block with `blocks`, keeping user text outside the markers.
+///
+/// Fails on a begin marker without its `# deep:end`, since everything after it
+/// would otherwise be dropped as part of the block.
+pub fn rebuild_caddyfile(contents: &str, blocks: &[(String, String)]) -> Result<String> {
+    let mut lines = Vec::new();
+    let mut open: Option<&str> = None;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("# deep:app:") {
+            if let Some(marker) = open {
+                bail!(
+                    "unterminated Caddyfile marker {:?}: no \"# deep:end\" before the next block",
+                    marker
+                );
+            }
+            open = Some(trimmed);
+            continue;
+        }
+        if open.is_some() && trimmed == "# deep:end" {
+            open = None;
+            continue;
+        }
+        if open.is_none() {
+            lines.push(line);
+        }
+    }
+    if let Some(marker) = open {
+        bail!(
+            "unterminated Caddyfile marker {:?}: no \"# deep:end\" before end of file",
+            marker
+        );
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let mut output = lines.join("\n");
+    if !output.is_empty() {
+        output.push_str("\n\n");
+    }
+    for (_, block) in blocks {
+        output.push_str(block);
+    }
+    Ok(output)
+}
+
+/// Render a unified diff (3 lines of context) between two texts; empty when equal.
+pub fn unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence table, filled from the end.
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    // (tag, line, old lines consumed before, new lines consumed before)
+    let mut ops: Vec<(char, &str, usize, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', a[i], i, j));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', a[i], i, j));
+            i += 1;
+        } else {
+            ops.push(('+', b[j], i, j));
+            j += 1;
+        }
+    }
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &k in &changes {
+        let start = k.saturating_sub(CONTEXT);
+        let end = (k + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, end) in hunks {
+        let slice = &ops[start..end];
+        let old_len = slice.iter().filter(|op| op.0 != '+').count();
+        let new_len = slice.iter().filter(|op| op.0 != '-').count();
+        let old_start = if old_len == 0 {
+            slice[0].2
+        } else {
+            slice[0].2 + 1
+        };
+        let new_start = if new_len == 0 {
+            slice[0].3
+        } else {
+            slice[0].3 + 1
+        };
+        output.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_len, new_start, new_len
+        ));
+        for (tag, line, _, _) in slice {
+            output.push(*tag);
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Parse deep-managed app blocks from Caddyfile contents.
+pub fn parse_caddyfile_routes(contents: &str) -> Vec<RouteStatus> {
     let mut routes = Vec::new();
     let mut current: Option<RouteStatus> = None;
+    let mut depth = 0usize;
     for line in contents.lines() {
         let trimmed = line.trim();
         if let Some(rest) = trimmed.strip_prefix("# deep:app:") {
@@ -154,9 +622,11 @@ fn parse_caddyfile_routes(contents: &str) -> Vec<RouteStatus> {
             }
             current = Some(RouteStatus {
                 id: format!("deep-app-{}", rest),
+                app: rest.to_string(),
                 hosts: Vec::new(),
                 upstreams: Vec::new(),
             });
+            depth = 0;
             continue;
         }
         if trimmed == "# deep:end" {
@@ -167,10 +637,15 @@ fn parse_caddyfile_routes(contents: &str) -> Vec<RouteStatus> {
         }
         if let Some(route) = current.as_mut() {
             if trimmed.ends_with('{') {
-                let hosts = trimmed.trim_end_matches('{').trim();
-                if !hosts.is_empty() {
-                    route.hosts = hosts.split(',').map(|h| h.trim().to_string()).collect();
+                if depth == 0 {
+                    let hosts = trimmed.trim_end_matches('{').trim();
+                    if !hosts.is_empty() {
+                        route.hosts = hosts.split(',').map(|h| h.trim().to_string()).collect();
+                    }
                 }
+                depth += 1;
+            } else if trimmed == "}" {
+                depth = depth.saturating_sub(1);
             } else if let Some(rest) = trimmed.strip_prefix("reverse_proxy ") {
                 route.upstreams = vec![rest.trim().to_string()];
             }
@@ -215,14 +690,272 @@ old.example.com {
 }
 # deep:end
 "#;
-        let updated = upsert_caddyfile_block(
-            contents,
+        let mut snapshot = test_snapshot();
+        snapshot.domains = vec![String::from("new.example.com")];
+        let block = render_app_block(
             "app",
-            &[String::from("new.example.com")],
-            "deep-app-app-new:3000",
-        );
+            "new",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render block");
+        let updated = upsert_caddyfile_block(contents, "app", &block);
         assert!(updated.contains("new.example.com"));
         assert!(updated.contains("deep-app-app-new:3000"));
         assert!(!updated.contains("old.example.com"));
     }
+
+    #[test]
+    fn render_block_includes_allowlist_and_basic_auth() {
+        let mut snapshot = test_snapshot();
+        snapshot.proxy.allow_cidrs = vec!["10.0.0.0/8".to_string(), "192.168.1.0/24".to_string()];
+        snapshot.proxy.auth = Some(crate::config::ProxyAuthConfig {
+            realm: Some("staging".to_string()),
+        });
+        let extras = RouteExtras {
+            auth_users: vec![BasicAuthUser {
+                username: "alice".to_string(),
+                password_hash: "$2a$14$hash".to_string(),
+            }],
+        };
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &extras,
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render block");
+        assert!(block.contains("@deep_denied not remote_ip 10.0.0.0/8 192.168.1.0/24"));
+        assert!(block.contains("respond @deep_denied 403"));
+        assert!(block.contains("basic_auth bcrypt \"staging\" {"));
+        assert!(block.contains("alice $2a$14$hash"));
+
+        let routes = parse_caddyfile_routes(&block);
+        assert_eq!(routes[0].hosts, vec!["app.example.com"]);
+        assert_eq!(routes[0].upstreams, vec!["deep-app-app-r1:3000"]);
+    }
+
+    #[test]
+    fn render_block_fails_closed_without_auth_users() {
+        let mut snapshot = test_snapshot();
+        snapshot.proxy.auth = Some(crate::config::ProxyAuthConfig::default());
+        let result = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn render_block_applies_tls_modes() {
+        let mut snapshot = test_snapshot();
+        snapshot.proxy.tls.mode = TlsMode::Internal;
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render internal");
+        assert!(block.contains("    tls internal"));
+
+        snapshot.proxy.tls.mode = TlsMode::Custom;
+        assert!(
+            render_app_block(
+                "app",
+                "r1",
+                &snapshot,
+                &RouteExtras::default(),
+                Path::new(DEFAULT_CADDY_CERTS_DIR)
+            )
+            .is_err()
+        );
+        snapshot.proxy.tls.cert = Some("app/fullchain.pem".to_string());
+        snapshot.proxy.tls.key = Some("/certs/app/key.pem".to_string());
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render custom");
+        assert!(block.contains("    tls /certs/app/fullchain.pem /certs/app/key.pem"));
+
+        snapshot.proxy.tls.key = Some("/srv/deep/caddy/certs/app/key.pem".to_string());
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render host path");
+        assert!(block.contains("    tls /certs/app/fullchain.pem /certs/app/key.pem"));
+        for key in ["/etc/ssl/key.pem", "../key.pem", "/certs/../etc/key.pem"] {
+            snapshot.proxy.tls.key = Some(key.to_string());
+            assert!(
+                render_app_block(
+                    "app",
+                    "r1",
+                    &snapshot,
+                    &RouteExtras::default(),
+                    Path::new(DEFAULT_CADDY_CERTS_DIR)
+                )
+                .is_err()
+            );
+        }
+        snapshot.proxy.tls.key = Some("app/key.pem".to_string());
+
+        let certs_dir = Path::new("/opt/deep/caddy/certs");
+        snapshot.proxy.tls.key = Some("/opt/deep/caddy/certs/app/key.pem".to_string());
+        let block = render_app_block("app", "r1", &snapshot, &RouteExtras::default(), certs_dir)
+            .expect("render configured certs dir");
+        assert!(block.contains("    tls /certs/app/fullchain.pem /certs/app/key.pem"));
+        snapshot.proxy.tls.key = Some("/srv/deep/caddy/certs/app/key.pem".to_string());
+        assert!(
+            render_app_block("app", "r1", &snapshot, &RouteExtras::default(), certs_dir).is_err()
+        );
+
+        snapshot.proxy.tls.mode = TlsMode::Off;
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render off");
+        assert!(block.contains("http://app.example.com {"));
+        assert!(!block.contains("tls "));
+    }
+
+    #[test]
+    fn render_block_writes_json_access_log() {
+        let mut snapshot = test_snapshot();
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render");
+        assert!(block.contains("        output file /logs/app.log {"));
+        assert!(block.contains("            roll_size 10MiB"));
+        assert!(block.contains("        format json"));
+        let routes = parse_caddyfile_routes(&block);
+        assert_eq!(routes[0].hosts, vec!["app.example.com".to_string()]);
+        assert_eq!(
+            routes[0].upstreams,
+            vec!["deep-app-app-r1:3000".to_string()]
+        );
+
+        snapshot.proxy.access_log.enabled = false;
+        let block = render_app_block(
+            "app",
+            "r1",
+            &snapshot,
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render");
+        assert!(!block.contains("log {"));
+    }
+
+    #[test]
+    fn rebuild_keeps_user_text_and_replaces_blocks() {
+        let contents = r#"{
+    email ops@example.com
+}
+
+# deep:app:old
+old.example.com {
+    reverse_proxy deep-app-old-r1:3000
+}
+# deep:end
+
+static.example.com {
+    root * /srv/www
+}
+"#;
+        let block = render_app_block(
+            "app",
+            "r2",
+            &test_snapshot(),
+            &RouteExtras::default(),
+            Path::new(DEFAULT_CADDY_CERTS_DIR),
+        )
+        .expect("render");
+        let updated = rebuild_caddyfile(contents, &[("app".to_string(), block)]).expect("rebuild");
+        assert!(updated.contains("email ops@example.com"));
+        assert!(updated.contains("root * /srv/www"));
+        assert!(!updated.contains("deep:app:old"));
+        assert!(updated.ends_with("reverse_proxy deep-app-app-r2:3000\n}\n# deep:end\n"));
+        let blocks = parse_caddyfile_routes(&updated);
+        assert_eq!(blocks.len(), 1);
+        let again = rebuild_caddyfile(&updated, &[("app".to_string(), blocks_text(&updated))])
+            .expect("rebuild again");
+        assert_eq!(again, updated);
+    }
+
+    #[test]
+    fn rebuild_rejects_unterminated_marker() {
+        let contents = "# deep:app:old\nold.example.com {\n}\n\nstatic.example.com {\n}\n";
+        let err = rebuild_caddyfile(contents, &[]).expect_err("unterminated block");
+        assert!(err.to_string().contains("# deep:app:old"), "{err}");
+
+        let contents =
+            "# deep:app:a\na.example.com {\n}\n# deep:app:b\nb.example.com {\n}\n# deep:end\n";
+        let err = rebuild_caddyfile(contents, &[]).expect_err("nested block");
+        assert!(err.to_string().contains("# deep:app:a"), "{err}");
+    }
+
+    #[test]
+    fn add_logs_mount_extends_old_caddy_quadlets() {
+        let old = "[Container]\nImage=caddy:2-alpine\nVolume=/opt/deep/caddy/data:/data\nVolume=/opt/deep/caddy/certs:/certs:ro\n\n[Service]\nRestart=always\n";
+        let (updated, logs_dir) = add_logs_mount(old).expect("mount added");
+        assert_eq!(logs_dir, PathBuf::from("/opt/deep/caddy/logs"));
+        assert!(updated.contains(
+            "Volume=/opt/deep/caddy/certs:/certs:ro\nVolume=/opt/deep/caddy/logs:/logs\n\n[Service]"
+        ));
+        assert!(add_logs_mount(&updated).is_none());
+    }
+
+    #[test]
+    fn unified_diff_reports_hunks() {
+        assert_eq!(unified_diff("a", "b", "x\ny\n", "x\ny\n"), "");
+        let diff = unified_diff("a", "b", "1\n2\n3\n4\n5\n", "1\n2\nthree\n4\n5\n");
+        assert_eq!(
+            diff,
+            "--- a\n+++ b\n@@ -1,5 +1,5 @@\n 1\n 2\n-3\n+three\n 4\n 5\n"
+        );
+        let diff = unified_diff("a", "b", "", "new\n");
+        assert_eq!(diff, "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+new\n");
+    }
+
+    fn blocks_text(contents: &str) -> String {
+        let start = contents.find("# deep:app:").expect("block start");
+        contents[start..].to_string()
+    }
+
+    fn test_snapshot() -> ConfigSnapshot {
+        ConfigSnapshot {
+            env: Default::default(),
+            port: 3000,
+            domains: vec!["app.example.com".to_string()],
+            addons: Vec::new(),
+            healthcheck: crate::config::HealthcheckConfig::default(),
+            deploy: crate::config::DeployConfig::default(),
+            proxy: crate::config::ProxyConfig::default(),
+            resources: Default::default(),
+            volumes: Vec::new(),
+        }
+    }
 }
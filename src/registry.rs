@@ -0,0 +1,214 @@
+//! Registry credentials in a deep-managed auth file.
+//!
+//! The file uses the `auths` format both engines read: Podman takes it via
+//! `--authfile`, Docker via `--config <dir>`, which is why it is named
+//! `config.json`. It is written with mode 0600 inside a 0700 directory.
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value, json};
+use std::path::{Path, PathBuf};
+
+/// Auth file used for every pull, build, push and container unit.
+pub const DEFAULT_AUTH_FILE: &str = "/srv/deep/registry/config.json";
+
+/// The auth file, if someone has logged in to a registry.
+pub fn auth_file() -> Option<PathBuf> {
+    let path = PathBuf::from(DEFAULT_AUTH_FILE);
+    path.exists().then_some(path)
+}
+
+/// Quadlet line pointing a container at the auth file, or empty when there is none.
+pub fn auth_line(auth_file: Option<&Path>) -> String {
+    auth_file
+        .map(|path| format!("PodmanArgs=--authfile={}", path.display()))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// One stored login, without the password.
+pub struct RegistryLogin {
+    pub registry: String,
+    pub username: String,
+}
+
+/// Store credentials for a registry, replacing any previous login.
+pub fn login(path: &Path, registry: &str, username: &str, password: &str) -> Result<()> {
+    let registry = normalize_registry(registry)?;
+    if username.is_empty() || password.is_empty() {
+        bail!("username and password are required");
+    }
+    let mut auths = load_auths(path)?;
+    let token = base64_encode(format!("{}:{}", username, password).as_bytes());
+    auths.insert(registry, json!({ "auth": token }));
+    save_auths(path, auths)
+}
+
+/// Forget a registry; returns false when it had no stored login.
+pub fn logout(path: &Path, registry: &str) -> Result<bool> {
+    let registry = normalize_registry(registry)?;
+    let mut auths = load_auths(path)?;
+    if auths.remove(&registry).is_none() {
+        return Ok(false);
+    }
+    save_auths(path, auths)?;
+    Ok(true)
+}
+
+/// Registries with stored logins, sorted by name.
+pub fn list(path: &Path) -> Result<Vec<RegistryLogin>> {
+    let mut logins: Vec<RegistryLogin> = load_auths(path)?
+        .into_iter()
+        .map(|(registry, entry)| {
+            let username = entry
+                .get("auth")
+                .and_then(Value::as_str)
+                .and_then(base64_decode)
+                .and_then(|raw| String::from_utf8(raw).ok())
+                .and_then(|pair| pair.split_once(':').map(|(user, _)| user.to_string()))
+                .unwrap_or_else(|| "-".to_string());
+            RegistryLogin { registry, username }
+        })
+        .collect();
+    logins.sort_by(|left, right| left.registry.cmp(&right.registry));
+    Ok(logins)
+}
+
+/// Registry host as engines key it: no scheme, no trailing slash.
+fn normalize_registry(registry: &str) -> Result<String> {
+    let registry = registry
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    if registry.is_empty() || registry.contains(char::is_whitespace) {
+        bail!("invalid registry {:?}", registry);
+    }
+    Ok(registry.to_string())
+}
+
+fn load_auths(path: &Path) -> Result<Map<String, Value>> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let value: Value = serde_json::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(value
+        .get("auths")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default())
+}
+
+fn save_auths(path: &Path, auths: Map<String, Value>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+        set_mode(parent, 0o700)?;
+    }
+    let contents = serde_json::to_string_pretty(&json!({ "auths": auths }))?;
+    // Create the file restricted before any secret is written to it.
+    std::fs::write(path, "").with_context(|| format!("failed to write {}", path.display()))?;
+    set_mode(path, 0o600)?;
+    std::fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        for (i, shift) in [18, 12, 6, 0].into_iter().enumerate() {
+            if i <= chunk.len() {
+                out.push(BASE64[((n >> shift) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in input.trim_end_matches('=').bytes() {
+        let value = BASE64.iter().position(|&c| c == byte)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_list_logout_round_trip_the_auth_file() -> Result<()> {
+        let temp = tempfile::TempDir::new()?;
+        let path = temp.path().join("registry").join("config.json");
+        assert!(list(&path)?.is_empty());
+
+        login(&path, "https://ghcr.io/", "me", "tok:en")?;
+        login(&path, "123.dkr.ecr.eu-west-1.amazonaws.com", "AWS", "x")?;
+        let raw = std::fs::read_to_string(&path)?;
+        let value: Value = serde_json::from_str(&raw)?;
+        assert_eq!(value["auths"]["ghcr.io"]["auth"], "bWU6dG9rOmVu");
+        assert_eq!(
+            list(&path)?,
+            [
+                RegistryLogin {
+                    registry: "123.dkr.ecr.eu-west-1.amazonaws.com".to_string(),
+                    username: "AWS".to_string(),
+                },
+                RegistryLogin {
+                    registry: "ghcr.io".to_string(),
+                    username: "me".to_string(),
+                },
+            ]
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+
+        assert!(logout(&path, "ghcr.io")?);
+        assert!(!logout(&path, "ghcr.io")?);
+        assert_eq!(list(&path)?.len(), 1);
+        assert_eq!(auth_line(None), "");
+        assert_eq!(
+            auth_line(Some(&path)),
+            format!("PodmanArgs=--authfile={}", path.display())
+        );
+        Ok(())
+    }
+}
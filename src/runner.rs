@@ -1,13 +1,54 @@
 //! Command runner abstraction for shelling out to system tools.
 
 use anyhow::{Context, Result};
-use std::process::{ExitStatus, Output};
-use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, mpsc};
 
 /// Runner interface for invoking external commands.
 pub trait Runner: Send + Sync {
     /// Execute a command and return its captured output.
     fn output(&self, program: &str, args: &[&str]) -> Result<Output>;
+
+    /// Execute a command with `input` written to its stdin and return its captured output.
+    ///
+    /// Used for secrets that must not appear in the process list. The default
+    /// implementation ignores the input, which is enough for test runners.
+    fn output_with_stdin(&self, program: &str, args: &[&str], input: &[u8]) -> Result<Output> {
+        let _ = input;
+        self.output(program, args)
+    }
+
+    /// Execute a command, passing stdout lines to `sink` and stderr lines to `err_sink`
+    /// as they arrive.
+    ///
+    /// The default implementation replays captured output, which is enough for test runners.
+    fn stream(
+        &self,
+        program: &str,
+        args: &[&str],
+        sink: &mut dyn FnMut(&str),
+        err_sink: &mut dyn FnMut(&str),
+    ) -> Result<ExitStatus> {
+        let output = self.output(program, args)?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            sink(line);
+        }
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            err_sink(line);
+        }
+        Ok(output.status)
+    }
+
+    /// Execute a command attached to the terminal (stdin, stdout and stderr inherited).
+    ///
+    /// The default implementation prints captured output, which is enough for test runners.
+    fn interactive(&self, program: &str, args: &[&str]) -> Result<ExitStatus> {
+        let output = self.output(program, args)?;
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+        Ok(output.status)
+    }
 }
 
 struct RealRunner;
@@ -19,6 +60,87 @@ impl Runner for RealRunner {
             .output()
             .with_context(|| format!("failed to run {} {:?}", program, args))
     }
+
+    fn output_with_stdin(&self, program: &str, args: &[&str], input: &[u8]) -> Result<Output> {
+        let mut child = std::process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {} {:?}", program, args))?;
+        let mut stdin = child.stdin.take().context("missing stdin pipe")?;
+        stdin
+            .write_all(input)
+            .with_context(|| format!("failed to write stdin of {}", program))?;
+        drop(stdin);
+        child
+            .wait_with_output()
+            .with_context(|| format!("failed to wait for {} {:?}", program, args))
+    }
+
+    fn stream(
+        &self,
+        program: &str,
+        args: &[&str],
+        sink: &mut dyn FnMut(&str),
+        err_sink: &mut dyn FnMut(&str),
+    ) -> Result<ExitStatus> {
+        let mut child = std::process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {} {:?}", program, args))?;
+        let (tx, rx) = mpsc::channel();
+        // Each line is tagged with whether it came from stderr.
+        let readers: Vec<(bool, Box<dyn Read + Send>)> = vec![
+            (
+                false,
+                Box::new(child.stdout.take().context("missing stdout pipe")?),
+            ),
+            (
+                true,
+                Box::new(child.stderr.take().context("missing stderr pipe")?),
+            ),
+        ];
+        let handles: Vec<_> = readers
+            .into_iter()
+            .map(|(is_stderr, reader)| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for line in BufReader::new(reader).lines() {
+                        let Ok(line) = line else { break };
+                        if tx.send((is_stderr, line)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        for (is_stderr, line) in rx {
+            if is_stderr {
+                err_sink(&line);
+            } else {
+                sink(&line);
+            }
+        }
+        for handle in handles {
+            let _ = handle.join();
+        }
+        child
+            .wait()
+            .with_context(|| format!("failed to wait for {} {:?}", program, args))
+    }
+
+    fn interactive(&self, program: &str, args: &[&str]) -> Result<ExitStatus> {
+        std::process::Command::new(program)
+            .args(args)
+            .status()
+            .with_context(|| format!("failed to run {} {:?}", program, args))
+    }
 }
 
 static RUNNER: OnceLock<RwLock<Arc<dyn Runner>>> = OnceLock::new();
@@ -34,11 +156,35 @@ pub fn run_output(program: &str, args: &[&str]) -> Result<Output> {
     runner.output(program, args)
 }
 
+/// Run a command with `input` on stdin and capture its output.
+pub fn run_output_with_stdin(program: &str, args: &[&str], input: &[u8]) -> Result<Output> {
+    let runner = runner_lock().read().expect("runner lock poisoned");
+    runner.output_with_stdin(program, args, input)
+}
+
 /// Run a command and return its exit status.
 pub fn run_status(program: &str, args: &[&str]) -> Result<ExitStatus> {
     Ok(run_output(program, args)?.status)
 }
 
+/// Run a command, streaming stdout lines to `sink` and stderr lines to `err_sink`,
+/// and return its exit status.
+pub fn run_streaming(
+    program: &str,
+    args: &[&str],
+    sink: &mut dyn FnMut(&str),
+    err_sink: &mut dyn FnMut(&str),
+) -> Result<ExitStatus> {
+    let runner = runner_lock().read().expect("runner lock poisoned").clone();
+    runner.stream(program, args, sink, err_sink)
+}
+
+/// Run a command attached to the terminal and return its exit status.
+pub fn run_interactive(program: &str, args: &[&str]) -> Result<ExitStatus> {
+    let runner = runner_lock().read().expect("runner lock poisoned").clone();
+    runner.interactive(program, args)
+}
+
 /// Check if a command is present on PATH.
 pub fn command_exists(command: &str) -> bool {
     let probe = format!("command -v {}", command);
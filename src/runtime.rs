@@ -1,41 +1,628 @@
-//! Podman CLI runtime helpers for image and container operations.
+//! Container runtime helpers (Podman or Docker) for image and container operations.
 
 use anyhow::{Context, Result, bail};
 use reqwest::blocking::Client;
 use std::net::{SocketAddr, TcpStream};
-use std::process::Output;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::config::HealthcheckKind;
+use crate::registry;
 use crate::runner;
 
-const NETWORK_NAME: &str = "deep-net";
+/// Network shared by Caddy, addons and every app without a private network.
+pub const NETWORK_NAME: &str = "deep-net";
 
-#[derive(Debug, Clone)]
-/// Podman runtime wrapper.
-pub struct Runtime {
-    engine: &'static str,
+#[derive(Debug, Clone, Default)]
+/// Options shared by the container and journald log backends.
+pub struct LogOptions {
+    pub follow: bool,
+    pub timestamps: bool,
+    pub since: Option<LogTime>,
+    pub until: Option<LogTime>,
+    pub tail: Option<u32>,
 }
 
-impl Runtime {
-    /// Detect the runtime (Podman only).
-    pub fn detect() -> Result<Self> {
-        if runner::command_exists("podman") {
-            return Ok(Self { engine: "podman" });
+#[derive(Debug, Clone, PartialEq)]
+/// A log window bound: relative to now or an absolute timestamp.
+pub enum LogTime {
+    /// Seconds before now.
+    Ago(u64),
+    /// Timestamp passed through to the backend unchanged.
+    At(String),
+}
+
+impl LogTime {
+    /// Format for `podman logs --since/--until`.
+    pub fn engine_arg(&self) -> String {
+        match self {
+            LogTime::Ago(seconds) => format!("{}s", seconds),
+            LogTime::At(value) => value.clone(),
         }
-        bail!("podman not found on PATH")
     }
 
-    /// Pull an image and return its resolved digest.
-    pub fn pull_image(&self, image_ref: &str) -> Result<String> {
-        self.run(&["pull", image_ref])?;
-        let digest = self.run_capture(&[
+    /// Format for `journalctl --since/--until`.
+    pub fn journal_arg(&self) -> String {
+        match self {
+            LogTime::Ago(seconds) => format!("-{}s", seconds),
+            LogTime::At(value) => value.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Point-in-time resource usage reported by `podman stats`.
+pub struct ContainerStats {
+    pub name: String,
+    pub cpu_percent: String,
+    pub mem_usage: String,
+    pub mem_percent: String,
+    pub pids: String,
+}
+
+impl ContainerStats {
+    /// Parse `podman stats --format json` output (podman and docker key styles).
+    pub fn parse_all(raw: &str) -> Vec<Self> {
+        let text = |value: &serde_json::Value, keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| match &value[*key] {
+                    serde_json::Value::String(text) => Some(text.clone()),
+                    serde_json::Value::Number(number) => Some(number.to_string()),
+                    _ => None,
+                })
+                .unwrap_or_default()
+        };
+        let values: Vec<serde_json::Value> = match serde_json::from_str(raw.trim()) {
+            Ok(serde_json::Value::Array(values)) => values,
+            // docker prints one JSON object per line
+            _ => raw
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+        };
+        values
+            .iter()
+            .map(|value| Self {
+                name: text(value, &["name", "Name"])
+                    .trim_start_matches('/')
+                    .to_string(),
+                cpu_percent: text(value, &["cpu_percent", "CPUPerc"]),
+                mem_usage: text(value, &["mem_usage", "MemUsage"]),
+                mem_percent: text(value, &["mem_percent", "MemPerc"]),
+                pids: text(value, &["pids", "PIDs"]),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Container state reported by `podman inspect`.
+pub struct ContainerState {
+    pub status: String,
+    pub health: Option<String>,
+    pub restart_count: u64,
+    pub started_at: Option<String>,
+}
+
+impl ContainerState {
+    /// Parse `podman inspect <name>` JSON output (an array with one object).
+    pub fn parse(raw: &str) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_str(raw.trim()).ok()?;
+        let value = match value {
+            serde_json::Value::Array(mut items) if !items.is_empty() => items.remove(0),
+            serde_json::Value::Object(_) => value,
+            _ => return None,
+        };
+        let state = &value["State"];
+        let health = state["Health"]["Status"]
+            .as_str()
+            .or_else(|| state["Healthcheck"]["Status"].as_str())
+            .filter(|status| !status.is_empty())
+            .map(str::to_string);
+        Some(Self {
+            status: state["Status"].as_str().unwrap_or("unknown").to_string(),
+            health,
+            restart_count: value["RestartCount"].as_u64().unwrap_or(0),
+            started_at: state["StartedAt"].as_str().map(str::to_string),
+        })
+    }
+
+    /// Seconds since the container started, when it is running.
+    pub fn uptime_secs(&self, now_unix: i64) -> Option<i64> {
+        if self.status != "running" {
+            return None;
+        }
+        let started = parse_rfc3339_unix(self.started_at.as_deref()?)?;
+        Some((now_unix - started).max(0))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// One local image from `image ls`.
+pub struct ImageInfo {
+    pub id: String,
+    pub repository: String,
+    pub tag: String,
+    /// `sha256:...`, empty for images that were built locally and never pushed.
+    pub digest: String,
+}
+
+impl ImageInfo {
+    /// Format for `image ls` that `parse_all` reads; the same in both engines.
+    const FORMAT: &'static str = "{{.ID}}\t{{.Repository}}\t{{.Tag}}\t{{.Digest}}";
+
+    /// Parse tab-separated `image ls` lines.
+    pub fn parse_all(raw: &str) -> Vec<Self> {
+        let known = |value: &str| {
+            let value = value.trim();
+            if value == "<none>" {
+                String::new()
+            } else {
+                value.to_string()
+            }
+        };
+        raw.lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let id = known(fields.next()?);
+                if id.is_empty() {
+                    return None;
+                }
+                Some(Self {
+                    id,
+                    repository: known(fields.next().unwrap_or_default()),
+                    tag: known(fields.next().unwrap_or_default()),
+                    digest: known(fields.next().unwrap_or_default()),
+                })
+            })
+            .collect()
+    }
+
+    /// `repository:tag`, if the image is tagged.
+    pub fn tagged_ref(&self) -> Option<String> {
+        (!self.repository.is_empty() && !self.tag.is_empty())
+            .then(|| format!("{}:{}", self.repository, self.tag))
+    }
+
+    /// `repository@digest`, if the image came from a registry.
+    pub fn digest_ref(&self) -> Option<String> {
+        (!self.repository.is_empty() && !self.digest.is_empty())
+            .then(|| format!("{}@{}", self.repository, self.digest))
+    }
+
+    /// The most specific name to remove the image by.
+    pub fn reference(&self) -> String {
+        self.tagged_ref()
+            .or_else(|| self.digest_ref())
+            .unwrap_or_else(|| self.id.clone())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// One container from `ps -a`.
+pub struct ContainerInfo {
+    pub name: String,
+    /// Image as the container was created from: a name, a digest reference or an ID.
+    pub image: String,
+    pub state: String,
+}
+
+impl ContainerInfo {
+    const FORMAT: &'static str = "{{.Names}}\t{{.Image}}\t{{.State}}";
+
+    /// Parse tab-separated `ps -a` lines.
+    pub fn parse_all(raw: &str) -> Vec<Self> {
+        raw.lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t').map(str::trim);
+                let name = fields.next().filter(|name| !name.is_empty())?;
+                Some(Self {
+                    name: name.to_string(),
+                    image: fields.next().unwrap_or_default().to_string(),
+                    state: fields.next().unwrap_or_default().to_lowercase(),
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the container is not running (exited, created, stopped or dead).
+    pub fn is_stopped(&self) -> bool {
+        !matches!(self.state.as_str(), "running" | "paused" | "restarting")
+    }
+
+    /// Whether this container was created from `image`.
+    pub fn uses(&self, image: &ImageInfo) -> bool {
+        let id = image.id.trim_start_matches("sha256:");
+        let own = self.image.trim_start_matches("sha256:");
+        Some(&self.image) == image.tagged_ref().as_ref()
+            || Some(&self.image) == image.digest_ref().as_ref()
+            || (own.len() >= 12 && (id.starts_with(own) || own.starts_with(id)))
+    }
+}
+
+/// Human-readable size in binary units, e.g. `1.5GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+/// Parse an RFC 3339 timestamp (`2024-05-01T10:00:00.123Z`, `...+02:00`) into unix seconds.
+pub fn parse_rfc3339_unix(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (date, rest) = value.split_once('T')?;
+    let mut date_parts = date.split('-');
+    let year: i32 = date_parts.next()?.parse().ok()?;
+    let month: u8 = date_parts.next()?.parse().ok()?;
+    let day: u8 = date_parts.next()?.parse().ok()?;
+    let offset_at = rest.find(['Z', 'z', '+', '-']).unwrap_or(rest.len());
+    let (clock, offset) = rest.split_at(offset_at);
+    let clock = clock.split('.').next()?;
+    let mut clock_parts = clock.split(':');
+    let hour: u8 = clock_parts.next()?.parse().ok()?;
+    let minute: u8 = clock_parts.next()?.parse().ok()?;
+    let second: u8 = clock_parts.next()?.parse().ok()?;
+    let offset_secs = match offset.chars().next() {
+        None | Some('Z') | Some('z') => 0,
+        Some(sign) => {
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let secs = hours.parse::<i64>().ok()? * 3_600 + minutes.parse::<i64>().ok()? * 60;
+            if sign == '-' { -secs } else { secs }
+        }
+    };
+    let date =
+        time::Date::from_calendar_date(year, time::Month::try_from(month).ok()?, day).ok()?;
+    let clock = time::Time::from_hms(hour, minute, second).ok()?;
+    let utc = time::PrimitiveDateTime::new(date, clock).assume_utc();
+    Some(utc.unix_timestamp() - offset_secs)
+}
+
+/// Format a duration in seconds compactly (`45s`, `12m`, `3h5m`, `2d4h`).
+pub fn format_uptime(secs: i64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3_600, secs % 3_600 / 60);
+    if days > 0 {
+        format!("{}d{}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h{}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A container described independently of quadlets, for `<engine> create`.
+pub struct RunSpec {
+    pub name: String,
+    pub image: String,
+    /// Networks to join; `create` takes the first, the rest are connected afterwards.
+    pub networks: Vec<String>,
+    /// Flags passed through unchanged (env, volumes, ports, limits, health, logging).
+    pub flags: Vec<String>,
+    pub command: Vec<String>,
+    /// Registry auth file for pulling the image, from `PodmanArgs=--authfile=`.
+    pub authfile: Option<String>,
+}
+
+/// Builds the engine-specific arguments for the commands deep runs.
+///
+/// Podman and Docker share most of their CLI, so the defaults are the common form.
+pub trait Engine: std::fmt::Debug + Send + Sync {
+    /// Binary name, also used for `command -v` and `/usr/bin/<name>` in units.
+    fn program(&self) -> &'static str;
+
+    /// Whether containers run from quadlets; otherwise from generated `.service` units.
+    fn uses_quadlets(&self) -> bool;
+
+    /// Pull an image.
+    fn pull_args(&self, image_ref: &str) -> Vec<String> {
+        strings(&["pull", image_ref])
+    }
+
+    /// Point a pull, build, push or create at a registry auth file.
+    fn auth_args(&self, args: Vec<String>, authfile: &Path) -> Vec<String> {
+        let mut args = args.into_iter();
+        let mut authed: Vec<String> = args.next().into_iter().collect();
+        authed.push("--authfile".to_string());
+        authed.push(authfile.to_string_lossy().to_string());
+        authed.extend(args);
+        authed
+    }
+
+    /// Print the repo digest of a pulled image.
+    fn digest_args(&self, image_ref: &str) -> Vec<String> {
+        strings(&[
             "image",
             "inspect",
             "--format",
             "{{index .RepoDigests 0}}",
             image_ref,
-        ])?;
+        ])
+    }
+
+    /// Create a labeled network.
+    fn network_create_args(&self, name: &str, labels: &[String]) -> Vec<String> {
+        let mut args = strings(&["network", "create"]);
+        for label in labels {
+            args.push("--label".to_string());
+            args.push(label.clone());
+        }
+        args.push(name.to_string());
+        args
+    }
+
+    /// Print (or follow) a container's logs.
+    fn logs_args(&self, container_name: &str, options: &LogOptions) -> Vec<String> {
+        let mut args = vec!["logs".to_string()];
+        if options.follow {
+            args.push("-f".to_string());
+        }
+        if options.timestamps {
+            args.push("-t".to_string());
+        }
+        if let Some(since) = &options.since {
+            args.push("--since".to_string());
+            args.push(since.engine_arg());
+        }
+        if let Some(until) = &options.until {
+            args.push("--until".to_string());
+            args.push(until.engine_arg());
+        }
+        if let Some(tail) = options.tail {
+            args.push("--tail".to_string());
+            args.push(tail.to_string());
+        }
+        args.push(container_name.to_string());
+        args
+    }
+
+    /// Run a command in a running container with stdin attached.
+    fn exec_args(&self, container_name: &str, command: &[String], tty: bool) -> Vec<String> {
+        let mut args = strings(&["exec", "-i"]);
+        if tty {
+            args.push("-t".to_string());
+        }
+        args.push(container_name.to_string());
+        args.extend(command.iter().cloned());
+        args
+    }
+
+    /// Create (not start) a container; units start it with `start -a` once networks are attached.
+    fn create_args(&self, spec: &RunSpec) -> Vec<String> {
+        let mut args = strings(&["create", "--name", &spec.name]);
+        if let Some(network) = spec.networks.first() {
+            args.push("--network".to_string());
+            args.push(network.clone());
+        }
+        args.extend(spec.flags.iter().cloned());
+        args.push(spec.image.clone());
+        args.extend(spec.command.iter().cloned());
+        match &spec.authfile {
+            Some(authfile) => self.auth_args(args, Path::new(authfile)),
+            None => args,
+        }
+    }
+
+    /// One-shot resource usage as JSON.
+    fn stats_args(&self, names: &[&str]) -> Vec<String> {
+        let mut args = strings(&["stats", "--no-stream", "--format", "json"]);
+        args.extend(names.iter().map(|name| name.to_string()));
+        args
+    }
+
+    /// Write a named volume's contents to a tar archive.
+    fn export_volume_args(&self, volume: &str, output: &Path) -> Vec<String> {
+        let output = output.to_string_lossy();
+        strings(&["volume", "export", "--output", output.as_ref(), volume])
+    }
+
+    /// Replace a named volume's contents with a tar archive.
+    fn import_volume_args(&self, volume: &str, input: &Path) -> Vec<String> {
+        let input = input.to_string_lossy();
+        strings(&["volume", "import", volume, input.as_ref()])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Podman with quadlets.
+pub struct Podman;
+
+impl Engine for Podman {
+    fn program(&self) -> &'static str {
+        "podman"
+    }
+
+    fn uses_quadlets(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Docker with generated systemd services.
+pub struct Docker;
+
+/// Image used to copy volume contents, since Docker has no `volume export`.
+const DOCKER_VOLUME_HELPER: &str = "docker.io/library/alpine:3";
+
+impl Engine for Docker {
+    fn program(&self) -> &'static str {
+        "docker"
+    }
+
+    fn uses_quadlets(&self) -> bool {
+        false
+    }
+
+    fn auth_args(&self, args: Vec<String>, authfile: &Path) -> Vec<String> {
+        // Docker reads `config.json` from a config directory instead of a file flag.
+        let dir = authfile.parent().unwrap_or(Path::new("."));
+        let mut authed = vec!["--config".to_string(), dir.to_string_lossy().to_string()];
+        authed.extend(args);
+        authed
+    }
+
+    fn stats_args(&self, names: &[&str]) -> Vec<String> {
+        // One JSON object per line; `ContainerStats::parse_all` reads both shapes.
+        let mut args = strings(&["stats", "--no-stream", "--format", "{{json .}}"]);
+        args.extend(names.iter().map(|name| name.to_string()));
+        args
+    }
+
+    fn export_volume_args(&self, volume: &str, output: &Path) -> Vec<String> {
+        let (dir, file) = split_archive_path(output);
+        strings(&[
+            "run",
+            "--rm",
+            "-v",
+            &format!("{}:/volume:ro", volume),
+            "-v",
+            &format!("{}:/archive", dir),
+            DOCKER_VOLUME_HELPER,
+            "tar",
+            "-cf",
+            &format!("/archive/{}", file),
+            "-C",
+            "/volume",
+            ".",
+        ])
+    }
+
+    fn import_volume_args(&self, volume: &str, input: &Path) -> Vec<String> {
+        let (dir, file) = split_archive_path(input);
+        strings(&[
+            "run",
+            "--rm",
+            "-v",
+            &format!("{}:/volume", volume),
+            "-v",
+            &format!("{}:/archive:ro", dir),
+            DOCKER_VOLUME_HELPER,
+            "sh",
+            "-c",
+            &format!(
+                "find /volume -mindepth 1 -delete && tar -xf /archive/{} -C /volume",
+                file
+            ),
+        ])
+    }
+}
+
+fn split_archive_path(path: &Path) -> (String, String) {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file = path
+        .file_name()
+        .map(|file| file.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (dir.to_string_lossy().to_string(), file)
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Engine named by `[runtime] engine` in the host config, if any.
+fn configured_engine(host_config: &Path) -> Result<Option<String>> {
+    Ok(crate::config::load_host_config(host_config)?
+        .runtime
+        .engine
+        .map(|engine| engine.trim().to_lowercase())
+        .filter(|engine| !engine.is_empty()))
+}
+
+#[derive(Debug, Clone)]
+/// Container runtime wrapper over a Podman or Docker engine.
+pub struct Runtime {
+    engine: Arc<dyn Engine>,
+    authfile: Option<PathBuf>,
+}
+
+impl Runtime {
+    /// Use the engine from the host config, else Podman, else Docker.
+    fn detect(configured: Option<String>) -> Result<Self> {
+        let engines: [Arc<dyn Engine>; 2] = [Arc::new(Podman), Arc::new(Docker)];
+        if let Some(name) = configured {
+            let Some(engine) = engines.into_iter().find(|engine| engine.program() == name) else {
+                bail!(
+                    "unknown runtime engine {:?} in deep.toml; use podman or docker",
+                    name
+                );
+            };
+            if !runner::command_exists(engine.program()) {
+                bail!("{} not found on PATH", engine.program());
+            }
+            return Ok(Self::with_engine(engine).with_auth_file(registry::auth_file()));
+        }
+        engines
+            .into_iter()
+            .find(|engine| runner::command_exists(engine.program()))
+            .map(|engine| Self::with_engine(engine).with_auth_file(registry::auth_file()))
+            .context("neither podman nor docker found on PATH")
+    }
+
+    /// Check that the engine binary is on PATH.
+    pub fn available(&self) -> Result<()> {
+        if !runner::command_exists(self.program()) {
+            bail!("{} not found on PATH", self.program());
+        }
+        Ok(())
+    }
+
+    /// Wrap a specific engine, skipping detection.
+    pub fn with_engine(engine: Arc<dyn Engine>) -> Self {
+        Self {
+            engine,
+            authfile: None,
+        }
+    }
+
+    /// Use a registry auth file for pulls, builds and pushes.
+    pub fn with_auth_file(mut self, authfile: Option<PathBuf>) -> Self {
+        self.authfile = authfile;
+        self
+    }
+
+    /// The registry auth file in use, if any.
+    pub fn auth_file(&self) -> Option<&Path> {
+        self.authfile.as_deref()
+    }
+
+    /// Add the auth file to registry-facing arguments when there is one.
+    pub fn authed(&self, args: Vec<String>) -> Vec<String> {
+        match &self.authfile {
+            Some(authfile) => self.engine.auth_args(args, authfile),
+            None => args,
+        }
+    }
+
+    /// The engine's binary name (`podman` or `docker`).
+    pub fn program(&self) -> &'static str {
+        self.engine.program()
+    }
+
+    /// The engine building this runtime's commands.
+    pub fn engine(&self) -> &dyn Engine {
+        self.engine.as_ref()
+    }
+
+    /// Pull an image and return its resolved digest.
+    pub fn pull_image(&self, image_ref: &str) -> Result<String> {
+        self.run_args(&self.authed(self.engine.pull_args(image_ref)))?;
+        let digest = self.capture_args(&self.engine.digest_args(image_ref))?;
         let digest = digest.trim();
         if digest.is_empty() || digest == "<no value>" {
             return Ok(image_ref.to_string());
@@ -109,15 +696,19 @@ impl Runtime {
         }
     }
 
-    /// Tail logs for a container.
-    pub fn logs(&self, container_name: &str, follow: bool) -> Result<()> {
-        let mut args = vec!["logs"];
-        if follow {
-            args.push("-f");
-        }
-        args.push(container_name);
-        let status =
-            runner::run_status(self.engine, &args).with_context(|| "failed to run logs command")?;
+    /// Stream logs for a container: its stdout to `sink`, and its stderr along with
+    /// any engine errors to `err_sink`.
+    pub fn logs(
+        &self,
+        container_name: &str,
+        options: &LogOptions,
+        sink: &mut dyn FnMut(&str),
+        err_sink: &mut dyn FnMut(&str),
+    ) -> Result<()> {
+        let args = self.engine.logs_args(container_name, options);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let status = runner::run_streaming(self.program(), &args, sink, err_sink)
+            .with_context(|| "failed to run logs command")?;
         if status.success() {
             Ok(())
         } else {
@@ -125,6 +716,95 @@ impl Runtime {
         }
     }
 
+    /// Run a command in a container attached to the terminal; allocates a TTY when stdin is one.
+    pub fn exec_interactive(&self, container_name: &str, command: &[String]) -> Result<ExitStatus> {
+        use std::io::IsTerminal;
+        let args = self
+            .engine
+            .exec_args(container_name, command, std::io::stdin().is_terminal());
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        runner::run_interactive(self.program(), &args)
+            .with_context(|| format!("failed to exec in {}", container_name))
+    }
+
+    /// Read one-shot resource usage for running containers (all when `names` is empty).
+    pub fn container_stats(&self, names: &[&str]) -> Result<Vec<ContainerStats>> {
+        let output = self.capture_args(&self.engine.stats_args(names))?;
+        Ok(ContainerStats::parse_all(&output))
+    }
+
+    /// Inspect a container's state; `None` when it does not exist.
+    pub fn container_state(&self, name: &str) -> Option<ContainerState> {
+        self.run_capture(&["inspect", name])
+            .ok()
+            .and_then(|raw| ContainerState::parse(&raw))
+    }
+
+    /// Poll until a container is healthy, or running when it has no healthcheck.
+    pub fn wait_for_container_health(&self, name: &str, timeout: Duration) -> Result<()> {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let state = self.container_state(name);
+            let ready = state.as_ref().is_some_and(|state| match &state.health {
+                Some(health) => health == "healthy",
+                None => state.status == "running",
+            });
+            if ready {
+                return Ok(());
+            }
+            if std::time::Instant::now() >= deadline {
+                let seen = state
+                    .map(|state| state.health.unwrap_or(state.status))
+                    .unwrap_or_else(|| "missing".to_string());
+                bail!(
+                    "container {} not healthy after {}s ({})",
+                    name,
+                    timeout.as_secs(),
+                    seen
+                );
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Check whether a container exists (running or stopped).
+    pub fn container_exists(&self, name: &str) -> bool {
+        self.run_capture(&["inspect", "--format", "{{.Id}}", name])
+            .is_ok()
+    }
+
+    /// Check whether a container exists and is running.
+    pub fn container_running(&self, name: &str) -> bool {
+        self.run_capture(&["inspect", "--format", "{{.State.Running}}", name])
+            .map(|output| output.trim() == "true")
+            .unwrap_or(false)
+    }
+
+    /// All containers, running or not.
+    pub fn list_containers(&self) -> Result<Vec<ContainerInfo>> {
+        let output = self.run_capture(&["ps", "-a", "--format", ContainerInfo::FORMAT])?;
+        Ok(ContainerInfo::parse_all(&output))
+    }
+
+    /// Bytes a container has written on top of its image.
+    pub fn container_size(&self, name: &str) -> Option<u64> {
+        self.run_capture(&[
+            "container",
+            "inspect",
+            "--size",
+            "--format",
+            "{{.SizeRw}}",
+            name,
+        ])
+        .ok()
+        .and_then(|output| output.trim().parse().ok())
+    }
+
+    /// Remove a stopped container.
+    pub fn remove_container(&self, name: &str) -> Result<()> {
+        self.run(&["rm", name])
+    }
+
     fn container_ip(&self, name: &str) -> Result<String> {
         let output = self.run_capture(&[
             "inspect",
@@ -165,26 +845,268 @@ impl Runtime {
         Err(last_err.unwrap_or_else(|| anyhow::anyhow!("healthcheck failed")))
     }
 
-    fn ensure_network(&self) -> Result<()> {
-        if self.run(&["network", "inspect", NETWORK_NAME]).is_ok() {
+    /// Create a network with the given labels unless it already exists.
+    pub fn ensure_network(&self, name: &str, labels: &[String]) -> Result<()> {
+        if self.network_exists(name) {
             return Ok(());
         }
-        self.run(&["network", "create", NETWORK_NAME])?;
+        self.run_args(&self.engine.network_create_args(name, labels))
+    }
+
+    /// Ensure each of the given networks exists; app networks get their app label.
+    pub fn ensure_networks(&self, names: &[String]) -> Result<()> {
+        for name in names {
+            let labels: Vec<String> = app_of_network(name)
+                .map(|app| format!("deep.app={}", app))
+                .into_iter()
+                .collect();
+            self.ensure_network(name, &labels)?;
+        }
         Ok(())
     }
 
     /// Ensure the shared deep-net network exists.
     pub fn ensure_deep_network(&self) -> Result<()> {
-        self.ensure_network()
+        self.ensure_network(NETWORK_NAME, &[])
     }
 
     /// Check whether the deep-net network exists.
     pub fn deep_network_exists(&self) -> bool {
-        self.run(&["network", "inspect", NETWORK_NAME]).is_ok()
+        self.network_exists(NETWORK_NAME)
+    }
+
+    /// Check whether a network exists.
+    pub fn network_exists(&self, name: &str) -> bool {
+        self.run(&["network", "inspect", name]).is_ok()
+    }
+
+    /// Names of the private app networks deep has created.
+    pub fn app_networks(&self) -> Result<Vec<String>> {
+        let output = self.run_capture(&[
+            "network",
+            "ls",
+            "--filter",
+            "label=deep.app",
+            "--format",
+            "{{.Name}}",
+        ])?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Remove a network; the engine refuses while containers are attached.
+    pub fn remove_network(&self, name: &str) -> Result<()> {
+        self.run(&["network", "rm", name])
+    }
+
+    /// Networks a container is currently attached to.
+    pub fn container_networks(&self, name: &str) -> Result<Vec<String>> {
+        let output = self.run_capture(&[
+            "inspect",
+            "--format",
+            "{{range $name, $net := .NetworkSettings.Networks}}{{$name}} {{end}}",
+            name,
+        ])?;
+        Ok(output.split_whitespace().map(str::to_string).collect())
+    }
+
+    /// Attach a running container to a network; returns false if there was nothing to do.
+    pub fn connect_network(&self, network: &str, container: &str) -> Result<bool> {
+        if !self.container_running(container)
+            || self
+                .container_networks(container)?
+                .iter()
+                .any(|name| name == network)
+        {
+            return Ok(false);
+        }
+        self.run(&["network", "connect", network, container])
+            .with_context(|| format!("failed to connect {} to {}", container, network))?;
+        Ok(true)
+    }
+
+    /// Detach a running container from a network; returns false if it was not attached.
+    pub fn disconnect_network(&self, network: &str, container: &str) -> Result<bool> {
+        if !self.container_running(container)
+            || !self
+                .container_networks(container)?
+                .iter()
+                .any(|name| name == network)
+        {
+            return Ok(false);
+        }
+        self.run(&["network", "disconnect", network, container])
+            .with_context(|| format!("failed to disconnect {} from {}", container, network))?;
+        Ok(true)
+    }
+
+    /// Create a labeled named volume for an app unless it already exists.
+    ///
+    /// An existing volume must carry the same `deep.app`/`deep.volume` labels, so two
+    /// apps whose names happen to build the same volume name never share data.
+    pub fn ensure_volume(&self, volume: &str, app_name: &str, name: &str) -> Result<()> {
+        if let Some((owner_app, owner_volume)) = self.volume_owner(volume)? {
+            if owner_app != app_name || owner_volume != name {
+                bail!(
+                    "volume {} already exists for app {:?} volume {:?}; rename volume {} in {}'s app.toml",
+                    volume,
+                    owner_app,
+                    owner_volume,
+                    name,
+                    app_name
+                );
+            }
+            return Ok(());
+        }
+        let app_label = format!("deep.app={}", app_name);
+        let name_label = format!("deep.volume={}", name);
+        self.run(&[
+            "volume",
+            "create",
+            "--label",
+            &app_label,
+            "--label",
+            &name_label,
+            volume,
+        ])
+    }
+
+    /// Check whether a named volume exists.
+    pub fn volume_exists(&self, volume: &str) -> bool {
+        self.run(&["volume", "inspect", volume]).is_ok()
+    }
+
+    /// Return the `deep.app` and `deep.volume` labels of a named volume, or None if it does not exist.
+    pub fn volume_owner(&self, volume: &str) -> Result<Option<(String, String)>> {
+        if !self.volume_exists(volume) {
+            return Ok(None);
+        }
+        let output = self.inspect_volume(volume)?;
+        let parsed: serde_json::Value = serde_json::from_str(&output)
+            .with_context(|| format!("failed to parse volume inspect output for {}", volume))?;
+        let labels = &parsed[0]["Labels"];
+        let label = |key: &str| labels[key].as_str().unwrap_or_default().to_string();
+        Ok(Some((label("deep.app"), label("deep.volume"))))
+    }
+
+    /// List named volumes created for an app.
+    pub fn list_app_volumes(&self, app_name: &str) -> Result<Vec<String>> {
+        let filter = format!("label=deep.app={}", app_name);
+        let output =
+            self.run_capture(&["volume", "ls", "--filter", &filter, "--format", "{{.Name}}"])?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Return `volume inspect` JSON for a named volume.
+    pub fn inspect_volume(&self, volume: &str) -> Result<String> {
+        self.run_capture(&["volume", "inspect", volume])
+    }
+
+    /// Remove a named volume.
+    pub fn remove_volume(&self, volume: &str) -> Result<()> {
+        self.run(&["volume", "rm", volume])
+    }
+
+    /// Volumes labeled by deep that no container uses.
+    pub fn dangling_volumes(&self) -> Result<Vec<String>> {
+        let output = self.run_capture(&[
+            "volume",
+            "ls",
+            "--filter",
+            "dangling=true",
+            "--filter",
+            "label=deep.app",
+            "--format",
+            "{{.Name}}",
+        ])?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Bytes used by a named volume, when its mountpoint is readable.
+    pub fn volume_size(&self, volume: &str) -> Option<u64> {
+        let mountpoint = self
+            .run_capture(&["volume", "inspect", "--format", "{{.Mountpoint}}", volume])
+            .ok()?;
+        let output = runner::run_output("du", &["-sk", mountpoint.trim()]).ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()?
+            .parse::<u64>()
+            .ok()
+            .map(|kib| kib * 1024)
+    }
+
+    /// Local images.
+    pub fn list_images(&self) -> Result<Vec<ImageInfo>> {
+        let output = self.run_capture(&["image", "ls", "--format", ImageInfo::FORMAT])?;
+        Ok(ImageInfo::parse_all(&output))
+    }
+
+    /// Local images carrying `label`, whatever its value.
+    pub fn labeled_images(&self, label: &str) -> Result<Vec<ImageInfo>> {
+        let filter = format!("label={}", label);
+        let output = self.run_capture(&[
+            "image",
+            "ls",
+            "--filter",
+            &filter,
+            "--format",
+            ImageInfo::FORMAT,
+        ])?;
+        Ok(ImageInfo::parse_all(&output))
+    }
+
+    /// Size of a local image in bytes.
+    pub fn image_size(&self, reference: &str) -> Option<u64> {
+        self.run_capture(&["image", "inspect", "--format", "{{.Size}}", reference])
+            .ok()
+            .and_then(|output| output.trim().parse().ok())
+    }
+
+    /// Remove an image; fails while any container still uses it.
+    pub fn remove_image(&self, reference: &str) -> Result<()> {
+        self.run(&["rmi", reference])
+    }
+
+    /// Export a named volume's contents to a tar archive.
+    pub fn export_volume(&self, volume: &str, output: &Path) -> Result<()> {
+        self.run_args(&self.engine.export_volume_args(volume, output))
+    }
+
+    /// Replace a named volume's contents with a tar archive.
+    pub fn import_volume(&self, volume: &str, input: &Path) -> Result<()> {
+        self.run_args(&self.engine.import_volume_args(volume, input))
+    }
+
+    fn run_args(&self, args: &[String]) -> Result<()> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run(&args)
+    }
+
+    fn capture_args(&self, args: &[String]) -> Result<String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run_capture(&args)
     }
 
     fn run(&self, args: &[&str]) -> Result<()> {
-        let output = runner::run_output(self.engine, args)?;
+        let output = runner::run_output(self.program(), args)?;
         if output.status.success() {
             return Ok(());
         }
@@ -192,7 +1114,7 @@ impl Runtime {
     }
 
     fn run_capture(&self, args: &[&str]) -> Result<String> {
-        let output = runner::run_output(self.engine, args)?;
+        let output = runner::run_output(self.program(), args)?;
         if !output.status.success() {
             bail!(command_error(&output));
         }
@@ -200,6 +1122,42 @@ impl Runtime {
     }
 }
 
+/// The runtime for this invocation; Podman when no engine is configured or installed yet.
+///
+/// Resolved once per command from the `-H/--host-config` file and passed down. A broken
+/// host config or a configured engine that is unknown or missing is an error rather
+/// than a silent switch to Podman.
+pub fn host_runtime(host_config: &Path) -> Result<Runtime> {
+    let configured = configured_engine(host_config)?;
+    if configured.is_some() {
+        return Runtime::detect(configured);
+    }
+    Ok(Runtime::detect(None).unwrap_or_else(|_| {
+        Runtime::with_engine(Arc::new(Podman)).with_auth_file(registry::auth_file())
+    }))
+}
+
+/// Name of an app's private network.
+pub fn app_network_name(app_name: &str) -> String {
+    format!("deep-app-{}-net", app_name)
+}
+
+/// The app a private network belongs to, if `name` is one.
+pub fn app_of_network(name: &str) -> Option<&str> {
+    name.strip_prefix("deep-app-")?
+        .strip_suffix("-net")
+        .filter(|app| !app.is_empty())
+}
+
+/// Quadlet `Network=` lines, one per network.
+pub fn network_lines(networks: &[String]) -> String {
+    networks
+        .iter()
+        .map(|network| format!("Network={}", network))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Generate an app container name based on app name and release id.
 pub fn app_container_name(app_name: &str, release_id: &str) -> String {
     format!("deep-app-{}-{}", app_name, release_id)
@@ -214,3 +1172,81 @@ fn command_error(output: &Output) -> String {
         stderr.trim()
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_state_parses_inspect_and_uptime() {
+        let raw = r#"[{"State":{"Status":"running","Health":{"Status":"healthy"},"StartedAt":"2024-05-01T10:00:00.123456789+02:00"},"RestartCount":2}]"#;
+        let state = ContainerState::parse(raw).expect("state");
+        assert_eq!(state.status, "running");
+        assert_eq!(state.health.as_deref(), Some("healthy"));
+        assert_eq!(state.restart_count, 2);
+        let started = parse_rfc3339_unix("2024-05-01T08:00:00Z").expect("utc");
+        assert_eq!(state.uptime_secs(started + 3_900), Some(3_900));
+        assert_eq!(format_uptime(3_900), "1h5m");
+        assert_eq!(format_uptime(200_000), "2d7h");
+
+        let stopped = ContainerState::parse(r#"[{"State":{"Status":"exited"}}]"#).expect("state");
+        assert_eq!(stopped.uptime_secs(started), None);
+        assert!(ContainerState::parse("[]").is_none());
+    }
+
+    #[test]
+    fn engines_share_commands_except_stats_and_volume_archives() {
+        let spec = RunSpec {
+            name: "deep-app-web-r1".to_string(),
+            image: "web:1".to_string(),
+            networks: vec!["deep-net".to_string(), "extra".to_string()],
+            flags: vec!["-e".to_string(), "PORT=3000".to_string()],
+            ..RunSpec::default()
+        };
+        assert_eq!(Podman.create_args(&spec), Docker.create_args(&spec));
+        assert_eq!(
+            Docker.create_args(&spec).join(" "),
+            "create --name deep-app-web-r1 --network deep-net -e PORT=3000 web:1"
+        );
+        assert_eq!(Podman.stats_args(&["a"])[3], "json");
+        assert_eq!(Docker.stats_args(&["a"])[3], "{{json .}}");
+        let archive = Path::new("/backups/pg/data.tar");
+        assert_eq!(
+            Podman.export_volume_args("data", archive).join(" "),
+            "volume export --output /backups/pg/data.tar data"
+        );
+        let export = Docker.export_volume_args("data", archive).join(" ");
+        assert!(export.starts_with("run --rm -v data:/volume:ro -v /backups/pg:/archive "));
+        assert!(export.ends_with("tar -cf /archive/data.tar -C /volume ."));
+    }
+
+    #[test]
+    fn auth_file_goes_where_each_engine_reads_it() {
+        let authfile = Path::new("/srv/deep/registry/config.json");
+        let pull = Podman.pull_args("ghcr.io/me/web:1");
+        assert_eq!(
+            Podman.auth_args(pull.clone(), authfile).join(" "),
+            "pull --authfile /srv/deep/registry/config.json ghcr.io/me/web:1"
+        );
+        assert_eq!(
+            Docker.auth_args(pull, authfile).join(" "),
+            "--config /srv/deep/registry pull ghcr.io/me/web:1"
+        );
+        let spec = RunSpec {
+            name: "deep-app-web-r1".to_string(),
+            image: "ghcr.io/me/web:1".to_string(),
+            authfile: Some(authfile.to_string_lossy().to_string()),
+            ..RunSpec::default()
+        };
+        assert!(
+            Docker
+                .create_args(&spec)
+                .join(" ")
+                .starts_with("--config /srv/deep/registry create --name deep-app-web-r1 ")
+        );
+        let runtime = Runtime::with_engine(Arc::new(Podman));
+        assert_eq!(runtime.authed(vec!["push".to_string()]), ["push"]);
+        let runtime = runtime.with_auth_file(Some(authfile.to_path_buf()));
+        assert_eq!(runtime.authed(vec!["push".to_string()])[1], "--authfile");
+    }
+}
@@ -1,6 +1,7 @@
 //! Helpers for running systemctl in user or system scope.
 
 use crate::runner;
+use crate::runtime::LogOptions;
 use anyhow::{Context, Result, bail};
 
 /// Default quadlet directory based on $HOME.
@@ -16,6 +17,17 @@ pub fn is_system_dir(dir: &str) -> bool {
     dir.starts_with("/etc/containers/systemd")
 }
 
+/// Directory for plain systemd units (timers, oneshot services) in the scope of a quadlet dir.
+pub fn systemd_unit_dir(quadlet_dir: &str) -> String {
+    if is_system_dir(quadlet_dir) {
+        return "/etc/systemd/system".to_string();
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return format!("{}/.config/systemd/user", home);
+    }
+    "/etc/systemd/system".to_string()
+}
+
 /// Run systemctl in the correct scope for a quadlet directory.
 pub fn systemctl_for_dir(dir: &str, args: &[&str]) -> Result<()> {
     let mut cmd_args = Vec::new();
@@ -65,3 +77,107 @@ pub fn systemctl_active_any(name: &str) -> Result<bool> {
         .with_context(|| "failed to run systemctl is-active")?;
     Ok(status.success())
 }
+
+/// Report `systemctl is-active` for a unit in the scope of a quadlet directory.
+pub fn unit_state_for_dir(dir: &str, unit: &str) -> String {
+    let mut args = Vec::new();
+    if !is_system_dir(dir) {
+        args.push("--user");
+    }
+    args.extend_from_slice(&["is-active", unit]);
+    is_active_output(&args)
+}
+
+/// Report `systemctl is-active` for a unit, preferring whichever scope has it active.
+pub fn unit_state_any(unit: &str) -> String {
+    let user = is_active_output(&["--user", "is-active", unit]);
+    if user == "active" {
+        return user;
+    }
+    let system = is_active_output(&["is-active", unit]);
+    if system == "active" { system } else { user }
+}
+
+fn is_active_output(args: &[&str]) -> String {
+    runner::run_output("systemctl", args)
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|state| !state.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Stream journald logs for a unit in user or system scope to `sink`; journalctl's
+/// own errors go to `err_sink`.
+pub fn journal_logs(
+    unit: &str,
+    user: bool,
+    options: &LogOptions,
+    sink: &mut dyn FnMut(&str),
+    err_sink: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let mut args = Vec::new();
+    if user {
+        args.push("--user".to_string());
+    }
+    args.extend(["-u".to_string(), unit.to_string(), "--no-pager".to_string()]);
+    if !options.timestamps {
+        args.extend(["-o".to_string(), "cat".to_string()]);
+    }
+    if options.follow {
+        args.push("-f".to_string());
+    }
+    if let Some(since) = &options.since {
+        args.push(format!("--since={}", since.journal_arg()));
+    }
+    if let Some(until) = &options.until {
+        args.push(format!("--until={}", until.journal_arg()));
+    }
+    if let Some(tail) = options.tail {
+        args.extend(["-n".to_string(), tail.to_string()]);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let status = runner::run_streaming("journalctl", &args, sink, err_sink)
+        .with_context(|| "failed to run journalctl")?;
+    if status.success() {
+        Ok(())
+    } else {
+        bail!("journalctl failed with status {}", status)
+    }
+}
+
+/// Add a `Network=` line to a quadlet after its existing ones; returns false if present.
+pub fn quadlet_add_network(path: &std::path::Path, network: &str) -> Result<bool> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let line = format!("Network={}", network);
+    let mut lines: Vec<&str> = contents.lines().collect();
+    if lines.contains(&line.as_str()) {
+        return Ok(false);
+    }
+    let anchor = lines
+        .iter()
+        .rposition(|existing| existing.starts_with("Network="))
+        .or_else(|| lines.iter().position(|existing| *existing == "[Container]"))
+        .with_context(|| format!("{} has no [Container] section", path.display()))?;
+    lines.insert(anchor + 1, &line);
+    std::fs::write(path, format!("{}\n", lines.join("\n")))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(true)
+}
+
+/// Drop a `Network=` line from a quadlet; returns false if it was not there.
+pub fn quadlet_remove_network(path: &std::path::Path, network: &str) -> Result<bool> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let line = format!("Network={}", network);
+    let lines: Vec<&str> = contents
+        .lines()
+        .filter(|existing| *existing != line)
+        .collect();
+    if lines.len() == contents.lines().count() {
+        return Ok(false);
+    }
+    std::fs::write(path, format!("{}\n", lines.join("\n")))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(true)
+}
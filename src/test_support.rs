@@ -0,0 +1,180 @@
+//! Shared unit-test fixtures: a scripted command runner, a Podman runtime and
+//! release factories.
+
+use anyhow::Result;
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+
+use crate::config::ConfigSnapshot;
+use crate::db::{ReleaseRow, Storage};
+use crate::runner::Runner;
+use crate::runtime::{Podman, Runtime};
+
+type Hook = Box<dyn Fn(&str, &[&str]) -> Result<()> + Send + Sync>;
+
+/// Runner that records every command line and answers from rules.
+///
+/// Rules are checked in the order they were added; the first one whose needles
+/// all appear in the command line answers. Unmatched commands succeed with no output.
+#[derive(Default)]
+pub(crate) struct TestRunner {
+    calls: Mutex<Vec<String>>,
+    rules: Mutex<Vec<Rule>>,
+    hook: Mutex<Option<Hook>>,
+}
+
+#[derive(Clone)]
+struct Rule {
+    contains: Vec<String>,
+    status: i32,
+    stdout: String,
+    stderr: String,
+    /// How many more commands this rule answers (`None`: unlimited).
+    remaining: Option<usize>,
+}
+
+impl Rule {
+    fn matches(&self, cmd: &str) -> bool {
+        self.remaining != Some(0) && self.contains.iter().all(|needle| cmd.contains(needle))
+    }
+}
+
+impl TestRunner {
+    /// Answer every command containing all of `contains`.
+    pub(crate) fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
+        self.push_rule(contains, status, stdout, stderr, None);
+    }
+
+    /// Answer only the next command containing all of `contains`.
+    pub(crate) fn add_rule_once(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
+        self.push_rule(contains, status, stdout, stderr, Some(1));
+    }
+
+    /// Run `hook` for each command before answering it, for side effects such as
+    /// files a real command would create.
+    pub(crate) fn on_command(
+        &self,
+        hook: impl Fn(&str, &[&str]) -> Result<()> + Send + Sync + 'static,
+    ) {
+        *self.hook.lock().expect("hook lock") = Some(Box::new(hook));
+    }
+
+    /// Command lines run so far, as `program arg...`.
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.calls.lock().expect("calls lock").clone()
+    }
+
+    fn push_rule(
+        &self,
+        contains: &[&str],
+        status: i32,
+        stdout: &str,
+        stderr: &str,
+        remaining: Option<usize>,
+    ) {
+        self.rules.lock().expect("rules lock").push(Rule {
+            contains: contains.iter().map(|s| s.to_string()).collect(),
+            status,
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            remaining,
+        });
+    }
+}
+
+impl Runner for TestRunner {
+    fn output(&self, program: &str, args: &[&str]) -> Result<Output> {
+        let cmdline = format!("{} {}", program, args.join(" "));
+        self.calls.lock().expect("calls lock").push(cmdline.clone());
+        if let Some(hook) = self.hook.lock().expect("hook lock").as_ref() {
+            hook(program, args)?;
+        }
+        let matched = self
+            .rules
+            .lock()
+            .expect("rules lock")
+            .iter_mut()
+            .find(|rule| rule.matches(&cmdline))
+            .map(|rule| {
+                if let Some(remaining) = rule.remaining.as_mut() {
+                    *remaining -= 1;
+                }
+                rule.clone()
+            });
+        let Some(rule) = matched else {
+            return Ok(Output {
+                status: exit_status(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        };
+        Ok(Output {
+            status: exit_status(rule.status),
+            stdout: rule.stdout.into_bytes(),
+            stderr: rule.stderr.into_bytes(),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Podman without detection, for tests that script the engine's output.
+pub(crate) fn test_podman() -> Runtime {
+    Runtime::with_engine(Arc::new(Podman))
+}
+
+/// A web app on port 3000 with every other setting at its default.
+pub(crate) fn base_snapshot() -> ConfigSnapshot {
+    ConfigSnapshot {
+        env: Default::default(),
+        port: 3000,
+        domains: Vec::new(),
+        addons: Vec::new(),
+        healthcheck: Default::default(),
+        deploy: Default::default(),
+        proxy: Default::default(),
+        resources: Default::default(),
+        volumes: Vec::new(),
+    }
+}
+
+/// An active release of `ghcr.io/me/app:latest` deployed with `snapshot`.
+pub(crate) fn release_row(app_id: &str, id: &str, snapshot: &ConfigSnapshot) -> ReleaseRow {
+    ReleaseRow {
+        id: id.to_string(),
+        app_id: app_id.to_string(),
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+        git_sha: "deadbeef".to_string(),
+        image_ref: "ghcr.io/me/app:latest".to_string(),
+        image_digest: "ghcr.io/me/app@sha256:deadbeef".to_string(),
+        config_json: serde_json::to_string(snapshot).expect("serialize snapshot"),
+        status: "active".to_string(),
+    }
+}
+
+/// Record `release` without making it current.
+pub(crate) fn insert_release(storage: &mut Storage, release: &ReleaseRow) -> Result<()> {
+    let tx = storage.transaction()?;
+    Storage::insert_release(&tx, release)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Record `release` and make it its app's current release.
+pub(crate) fn insert_current_release(storage: &mut Storage, release: &ReleaseRow) -> Result<()> {
+    let tx = storage.transaction()?;
+    Storage::insert_release(&tx, release)?;
+    Storage::set_current_release(&tx, &release.app_id, &release.id)?;
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,428 @@
+//! Container units: quadlets on Podman, generated `.service` files on Docker.
+//!
+//! Callers always render a quadlet. On Docker hosts it is translated into a plain
+//! systemd service that creates the container, attaches its extra networks and runs
+//! it with `docker start -a`, under the same `deep-*` unit and container names.
+
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+use crate::runtime::{Engine, RunSpec, Runtime};
+use crate::systemd::{default_quadlet_dir, systemd_unit_dir};
+
+const GENERATED_HEADER: &str = "# Generated by deep from a quadlet; rewritten on every change.";
+
+/// File a container unit lives in for the scope of `quadlet_dir`.
+pub fn unit_path(runtime: &Runtime, quadlet_dir: &str, unit_name: &str) -> PathBuf {
+    if runtime.engine().uses_quadlets() {
+        Path::new(quadlet_dir).join(format!("{}.container", unit_name))
+    } else {
+        Path::new(&systemd_unit_dir(quadlet_dir)).join(format!("{}.service", unit_name))
+    }
+}
+
+/// Write a rendered quadlet, translated into a service on engines without quadlets.
+pub fn write_unit(
+    runtime: &Runtime,
+    quadlet_dir: &str,
+    unit_name: &str,
+    quadlet: &str,
+) -> Result<PathBuf> {
+    let path = unit_path(runtime, quadlet_dir, unit_name);
+    let contents = render_unit(runtime, unit_name, quadlet)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The file contents `write_unit` writes for a rendered quadlet.
+pub fn render_unit(runtime: &Runtime, unit_name: &str, quadlet: &str) -> Result<String> {
+    if runtime.engine().uses_quadlets() {
+        return Ok(quadlet.to_string());
+    }
+    service_from_quadlet(runtime.engine(), quadlet)
+        .with_context(|| format!("failed to generate a service for {}", unit_name))
+}
+
+/// Delete a container unit file; returns false if there was none.
+pub fn remove_unit(runtime: &Runtime, quadlet_dir: &str, unit_name: &str) -> Result<bool> {
+    let path = unit_path(runtime, quadlet_dir, unit_name);
+    if !path.exists() {
+        return Ok(false);
+    }
+    std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(true)
+}
+
+/// Find a container unit by name in the user or system scope.
+pub fn find_unit(runtime: &Runtime, unit_name: &str) -> Option<PathBuf> {
+    [default_quadlet_dir(), "/etc/containers/systemd".to_string()]
+        .into_iter()
+        .map(|dir| unit_path(runtime, &dir, unit_name))
+        .find(|path| path.exists())
+}
+
+/// Names of container units in `quadlet_dir`'s scope that start with `prefix`.
+pub fn unit_names(runtime: &Runtime, quadlet_dir: &str, prefix: &str) -> Result<Vec<String>> {
+    let quadlets = runtime.engine().uses_quadlets();
+    let (dir, suffix) = if quadlets {
+        (quadlet_dir.to_string(), ".container")
+    } else {
+        (systemd_unit_dir(quadlet_dir), ".service")
+    };
+    let dir = Path::new(&dir);
+    let mut names = Vec::new();
+    if !dir.exists() {
+        return Ok(names);
+    }
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|file| file.to_str())
+            .and_then(|file| file.strip_suffix(suffix))
+            .filter(|name| name.starts_with(prefix))
+        else {
+            continue;
+        };
+        // Other deep services (timers, shippers) share the directory on Docker hosts.
+        if !quadlets && !is_generated(&path) {
+            continue;
+        }
+        names.push(name.to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn is_generated(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|contents| contents.starts_with(GENERATED_HEADER))
+}
+
+/// Attach a network in a unit file; returns false if it was already there.
+pub fn add_network(path: &Path, network: &str) -> Result<bool> {
+    if path.extension().is_some_and(|ext| ext == "container") {
+        return crate::systemd::quadlet_add_network(path, network);
+    }
+    let contents = read(path)?;
+    let Some(container) = service_container(&contents) else {
+        bail!("{} is not a deep container service", path.display());
+    };
+    if service_networks(&contents)
+        .iter()
+        .any(|name| name == network)
+    {
+        return Ok(false);
+    }
+    let program = service_program(&contents);
+    let line = connect_line(&program, network, &container);
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let anchor = lines
+        .iter()
+        .rposition(|existing| existing.starts_with("ExecStartPre="))
+        .with_context(|| format!("{} has no ExecStartPre lines", path.display()))?;
+    lines.insert(anchor + 1, line);
+    write_lines(path, &lines)?;
+    Ok(true)
+}
+
+/// Drop a network from a unit file; returns false if it was not attached there.
+pub fn remove_network(path: &Path, network: &str) -> Result<bool> {
+    if path.extension().is_some_and(|ext| ext == "container") {
+        return crate::systemd::quadlet_remove_network(path, network);
+    }
+    let contents = read(path)?;
+    let Some(container) = service_container(&contents) else {
+        return Ok(false);
+    };
+    let line = connect_line(&service_program(&contents), network, &container);
+    let lines: Vec<String> = contents
+        .lines()
+        .filter(|existing| *existing != line)
+        .map(str::to_string)
+        .collect();
+    if lines.len() == contents.lines().count() {
+        return Ok(false);
+    }
+    write_lines(path, &lines)?;
+    Ok(true)
+}
+
+/// Translate a rendered quadlet into a systemd service driving `engine`.
+pub fn service_from_quadlet(engine: &dyn Engine, quadlet: &str) -> Result<String> {
+    let mut unit = Vec::new();
+    let mut service = Vec::new();
+    let mut install = Vec::new();
+    let mut spec = RunSpec::default();
+    let program = format!("/usr/bin/{}", engine.program());
+    let mut section = "";
+    for line in quadlet.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+        match section {
+            "[Unit]" => unit.push(line.to_string()),
+            "[Install]" => install.push(line.to_string()),
+            "[Service]" => service.push(line.replace("/usr/bin/podman ", &format!("{} ", program))),
+            "[Container]" => container_key(&mut spec, line)?,
+            other => bail!("unsupported quadlet section {}", other),
+        }
+    }
+    if spec.name.is_empty() || spec.image.is_empty() {
+        bail!("quadlet needs ContainerName= and Image=");
+    }
+    let name = spec.name.clone();
+    let mut out = vec![GENERATED_HEADER.to_string(), "[Unit]".to_string()];
+    out.extend(unit);
+    let engine_unit = format!("{}.service", engine.program());
+    // `Wants=` rather than `Requires=`: user units cannot see the system docker.service.
+    out.push(format!("After={}", engine_unit));
+    out.push(format!("Wants={}", engine_unit));
+    out.push(String::new());
+    out.push("[Service]".to_string());
+    out.push(format!("ExecStartPre=-{} rm -f {}", program, name));
+    out.push(format!(
+        "ExecStartPre={} {}",
+        program,
+        exec_args(&engine.create_args(&spec))
+    ));
+    for network in spec.networks.iter().skip(1) {
+        out.push(connect_line(&program, network, &name));
+    }
+    out.push(format!("ExecStart={} start -a {}", program, name));
+    out.push(format!("ExecStop={} stop {}", program, name));
+    out.push(format!("ExecStopPost=-{} rm -f {}", program, name));
+    out.extend(service);
+    if !install.is_empty() {
+        out.push(String::new());
+        out.push("[Install]".to_string());
+        out.extend(install);
+    }
+    Ok(format!("{}\n", out.join("\n")))
+}
+
+/// Map one quadlet `[Container]` line onto the run spec.
+fn container_key(spec: &mut RunSpec, line: &str) -> Result<()> {
+    let Some((key, value)) = line.split_once('=') else {
+        bail!("invalid quadlet line {:?}", line);
+    };
+    let flag = match key {
+        "Image" => {
+            spec.image = value.to_string();
+            return Ok(());
+        }
+        "ContainerName" => {
+            spec.name = value.to_string();
+            return Ok(());
+        }
+        "Network" => {
+            spec.networks.push(value.to_string());
+            return Ok(());
+        }
+        "Exec" => {
+            spec.command = split_words(value);
+            return Ok(());
+        }
+        "PodmanArgs" => {
+            let mut words = split_words(value).into_iter();
+            while let Some(word) = words.next() {
+                if let Some(authfile) = word.strip_prefix("--authfile=") {
+                    spec.authfile = Some(authfile.to_string());
+                } else if word == "--authfile" {
+                    spec.authfile = words.next();
+                } else {
+                    spec.flags.push(word);
+                }
+            }
+            return Ok(());
+        }
+        "Environment" => "-e",
+        "Volume" => "-v",
+        "PublishPort" => "-p",
+        "Label" => "--label",
+        "HealthCmd" => "--health-cmd",
+        "HealthInterval" => "--health-interval",
+        "HealthTimeout" => "--health-timeout",
+        "HealthRetries" => "--health-retries",
+        "Memory" => "--memory",
+        "PidsLimit" => "--pids-limit",
+        "ShmSize" => "--shm-size",
+        "LogDriver" => "--log-driver",
+        "LogOpt" => "--log-opt",
+        other => bail!("quadlet key {} has no run equivalent", other),
+    };
+    spec.flags.push(flag.to_string());
+    spec.flags.push(value.to_string());
+    Ok(())
+}
+
+fn connect_line(program: &str, network: &str, container: &str) -> String {
+    format!(
+        "ExecStartPre={} network connect {} {}",
+        program, network, container
+    )
+}
+
+/// Container name from a generated service's `start -a` line.
+fn service_container(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .and_then(|exec| exec.split_once(" start -a "))
+        .map(|(_, name)| name.trim().to_string())
+}
+
+fn service_program(contents: &str) -> String {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .and_then(|exec| exec.split_whitespace().next())
+        .unwrap_or("/usr/bin/docker")
+        .to_string()
+}
+
+/// Networks a generated service joins: `--network` on create plus `network connect` lines.
+fn service_networks(contents: &str) -> Vec<String> {
+    let mut networks = Vec::new();
+    for line in contents.lines() {
+        let Some(exec) = line.strip_prefix("ExecStartPre=") else {
+            continue;
+        };
+        let words = split_words(exec);
+        if let Some(index) = words.iter().position(|word| word == "--network") {
+            networks.extend(words.get(index + 1).cloned());
+        } else if words.get(1..3) == Some(&["network".to_string(), "connect".to_string()]) {
+            networks.extend(words.get(3).cloned());
+        }
+    }
+    networks
+}
+
+/// Split a command line on whitespace, honouring single and double quotes.
+fn split_words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_word = false;
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (Some(open), ch) if ch == open => quote = None,
+            (Some('"'), '\\') => current.extend(chars.next()),
+            (Some(_), ch) => current.push(ch),
+            (None, '"' | '\'') => {
+                quote = Some(ch);
+                in_word = true;
+            }
+            (None, ch) if ch.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, ch) => {
+                current.push(ch);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+/// Join arguments for an `Exec*=` line, quoting and escaping for systemd.
+fn exec_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let escaped = arg.replace('%', "%%").replace('$', "$$");
+            if escaped.is_empty()
+                || escaped.contains(|ch: char| {
+                    ch.is_whitespace() || ch == '"' || ch == '\'' || ch == '\\'
+                })
+            {
+                format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                escaped
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn write_lines(path: &Path, lines: &[String]) -> Result<()> {
+    std::fs::write(path, format!("{}\n", lines.join("\n")))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Docker;
+
+    #[test]
+    fn docker_service_runs_the_quadlet_container() -> Result<()> {
+        let quadlet = "[Unit]\nDescription=Deep addon pg\nAfter=deep-addon-x.service\n\n\
+            [Container]\nImage=postgres:16\nContainerName=deep-addon-pg\n\
+            Network=deep-net\nNetwork=deep-app-web-net\n\
+            Environment=POSTGRES_PASSWORD=pa$$ 100%\nVolume=pg-data:/var/lib/postgresql/data\n\
+            HealthCmd=pg_isready -U postgres\nHealthInterval=1s\nMemory=256m\n\
+            PodmanArgs=--cpus=0.5 --authfile=/srv/deep/registry/config.json\nExec=postgres -c \"shared_buffers=128MB\"\n\n\
+            [Service]\nRestart=always\nExecReload=/usr/bin/podman exec deep-addon-pg true\n\n\
+            [Install]\nWantedBy=multi-user.target\n";
+        let service = service_from_quadlet(&Docker, quadlet)?;
+        let lines: Vec<&str> = service.lines().collect();
+        assert_eq!(lines[0], GENERATED_HEADER);
+        assert!(lines.contains(&"After=docker.service"));
+        assert!(lines.contains(&"ExecStartPre=-/usr/bin/docker rm -f deep-addon-pg"));
+        assert!(lines.contains(
+            &"ExecStartPre=/usr/bin/docker --config /srv/deep/registry \
+              create --name deep-addon-pg --network deep-net \
+              -e \"POSTGRES_PASSWORD=pa$$$$ 100%%\" -v pg-data:/var/lib/postgresql/data \
+              --health-cmd \"pg_isready -U postgres\" --health-interval 1s --memory 256m \
+              --cpus=0.5 postgres:16 postgres -c shared_buffers=128MB"
+        ));
+        assert!(lines.contains(
+            &"ExecStartPre=/usr/bin/docker network connect deep-app-web-net deep-addon-pg"
+        ));
+        assert!(lines.contains(&"ExecStart=/usr/bin/docker start -a deep-addon-pg"));
+        assert!(lines.contains(&"ExecReload=/usr/bin/docker exec deep-addon-pg true"));
+        assert!(lines.contains(&"WantedBy=multi-user.target"));
+
+        let temp = tempfile::TempDir::new()?;
+        let path = temp.path().join("deep-addon-pg.service");
+        std::fs::write(&path, &service)?;
+        assert!(!add_network(&path, "deep-net")?);
+        assert!(add_network(&path, "deep-app-api-net")?);
+        assert_eq!(
+            service_networks(&read(&path)?),
+            ["deep-net", "deep-app-web-net", "deep-app-api-net"]
+        );
+        assert!(remove_network(&path, "deep-app-web-net")?);
+        assert!(!remove_network(&path, "deep-app-web-net")?);
+        assert!(is_generated(&path));
+
+        let err = service_from_quadlet(
+            &Docker,
+            "[Container]\nImage=x\nContainerName=y\nTmpfs=/tmp\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Tmpfs"));
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! Fixtures shared by the integration tests.
+// Each test crate compiles its own copy and uses only some of these.
+#![allow(dead_code)]
+
+use anyhow::Result;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+
+use deep::cli::deploy::DeployArgs;
+use deep::runner::Runner;
+use deep::runtime::{Podman, Runtime};
+
+/// Runner that answers from rules; the first rule whose needles all appear in
+/// the command line wins and unmatched commands succeed with no output.
+#[derive(Default)]
+pub struct TestRunner {
+    rules: Mutex<Vec<Rule>>,
+}
+
+struct Rule {
+    contains: Vec<String>,
+    status: i32,
+    stdout: String,
+    stderr: String,
+}
+
+impl TestRunner {
+    pub fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
+        self.rules.lock().expect("rules lock").push(Rule {
+            contains: contains.iter().map(|s| s.to_string()).collect(),
+            status,
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        });
+    }
+}
+
+impl Runner for TestRunner {
+    fn output(&self, program: &str, args: &[&str]) -> Result<Output> {
+        let cmdline = format!("{} {}", program, args.join(" "));
+        let rules = self.rules.lock().expect("rules lock");
+        let rule = rules
+            .iter()
+            .find(|rule| rule.contains.iter().all(|needle| cmdline.contains(needle)));
+        Ok(match rule {
+            Some(rule) => Output {
+                status: exit_status(rule.status),
+                stdout: rule.stdout.clone().into_bytes(),
+                stderr: rule.stderr.clone().into_bytes(),
+            },
+            None => Output {
+                status: exit_status(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            },
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Podman without detection, for tests that script the engine's output.
+pub fn test_podman() -> Runtime {
+    Runtime::with_engine(Arc::new(Podman))
+}
+
+/// `deep deploy app --config <config>` with every other flag off.
+pub fn deploy_args(config: PathBuf) -> DeployArgs {
+    DeployArgs {
+        app: "app".to_string(),
+        image: None,
+        git_sha: None,
+        image_digest: None,
+        health_path: None,
+        health_tcp: false,
+        health_retries: None,
+        health_timeout_ms: None,
+        health_interval_ms: None,
+        skip_proxy: false,
+        skip_pull: false,
+        config: Some(config),
+        record_only: false,
+        dry_run: false,
+        steal: false,
+    }
+}
@@ -1,4 +1,7 @@
-use deep::config::{AppConfig, HealthcheckKind};
+use deep::config::{
+    AppConfig, HealthcheckKind, HostConfig, ShipSink, TlsMode, validate_auth_username,
+    validate_cidr, validate_proxy, validate_volumes,
+};
 
 #[test]
 fn parse_minimal_app_config_defaults() {
@@ -96,3 +99,195 @@ retain = 7
     );
     assert_eq!(cfg.deploy.retain, 7);
 }
+
+#[test]
+fn parse_app_config_with_proxy_tls() {
+    let raw = r#"
+[app]
+name = "myapp"
+port = 8080
+domains = ["myapp.internal"]
+
+[proxy.tls]
+mode = "custom"
+cert = "myapp/fullchain.pem"
+key = "myapp/privkey.pem"
+"#;
+    let cfg: AppConfig = toml::from_str(raw).expect("parse config");
+    assert_eq!(cfg.proxy.tls.mode, TlsMode::Custom);
+    assert_eq!(cfg.proxy.tls.cert.as_deref(), Some("myapp/fullchain.pem"));
+    assert_eq!(cfg.proxy.tls.key.as_deref(), Some("myapp/privkey.pem"));
+    assert!(cfg.proxy.auth.is_none());
+    assert!(cfg.proxy.allow_cidrs.is_empty());
+
+    let minimal: AppConfig = toml::from_str(
+        r#"
+[app]
+name = "myapp"
+port = 8080
+"#,
+    )
+    .expect("parse config");
+    assert_eq!(minimal.proxy.tls.mode, TlsMode::Auto);
+}
+
+#[test]
+fn parse_host_config_logging() {
+    let raw = r#"
+[logging]
+driver = "journald"
+options = ["tag=deep"]
+
+[logging.ship]
+sink = "loki"
+url = "http://127.0.0.1:3100/loki/api/v1/push"
+labels = { host = "vps1" }
+"#;
+    let cfg: HostConfig = toml::from_str(raw).expect("parse host config");
+    assert_eq!(
+        cfg.logging.quadlet_lines(),
+        "LogDriver=journald\nLogOpt=tag=deep"
+    );
+    let ship = cfg.logging.ship.expect("ship section");
+    assert_eq!(ship.sink, ShipSink::Loki);
+    assert_eq!(ship.units, vec!["deep-app-*"]);
+    assert_eq!(ship.interval_secs, 5);
+    assert_eq!(ship.labels["host"], "vps1");
+
+    let empty: HostConfig = toml::from_str("").expect("parse empty host config");
+    assert_eq!(empty.logging.quadlet_lines(), "");
+}
+
+#[test]
+fn parse_app_config_with_resources() {
+    let raw = r#"
+[app]
+name = "myapp"
+port = 3000
+
+[resources]
+memory = "512m"
+cpus = 2
+pids_limit = 256
+"#;
+    let cfg: AppConfig = toml::from_str(raw).expect("parse config");
+    assert_eq!(cfg.resources.memory.as_deref(), Some("512m"));
+    assert_eq!(cfg.resources.cpus, Some(2.0));
+    let snapshot = cfg.to_snapshot(Vec::new());
+    assert_eq!(
+        snapshot.resources.quadlet_lines(),
+        "Memory=512m\nPodmanArgs=--cpus=2\nPidsLimit=256"
+    );
+}
+
+#[test]
+fn parse_app_config_with_volumes() {
+    let raw = r#"
+[app]
+name = "myapp"
+port = 3000
+
+[[volumes]]
+name = "data"
+path = "/var/lib/myapp"
+
+[[volumes]]
+name = "uploads"
+path = "/app/uploads"
+host_path = "/srv/uploads"
+"#;
+    let cfg: AppConfig = toml::from_str(raw).expect("parse config");
+    validate_volumes(&cfg.volumes).expect("valid volumes");
+    let lines: Vec<String> = cfg
+        .volumes
+        .iter()
+        .map(|volume| volume.quadlet_line("myapp"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "Volume=deep-vol-myapp-data:/var/lib/myapp",
+            "Volume=/srv/uploads:/app/uploads"
+        ]
+    );
+    assert_eq!(cfg.to_snapshot(Vec::new()).volumes, cfg.volumes);
+
+    let mut bad = cfg.volumes.clone();
+    bad[1].name = "data".to_string();
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].name = "../x".to_string();
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].name = "uploads".to_string();
+    bad[1].path = "relative".to_string();
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].path = "/app/uploads:ro".to_string();
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].path = "/app/uploads\nExec=/bin/sh".to_string();
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].path = "/app/uploads".to_string();
+    bad[1].host_path = Some("/srv/uploads:/etc".to_string());
+    assert!(validate_volumes(&bad).is_err());
+    bad[1].host_path = Some("/srv/up\tloads".to_string());
+    assert!(validate_volumes(&bad).is_err());
+}
+
+#[test]
+fn validate_proxy_rejects_unsafe_values() {
+    for cidr in [
+        "10.0.0.0/8",
+        "203.0.113.7",
+        "::1/128",
+        "2001:db8::/32",
+        "0.0.0.0/0",
+    ] {
+        validate_cidr(cidr).unwrap_or_else(|err| panic!("{cidr}: {err}"));
+    }
+    for cidr in [
+        "10.0.0.0/33",
+        "::/129",
+        "10.0.0.0/",
+        "10.0.0.0/+8",
+        "host",
+        "1.2.3.4 }",
+    ] {
+        assert!(validate_cidr(cidr).is_err(), "{cidr} accepted");
+    }
+
+    validate_auth_username("alice.smith@example.com").expect("valid username");
+    for user in ["", "bob smith", "eve}", "mallory\nreverse_proxy"] {
+        assert!(validate_auth_username(user).is_err(), "{user:?} accepted");
+    }
+
+    let raw = r#"
+[app]
+name = "myapp"
+port = 3000
+
+[proxy.auth]
+realm = "staging area"
+"#;
+    let mut cfg: AppConfig = toml::from_str(raw).expect("parse config");
+    validate_proxy(&cfg.proxy).expect("valid realm");
+    cfg.proxy.auth.as_mut().expect("auth").realm = Some("x\" {".to_string());
+    assert!(validate_proxy(&cfg.proxy).is_err());
+    cfg.proxy.auth = None;
+
+    for (size, keep_for) in [("10MiB", "720h"), ("100 MB", "30d"), ("1048576", "1h30m")] {
+        cfg.proxy.access_log.roll_size = size.to_string();
+        cfg.proxy.access_log.roll_keep_for = keep_for.to_string();
+        validate_proxy(&cfg.proxy).unwrap_or_else(|err| panic!("{size} {keep_for}: {err}"));
+    }
+    for (size, keep_for) in [
+        ("10 parsecs", "720h"),
+        ("10MiB", "720"),
+        ("MiB", "1w"),
+        ("10MiB\n}", "1h"),
+    ] {
+        cfg.proxy.access_log.roll_size = size.to_string();
+        cfg.proxy.access_log.roll_keep_for = keep_for.to_string();
+        assert!(
+            validate_proxy(&cfg.proxy).is_err(),
+            "{size:?} {keep_for:?} accepted"
+        );
+    }
+}
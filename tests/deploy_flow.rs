@@ -1,82 +1,17 @@
+mod common;
+
 use anyhow::Result;
 use std::net::TcpListener;
 use std::path::Path;
-use std::process::{ExitStatus, Output};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tempfile::TempDir;
 
 use deep::cli::deploy::{DeployArgs, RollbackArgs, handle_deploy, handle_rollback};
 use deep::db::Storage;
 use deep::proxy::CaddyFile;
-use deep::runner::{Runner, set_runner_for_tests};
-
-#[derive(Default)]
-struct TestRunner {
-    rules: Mutex<Vec<Rule>>,
-}
-
-#[derive(Clone)]
-struct Rule {
-    contains: Vec<String>,
-    status: i32,
-    stdout: String,
-    stderr: String,
-}
-
-impl Rule {
-    fn matches(&self, cmd: &str) -> bool {
-        self.contains.iter().all(|needle| cmd.contains(needle))
-    }
-}
-
-impl TestRunner {
-    fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
-        self.rules.lock().expect("rules lock").push(Rule {
-            contains: contains.iter().map(|s| s.to_string()).collect(),
-            status,
-            stdout: stdout.to_string(),
-            stderr: stderr.to_string(),
-        });
-    }
-}
-
-impl Runner for TestRunner {
-    fn output(&self, program: &str, args: &[&str]) -> Result<Output> {
-        let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-        let cmdline = format!("{} {}", program, args_joined);
-        if let Some(rule) = self
-            .rules
-            .lock()
-            .expect("rules lock")
-            .iter()
-            .find(|rule| rule.matches(&cmdline))
-            .cloned()
-        {
-            return Ok(Output {
-                status: exit_status(rule.status),
-                stdout: rule.stdout.into_bytes(),
-                stderr: rule.stderr.into_bytes(),
-            });
-        }
-        Ok(Output {
-            status: exit_status(0),
-            stdout: Vec::new(),
-            stderr: Vec::new(),
-        })
-    }
-}
+use deep::runner::set_runner_for_tests;
 
-#[cfg(unix)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::unix::process::ExitStatusExt;
-    ExitStatus::from_raw(code << 8)
-}
-
-#[cfg(windows)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::windows::process::ExitStatusExt;
-    ExitStatus::from_raw(code as u32)
-}
+use common::{TestRunner, deploy_args, test_podman};
 
 fn write_app_toml(path: &Path, quadlet_dir: &Path, port: u16) -> Result<()> {
     let contents = format!(
@@ -103,6 +38,7 @@ retain = 5
 #[test]
 fn deploy_then_rollback_switches_current_and_routes() -> Result<()> {
     let dir = TempDir::new()?;
+    let host_config = dir.path().join("deep.toml");
     let db_path = dir.path().join("deep.db");
     let quadlet_dir = dir.path().join("quadlets");
     let app_toml = dir.path().join("app.toml");
@@ -133,45 +69,21 @@ fn deploy_then_rollback_switches_current_and_routes() -> Result<()> {
     let mut storage = Storage::open(&db_path)?;
     let app_row = storage.create_app("app", dir.path().to_string_lossy().as_ref())?;
 
-    let proxy = CaddyFile::new(caddyfile.clone(), "deep-caddy".to_string());
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(caddyfile.clone(), "deep-caddy".to_string(), runtime.clone());
     let record_args = DeployArgs {
-        app: "app".to_string(),
-        image: None,
-        git_sha: None,
-        image_digest: None,
-        health_path: None,
-        health_tcp: false,
-        health_retries: None,
-        health_timeout_ms: None,
-        health_interval_ms: None,
-        skip_proxy: false,
         skip_pull: true,
-        config: Some(app_toml.clone()),
         record_only: true,
-        dry_run: false,
+        ..deploy_args(app_toml.clone())
     };
-    handle_deploy(&mut storage, &proxy, record_args)?;
+    handle_deploy(&mut storage, &runtime, &proxy, &host_config, record_args)?;
 
     let first_release = storage.current_release_id(&app_row.id)?;
     let first_release = first_release.expect("first release");
 
-    let deploy_args = DeployArgs {
-        app: "app".to_string(),
-        image: None,
-        git_sha: None,
-        image_digest: None,
-        health_path: None,
-        health_tcp: false,
-        health_retries: None,
-        health_timeout_ms: None,
-        health_interval_ms: None,
-        skip_proxy: false,
-        skip_pull: false,
-        config: Some(app_toml.clone()),
-        record_only: false,
-        dry_run: false,
-    };
-    handle_deploy(&mut storage, &proxy, deploy_args)?;
+    let args = deploy_args(app_toml.clone());
+    handle_deploy(&mut storage, &runtime, &proxy, &host_config, args)?;
 
     let second_release = storage.current_release_id(&app_row.id)?;
     let second_release = second_release.expect("second release");
@@ -184,8 +96,9 @@ fn deploy_then_rollback_switches_current_and_routes() -> Result<()> {
         app: "app".to_string(),
         release_id: first_release.clone(),
         dry_run: false,
+        steal: false,
     };
-    handle_rollback(&mut storage, &proxy, rollback_args)?;
+    handle_rollback(&mut storage, &runtime, &proxy, &host_config, rollback_args)?;
 
     let current = storage.current_release_id(&app_row.id)?;
     assert_eq!(current.as_deref(), Some(first_release.as_str()));
@@ -1,82 +1,17 @@
+mod common;
+
 use anyhow::Result;
 use std::path::Path;
-use std::process::{ExitStatus, Output};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tempfile::TempDir;
 
-use deep::cli::deploy::{DeployArgs, handle_deploy};
-use deep::config::{ConfigSnapshot, DeployConfig, HealthcheckConfig};
+use deep::cli::deploy::{DeployArgs, RollbackArgs, handle_deploy, handle_rollback};
+use deep::config::{ConfigSnapshot, DeployConfig, HealthcheckConfig, ProxyConfig};
 use deep::db::{ReleaseRow, Storage};
 use deep::proxy::CaddyFile;
-use deep::runner::{Runner, set_runner_for_tests};
-
-#[derive(Default)]
-struct TestRunner {
-    rules: Mutex<Vec<Rule>>,
-}
+use deep::runner::set_runner_for_tests;
 
-#[derive(Clone)]
-struct Rule {
-    contains: Vec<String>,
-    status: i32,
-    stdout: String,
-    stderr: String,
-}
-
-impl Rule {
-    fn matches(&self, cmd: &str) -> bool {
-        self.contains.iter().all(|needle| cmd.contains(needle))
-    }
-}
-
-impl TestRunner {
-    fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
-        self.rules.lock().expect("rules lock").push(Rule {
-            contains: contains.iter().map(|s| s.to_string()).collect(),
-            status,
-            stdout: stdout.to_string(),
-            stderr: stderr.to_string(),
-        });
-    }
-}
-
-impl Runner for TestRunner {
-    fn output(&self, program: &str, args: &[&str]) -> Result<Output> {
-        let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-        let cmdline = format!("{} {}", program, args_joined);
-        if let Some(rule) = self
-            .rules
-            .lock()
-            .expect("rules lock")
-            .iter()
-            .find(|rule| rule.matches(&cmdline))
-            .cloned()
-        {
-            return Ok(Output {
-                status: exit_status(rule.status),
-                stdout: rule.stdout.into_bytes(),
-                stderr: rule.stderr.into_bytes(),
-            });
-        }
-        Ok(Output {
-            status: exit_status(0),
-            stdout: Vec::new(),
-            stderr: Vec::new(),
-        })
-    }
-}
-
-#[cfg(unix)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::unix::process::ExitStatusExt;
-    ExitStatus::from_raw(code << 8)
-}
-
-#[cfg(windows)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::windows::process::ExitStatusExt;
-    ExitStatus::from_raw(code as u32)
-}
+use common::{TestRunner, deploy_args, test_podman};
 
 fn write_app_toml(path: &Path, quadlet_dir: &Path, retain: u32) -> Result<()> {
     let contents = format!(
@@ -143,13 +78,22 @@ fn base_snapshot(quadlet_dir: &Path, retain: u32) -> ConfigSnapshot {
             quadlet_dir: Some(quadlet_dir.to_string_lossy().to_string()),
             image_template: None,
             retain,
+            retain_failed: 1,
+            retain_days: None,
+            keep_tagged: false,
+            addon_wait_secs: None,
+            private_network: false,
         },
+        proxy: ProxyConfig::default(),
+        resources: Default::default(),
+        volumes: Vec::new(),
     }
 }
 
 #[test]
 fn deploy_start_failure_does_not_flip_current() -> Result<()> {
     let dir = TempDir::new()?;
+    let host_config = dir.path().join("deep.toml");
     let db_path = dir.path().join("deep.db");
     let quadlet_dir = dir.path().join("quadlets");
     let app_toml = dir.path().join("app.toml");
@@ -179,25 +123,20 @@ fn deploy_start_failure_does_not_flip_current() -> Result<()> {
     )?;
     set_current(&mut storage, &app.id, "r1")?;
 
-    let proxy = CaddyFile::new(dir.path().join("Caddyfile"), "deep-caddy".to_string());
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        dir.path().join("Caddyfile"),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
     let args = DeployArgs {
-        app: "app".to_string(),
-        image: None,
-        git_sha: None,
-        image_digest: None,
-        health_path: None,
-        health_tcp: false,
-        health_retries: None,
-        health_timeout_ms: None,
-        health_interval_ms: None,
         skip_proxy: true,
         skip_pull: true,
-        config: Some(app_toml),
-        record_only: false,
-        dry_run: false,
+        ..deploy_args(app_toml)
     };
 
-    let result = handle_deploy(&mut storage, &proxy, args);
+    let result = handle_deploy(&mut storage, &runtime, &proxy, &host_config, args);
     assert!(result.is_err());
 
     let current = storage.current_release_id(&app.id)?;
@@ -212,6 +151,7 @@ fn deploy_start_failure_does_not_flip_current() -> Result<()> {
 #[test]
 fn retention_prunes_old_releases() -> Result<()> {
     let dir = TempDir::new()?;
+    let host_config = dir.path().join("deep.toml");
     let db_path = dir.path().join("deep.db");
     let quadlet_dir = dir.path().join("quadlets");
     let app_toml = dir.path().join("app.toml");
@@ -242,25 +182,21 @@ fn retention_prunes_old_releases() -> Result<()> {
     )?;
     set_current(&mut storage, &app.id, "r2")?;
 
-    let proxy = CaddyFile::new(dir.path().join("Caddyfile"), "deep-caddy".to_string());
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        dir.path().join("Caddyfile"),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
     let args = DeployArgs {
-        app: "app".to_string(),
-        image: None,
-        git_sha: None,
-        image_digest: None,
-        health_path: None,
-        health_tcp: false,
-        health_retries: None,
-        health_timeout_ms: None,
-        health_interval_ms: None,
         skip_proxy: true,
         skip_pull: true,
-        config: Some(app_toml),
         record_only: true,
-        dry_run: false,
+        ..deploy_args(app_toml)
     };
 
-    handle_deploy(&mut storage, &proxy, args)?;
+    handle_deploy(&mut storage, &runtime, &proxy, &host_config, args)?;
 
     let releases = storage.list_releases(&app.id)?;
     assert_eq!(releases.len(), 2);
@@ -269,3 +205,132 @@ fn retention_prunes_old_releases() -> Result<()> {
     assert!(!ids.contains(&"r1".to_string()));
     Ok(())
 }
+
+#[test]
+fn retention_keeps_pinned_releases() -> Result<()> {
+    let dir = TempDir::new()?;
+    let host_config = dir.path().join("deep.toml");
+    let db_path = dir.path().join("deep.db");
+    let quadlet_dir = dir.path().join("quadlets");
+    let app_toml = dir.path().join("app.toml");
+    write_app_toml(&app_toml, &quadlet_dir, 2)?;
+
+    let runner = Arc::new(TestRunner::default());
+    let _guard = set_runner_for_tests(runner);
+
+    let mut storage = Storage::open(&db_path)?;
+    let app = storage.create_app("app", dir.path().to_string_lossy().as_ref())?;
+
+    let snapshot = base_snapshot(&quadlet_dir, 2);
+    insert_release(
+        &mut storage,
+        &app.id,
+        "r1",
+        "2024-01-01T00:00:00Z",
+        &snapshot,
+        "active",
+    )?;
+    insert_release(
+        &mut storage,
+        &app.id,
+        "r2",
+        "2024-01-02T00:00:00Z",
+        &snapshot,
+        "active",
+    )?;
+    set_current(&mut storage, &app.id, "r2")?;
+    assert!(storage.pin_release("r1")?);
+    assert!(!storage.pin_release("r1")?);
+
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        dir.path().join("Caddyfile"),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
+    let args = DeployArgs {
+        skip_proxy: true,
+        skip_pull: true,
+        record_only: true,
+        ..deploy_args(app_toml)
+    };
+
+    handle_deploy(&mut storage, &runtime, &proxy, &host_config, args)?;
+
+    let releases = storage.list_releases(&app.id)?;
+    assert_eq!(releases.len(), 3);
+    assert!(storage.pinned_release_ids(&app.id)?.contains("r1"));
+    assert!(storage.unpin_release("r1")?);
+    assert!(!storage.unpin_release("r1")?);
+    Ok(())
+}
+
+#[test]
+fn deploy_refuses_claimed_domain_unless_stealing() -> Result<()> {
+    let dir = TempDir::new()?;
+    let host_config = dir.path().join("deep.toml");
+    let db_path = dir.path().join("deep.db");
+    let quadlet_dir = dir.path().join("quadlets");
+    let app_toml = dir.path().join("app.toml");
+    write_app_toml(&app_toml, &quadlet_dir, 5)?;
+
+    let runner = Arc::new(TestRunner::default());
+    let _guard = set_runner_for_tests(runner);
+
+    let mut storage = Storage::open(&db_path)?;
+    storage.create_app("app", dir.path().to_string_lossy().as_ref())?;
+    let other = storage.create_app("other", dir.path().to_string_lossy().as_ref())?;
+
+    let mut snapshot = base_snapshot(&quadlet_dir, 5);
+    snapshot.domains = vec![
+        "app.example.com".to_string(),
+        "other.example.com".to_string(),
+    ];
+    insert_release(
+        &mut storage,
+        &other.id,
+        "o1",
+        "2024-01-01T00:00:00Z",
+        &snapshot,
+        "active",
+    )?;
+    set_current(&mut storage, &other.id, "o1")?;
+
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        dir.path().join("Caddyfile"),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
+    let args = |steal: bool| DeployArgs {
+        skip_proxy: true,
+        skip_pull: true,
+        record_only: true,
+        steal,
+        ..deploy_args(app_toml.clone())
+    };
+
+    let err = handle_deploy(&mut storage, &runtime, &proxy, &host_config, args(false)).unwrap_err();
+    assert!(err.to_string().contains("domain conflict"));
+
+    handle_deploy(&mut storage, &runtime, &proxy, &host_config, args(true))?;
+
+    // The owner's release is left as deployed; the move is recorded on the app.
+    let releases = storage.list_releases(&other.id)?;
+    let owner: ConfigSnapshot = serde_json::from_str(&releases[0].config_json)?;
+    assert_eq!(owner.domains.len(), 2);
+    assert_eq!(storage.stolen_domains(&other.id)?, ["app.example.com"]);
+
+    // Rolling the owner back onto the taken host needs --steal as well.
+    let rollback = RollbackArgs {
+        app: "other".to_string(),
+        release_id: "o1".to_string(),
+        dry_run: true,
+        steal: false,
+    };
+    let err = handle_rollback(&mut storage, &runtime, &proxy, &host_config, rollback).unwrap_err();
+    assert!(err.to_string().contains("domain conflict"));
+    Ok(())
+}
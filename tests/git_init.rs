@@ -1,5 +1,7 @@
 use deep::cli::git::init_repo_for_app;
 use deep::db::Storage;
+use deep::runtime::{Podman, Runtime};
+use std::sync::Arc;
 use tempfile::TempDir;
 
 #[test]
@@ -11,13 +13,13 @@ fn git_init_writes_post_receive_hook() {
         .create_app("myapp", "/srv/deep/repos/myapp.git")
         .expect("create app");
 
-    let repos_dir = temp.path().join("repos");
-    let repo_path = repos_dir.join("myapp.git");
+    let runtime = Runtime::with_engine(Arc::new(Podman));
+    let repo_path = temp.path().join("repos").join("myapp.git");
     let repo_path = init_repo_for_app(
         &mut storage,
+        &runtime,
         &app.name,
-        repos_dir.clone(),
-        Some(repo_path.clone()),
+        repo_path.clone(),
         Some("local/{{app}}:{{sha}}".to_string()),
         "Dockerfile",
         "deep",
@@ -26,7 +28,8 @@ fn git_init_writes_post_receive_hook() {
 
     let hook_path = repo_path.join("hooks").join("post-receive");
     let hook = std::fs::read_to_string(&hook_path).expect("read hook");
-    assert!(hook.contains("podman build"));
+    assert!(hook.contains("podman build --label \"deep.app=$app\" -t"));
+    assert!(hook.contains("REGISTRY_AUTH_FILE=\"/srv/deep/registry/config.json\""));
     assert!(hook.contains("deep deploy"));
     assert!(hook.contains("--skip-pull"));
     assert!(hook.contains("local/{{app}}:{{sha}}"));
@@ -1,10 +1,17 @@
 use deep::proxy::CaddyFile;
+use deep::runtime::{Podman, Runtime};
+use std::sync::Arc;
 use tempfile::TempDir;
 
 #[test]
 fn list_routes_empty_when_file_missing() {
     let temp = TempDir::new().expect("temp");
-    let proxy = CaddyFile::new(temp.path().join("Caddyfile"), "deep-caddy".to_string());
+    let runtime = Runtime::with_engine(Arc::new(Podman));
+    let proxy = CaddyFile::new(
+        temp.path().join("Caddyfile"),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
     let routes = proxy.list_routes().expect("routes");
     assert!(routes.is_empty());
 }
@@ -1,80 +1,15 @@
+mod common;
+
 use anyhow::Result;
 use std::path::PathBuf;
-use std::process::{ExitStatus, Output};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tempfile::TempDir;
 
-use deep::config::{ConfigSnapshot, DeployConfig, HealthcheckConfig};
+use deep::config::{ConfigSnapshot, DeployConfig, HealthcheckConfig, ProxyConfig};
 use deep::proxy::CaddyFile;
-use deep::runner::{Runner, set_runner_for_tests};
-
-#[derive(Default)]
-struct TestRunner {
-    rules: Mutex<Vec<Rule>>,
-}
-
-#[derive(Clone)]
-struct Rule {
-    contains: Vec<String>,
-    status: i32,
-    stdout: String,
-    stderr: String,
-}
-
-impl Rule {
-    fn matches(&self, cmd: &str) -> bool {
-        self.contains.iter().all(|needle| cmd.contains(needle))
-    }
-}
-
-impl TestRunner {
-    fn add_rule(&self, contains: &[&str], status: i32, stdout: &str, stderr: &str) {
-        self.rules.lock().expect("rules lock").push(Rule {
-            contains: contains.iter().map(|s| s.to_string()).collect(),
-            status,
-            stdout: stdout.to_string(),
-            stderr: stderr.to_string(),
-        });
-    }
-}
-
-impl Runner for TestRunner {
-    fn output(&self, program: &str, args: &[&str]) -> Result<Output> {
-        let args_joined = args.iter().copied().collect::<Vec<&str>>().join(" ");
-        let cmdline = format!("{} {}", program, args_joined);
-        if let Some(rule) = self
-            .rules
-            .lock()
-            .expect("rules lock")
-            .iter()
-            .find(|rule| rule.matches(&cmdline))
-            .cloned()
-        {
-            return Ok(Output {
-                status: exit_status(rule.status),
-                stdout: rule.stdout.into_bytes(),
-                stderr: rule.stderr.into_bytes(),
-            });
-        }
-        Ok(Output {
-            status: exit_status(0),
-            stdout: Vec::new(),
-            stderr: Vec::new(),
-        })
-    }
-}
-
-#[cfg(unix)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::unix::process::ExitStatusExt;
-    ExitStatus::from_raw(code << 8)
-}
+use deep::runner::set_runner_for_tests;
 
-#[cfg(windows)]
-fn exit_status(code: i32) -> ExitStatus {
-    use std::os::windows::process::ExitStatusExt;
-    ExitStatus::from_raw(code as u32)
-}
+use common::{TestRunner, test_podman};
 
 fn snapshot() -> ConfigSnapshot {
     ConfigSnapshot {
@@ -84,6 +19,9 @@ fn snapshot() -> ConfigSnapshot {
         addons: Vec::new(),
         healthcheck: HealthcheckConfig::default(),
         deploy: DeployConfig::default(),
+        proxy: ProxyConfig::default(),
+        resources: Default::default(),
+        volumes: Vec::new(),
     }
 }
 
@@ -109,7 +47,13 @@ fn proxy_reload_failure_restores_previous_caddyfile() -> Result<()> {
     );
     let _guard = set_runner_for_tests(runner);
 
-    let proxy = CaddyFile::new(PathBuf::from(&caddyfile), "deep-caddy".to_string());
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        PathBuf::from(&caddyfile),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
     let result = proxy.upsert_route("app", "r2", &snapshot());
     assert!(result.is_err());
 
@@ -121,3 +65,39 @@ fn proxy_reload_failure_restores_previous_caddyfile() -> Result<()> {
     assert_eq!(backup_contents, initial);
     Ok(())
 }
+
+#[test]
+fn proxy_validation_failure_keeps_caddyfile_and_skips_reload() -> Result<()> {
+    let dir = TempDir::new()?;
+    let caddyfile = dir.path().join("Caddyfile");
+    let initial = "# deep:app:old\nold.example.com {\n    reverse_proxy old:3000\n}\n# deep:end\n";
+    std::fs::write(&caddyfile, initial)?;
+
+    let runner = Arc::new(TestRunner::default());
+    runner.add_rule(
+        &["podman exec deep-caddy caddy validate --config /etc/caddy/Caddyfile.deep-tmp"],
+        1,
+        "",
+        "unrecognized directive",
+    );
+    runner.add_rule(&["reload deep-caddy.service"], 1, "", "reload must not run");
+    let _guard = set_runner_for_tests(runner);
+
+    let runtime = test_podman();
+
+    let proxy = CaddyFile::new(
+        PathBuf::from(&caddyfile),
+        "deep-caddy".to_string(),
+        runtime.clone(),
+    );
+    let err = proxy
+        .upsert_route("app", "r2", &snapshot())
+        .expect_err("validation should fail");
+    assert!(err.to_string().contains("unrecognized directive"));
+
+    let current = std::fs::read_to_string(&caddyfile)?;
+    assert_eq!(current, initial);
+    assert!(!caddyfile.with_extension("deep-tmp").exists());
+    assert!(!caddyfile.with_extension("bak").exists());
+    Ok(())
+}